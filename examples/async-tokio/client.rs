@@ -1,14 +1,15 @@
 use crate::NAME;
 use gipc::connection::AsyncConnection;
+use std::time::Duration;
 
 // The asynchronous client code is virtually the same as the synchronous client code.
 // Every method signature is as close to the synchronous API as possible.
 
 pub async fn main() {
-    // Much like the synchronous connection, you connect using the `connect_to_socket` function.
-    // It has the same parameters - the first one is the name of the socket, and the second is whether
-    // the socket is global or not.
-    let mut connection = AsyncConnection::connect_to_socket(NAME, false)
+    // Much like the synchronous connection, you connect using the `connect_to_socket_wait` function.
+    // It has the same parameters - the first one is the name of the socket, the second is whether
+    // the socket is global or not, and the third is how long to wait for the listener to start listening.
+    let mut connection = AsyncConnection::connect_to_socket_wait(NAME, false, Duration::from_secs(5))
         .await
         .expect("Connection worked");
 
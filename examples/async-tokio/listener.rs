@@ -17,6 +17,7 @@ pub async fn main() {
     // This is in general good practice, especially if you need to be able to handle multiple connections.
     // Even if you don't, you should still do this.
     let mut handles = vec![]; // to allow for joining the handles later on
+    #[allow(clippy::never_loop)] // see the break at the end of the loop
     while let Ok(connection) = listener.accept().await {
         // This is the good thing about the async support listener-side:
         // Using this, you can spawn the handler and then continue to accept connections,
@@ -1,6 +1,4 @@
 use std::io;
-use std::time::Duration;
-use tokio::time::sleep;
 
 mod client;
 mod listener;
@@ -12,7 +10,7 @@ async fn main() -> io::Result<()> {
     println!("An example of how gipc works asynchronously (using Tokio):");
 
     let listener = tokio::spawn(listener::main());
-    sleep(Duration::from_secs(1)).await; // once again, sleep to let the listener listen
+    // once again, the client waits for the listener to start listening by itself
     let client = tokio::spawn(client::main());
     listener.await?;
     client.await?;
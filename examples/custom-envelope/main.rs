@@ -0,0 +1,94 @@
+use gipc::connection::{Connection, Listener};
+use gipc::message::Envelope;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::time::Duration;
+
+const NAME: &str = "gipc-example-custom-envelope";
+
+// Normally, everything you send through a connection is wrapped in gipc's own `Message` type.
+// If your protocol needs more than that, you can define your own envelope instead.
+// The only requirement is that it has a `ClosingConnection` unit variant as its first variant,
+// since that's what gipc sends when a connection is closed.
+#[derive(Debug, Serialize, Deserialize)]
+enum Frame {
+    ClosingConnection,
+    Data(String),
+    // Tells the other side how many more messages it's allowed to send.
+    WindowUpdate(u32),
+}
+
+impl Envelope for Frame {
+    fn is_closing(&self) -> bool {
+        matches!(self, Frame::ClosingConnection)
+    }
+}
+
+fn listener() {
+    let mut listener =
+        Listener::listen_as_socket(NAME, false).expect("Couldn't listen! That's sad.");
+    let mut connection = listener.accept().expect("Couldn't accept a connection!");
+
+    // Let the client know that it can send two messages.
+    connection
+        .send_envelope(&Frame::WindowUpdate(2))
+        .expect("Couldn't send window update!");
+    loop {
+        // Receiving an envelope gives you the entire frame, so you can tell control frames and data apart yourself.
+        // Once the client closes the connection, this fails with `Error::Closed(true)`.
+        match connection.receive_envelope::<Frame>() {
+            Ok(Frame::Data(data)) => {
+                println!("[listener] Got {:?}, allowing another message", data);
+                // This can fail if the client has already closed the connection because it has nothing else to send,
+                // but anything it sent before that can still be received, so we just carry on.
+                let _ = connection.send_envelope(&Frame::WindowUpdate(1));
+            }
+            Ok(frame) => println!("[listener] Got an unexpected frame: {:?}", frame),
+            Err(_) => break,
+        }
+    }
+    println!("[listener] The client is done");
+}
+
+fn client() {
+    let mut connection = Connection::connect_to_socket_wait(NAME, false, Duration::from_secs(5))
+        .expect("Connection should connect properly");
+
+    let mut window = 0;
+    for message in ["first", "second", "third"] {
+        // Wait until the listener allows us to send something.
+        while window == 0 {
+            match connection
+                .receive_envelope::<Frame>()
+                .expect("Couldn't receive a frame!")
+            {
+                Frame::WindowUpdate(more) => window += more,
+                frame => println!("[client] Got an unexpected frame: {:?}", frame),
+            }
+        }
+        println!("[client] Sending {:?}", message);
+        connection
+            .send_envelope(&Frame::Data(message.to_string()))
+            .expect("Couldn't send message!");
+        window -= 1;
+    }
+
+    // Closing the connection sends a `ClosingConnection` frame, which the listener understands as `Frame::ClosingConnection`.
+    connection.close();
+}
+
+// Just some boilerplate to demonstrate the example
+fn main() -> Result<(), io::Error> {
+    println!("An example of how gipc can be used with a custom envelope:");
+
+    let listener = std::thread::Builder::new()
+        .name("listener thread".to_string())
+        .spawn(listener)?;
+    let client = std::thread::Builder::new()
+        .name("client thread".to_string())
+        .spawn(client)?;
+    listener.join().expect("Couldn't join listener thread");
+    client.join().expect("Couldn't join client thread");
+
+    Ok(())
+}
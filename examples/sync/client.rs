@@ -1,5 +1,6 @@
 use crate::NAME;
 use gipc::connection::Connection;
+use std::time::Duration;
 
 pub fn main() {
     println!("[client] Connecting to socket {}", NAME);
@@ -8,8 +9,10 @@ pub fn main() {
     // Internally, gipc resolves the name to some location deterministically.
     // The second parameter here specifies whether or not to resolve the socket globally (i.e. when the listening process exists for the entire system).
     // In our case, it doesn't, so we set that parameter to false.
-    let mut connection =
-        Connection::connect_to_socket(NAME, false).expect("Connection should connect properly");
+    // Since the listener is started at the same time as the client, it might not be listening yet,
+    // so we use `connect_to_socket_wait`, which keeps trying to connect for at most as long as the third parameter says.
+    let mut connection = Connection::connect_to_socket_wait(NAME, false, Duration::from_secs(5))
+        .expect("Connection should connect properly");
 
     // Once we've successfully connected, we can use its two main methods: `send` and `receive`.
     // `receive` attempts to receive a message from the other process (in this case, the listener).
@@ -1,6 +1,4 @@
 use std::io;
-use std::thread::sleep;
-use std::time::Duration;
 
 mod client;
 mod listener;
@@ -15,8 +13,7 @@ fn main() -> Result<(), io::Error> {
     let listener = std::thread::Builder::new()
         .name("listener thread".to_string())
         .spawn(listener::main)?;
-    // Run the client after sleeping for a bit so that we know that the listener is connected
-    sleep(Duration::from_secs(1));
+    // Run the client at the same time - it waits for the listener to start listening by itself
     let client = std::thread::Builder::new()
         .name("client thread".to_string())
         .spawn(client::main)?;
@@ -0,0 +1,271 @@
+//! Codecs determine how messages are encoded in the payload of a frame.
+//!
+//! gipc uses [`CborCodec`] unless told otherwise. Other codecs only change how the payload is encoded -
+//! the framing around it stays the same, so every connection can use several codecs as long as both sides agree on which one is used when.
+
+use crate::{Error, Result};
+use ciborium::value::Value;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::Arc;
+
+/// An encoding for the payload of messages.
+pub trait Codec: Send + Sync {
+    /// Serialises `value`, appending the serialised bytes to `buffer`.
+    fn serialise<T>(&self, value: &T, buffer: &mut Vec<u8>) -> Result<()>
+    where
+        T: Serialize + ?Sized;
+    /// Deserialises a value from `bytes`.
+    /// Implementations should fail with [`Error::Deserialise`] if `bytes` contains anything after the value,
+    /// as that indicates that the frame doesn't contain what the sender meant to send.
+    fn deserialise<T>(&self, bytes: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned;
+}
+
+/// A codec that can be used as a trait object, unlike [`Codec`].
+/// This allows the codec a connection uses to be chosen at runtime, e.g. using `set_codec` on connections.
+///
+/// Values are converted to and from a dynamic CBOR [`Value`] when they are encoded and decoded, which costs some performance.
+/// This only works for self-describing formats, which is why [`PostcardCodec`] doesn't implement this trait.
+/// It also means that whatever a format can't represent natively is lost - for example, byte strings are encoded as arrays by JSON,
+/// so they can't be received as byte strings from a connection using [`JsonCodec`] this way.
+pub trait DynCodec: Send + Sync {
+    /// Serialises `value`, appending the serialised bytes to `buffer`.
+    fn serialise_value(&self, value: &Value, buffer: &mut Vec<u8>) -> Result<()>;
+    /// Deserialises a value from `bytes`.
+    /// Like [`Codec::deserialise`], this should fail if `bytes` contains anything after the value.
+    fn deserialise_value(&self, bytes: &[u8]) -> Result<Value>;
+    /// Gets a short, human-readable name for this codec, as reported by `describe` on connections.
+    /// The default implementation uses the name of the type implementing this trait.
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+}
+
+/// The codec a connection currently uses.
+#[derive(Clone)]
+pub(crate) enum ActiveCodec {
+    /// The default codec, [`CborCodec`].
+    Default,
+    /// A codec chosen at runtime.
+    Dyn(Arc<dyn DynCodec>),
+}
+
+impl ActiveCodec {
+    /// Whether this is the default codec.
+    pub fn is_default(&self) -> bool {
+        matches!(self, ActiveCodec::Default)
+    }
+    /// The name of this codec, as given by [`DynCodec::name`].
+    pub fn name(&self) -> &'static str {
+        match self {
+            ActiveCodec::Default => CborCodec.name(),
+            ActiveCodec::Dyn(codec) => codec.name(),
+        }
+    }
+}
+
+impl Codec for ActiveCodec {
+    fn serialise<T>(&self, value: &T, buffer: &mut Vec<u8>) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        match self {
+            ActiveCodec::Default => CborCodec.serialise(value, buffer),
+            ActiveCodec::Dyn(codec) => {
+                let value = Value::serialized(value).map_err(|v| Error::Serialise(v.into()))?;
+                codec.serialise_value(&value, buffer)
+            }
+        }
+    }
+    fn deserialise<T>(&self, bytes: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        match self {
+            ActiveCodec::Default => CborCodec.deserialise(bytes),
+            ActiveCodec::Dyn(codec) => codec
+                .deserialise_value(bytes)?
+                .deserialized()
+                .map_err(|v| Error::Deserialise(v.into())),
+        }
+    }
+}
+
+/// The default codec, which encodes values using [CBOR](https://cbor.io/) through [`ciborium`].
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CborCodec;
+
+impl Codec for CborCodec {
+    fn serialise<T>(&self, value: &T, buffer: &mut Vec<u8>) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        ciborium::ser::into_writer(value, buffer).map_err(|v| Error::Serialise(v.into()))
+    }
+    fn deserialise<T>(&self, bytes: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let mut reader = bytes;
+        let value =
+            ciborium::de::from_reader(&mut reader).map_err(|v| Error::Deserialise(v.into()))?;
+        check_trailing(reader)?;
+        Ok(value)
+    }
+}
+
+impl DynCodec for CborCodec {
+    fn serialise_value(&self, value: &Value, buffer: &mut Vec<u8>) -> Result<()> {
+        self.serialise(value, buffer)
+    }
+    fn deserialise_value(&self, bytes: &[u8]) -> Result<Value> {
+        self.deserialise(bytes)
+    }
+    fn name(&self) -> &'static str {
+        "CBOR"
+    }
+}
+
+/// A codec that encodes values as JSON through [`serde_json`].
+#[cfg(feature = "json")]
+#[cfg_attr(docsrs, doc(cfg(feature = "json")))]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct JsonCodec;
+
+#[cfg(feature = "json")]
+impl Codec for JsonCodec {
+    fn serialise<T>(&self, value: &T, buffer: &mut Vec<u8>) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        serde_json::to_writer(buffer, value).map_err(|v| Error::Serialise(v.into()))
+    }
+    fn deserialise<T>(&self, bytes: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        serde_json::from_slice(bytes).map_err(|v| Error::Deserialise(v.into()))
+    }
+}
+
+#[cfg(feature = "json")]
+impl DynCodec for JsonCodec {
+    fn serialise_value(&self, value: &Value, buffer: &mut Vec<u8>) -> Result<()> {
+        self.serialise(value, buffer)
+    }
+    fn deserialise_value(&self, bytes: &[u8]) -> Result<Value> {
+        self.deserialise(bytes)
+    }
+    fn name(&self) -> &'static str {
+        "JSON"
+    }
+}
+
+/// A codec that encodes values using [`postcard`].
+///
+/// Unlike the other codecs, postcard isn't self-describing - values are encoded without any information about their structure.
+/// Both sides therefore have to agree on the exact layout of the [`Message`](crate::message::Message) being sent,
+/// including the type of the data it contains.
+/// The variants of [`Message`](crate::message::Message) are encoded as their index as a varint, in declaration order: `ClosingConnection` is `0`, `Data` is `1`, `Signal` is `2`, `Acked` is `3`, `Ack` is `4`, `Priority` is `5`, `Stamped` is `6`, `ClosingWithReason` is `7`, `Ping` is `8` and `Pong` is `9`,
+/// followed by the fields of the variant in order (e.g. `Stamped` is followed by its timestamp and then its data),
+/// and the encoding of a given value is always the same, so any other postcard implementation can decode the messages.
+#[cfg(feature = "postcard")]
+#[cfg_attr(docsrs, doc(cfg(feature = "postcard")))]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PostcardCodec;
+
+#[cfg(feature = "postcard")]
+impl Codec for PostcardCodec {
+    fn serialise<T>(&self, value: &T, buffer: &mut Vec<u8>) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        postcard::to_extend(value, std::mem::take(buffer))
+            .map(|serialised| *buffer = serialised)
+            .map_err(|v| Error::Serialise(v.into()))
+    }
+    fn deserialise<T>(&self, bytes: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let (value, rest) =
+            postcard::take_from_bytes(bytes).map_err(|v| Error::Deserialise(v.into()))?;
+        check_trailing(rest)?;
+        Ok(value)
+    }
+}
+
+/// Fails with [`Error::Deserialise`] if there is anything left in `rest` after deserialising a value.
+fn check_trailing(rest: &[u8]) -> Result<()> {
+    if rest.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Deserialise(
+            format!(
+                "trailing bytes after the message ({} left over)",
+                rest.len()
+            )
+            .into(),
+        ))
+    }
+}
+
+#[cfg(all(test, feature = "postcard"))]
+mod tests {
+    use super::*;
+    use crate::connection::CloseCode;
+    use crate::message::Message;
+
+    fn round_trip(message: &Message<String>) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        PostcardCodec.serialise(message, &mut buffer).unwrap();
+        let decoded: Message<String> = PostcardCodec.deserialise(&buffer).unwrap();
+        assert_eq!(decoded.kind(), message.kind());
+        assert_eq!(
+            postcard::to_allocvec(&decoded).unwrap(),
+            buffer,
+            "{:?} didn't survive a round trip",
+            message
+        );
+        buffer
+    }
+
+    #[test]
+    fn postcard_round_trips_and_matches_standalone_postcard() {
+        let data = Message::Data("hello".to_owned());
+        let buffer = round_trip(&data);
+        assert_eq!(buffer, postcard::to_allocvec(&data).unwrap());
+        assert_eq!(buffer, [1, 5, b'h', b'e', b'l', b'l', b'o']);
+    }
+
+    #[test]
+    fn postcard_variant_indices_match_the_docs() {
+        let messages = [
+            Message::ClosingConnection,
+            Message::Data(String::new()),
+            Message::Signal(0),
+            Message::Acked(String::new()),
+            Message::Ack,
+            Message::Priority(String::new()),
+            Message::Stamped(0, String::new()),
+            Message::ClosingWithReason(CloseCode::NORMAL, None),
+            Message::Ping,
+            Message::Pong,
+        ];
+        for (index, message) in messages.iter().enumerate() {
+            assert_eq!(round_trip(message)[0] as usize, index);
+        }
+    }
+
+    #[test]
+    fn postcard_rejects_trailing_bytes() {
+        let mut buffer = postcard::to_allocvec(&Message::<String>::Signal(3)).unwrap();
+        buffer.push(0);
+        assert!(matches!(
+            PostcardCodec.deserialise::<Message<String>>(&buffer),
+            Err(Error::Deserialise(_))
+        ));
+    }
+}
@@ -11,19 +11,134 @@
 //!
 //! See the [`async-tokio` example directory](https://github.com/tecc/gipc/tree/dev/examples/async-tokio) for both an example client and listener.
 
-use super::interprocess::name_onto;
-use crate::message::Message;
+#[cfg(feature = "auth")]
+use super::auth;
+use super::buffer::{noop_waker, BufferedStream};
+use super::interprocess::{bind_with_user_fallback, name_onto};
+#[cfg(windows)]
+use super::pipe::{PipeConfig, PipeOptions};
+use super::registry::Registration;
+use super::{
+    is_not_listening, next_connection_id, AcceptFilter, AcceptMeta, AcceptRetryPolicy,
+    BackoffPolicy, CloseCode, CloseReason, ConnectionDescription, ConnectionRegistry, Direction,
+    Failure, Lane, MessageHook, Observer, PeerCredentials, RateLimit, RateLimiter, ReceiveOutcome,
+    ResolvedName, SendInstant, CONNECT_POLL_INTERVAL, MAX_GRACEFUL_ACCEPT_FAILURES,
+};
+use crate::codec::{ActiveCodec, Codec, DynCodec};
+#[cfg(feature = "type-check")]
+use crate::message::fingerprint;
+use crate::message::{
+    frame_payload, framed_len, is_priority_frame, ping_or_pong, write_envelope_async,
+    write_stream_async, Envelope, Message, MessageKind, Payload, MAX_PING_LEN,
+};
 use crate::{Error, Result};
 use async_trait::async_trait;
 use futures_io::{AsyncRead, AsyncWrite};
 use interprocess::local_socket::tokio::{LocalSocketListener, LocalSocketStream};
-use serde::de::DeserializeOwned;
+use serde::de::{DeserializeOwned, IgnoredAny};
 use serde::Serialize;
+#[cfg(windows)]
+use interprocess::os::windows::named_pipe::tokio::DuplexBytePipeStream;
+use std::cell::{Cell, OnceCell};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+#[cfg(windows)]
+use tokio::net::windows::named_pipe::NamedPipeServer;
+
+/// A function that is called with every connection a listener accepts, right before it is returned, as set by [`Listener::set_connect_hook`].
+pub type ConnectHook = Box<dyn Fn(&Connection) + Send + Sync>;
+
+/// A function that is called in debug builds whenever a connection or listener is dropped without being closed, as set by [`set_unclosed_drop_hook`].
+/// It is given a message that describes what was dropped.
+pub type UnclosedDropHook = fn(&str);
+
+/// What happens when a connection or listener is dropped without being closed.
+static UNCLOSED_DROP_HOOK: RwLock<Option<UnclosedDropHook>> =
+    RwLock::new(Some(print_unclosed_drop));
+
+fn print_unclosed_drop(message: &str) {
+    eprintln!("gipc: {}", message);
+}
+
+/// Sets what happens in debug builds when a connection or listener is dropped without being closed, replacing what happened before.
+/// By default, a warning is printed to stderr; `None` silences these warnings entirely.
+/// Release builds don't check for this at all, so the hook is never called in them.
+pub fn set_unclosed_drop_hook(hook: Option<UnclosedDropHook>) {
+    *UNCLOSED_DROP_HOOK
+        .write()
+        .unwrap_or_else(|error| error.into_inner()) = hook;
+}
+
+#[cfg(debug_assertions)]
+fn report_unclosed_drop(message: std::fmt::Arguments) {
+    let hook = *UNCLOSED_DROP_HOOK
+        .read()
+        .unwrap_or_else(|error| error.into_inner());
+    if let Some(hook) = hook {
+        hook(&message.to_string());
+    }
+}
+
+/// A handle that pauses and resumes accepting connections on a [`Listener`], as returned by [`Listener::pause_handle`].
+/// Since [`accept`](Listener::accept) borrows the listener mutably, this is how another task resumes a listener that is waiting whilst paused.
+/// Cloning a handle gives you another handle to the same listener.
+#[derive(Clone, Default)]
+pub struct PauseHandle {
+    state: Arc<PauseState>,
+}
+
+#[derive(Default)]
+struct PauseState {
+    paused: AtomicBool,
+    resumed: tokio::sync::Notify,
+}
+
+impl PauseHandle {
+    /// Pauses accepting connections, like [`Listener::pause`].
+    pub fn pause(&self) {
+        self.state.paused.store(true, Ordering::SeqCst);
+    }
+    /// Resumes accepting connections, like [`Listener::resume`].
+    pub fn resume(&self) {
+        self.state.paused.store(false, Ordering::SeqCst);
+        self.state.resumed.notify_waiters();
+    }
+    /// Checks whether accepting connections is paused.
+    pub fn is_paused(&self) -> bool {
+        self.state.paused.load(Ordering::SeqCst)
+    }
+    /// Waits until accepting connections isn't paused anymore, returning immediately if it isn't paused.
+    async fn wait_resumed(&self) {
+        loop {
+            // the notification has to be listened for before checking, so that a resume in between isn't missed
+            let resumed = self.state.resumed.notified();
+            if !self.is_paused() {
+                return;
+            }
+            resumed.await;
+        }
+    }
+}
 
 /// Listeners allow you to wait until new [`Connection`s](Connection) can be established.
+///
+/// Asynchronous listeners can't close themselves when dropped, so they have to be closed manually using [`close`](Self::close).
+/// In debug builds, dropping a listener that is still open prints a warning.
 pub struct Listener {
     internal: Box<dyn ListenerImpl>,
     closed: bool,
+    user_fallback: bool,
+    resolved_name: Option<ResolvedName>,
+    registry: Option<ConnectionRegistry>,
+    accept_retry: AcceptRetryPolicy,
+    accept_filter: Option<AcceptFilter>,
+    connect_hook: Option<ConnectHook>,
+    pause: OnceLock<PauseHandle>,
+    #[cfg(feature = "auth")]
+    auth_secret: Option<Vec<u8>>,
 }
 
 impl Listener {
@@ -33,24 +148,329 @@ impl Listener {
         Self {
             internal,
             closed: false,
+            user_fallback: false,
+            resolved_name: None,
+            registry: None,
+            accept_retry: AcceptRetryPolicy::DEFAULT,
+            accept_filter: None,
+            connect_hook: None,
+            pause: OnceLock::new(),
+            #[cfg(feature = "auth")]
+            auth_secret: None,
         }
     }
     /// Listens to a socket on the local machine with a name based on `name`.
     /// The actual name used is generated internally.
+    /// Names that would be too long for the platform are replaced by a hash of themselves, failing with [`Error::NameTooLong`] if even that is too long.
+    /// Fails with [`Error::Bind`] if the socket can't be bound to.
     pub fn listen_as_socket<S>(name: S, global: bool) -> Result<Self>
     where
         S: AsRef<str>,
     {
-        let bound = name_onto!(LocalSocketListener::bind; name, global)?;
-        Ok(Self::new(Box::new(bound)))
+        let (resolved, bound) = name_onto!(LocalSocketListener::bind; name, global);
+        let bound = bound.map_err(|source| Error::Bind {
+            name: resolved.to_string(),
+            source,
+        })?;
+        let mut listener = Self::new(Box::new(bound));
+        listener.resolved_name = Some(resolved);
+        Ok(listener)
+    }
+
+    /// Listens to a Unix domain socket at exactly `path`, instead of at a path generated from a name like [`listen_as_socket`](Self::listen_as_socket) does.
+    /// This is useful when the location of the socket is decided elsewhere, such as in a configuration file or by another program.
+    /// Fails with [`Error::Bind`] if the socket can't be bound to, e.g. because a file already exists at `path`.
+    #[cfg(unix)]
+    #[cfg_attr(docsrs, doc(cfg(unix)))]
+    pub fn listen_on_path<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let resolved = ResolvedName::Path(path.as_ref().to_owned());
+        let bound = LocalSocketListener::bind(path.as_ref()).map_err(|source| Error::Bind {
+            name: resolved.to_string(),
+            source,
+        })?;
+        let mut listener = Self::new(Box::new(bound));
+        listener.resolved_name = Some(resolved);
+        Ok(listener)
+    }
+
+    /// Listens to a socket like [`listen_as_socket`](Self::listen_as_socket),
+    /// but if `global` and `fallback_to_user` are both `true` and the process isn't permitted to create the global socket,
+    /// the socket is created as a non-global socket instead. [`is_user_fallback`](Self::is_user_fallback) tells you whether that happened.
+    ///
+    /// Note that non-global sockets are scoped to the current user, so processes running as other users won't be able to connect to the listener.
+    /// Clients have to connect with `global` set to `false` to reach a listener that fell back.
+    pub fn listen_as_socket_with_fallback<S>(
+        name: S,
+        global: bool,
+        fallback_to_user: bool,
+    ) -> Result<Self>
+    where
+        S: AsRef<str>,
+    {
+        let (mut listener, fell_back) =
+            bind_with_user_fallback(global, fallback_to_user, |global| {
+                Self::listen_as_socket(name.as_ref(), global)
+            })?;
+        listener.user_fallback = fell_back;
+        Ok(listener)
+    }
+
+    /// Listens to a socket like [`listen_as_socket`](Self::listen_as_socket),
+    /// retrying up to `retries` times with `delay` between attempts if binding fails with a transient I/O error
+    /// (for example if the directory the socket is in doesn't exist yet).
+    /// Errors that won't go away by retrying are returned immediately, as is the last error if all attempts fail.
+    pub async fn listen_as_socket_retry<S>(
+        name: S,
+        global: bool,
+        retries: usize,
+        delay: Duration,
+    ) -> Result<Self>
+    where
+        S: AsRef<str>,
+    {
+        let mut attempt = 0;
+        loop {
+            match Self::listen_as_socket(name.as_ref(), global) {
+                Err(error) if error.is_transient() && attempt < retries => {
+                    attempt += 1;
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Listens on a Windows named pipe created using `options`,
+    /// which allows you to control pipe-specific options that [`listen_as_socket`](Self::listen_as_socket) doesn't expose, such as who may connect.
+    /// Clients connect to it using [`Connection::connect_to_pipe`] with the same name.
+    /// The pipe is registered with the current Tokio runtime, so this has to be called from within one.
+    /// Fails with [`Error::Bind`] if the pipe can't be created, including when another pipe with the same name exists or the security descriptor is invalid.
+    ///
+    /// # Security
+    /// Unless `options` has a [security descriptor](PipeOptions::security_descriptor), the pipe gets the default one, which lets other users on the machine connect to it.
+    /// Connections from other machines are always rejected.
+    #[cfg(windows)]
+    #[cfg_attr(docsrs, doc(cfg(windows)))]
+    pub fn listen_on_pipe(options: &PipeOptions) -> Result<Self> {
+        let resolved = pipe_path(&options.name);
+        let bound = PipeServer::create(options).map_err(|source| Error::Bind {
+            name: resolved.to_string(),
+            source,
+        })?;
+        let mut listener = Self::new(Box::new(bound));
+        listener.resolved_name = Some(resolved);
+        Ok(listener)
+    }
+
+    /// Wraps `listener`, a Unix domain socket that is already listening, such as one handed to the process by a service manager using socket activation.
+    /// The socket is switched to non-blocking mode and registered with the current Tokio runtime, so this has to be called from within one.
+    ///
+    /// The returned listener takes ownership of the socket's file descriptor and closes it when it's dropped,
+    /// but never removes the socket's file - that is left to whoever bound the socket.
+    #[cfg(unix)]
+    #[cfg_attr(docsrs, doc(cfg(unix)))]
+    pub fn from_std(listener: std::os::unix::net::UnixListener) -> Result<Self> {
+        let resolved = super::interprocess::std_listener_name(&listener);
+        listener.set_nonblocking(true)?;
+        let mut listener = Self::from(tokio::net::UnixListener::from_std(listener)?);
+        listener.resolved_name = resolved;
+        Ok(listener)
     }
 
     /// Accept a new connection.
+    /// Errors that are likely to go away by themselves (like running out of file descriptors) are handled according to this listener's
+    /// [accept retry policy](Self::set_accept_retry_policy), which by default retries them after a short delay.
+    /// Connections rejected by the [accept filter](Self::set_accept_filter) are closed, and accepting continues with the next connection.
     pub async fn accept(&mut self) -> Result<Connection> {
         if self.closed {
             return Err(Error::Closed(false));
         }
-        self.internal.accept().await
+        if let Some(pause) = self.pause.get() {
+            pause.wait_resumed().await;
+        }
+        loop {
+            let connection = match self.internal.accept().await {
+                Err(error) if error.is_transient_accept() => match self.accept_retry {
+                    AcceptRetryPolicy::Never => return Err(error),
+                    AcceptRetryPolicy::After(delay) => {
+                        tokio::time::sleep(delay).await;
+                        continue;
+                    }
+                },
+                result => result?,
+            };
+            if let Some(connection) = self.admit(connection).await? {
+                return Ok(connection);
+            }
+        }
+    }
+    /// Accept a new connection like [`accept`](Self::accept), then accept up to `max` connections in total by also accepting those that are already pending,
+    /// without waiting for more to arrive. This is useful for working through a burst of connections, such as when many clients connect at startup.
+    /// Returns no connections if `max` is 0.
+    ///
+    /// Errors that occur after the first connection has been accepted end the batch early instead of being returned, so that no accepted connection is lost.
+    /// The underlying [`ListenerImpl::accept`] is polled once for each further connection and dropped if it isn't ready,
+    /// so this only accepts more than one connection from listeners whose accept is [cancel safe](ListenerImpl::is_accept_cancel_safe),
+    /// like the one for Unix domain sockets. Named pipes on Windows aren't, so this accepts a single connection from those.
+    pub async fn accept_many(&mut self, max: usize) -> Result<Vec<Connection>> {
+        let mut connections = Vec::new();
+        if max == 0 {
+            return Ok(connections);
+        }
+        connections.push(self.accept().await?);
+        while connections.len() < max && !self.is_paused() {
+            let Some(Ok(connection)) = self.accept_pending() else {
+                break;
+            };
+            match self.admit(connection).await {
+                Ok(Some(connection)) => connections.push(connection),
+                Ok(None) => {}
+                Err(_) => break,
+            }
+        }
+        Ok(connections)
+    }
+    /// Accepts a connection from the underlying listener if one is already pending, returning `None` if there isn't one,
+    /// or if the underlying listener's accept isn't [cancel safe](ListenerImpl::is_accept_cancel_safe).
+    fn accept_pending(&mut self) -> Option<Result<Connection>> {
+        if !self.internal.is_accept_cancel_safe() {
+            return None;
+        }
+        // the accept is polled only once, so connections that aren't pending yet are left for later
+        let waker = noop_waker();
+        let mut context = Context::from_waker(&waker);
+        match self.internal.accept().as_mut().poll(&mut context) {
+            Poll::Ready(result) => Some(result),
+            Poll::Pending => None,
+        }
+    }
+    /// Runs a connection that has just been accepted through the accept filter and authentication, then registers it,
+    /// returning `None` if the filter rejected it.
+    async fn admit(&mut self, mut connection: Connection) -> Result<Option<Connection>> {
+        if let Some(filter) = &self.accept_filter {
+            let meta = AcceptMeta {
+                peer_credentials: connection.peer_credentials().ok(),
+            };
+            if !filter(&meta) {
+                connection.close().await;
+                return Ok(None);
+            }
+        }
+        #[cfg(feature = "auth")]
+        if let Some(secret) = &self.auth_secret {
+            connection.set_read_deadline(Some(Instant::now() + auth::LISTENER_TIMEOUT));
+            if let Err(error) = connection.authenticate_peer(secret).await {
+                connection.close().await;
+                return Err(error);
+            }
+            connection.set_read_deadline(None);
+        }
+        self.register(&mut connection);
+        if let Some(hook) = &self.connect_hook {
+            hook(&connection);
+        }
+        Ok(Some(connection))
+    }
+    /// Sets the registry that connections accepted by this listener are added to, replacing any previous registry.
+    /// Connections that were accepted before this is called aren't added to the new registry.
+    pub fn set_registry(&mut self, registry: ConnectionRegistry) {
+        self.registry = Some(registry);
+    }
+    /// Removes the registry set by [`set_registry`](Self::set_registry), if any.
+    /// Connections already in the registry stay there until they are closed.
+    pub fn clear_registry(&mut self) {
+        self.registry = None;
+    }
+    /// Sets a secret that every connection has to prove it knows before it is accepted, replacing any previous secret.
+    /// Once this is set, accepting a connection also runs the handshake described by [`Connection::authenticate_peer`],
+    /// so clients have to call [`Connection::authenticate`] with the same secret right after connecting.
+    ///
+    /// If a client gives the wrong answer, accepting it fails with [`Error::AuthFailed`];
+    /// if it doesn't answer within 5 seconds, accepting it fails with [`Error::Timeout`].
+    /// Either way, the client's connection is closed and the listener can still be used.
+    ///
+    /// # Blocking
+    /// The handshake runs inside `accept`, so until a client has answered (or the 5 seconds have passed), no other connection is accepted.
+    /// A client that connects and then stays silent holds up every client behind it.
+    /// If processes that aren't trusted can reach the socket, leave this unset and call [`Connection::authenticate_peer`]
+    /// on each accepted connection instead, away from the accept loop.
+    #[cfg(feature = "auth")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "auth")))]
+    pub fn set_auth_secret<B>(&mut self, secret: B)
+    where
+        B: Into<Vec<u8>>,
+    {
+        self.auth_secret = Some(secret.into());
+    }
+    /// Removes the secret set by [`set_auth_secret`](Self::set_auth_secret), if any.
+    #[cfg(feature = "auth")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "auth")))]
+    pub fn clear_auth_secret(&mut self) {
+        self.auth_secret = None;
+    }
+    fn register(&self, connection: &mut Connection) {
+        connection.endpoint = self.resolved_name.clone();
+        if let Some(registry) = &self.registry {
+            let peer_credentials = connection.peer_credentials().ok();
+            connection.registration = Some(registry.register(connection.id(), peer_credentials));
+        }
+    }
+
+    /// Sets a function that decides whether connections are accepted, replacing any previous filter.
+    /// When the filter returns `false` for a connection, [`accept`](Self::accept) closes it immediately rather than returning it.
+    ///
+    /// Together with the [credentials of the peer](AcceptMeta::peer_credentials), this can be used to only allow certain users to connect.
+    pub fn set_accept_filter(&mut self, filter: AcceptFilter) {
+        self.accept_filter = Some(filter);
+    }
+    /// Removes the filter set by [`set_accept_filter`](Self::set_accept_filter), if any.
+    pub fn clear_accept_filter(&mut self) {
+        self.accept_filter = None;
+    }
+    /// Sets a function that is called with every connection this listener accepts, replacing any previous hook. There is no hook by default.
+    /// It is called right before the connection is returned, once it has been through the [accept filter](Self::set_accept_filter), authenticated and added to the [registry](Self::set_registry), so it sees the connection's final [ID](Connection::id).
+    ///
+    /// This is a single place for per-connection setup, such as adding the connection to a map or counting it in a metric.
+    pub fn set_connect_hook(&mut self, hook: ConnectHook) {
+        self.connect_hook = Some(hook);
+    }
+    /// Removes the hook set by [`set_connect_hook`](Self::set_connect_hook), if any.
+    pub fn clear_connect_hook(&mut self) {
+        self.connect_hook = None;
+    }
+
+    /// Pauses accepting connections until [`resume`](Self::resume) is called, without closing the listener.
+    /// Whilst paused, [`accept`](Self::accept) waits before accepting anything, so use a [`PauseHandle`] to resume it from another task.
+    ///
+    /// Clients can still connect whilst the listener is paused, but their connections wait in the socket's backlog until it is resumed,
+    /// and once the backlog is full, further clients are refused by the operating system.
+    /// Pausing doesn't affect connections that have already been accepted. Accepting one that is already underway isn't interrupted,
+    /// but [`accept_many`](Self::accept_many) stops accepting further connections for its batch once paused.
+    pub fn pause(&self) {
+        self.pause.get_or_init(PauseHandle::default).pause();
+    }
+    /// Resumes accepting connections after [`pause`](Self::pause), letting any waiting [`accept`](Self::accept) continue.
+    pub fn resume(&self) {
+        if let Some(pause) = self.pause.get() {
+            pause.resume();
+        }
+    }
+    /// Checks whether accepting connections is [paused](Self::pause).
+    pub fn is_paused(&self) -> bool {
+        self.pause.get().is_some_and(PauseHandle::is_paused)
+    }
+    /// Gets a handle that pauses and resumes accepting connections on this listener, which can be sent to other tasks.
+    pub fn pause_handle(&self) -> PauseHandle {
+        self.pause.get_or_init(PauseHandle::default).clone()
+    }
+
+    /// Sets what [`accept`](Self::accept) does when it fails with an error that is likely to go away by itself.
+    /// Use [`AcceptRetryPolicy::Never`] to have every error returned.
+    pub fn set_accept_retry_policy(&mut self, policy: AcceptRetryPolicy) {
+        self.accept_retry = policy;
     }
 
     /// Closes this listener, returning any error that occurred whilst closing it.
@@ -62,142 +482,2086 @@ impl Listener {
         self.closed = true; // we set it to closed either way
         self.internal.close().await
     }
+    /// Closes this listener like [`close`](Self::close), but first accepts the connections that clients have already made and are waiting to be accepted,
+    /// returning them so that they can be served before shutting down. As with [`close`](Self::close), no connections are accepted afterwards.
+    ///
+    /// Pending connections go through the [accept filter](Self::set_accept_filter) and authentication like any other,
+    /// and those that are rejected, fail to authenticate or fail to be accepted at all are left out rather than failing the whole close.
+    /// Since accepting takes `&mut self`, no other accept can be in flight whilst this runs;
+    /// an accept that was cancelled before reaching this point has already left its connection pending, so it is picked up here.
+    ///
+    /// Like [`accept_many`](Self::accept_many), this polls the underlying [`ListenerImpl::accept`] without running it to completion,
+    /// so pending connections are only accepted from listeners whose accept is [cancel safe](ListenerImpl::is_accept_cancel_safe).
+    /// Other listeners, such as named pipes on Windows, are closed like [`close`](Self::close) would, and their pending connections are dropped.
+    pub async fn close_graceful(&mut self) -> Result<Vec<Connection>> {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let mut connections = Vec::new();
+        // lets the runtime's I/O driver see the connections that arrived since it last checked, or they wouldn't be ready yet
+        tokio::task::yield_now().await;
+        let mut failures = 0;
+        while let Some(result) = self.accept_pending() {
+            match result {
+                Ok(connection) => {
+                    failures = 0;
+                    // a connection that fails to authenticate has nothing left to serve, so it is simply skipped
+                    if let Ok(Some(connection)) = self.admit(connection).await {
+                        connections.push(connection);
+                    }
+                }
+                Err(_) if failures < MAX_GRACEFUL_ACCEPT_FAILURES => failures += 1,
+                Err(_) => break,
+            }
+        }
+        self.closed = true;
+        self.internal.close().await?;
+        Ok(connections)
+    }
 
     /// Check if this listener is closed.
     pub fn is_closed(&self) -> bool {
         self.closed
     }
+
+    /// Gets the name that this listener's socket actually resolved to.
+    /// Returns `None` if this listener wasn't created from a name, e.g. when it was created using [`new`](Self::new).
+    pub fn resolved_name(&self) -> Option<&ResolvedName> {
+        self.resolved_name.as_ref()
+    }
+
+    /// Check if this listener was created as a non-global socket because it wasn't permitted to create a global one.
+    /// See [`listen_as_socket_with_fallback`](Self::listen_as_socket_with_fallback).
+    pub fn is_user_fallback(&self) -> bool {
+        self.user_fallback
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        if !self.closed {
+            report_unclosed_drop(format_args!(
+                "an async listener was dropped without being closed - call `close().await` first"
+            ));
+        }
+    }
 }
 
 /// Connections represent a two-way bidirectional stream that you can send and receive messages through.
+/// Every message is flushed as soon as it has been written, so nothing that has been sent is held back in a buffer on this side.
+///
+/// Asynchronous connections can't close themselves when dropped, so they have to be closed manually using [`close`](Self::close),
+/// otherwise the other side isn't told that the connection is closing.
+/// In debug builds, dropping a connection that is still open prints a warning, which can be changed using [`set_unclosed_drop_hook`].
 pub struct Connection {
-    internal: Box<dyn ConnectionImpl>,
+    internal: BufferedStream<Box<dyn ConnectionImpl>>,
+    encode_buffer: Vec<u8>,
+    codec: ActiveCodec,
+    read_deadline: Option<Instant>,
+    write_deadline: Option<Instant>,
+    observer: Option<Observer>,
+    message_hook: Option<MessageHook>,
     closed: bool,
+    close_reason: Option<CloseReason>,
+    send_close_frame: bool,
+    send_timestamps: bool,
+    idle_timeout: Option<Duration>,
+    last_activity: Cell<Option<Instant>>,
+    id: OnceCell<u64>,
+    registration: Option<Registration>,
+    endpoint: Option<ResolvedName>,
+    rate_limiter: Option<RateLimiter>,
+    peer_credentials: Option<PeerCredentials>,
+    failure: Option<Failure>,
+    peer_max_message_size: Option<u64>,
 }
 
 impl Connection {
     /// Creates a new connection based on a specified [`ConnectionImpl`].
     /// Generally, you won't call this directly unless you're extending gipc.
     pub const fn new(internal: Box<dyn ConnectionImpl>) -> Self {
+        Self::from_stream(BufferedStream::new(internal))
+    }
+    const fn from_stream(internal: BufferedStream<Box<dyn ConnectionImpl>>) -> Self {
         Self {
             internal,
+            encode_buffer: Vec::new(),
+            codec: ActiveCodec::Default,
+            read_deadline: None,
+            write_deadline: None,
+            observer: None,
+            message_hook: None,
             closed: false,
+            close_reason: None,
+            send_close_frame: true,
+            send_timestamps: false,
+            idle_timeout: None,
+            last_activity: Cell::new(None),
+            id: OnceCell::new(),
+            registration: None,
+            endpoint: None,
+            rate_limiter: None,
+            peer_credentials: None,
+            failure: None,
+            peer_max_message_size: None,
         }
     }
+    /// Creates a connection that continues where a synchronous connection left off, as done by `sync::Connection::into_async`.
+    /// `buffer` holds the bytes the synchronous connection had read but not yet received as messages.
+    #[cfg(feature = "sync")]
+    pub(crate) fn from_sync(
+        internal: Box<dyn ConnectionImpl>,
+        buffer: Vec<u8>,
+        codec: ActiveCodec,
+        id: u64,
+        registration: Option<Registration>,
+        endpoint: Option<ResolvedName>,
+        peer_credentials: Option<PeerCredentials>,
+    ) -> Self {
+        let mut connection = Self::from_stream(BufferedStream::with_buffer(internal, buffer));
+        connection.codec = codec;
+        connection.id = OnceCell::from(id);
+        connection.registration = registration;
+        connection.endpoint = endpoint;
+        connection.peer_credentials = peer_credentials;
+        connection
+    }
     /// Connects to a socket using a name based on `name`.
     /// The actual name used is generated internally.
+    /// Names that would be too long for the platform are replaced by a hash of themselves, failing with [`Error::NameTooLong`] if even that is too long.
+    /// Fails with [`Error::Connect`] if the socket can't be connected to.
     pub async fn connect_to_socket<S>(name: S, global: bool) -> Result<Self>
     where
         S: AsRef<str>,
     {
-        let bound = name_onto!(await LocalSocketStream::connect; name, global)?;
-        Ok(Self::new(Box::new(bound)))
+        Self::connect_to_socket_detailed(name, global)
+            .await
+            .map(|(connection, _)| connection)
+    }
+    /// Connects to a socket like [`connect_to_socket`](Self::connect_to_socket),
+    /// but if nothing is listening on the socket yet, keeps trying until something is or until `timeout` has passed.
+    /// This lets a client be started before the listener it connects to, without having to guess how long the listener takes to start.
+    ///
+    /// Errors other than the socket not existing or refusing the connection are returned immediately.
+    /// If the socket still can't be connected to once `timeout` has passed, the last [`Error::Connect`] is returned.
+    pub async fn connect_to_socket_wait<S>(name: S, global: bool, timeout: Duration) -> Result<Self>
+    where
+        S: AsRef<str>,
+    {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match Self::connect_to_socket(name.as_ref(), global).await {
+                Err(error) if is_not_listening(&error) && Instant::now() < deadline => {
+                    let delay = CONNECT_POLL_INTERVAL.min(deadline - Instant::now());
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+    /// Connects to a socket like [`connect_to_socket`](Self::connect_to_socket),
+    /// but if nothing is listening on the socket yet, keeps trying with increasing delays as described by `policy`.
+    /// This is useful for clients that start whilst the service they connect to may still be starting.
+    ///
+    /// Errors other than the socket not existing or refusing the connection are returned immediately.
+    /// Once the policy's `max_attempts` have been made this fails with the last [`Error::Connect`], and once its `timeout` has passed this fails with [`Error::Timeout`].
+    pub async fn connect_to_socket_backoff<S>(
+        name: S,
+        global: bool,
+        policy: &BackoffPolicy,
+    ) -> Result<Self>
+    where
+        S: AsRef<str>,
+    {
+        let started = Instant::now();
+        let mut attempt = 0;
+        loop {
+            match Self::connect_to_socket(name.as_ref(), global).await {
+                Err(error) if is_not_listening(&error) => {
+                    attempt += 1;
+                    let delay = policy.retry_delay(attempt, started, error)?;
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+    /// Connects to a socket like [`connect_to_socket`](Self::connect_to_socket),
+    /// but also returns the name that the socket actually resolved to.
+    pub async fn connect_to_socket_detailed<S>(
+        name: S,
+        global: bool,
+    ) -> Result<(Self, ResolvedName)>
+    where
+        S: AsRef<str>,
+    {
+        let (resolved, bound) = name_onto!(await LocalSocketStream::connect; name, global);
+        let bound = bound.map_err(|source| Error::Connect {
+            name: resolved.to_string(),
+            source,
+        })?;
+        let mut connection = Self::new(Box::new(bound));
+        connection.endpoint = Some(resolved.clone());
+        Ok((connection, resolved))
+    }
+
+    /// Connects to a Unix domain socket at exactly `path`, instead of at a path generated from a name like [`connect_to_socket`](Self::connect_to_socket) does.
+    /// This is meant for connecting to sockets whose location is decided elsewhere, including ones created using [`Listener::listen_on_path`].
+    /// Fails with [`Error::Connect`] if the socket can't be connected to.
+    #[cfg(unix)]
+    #[cfg_attr(docsrs, doc(cfg(unix)))]
+    pub async fn connect_to_path<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let stream = LocalSocketStream::connect(path.as_ref())
+            .await
+            .map_err(|source| Error::Connect {
+                name: ResolvedName::Path(path.as_ref().to_owned()).to_string(),
+                source,
+            })?;
+        let mut connection = Self::new(Box::new(stream));
+        connection.endpoint = Some(ResolvedName::Path(path.as_ref().to_owned()));
+        Ok(connection)
+    }
+
+    /// Connects to a Windows named pipe with the name `name`, without the `\\.\pipe\` prefix.
+    /// This is meant for connecting to listeners created using [`Listener::listen_on_pipe`].
+    /// Fails with [`Error::Connect`] if the pipe can't be connected to.
+    #[cfg(windows)]
+    #[cfg_attr(docsrs, doc(cfg(windows)))]
+    pub async fn connect_to_pipe<S>(name: S) -> Result<Self>
+    where
+        S: AsRef<std::ffi::OsStr>,
+    {
+        let stream = DuplexBytePipeStream::connect(name.as_ref()).map_err(|source| {
+            Error::Connect {
+                name: pipe_path(name.as_ref()).to_string(),
+                source,
+            }
+        })?;
+        let mut connection = Self::new(Box::new(stream));
+        connection.endpoint = Some(pipe_path(name.as_ref()));
+        Ok(connection)
     }
 
     async fn _send<T>(&mut self, message: Message<T>) -> Result<()>
     where
         T: Serialize,
     {
-        message.write_to_async(&mut self.internal).await
+        self._send_with(&self.codec.clone(), message).await
+    }
+    async fn _send_with<C, E>(&mut self, codec: &C, envelope: E) -> Result<()>
+    where
+        C: Codec + ?Sized,
+        E: Serialize,
+    {
+        self.check_idle().await?;
+        self.throttle().await?;
+        self._check_writable()?;
+        let written = self.internal.bytes_written();
+        with_deadline(
+            self.write_deadline,
+            write_envelope_async(
+                &mut self.internal,
+                codec,
+                &envelope,
+                &mut self.encode_buffer,
+                self.peer_max_message_size,
+            ),
+        )
+        .await
+        .map_err(|error| self._write_failed(written, error))?;
+        self.observe(Direction::Sent, &self.encode_buffer);
+        if let Some(limiter) = &mut self.rate_limiter {
+            limiter.record(self.encode_buffer.len());
+        }
+        Ok(())
+    }
+    /// Passes on `error` from a failed write,
+    /// noting that the other side has been left partway through a frame if anything was written since `written` bytes had been.
+    fn _write_failed(&mut self, written: u64, error: Error) -> Error {
+        if self.internal.bytes_written() != written {
+            self._fail(Failure::PartialWrite);
+        } else if let Error::Io(_) | Error::Timeout = error {
+            self._fail(Failure::Io);
+        }
+        error
+    }
+    /// Notes that an operation on this connection failed with `failure` without closing it, for [`reset`](Self::reset) to check.
+    /// A partial write can't be recovered from, so it is never replaced by a lesser failure.
+    fn _fail(&mut self, failure: Failure) {
+        if self.failure != Some(Failure::PartialWrite) {
+            self.failure = Some(failure);
+        }
+    }
+    /// Fails with [`Error::MidFrame`] if sending has failed partway through a frame before,
+    /// since the other side would take whatever is sent next for the rest of that frame.
+    fn _check_writable(&self) -> Result<()> {
+        match self.failure {
+            Some(Failure::PartialWrite) => Err(Error::MidFrame),
+            _ => Ok(()),
+        }
+    }
+    async fn _receive_with<C, E>(&mut self, codec: &C) -> Result<E>
+    where
+        C: Codec + ?Sized,
+        E: DeserializeOwned,
+    {
+        let payload = self._receive_payload().await?;
+        codec.deserialise(&payload)
     }
-    async fn _receive<T>(&mut self) -> Result<Message<T>>
+    async fn _receive_message<C, T>(&mut self, codec: &C) -> Result<Message<T>>
     where
+        C: Codec + ?Sized,
         T: DeserializeOwned,
     {
-        Message::<T>::read_from_async(&mut self.internal).await
+        let payload = self._receive_payload().await?;
+        let message = codec.deserialise::<Message<T>>(&payload)?;
+        if message.carries_data() {
+            self.report::<T>(Direction::Received, payload.len());
+        }
+        Ok(message)
+    }
+    async fn _receive_payload(&mut self) -> Result<Payload> {
+        let payload = self._read_payload().await?;
+        self.observe(Direction::Received, &payload);
+        Ok(payload)
+    }
+    /// Reads the next payload, answering pings and skipping pongs along the way.
+    async fn _read_payload(&mut self) -> Result<Payload> {
+        self._read_payload_with_progress(|_, _| {}).await
+    }
+    /// Reads the next payload like [`_read_payload`](Self::_read_payload), calling `progress` as more of it is read and once it has been read in its entirety.
+    async fn _read_payload_with_progress(
+        &mut self,
+        mut progress: impl FnMut(u64, u64),
+    ) -> Result<Payload> {
+        let mut last = None;
+        loop {
+            let payload = self
+                ._read_any_payload_with_progress(|read, total| {
+                    // pings and pongs are never longer than this, so they don't show up as progress on the message that follows them
+                    if total > MAX_PING_LEN as u64 {
+                        last = Some((read, total));
+                        progress(read, total);
+                    }
+                })
+                .await?;
+            match ping_or_pong(&self.codec, &payload) {
+                Some(kind) => self._answer_ping(kind, &payload).await,
+                None => {
+                    let length = payload.len() as u64;
+                    if last != Some((length, length)) {
+                        progress(length, length);
+                    }
+                    return Ok(payload);
+                }
+            }
+        }
+    }
+    /// Answers a ping of kind `kind`, or does nothing more than observe it if it is a pong.
+    async fn _answer_ping(&mut self, kind: MessageKind, payload: &[u8]) {
+        self.observe(Direction::Received, payload);
+        if kind == MessageKind::Ping {
+            // like acknowledgements, a failure to answer is left for the next operation to run into
+            let _ = self._send::<()>(Message::Pong).await;
+        }
+    }
+    async fn _read_any_payload(&mut self) -> Result<Payload> {
+        self._read_any_payload_with_progress(|_, _| {}).await
+    }
+    async fn _read_any_payload_with_progress(
+        &mut self,
+        progress: impl FnMut(u64, u64),
+    ) -> Result<Payload> {
+        self.check_idle().await?;
+        let deadline = [self.read_deadline, self.idle_deadline()]
+            .into_iter()
+            .flatten()
+            .min();
+        let frame = self.internal.read_frame_async(progress);
+        let payload = match with_deadline(deadline, frame).await {
+            Ok(frame) => frame_payload(frame)?,
+            Err(error) => {
+                match CloseReason::for_error(&error) {
+                    Some(reason) => self._close(reason).await,
+                    None => self._fail(Failure::Io),
+                }
+                if let Error::Timeout = error {
+                    self.check_idle().await?;
+                }
+                return Err(error);
+            }
+        };
+        Ok(payload)
+    }
+    async fn _unwrap_message<T>(&mut self, message: Message<T>) -> Result<T> {
+        match message {
+            Message::ClosingConnection => {
+                self._close(CloseReason::Peer).await;
+                Err(Error::Closed(true))
+            }
+            Message::ClosingWithReason(code, message) => {
+                self._close(CloseReason::Peer).await;
+                Err(Error::ClosedWithReason { code, message })
+            }
+            Message::Data(data) | Message::Priority(data) | Message::Stamped(_, data) => Ok(data),
+            Message::Signal(code) => Err(Error::Signal(code)),
+            Message::Acked(data) => {
+                // the message has been received either way, so a failure to acknowledge it is left for the next operation to run into
+                let _ = self._send::<()>(Message::Ack).await;
+                Ok(data)
+            }
+            Message::Ack => Err(Error::UnexpectedAck),
+            Message::Ping | Message::Pong => {
+                unreachable!("pings and pongs are handled whilst reading payloads")
+            }
+        }
+    }
+    /// Tells the message hook, if any, about a message of `length` bytes of type `T`.
+    fn report<T: ?Sized>(&self, direction: Direction, length: usize) {
+        if let Some(hook) = &self.message_hook {
+            hook(direction, std::any::type_name::<T>(), length);
+        }
+    }
+    fn observe(&self, direction: Direction, payload: &[u8]) {
+        self.last_activity.set(Some(Instant::now()));
+        if let Some(observer) = &self.observer {
+            observer(direction, payload);
+        }
+        if let Some(registration) = &self.registration {
+            registration.record(direction, payload.len());
+        }
     }
 
     /// Send a message through this connection.
-    /// Will immediately fail with [`Error::Closed(false)`](Error::Closed) if this connection is already closed.
+    /// Will immediately fail with [`Error::Closed(false)`](Error::Closed) if this connection is already closed,
+    /// and with [`Error::MessageTooLarge`] if the message is larger than the other side [accepts receiving](Self::peer_max_message_size).
     pub async fn send<T>(&mut self, message_data: T) -> Result<()>
     where
         T: Serialize,
     {
-        if self.closed {
-            return Err(Error::Closed(false));
-        }
-        let message = Message::Data(message_data);
-        self._send(message).await
+        self.send_with(&self.codec.clone(), message_data).await
+    }
+    /// Send a message given as a [`Serialize`](erased_serde::Serialize) trait object, for when the type of the message isn't known where it is sent.
+    /// The message is serialised using this connection's codec, so it can be received as its concrete type using [`receive`](Self::receive).
+    /// The trait object has to be [`Sync`] so that the returned future can be sent between threads.
+    /// Fails in the same way as [`send`](Self::send).
+    #[cfg(feature = "erased-serde")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "erased-serde")))]
+    pub async fn send_erased(
+        &mut self,
+        value: &(dyn erased_serde::Serialize + Sync),
+    ) -> Result<()> {
+        self.send(value).await
     }
     /// Receive a message from this connection.
     /// Will immediately fail with [`Error::Closed(false)`](Error::Closed) if this connection is already closed,
     /// or fail with [`Error::Closed(true)`](Error::Closed) if this connection was closed whilst trying to read the message.
+    /// If a signal sent using [`send_signal`](Self::send_signal) is received instead of a message, fails with [`Error::Signal`].
+    /// If the message can't be deserialised, this fails with [`Error::Deserialise`], but the message is still consumed so that the next one can be received.
     pub async fn receive<T>(&mut self) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.receive_with(&self.codec.clone()).await
+    }
+    /// Receive a message from this connection like [`receive`](Self::receive), but return [`ReceiveOutcome::Closed`] instead of failing once the other side has closed the connection.
+    /// This is useful for loops that consume messages until the other side is done, where the end of the stream isn't an error.
+    /// Once the connection is closed, further calls fail with [`Error::Closed(false)`](Error::Closed) like [`receive`](Self::receive) does.
+    pub async fn receive_outcome<T>(&mut self) -> Result<ReceiveOutcome<T>>
+    where
+        T: DeserializeOwned,
+    {
+        match self.receive().await {
+            Ok(message_data) => Ok(ReceiveOutcome::Data(message_data)),
+            Err(Error::Closed(true) | Error::ClosedWithReason { .. }) => Ok(ReceiveOutcome::Closed),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Receive a message from this connection like [`receive`](Self::receive), calling `progress` with how many bytes of it have been read and how many bytes it has in total as it arrives.
+    /// This is meant for large messages, so that e.g. a progress bar can be shown whilst they are read - the message is only deserialised once it has been read in its entirety.
+    ///
+    /// The amount read never decreases, and the last call is always with both amounts equal, once the whole message has been read.
+    /// Messages sent using [`send_iter`](Self::send_iter) don't declare their length up front, so `progress` is only called once for them, after they have been read.
+    /// Fails in the same way as [`receive`](Self::receive).
+    pub async fn receive_with_progress<T>(&mut self, progress: impl FnMut(u64, u64)) -> Result<T>
     where
         T: DeserializeOwned,
     {
         if self.closed {
             return Err(Error::Closed(false));
         }
-        let message = self._receive().await?;
-        match message {
-            Message::ClosingConnection => {
-                self._close().await;
-                Err(Error::Closed(true))
-            }
-            Message::Data(data) => Ok(data),
+        let payload = self._read_payload_with_progress(progress).await?;
+        self.observe(Direction::Received, &payload);
+        let message = self.codec.clone().deserialise::<Message<T>>(&payload)?;
+        if message.carries_data() {
+            self.report::<T>(Direction::Received, payload.len());
         }
+        self._unwrap_message(message).await
+    }
+    /// Changes the codec that this connection uses for all messages from now on, including signals and the message sent when closing it.
+    /// [`send_with`](Self::send_with) and [`receive_with`](Self::receive_with) still use the codec they're given.
+    ///
+    /// Both sides have to switch to the same codec at the same point - usually right after a message that tells the other side which codec to use.
+    /// Messages that have been sent with one codec can't be received with another.
+    pub fn set_codec(&mut self, codec: Box<dyn DynCodec>) {
+        self.codec = ActiveCodec::Dyn(codec.into());
+    }
+    /// Changes the codec that this connection uses back to the default, [`CborCodec`](crate::codec::CborCodec).
+    pub fn reset_codec(&mut self) {
+        self.codec = ActiveCodec::Default;
     }
 
-    /// Shorthand for calling [`send`] and [`receive`] after one another.
-    pub async fn send_and_receive<A, B>(&mut self, data: &A) -> Result<B>
+    /// Send a message through this connection, serialising it using `codec` instead of the codec this connection uses.
+    /// Fails in the same way as [`send`](Self::send).
+    pub async fn send_with<C, T>(&mut self, codec: &C, message_data: T) -> Result<()>
     where
-        A: Serialize,
-        B: DeserializeOwned,
+        C: Codec + ?Sized,
+        T: Serialize,
     {
-        self.send(data).await?;
-        self.receive().await
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        if self.send_timestamps {
+            let message = Message::Stamped(SendInstant::now().as_nanos(), message_data);
+            self._send_with(codec, message).await?;
+        } else {
+            self._send_with(codec, Message::Data(message_data)).await?;
+        }
+        self.report::<T>(Direction::Sent, self.encode_buffer.len());
+        Ok(())
     }
-
-    async fn _close(&mut self) {
-        self.internal.close().await;
-        self.closed = true;
+    /// Receive a message from this connection, deserialising it using `codec` instead of the codec this connection uses.
+    /// Fails in the same way as [`receive`](Self::receive).
+    pub async fn receive_with<C, T>(&mut self, codec: &C) -> Result<T>
+    where
+        C: Codec + ?Sized,
+        T: DeserializeOwned,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let message = self._receive_message(codec).await?;
+        self._unwrap_message(message).await
     }
-
-    /// Closes this connection if it isn't already closed.
-    /// This operation can never fail.
-    pub async fn close(&mut self) {
+    /// Receive a message from this connection along with when it was sent, if the other side has [timestamps enabled](Self::set_send_timestamps).
+    /// Messages sent without a timestamp are returned with `None`.
+    /// Fails in the same way as [`receive`](Self::receive).
+    ///
+    /// Comparing the timestamp to [`SendInstant::now`] gives how long the message took to arrive, including how long it waited to be received.
+    pub async fn receive_with_timestamp<T>(&mut self) -> Result<(T, Option<SendInstant>)>
+    where
+        T: DeserializeOwned,
+    {
         if self.closed {
-            return;
+            return Err(Error::Closed(false));
+        }
+        let message = self._receive_message(&self.codec.clone()).await?;
+        match message {
+            Message::Stamped(nanos, data) => Ok((data, Some(SendInstant::from_nanos(nanos)))),
+            message => Ok((self._unwrap_message(message).await?, None)),
         }
-        // ignore the results of this - it doesn't matter since we're closing it either way
-        let _ = self._send::<()>(Message::ClosingConnection);
-        self._close().await;
     }
-
-    /// Check if this connection is closed.
-    pub fn is_closed(&self) -> bool {
-        self.closed
+    /// Receive a message from this connection like [`receive`](Self::receive), but return `None` instead of failing with [`Error::Deserialise`] if it can't be deserialised.
+    /// Messages are framed independently of their contents, so a message that can't be deserialised is still received in its entirety and discarded,
+    /// and the next message can be received as usual.
+    pub async fn receive_or_skip<T>(&mut self) -> Result<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        match self.receive().await {
+            Err(Error::Deserialise(_)) => Ok(None),
+            result => result.map(Some),
+        }
     }
-}
-
-/// Listener implementation.
-#[async_trait]
-pub trait ListenerImpl: Send + Unpin {
-    /// Accept a new connection.
-    /// This function should return when a connection can be established.
-    async fn accept(&mut self) -> Result<Connection>;
-    /// Closes this listener implementation.
-    /// After this function is called, no more functions will be called from the implementation.
-    async fn close(&mut self) -> Result<()>;
-}
-
-#[async_trait]
-impl ListenerImpl for LocalSocketListener {
-    async fn accept(&mut self) -> Result<Connection> {
-        Ok(Connection::from(LocalSocketListener::accept(self).await?))
+    /// Gets the kind of the next message on this connection without receiving it, waiting for it to arrive if necessary.
+    /// The message is kept so that it is received as usual afterwards, which lets close frames and signals be told apart from data without deserialising the data.
+    /// Fails with [`Error::Deserialise`] if the message isn't a gipc message at all, in which case it is still kept, and otherwise in the same way as [`receive`](Self::receive).
+    ///
+    /// The message is only passed to the [observer](Self::set_observer) once it is received.
+    pub async fn peek_variant(&mut self) -> Result<MessageKind> {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let payload = self._read_payload().await?;
+        let kind = self.codec.deserialise::<Message<IgnoredAny>>(&payload);
+        self.internal.restore_frame(payload.into_frame());
+        kind.map(|message| message.kind())
     }
-    async fn close(&mut self) -> Result<()> {
-        Ok(())
+    /// Receive a message from this connection like [`receive`](Self::receive), but if it can't be deserialised as a `T`,
+    /// keep it so that it can be received again, for example by calling this again with a different type.
+    /// This is useful when a message can be one of several types that don't share an enum.
+    /// If all types the message can be are known up front, receiving an enum that derives [`Deserialize`](serde::Deserialize) is simpler.
+    ///
+    /// The message is passed to the [observer](Self::set_observer) every time it is received.
+    pub async fn try_receive_as<T>(&mut self) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let payload = self._receive_payload().await?;
+        match self.codec.deserialise::<Message<T>>(&payload) {
+            Ok(message) => {
+                if message.carries_data() {
+                    self.report::<T>(Direction::Received, payload.len());
+                }
+                self._unwrap_message(message).await
+            }
+            Err(error) => {
+                self.internal.restore_frame(payload.into_frame());
+                Err(error)
+            }
+        }
     }
-}
 
-/// Internal implementation for a [`Connection`].
-#[async_trait]
-pub trait ConnectionImpl: AsyncRead + AsyncWrite + Send + Unpin {
+    /// Send every item in `items` through this connection as a single message containing a sequence of them, without collecting them first.
+    /// The other side receives this like any other message, e.g. as a `Vec` of the items using [`receive`](Self::receive).
+    ///
+    /// The message is sent in chunks whilst it's being serialised, so `items` never has to be held in memory all at once.
+    /// The downside is that the size of the message isn't known until it has been sent in its entirety,
+    /// and the other side still has to buffer the entire message before deserialising it.
+    /// A receiver with a [maximum message size](Self::set_max_message_size) can therefore only reject the message once enough of its chunks have arrived to exceed it.
+    /// If serialising an item fails, the message has only been sent in part and the connection can't be used anymore.
+    ///
+    /// Messages sent like this are always encoded as CBOR, so this fails with [`Error::Serialise`] if [a different codec](Self::set_codec) is used.
+    /// They also aren't passed to the [observer](Self::set_observer) when they're sent.
+    pub async fn send_iter<I>(&mut self, items: I) -> Result<()>
+    where
+        I: IntoIterator,
+        I::Item: Serialize,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        if !self.codec.is_default() {
+            return Err(Error::Serialise(
+                "streamed messages can only be sent using the default codec".into(),
+            ));
+        }
+        self._check_writable()?;
+        let written = self.internal.bytes_written();
+        with_deadline(
+            self.write_deadline,
+            write_stream_async(&mut self.internal, items, &mut self.encode_buffer),
+        )
+        .await
+        .map_err(|error| self._write_failed(written, error))
+    }
+    /// Send `envelope` through this connection as-is, instead of wrapping data in a [`Message`].
+    /// This allows protocols to define their own control frames - see [`Envelope`] for what envelopes need to look like.
+    /// Fails in the same way as [`send`](Self::send).
+    pub async fn send_envelope<E>(&mut self, envelope: E) -> Result<()>
+    where
+        E: Envelope,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        self._send_with(&self.codec.clone(), envelope).await
+    }
+    /// Receive an envelope sent using [`send_envelope`](Self::send_envelope) from this connection.
+    /// If the envelope [indicates that the connection is closing](Envelope::is_closing), this connection is closed and this fails with [`Error::Closed(true)`](Error::Closed);
+    /// otherwise, it fails in the same way as [`receive`](Self::receive).
+    pub async fn receive_envelope<E>(&mut self) -> Result<E>
+    where
+        E: Envelope,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let envelope: E = self._receive_with(&self.codec.clone()).await?;
+        if envelope.is_closing() {
+            self._close(CloseReason::Peer).await;
+            return Err(Error::Closed(true));
+        }
+        Ok(envelope)
+    }
+
+    /// Send a message through this connection on the priority lane.
+    /// The other side receives it like any other message, but [`receive_any`](Self::receive_any) returns it ahead of normal messages that have already arrived.
+    ///
+    /// Messages aren't split up whilst they're being sent, so a priority message can't overtake a normal message that is still being sent,
+    /// only normal messages that are waiting to be received.
+    /// Will immediately fail with [`Error::Closed(false)`](Error::Closed) if this connection is already closed.
+    pub async fn send_priority<T>(&mut self, message_data: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        self._send(Message::Priority(message_data)).await?;
+        self.report::<T>(Direction::Sent, self.encode_buffer.len());
+        Ok(())
+    }
+    /// Receive a message from this connection along with the lane it was sent on.
+    /// If a message sent using [`send_priority`](Self::send_priority) has already arrived in its entirety, it is received first,
+    /// even if normal messages arrived before it; otherwise, the next message is received in order.
+    /// Fails in the same way as [`receive`](Self::receive).
+    pub async fn receive_any<T>(&mut self) -> Result<(Lane, T)>
+    where
+        T: DeserializeOwned,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        self.internal.fill_available_async()?;
+        let priority = self
+            .internal
+            .take_frame_matching(|frame| is_priority_frame(&self.codec, frame));
+        let payload = match priority {
+            Some(frame) => {
+                let payload = frame_payload(frame)?;
+                self.observe(Direction::Received, &payload);
+                payload
+            }
+            None => self._receive_payload().await?,
+        };
+        let message = self.codec.deserialise::<Message<T>>(&payload)?;
+        if message.carries_data() {
+            self.report::<T>(Direction::Received, payload.len());
+        }
+        match message {
+            Message::Priority(data) => Ok((Lane::Priority, data)),
+            message => Ok((Lane::Normal, self._unwrap_message(message).await?)),
+        }
+    }
+    /// Gets how many bytes sending `message_data` using [`send`](Self::send) would write to this connection, including the length prefix, without sending anything.
+    /// This serialises the message using this connection's codec, so it costs about as much as serialising it for sending.
+    /// Fails with [`Error::MessageTooLarge`] if the message is too large to be sent at all.
+    pub fn serialised_len<T>(&self, message_data: &T) -> Result<usize>
+    where
+        T: Serialize + ?Sized,
+    {
+        framed_len(&self.codec, &Message::Data(message_data))
+    }
+    /// Send a signal with `code` through this connection.
+    /// Signals are lightweight notifications that don't carry any data,
+    /// and are surfaced on the other side as [`Error::Signal`] by [`receive`](Self::receive).
+    /// Will immediately fail with [`Error::Closed(false)`](Error::Closed) if this connection is already closed.
+    pub async fn send_signal(&mut self, code: u8) -> Result<()> {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        self._send::<()>(Message::Signal(code)).await
+    }
+
+    /// Send raw bytes through this connection.
+    /// The bytes are encoded as a byte string, which is far more compact than sending a `Vec<u8>` through [`send`](Self::send).
+    /// Will immediately fail with [`Error::Closed(false)`](Error::Closed) if this connection is already closed.
+    pub async fn send_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.send(serde_bytes::Bytes::new(bytes)).await
+    }
+    /// Receive raw bytes sent using [`send_bytes`](Self::send_bytes) from this connection.
+    /// Fails in the same way as [`receive`](Self::receive).
+    pub async fn receive_bytes(&mut self) -> Result<Vec<u8>> {
+        self.receive().await.map(serde_bytes::ByteBuf::into_vec)
+    }
+    /// Send the bytes in `buf` through this connection, like [`send_bytes`](Self::send_bytes) does.
+    /// Will immediately fail with [`Error::Closed(false)`](Error::Closed) if this connection is already closed.
+    #[cfg(feature = "bytes")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "bytes")))]
+    pub async fn send_bytes_buf(&mut self, buf: &bytes::Bytes) -> Result<()> {
+        self.send_bytes(buf).await
+    }
+    /// Receive raw bytes sent using [`send_bytes`](Self::send_bytes) or [`send_bytes_buf`](Self::send_bytes_buf) from this connection.
+    /// With the default codec, the returned [`Bytes`](bytes::Bytes) shares its storage with the payload of the frame the bytes were received in, rather than the bytes being copied out of it.
+    /// Other codecs deserialise the bytes like any other message, which copies them.
+    /// Fails in the same way as [`receive`](Self::receive).
+    #[cfg(feature = "bytes")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "bytes")))]
+    pub async fn receive_bytes_buf(&mut self) -> Result<bytes::Bytes> {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let payload = self._receive_payload().await?;
+        if self.codec.is_default() {
+            if let Some(range) = crate::message::data_bytes_range(&payload) {
+                self.report::<serde_bytes::ByteBuf>(Direction::Received, payload.len());
+                return Ok(payload.into_bytes(range));
+            }
+        }
+        let message = self.codec.deserialise::<Message<serde_bytes::ByteBuf>>(&payload)?;
+        if message.carries_data() {
+            self.report::<serde_bytes::ByteBuf>(Direction::Received, payload.len());
+        }
+        self._unwrap_message(message)
+            .await
+            .map(|bytes| bytes::Bytes::from(bytes.into_vec()))
+    }
+    /// Send bytes that `write` produces, like [`send_bytes`](Self::send_bytes) does, for formats that don't fit the [`Codec`] trait, like hand-written binary layouts.
+    /// `write` is given an empty buffer to append its encoding of the message to.
+    ///
+    /// This is a helper around [`send_bytes`](Self::send_bytes), not a way of writing frames directly:
+    /// the bytes are still wrapped in a byte-string message in the usual envelope, so that signals, acknowledgements and closing the connection keep working,
+    /// and the other side has to receive them using [`receive_bytes_with`](Self::receive_bytes_with) or [`receive_bytes`](Self::receive_bytes).
+    /// Fails with whatever `write` fails with, in which case nothing is sent, and otherwise in the same way as [`send_bytes`](Self::send_bytes).
+    pub async fn send_bytes_with<F>(&mut self, write: F) -> Result<()>
+    where
+        F: FnOnce(&mut Vec<u8>) -> Result<()>,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let mut payload = Vec::new();
+        write(&mut payload)?;
+        self.send_bytes(&payload).await
+    }
+    /// Receive bytes sent using [`send_bytes_with`](Self::send_bytes_with) or [`send_bytes`](Self::send_bytes), decoding them using `read`.
+    /// `read` is given the bytes that were sent, and what it returns is returned from this.
+    /// Fails in the same way as [`receive_bytes`](Self::receive_bytes), or with whatever `read` fails with, in which case the message is still consumed.
+    pub async fn receive_bytes_with<F, T>(&mut self, read: F) -> Result<T>
+    where
+        F: FnOnce(&[u8]) -> Result<T>,
+    {
+        read(&self.receive_bytes().await?)
+    }
+    /// Send a dynamically-typed CBOR value through this connection.
+    /// This is useful when the type of a message isn't known at compile time, e.g. when forwarding messages between other processes.
+    /// Will immediately fail with [`Error::Closed(false)`](Error::Closed) if this connection is already closed.
+    pub async fn send_value(&mut self, value: &ciborium::value::Value) -> Result<()> {
+        self.send(value).await
+    }
+    /// Receive a message from this connection as a dynamically-typed CBOR value, regardless of what type it was sent as.
+    /// Fails in the same way as [`receive`](Self::receive).
+    pub async fn receive_value(&mut self) -> Result<ciborium::value::Value> {
+        self.receive().await
+    }
+
+    /// Reads and discards every message that has already arrived in its entirety, returning how many were discarded.
+    /// This never waits for more data - a message that has only partially arrived is kept to be received later.
+    /// If one of the messages indicates that the connection is closing, this connection is closed and draining stops there.
+    /// This connection is also closed if the stream has ended, i.e. the other side has gone away without closing the connection.
+    /// Messages that can't be deserialised are discarded and counted like any other, so one malformed message doesn't stop the rest from being drained.
+    pub async fn drain_pending(&mut self) -> Result<usize> {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let ended = self.internal.fill_available_async()?;
+
+        let mut count = 0;
+        while let Some(frame) = self.internal.take_frame() {
+            let payload = frame_payload(frame)?;
+            self.observe(Direction::Received, &payload);
+            match self.codec.deserialise::<Message<IgnoredAny>>(&payload) {
+                Ok(Message::ClosingConnection | Message::ClosingWithReason(..)) => {
+                    self._close(CloseReason::Peer).await;
+                    break;
+                }
+                // a frame that can't be deserialised is still a whole message, so it is discarded like the rest
+                Err(_)
+                | Ok(
+                    Message::Data(_)
+                    | Message::Signal(_)
+                    | Message::Priority(_)
+                    | Message::Stamped(..),
+                ) => count += 1,
+                Ok(Message::Acked(_)) => {
+                    let _ = self._send::<()>(Message::Ack).await;
+                    count += 1;
+                }
+                Ok(Message::Ping) => {
+                    let _ = self._send::<()>(Message::Pong).await;
+                }
+                Ok(Message::Ack | Message::Pong) => {}
+            }
+        }
+        if ended && !self.closed {
+            self._close(CloseReason::Dropped).await;
+        }
+        Ok(count)
+    }
+
+    /// Receives up to `max` messages that have already arrived in their entirety, without waiting for more data.
+    /// This returns early with fewer messages (possibly none) if no more complete messages are available,
+    /// so that one busy connection can't keep the caller from serving others.
+    ///
+    /// A signal, a closing message or a message that can't be deserialised stops the batch.
+    /// If it is the first message, the error [`receive`](Self::receive) would give is returned;
+    /// otherwise the messages before it are returned, and it is left for the next call.
+    /// If the stream has ended, this connection is closed once every complete message has been received.
+    pub async fn receive_batch<T>(&mut self, max: usize) -> Result<Vec<T>>
+    where
+        T: DeserializeOwned,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        if max == 0 {
+            return Ok(Vec::new());
+        }
+        let ended = self.internal.fill_available_async()?;
+
+        let mut messages = Vec::new();
+        while messages.len() < max {
+            let frame = match self.internal.take_frame() {
+                Some(frame) => frame,
+                None => {
+                    if ended {
+                        self._close(CloseReason::Dropped).await;
+                    }
+                    break;
+                }
+            };
+            let payload = frame_payload(frame)?;
+            match self.codec.deserialise::<Message<T>>(&payload) {
+                Ok(Message::Data(data) | Message::Priority(data) | Message::Stamped(_, data)) => {
+                    self.observe(Direction::Received, &payload);
+                    self.report::<T>(Direction::Received, payload.len());
+                    messages.push(data);
+                }
+                Ok(Message::Ping) => self._answer_ping(MessageKind::Ping, &payload).await,
+                Ok(Message::Pong) => self._answer_ping(MessageKind::Pong, &payload).await,
+                _ if !messages.is_empty() => {
+                    self.internal.restore_frame(payload.into_frame());
+                    break;
+                }
+                result => {
+                    self.observe(Direction::Received, &payload);
+                    return self._unwrap_message(result?).await.map(|data| vec![data]);
+                }
+            }
+        }
+        Ok(messages)
+    }
+    /// Flushes this connection, then waits until everything sent through it has left the kernel's send buffer, or until `timeout` has passed.
+    /// For local sockets, data generally only leaves the send buffer once the other side has read it.
+    /// Fails with [`Error::Timeout`] if there is still unsent data after `timeout`.
+    ///
+    /// This is best-effort: only Linux, Android, macOS and iOS let gipc see how much data is unsent.
+    /// On other platforms, and for [`ConnectionImpl`]s that don't [support it](ConnectionImpl::unsent_bytes), this only flushes the connection.
+    pub async fn flush_and_wait_drain(&mut self, timeout: Duration) -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+        use tokio_util::compat::FuturesAsyncWriteCompatExt;
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        (&mut self.internal).compat_write().flush().await?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.internal.inner_mut().unsent_bytes() {
+                Ok(0) => return Ok(()),
+                Ok(_) if Instant::now() >= deadline => return Err(Error::Timeout),
+                Ok(_) => tokio::time::sleep(DRAIN_POLL_INTERVAL).await,
+                Err(Error::Io(error)) if error.kind() == std::io::ErrorKind::Unsupported => {
+                    return Ok(())
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Proves to the other side of this connection that this side knows `secret`,
+    /// as checked by [`authenticate_peer`](Self::authenticate_peer) or by a listener with an [authentication secret](Listener::set_auth_secret).
+    /// This has to happen before any other messages are sent through the connection.
+    /// Fails with [`Error::AuthFailed`] if the other side doesn't accept the answer, and otherwise in the same way as [`receive`](Self::receive).
+    /// This connection is closed whenever the handshake fails, since it can't be told where the handshake stopped.
+    ///
+    /// The handshake keeps processes that don't know the secret from using the connection, but it doesn't encrypt anything,
+    /// and only the side calling this is checked - a process listening in place of the expected one isn't detected.
+    #[cfg(feature = "auth")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "auth")))]
+    pub async fn authenticate(&mut self, secret: &[u8]) -> Result<()> {
+        let result = self._authenticate(secret).await;
+        if result.is_err() {
+            self.close().await;
+        }
+        result
+    }
+    #[cfg(feature = "auth")]
+    async fn _authenticate(&mut self, secret: &[u8]) -> Result<()> {
+        let nonce = self.receive_bytes().await?;
+        self.send_bytes(&auth::respond(secret, &nonce)).await?;
+        match self.receive::<bool>().await? {
+            true => Ok(()),
+            false => Err(Error::AuthFailed),
+        }
+    }
+    /// Checks that the other side of this connection knows `secret`, by sending it a random nonce that it has to answer with `HMAC-SHA256(secret, nonce)`
+    /// using [`authenticate`](Self::authenticate).
+    /// This has to happen before any other messages are sent through the connection.
+    /// Fails with [`Error::AuthFailed`] if the answer is wrong, and otherwise in the same way as [`receive`](Self::receive).
+    /// Like with [`authenticate`](Self::authenticate), this connection is closed whenever the handshake fails.
+    #[cfg(feature = "auth")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "auth")))]
+    pub async fn authenticate_peer(&mut self, secret: &[u8]) -> Result<()> {
+        let result = self._authenticate_peer(secret).await;
+        if result.is_err() {
+            self.close().await;
+        }
+        result
+    }
+    #[cfg(feature = "auth")]
+    async fn _authenticate_peer(&mut self, secret: &[u8]) -> Result<()> {
+        let nonce = auth::nonce()?;
+        self.send_bytes(&nonce).await?;
+        let accepted = match self.receive_bytes().await {
+            Ok(response) => auth::verify(secret, &nonce, &response),
+            Err(Error::Deserialise(_)) => false,
+            Err(error) => return Err(error),
+        };
+        self.send(&accepted).await?;
+        match accepted {
+            true => Ok(()),
+            false => Err(Error::AuthFailed),
+        }
+    }
+
+    /// Shorthand for calling [`send`] and [`receive`] after one another.
+    pub async fn send_and_receive<A, B>(&mut self, data: &A) -> Result<B>
+    where
+        A: Serialize,
+        B: DeserializeOwned,
+    {
+        self.send(data).await?;
+        self.receive().await
+    }
+    /// Sends `data` and receives the response like [`send_and_receive`](Self::send_and_receive), but gives up once `timeout` has passed,
+    /// failing with [`Error::Timeout`]. The timeout covers sending and receiving together, and any deadlines that are already set still apply.
+    ///
+    /// Timing out closes this connection, since a request that may have been sent could still be answered, and that answer would be mistaken for the response to the next request.
+    /// The other side isn't told that the connection was closed, and its close reason is [`CloseReason::Error`] with [`TimedOut`](std::io::ErrorKind::TimedOut).
+    pub async fn send_and_receive_timeout<A, B>(&mut self, data: &A, timeout: Duration) -> Result<B>
+    where
+        A: Serialize,
+        B: DeserializeOwned,
+    {
+        let deadline = Instant::now() + timeout;
+        let result = with_deadline(Some(deadline), self.send_and_receive(data)).await;
+        if let Err(Error::Timeout) = result {
+            self._close(CloseReason::Error(std::io::ErrorKind::TimedOut))
+                .await;
+        }
+        result
+    }
+    /// Answers requests on this connection until the other side closes it: receives a request, passes it to `handler`, sends the response `handler` produces, and repeats.
+    /// This is the server side of [`send_and_receive`](Self::send_and_receive).
+    ///
+    /// Returns `Ok(())` once the other side has closed the connection cleanly.
+    /// Any other error stops serving and is returned, including the other side going away without closing the connection,
+    /// in which case the connection is closed as usual for that error.
+    pub async fn serve<Req, Resp, F, Fut>(&mut self, mut handler: F) -> Result<()>
+    where
+        Req: DeserializeOwned,
+        Resp: Serialize,
+        F: FnMut(Req) -> Fut,
+        Fut: std::future::Future<Output = Resp>,
+    {
+        loop {
+            let request = match self.receive().await {
+                Err(Error::Closed(true) | Error::ClosedWithReason { .. }) => return Ok(()),
+                result => result?,
+            };
+            let response = handler(request).await;
+            self.send(response).await?;
+        }
+    }
+    /// Send a message through this connection, then wait until the other side has received it.
+    /// The other side acknowledges the message automatically as soon as [`receive`](Self::receive) (or any other way of receiving it) has returned it,
+    /// which is a stronger guarantee than [`flush_and_wait_drain`](Self::flush_and_wait_drain) gives.
+    ///
+    /// This costs a full round trip per message, so it is far slower than [`send`](Self::send) when sending many messages.
+    /// Messages that the other side sends whilst this is waiting are kept to be received afterwards,
+    /// but the other side can't receive anything whilst it's waiting for an acknowledgement itself,
+    /// so both sides calling this at the same time waits forever unless a [read deadline](Self::set_read_deadline) is set.
+    /// If waiting fails or is cancelled, the acknowledgement may still arrive later, in which case it is received as [`Error::UnexpectedAck`].
+    /// Cancelling this also discards the messages that were kept whilst waiting.
+    pub async fn send_acked<T>(&mut self, message_data: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        self._send(Message::Acked(message_data)).await?;
+        self.report::<T>(Direction::Sent, self.encode_buffer.len());
+        self._wait_for(MessageKind::Ack).await
+    }
+    /// Primes this connection by sending a tiny ping and waiting for the other side to answer it, so that a latency-sensitive first request doesn't pay for cold buffers and lazy setup in the OS.
+    /// The other side answers automatically whilst it is receiving, without either side's messages being affected, and messages that it sends in the meantime are kept to be received afterwards.
+    ///
+    /// Other sides using a version of gipc that predates pings can't make sense of them, and fail to deserialise them instead of answering,
+    /// so this waits forever unless a [read deadline](Self::set_read_deadline) is set.
+    /// Will immediately fail with [`Error::Closed(false)`](Error::Closed) if this connection is already closed.
+    pub async fn warm_up(&mut self) -> Result<()> {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        self._send::<()>(Message::Ping).await?;
+        self._wait_for(MessageKind::Pong).await
+    }
+
+    /// Waits for a message of kind `kind` that answers one this connection has just sent, keeping the messages that arrive in the meantime to be received afterwards.
+    async fn _wait_for(&mut self, kind: MessageKind) -> Result<()> {
+        let mut skipped = Vec::new();
+        let result = loop {
+            let payload = match self._read_any_payload().await {
+                Ok(payload) => payload,
+                Err(error) => break Err(error),
+            };
+            match self.codec.deserialise::<Message<IgnoredAny>>(&payload) {
+                Ok(message) if message.kind() == kind => {
+                    self.observe(Direction::Received, &payload);
+                    break Ok(());
+                }
+                Ok(Message::Ping) => self._answer_ping(MessageKind::Ping, &payload).await,
+                Ok(Message::Pong) => self._answer_ping(MessageKind::Pong, &payload).await,
+                Ok(Message::ClosingConnection) => {
+                    self.observe(Direction::Received, &payload);
+                    self._close(CloseReason::Peer).await;
+                    break Err(Error::Closed(true));
+                }
+                Ok(Message::ClosingWithReason(code, message)) => {
+                    self.observe(Direction::Received, &payload);
+                    self._close(CloseReason::Peer).await;
+                    break Err(Error::ClosedWithReason { code, message });
+                }
+                _ => skipped.push(payload),
+            }
+        };
+        for payload in skipped.into_iter().rev() {
+            self.internal.restore_frame(payload.into_frame());
+        }
+        result
+    }
+
+    /// Gets when this connection's idle timeout runs out if nothing is sent or received before then.
+    fn idle_deadline(&self) -> Option<Instant> {
+        self.idle_timeout
+            .and_then(|timeout| self.last_activity.get()?.checked_add(timeout))
+    }
+    /// Closes this connection if its idle timeout has run out, failing with [`Error::Closed(true)`](Error::Closed) if it did.
+    async fn check_idle(&mut self) -> Result<()> {
+        match self.idle_deadline() {
+            Some(deadline) if deadline <= Instant::now() => {
+                self._close(CloseReason::Idle).await;
+                Err(Error::Closed(true))
+            }
+            _ => Ok(()),
+        }
+    }
+    /// Waits until this connection's rate limit allows another message to be sent,
+    /// failing with [`Error::Timeout`] straight away if that would take until after the write deadline.
+    async fn throttle(&mut self) -> Result<()> {
+        let Some(limiter) = &mut self.rate_limiter else {
+            return Ok(());
+        };
+        let delay = limiter.delay();
+        if !delay.is_zero() {
+            if matches!(self.write_deadline, Some(deadline) if Instant::now() + delay > deadline) {
+                return Err(Error::Timeout);
+            }
+            tokio::time::sleep(delay).await;
+        }
+        Ok(())
+    }
+    async fn _close(&mut self, reason: CloseReason) {
+        self.internal.inner_mut().close().await;
+        self.closed = true;
+        self.close_reason = Some(reason);
+        self.registration = None;
+    }
+
+    /// Sets the largest message the other side accepts receiving, for a synchronous connection this one is made from that has already [exchanged](Self::exchange_max_message_sizes) it.
+    #[cfg(feature = "sync")]
+    pub(crate) fn set_peer_max_message_size(&mut self, max: Option<u64>) {
+        self.peer_max_message_size = max;
+    }
+    /// Closes this connection without waiting for it to be closed, for places that have to let go of a connection but can't wait, such as `Drop` implementations.
+    /// The close is spawned onto the current Tokio runtime; outside of one, the connection is closed without telling the other side.
+    pub(crate) fn close_detached(&mut self) {
+        if self.closed {
+            return;
+        }
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                let mut connection = self.take();
+                handle.spawn(async move { connection.close().await });
+            }
+            Err(_) => {
+                self.closed = true;
+                self.close_reason = Some(CloseReason::Local);
+                self.registration = None;
+            }
+        }
+    }
+    /// Takes this connection out of `self`, leaving a connection that is already closed in its place.
+    pub(crate) fn take(&mut self) -> Connection {
+        let mut detached = Connection::new(Box::new(Detached));
+        detached.closed = true;
+        std::mem::replace(self, detached)
+    }
+
+    /// Closes this connection if it isn't already closed.
+    /// Unless [disabled](Self::set_send_close_frame), the other side is told that the connection is closing first.
+    /// This operation can never fail - use [`try_close`](Self::try_close) to find out whether the other side could be told.
+    pub async fn close(&mut self) {
+        if self.closed {
+            return;
+        }
+        // ignore the results of this - it doesn't matter since we're closing it either way
+        let _ = self.try_close().await;
+    }
+    /// Closes this connection like [`close`](Self::close), but returns the error that occurred whilst telling the other side that the connection is closing, if any.
+    /// The connection is closed even if this fails.
+    /// Fails with [`Error::Closed(false)`](Error::Closed) if this connection is already closed.
+    pub async fn try_close(&mut self) -> Result<()> {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let result = if self.send_close_frame {
+            self._send::<()>(Message::ClosingConnection).await
+        } else {
+            Ok(())
+        };
+        self._close(CloseReason::Local).await;
+        result
+    }
+    /// Closes this connection like [`try_close`](Self::try_close), but first receives the messages the other side sent before it saw that the connection is closing, returning them.
+    ///
+    /// Closing races with the other side: it may send more messages after this side has decided to close, but before the close frame reaches it,
+    /// and [`close`](Self::close) silently drops them. This instead tells the other side that the connection is closing, then keeps receiving messages as `T`
+    /// until the other side closes the connection or goes away, or until `timeout` has passed, whichever comes first.
+    /// Signals are skipped, and a message that can't be deserialised as `T` stops receiving and fails with [`Error::Deserialise`].
+    ///
+    /// The connection is closed afterwards either way, even if sending the close frame or receiving fails, in which case the first error is returned.
+    /// Fails with [`Error::Closed(false)`](Error::Closed) if the connection is already closed.
+    pub async fn close_draining<T>(&mut self, timeout: Duration) -> Result<Vec<T>>
+    where
+        T: DeserializeOwned,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let mut result = if self.send_close_frame {
+            self._send::<()>(Message::ClosingConnection).await
+        } else {
+            Ok(())
+        };
+        let mut messages = Vec::new();
+        let read_deadline = self.read_deadline;
+        let deadline = Instant::now() + timeout;
+        // even if the close frame couldn't be sent, the messages that have already arrived can still be received
+        if !self.closed {
+            self.set_read_deadline(Some(read_deadline.map_or(deadline, |d| d.min(deadline))));
+            loop {
+                match self.receive().await {
+                    Ok(message_data) => messages.push(message_data),
+                    Err(Error::Signal(_)) => {}
+                    // receiving closes the connection once the other side has closed it or gone away
+                    Err(_) if self.closed => break,
+                    Err(Error::Timeout) => break,
+                    Err(error) => {
+                        result = result.and(Err(error));
+                        break;
+                    }
+                }
+            }
+        }
+        if !self.closed {
+            self.set_read_deadline(read_deadline);
+            self._close(CloseReason::Local).await;
+        }
+        result.map(|()| messages)
+    }
+    /// Closes this connection like [`try_close`](Self::try_close), but tells the other side why using `code` and optionally `message`,
+    /// which it receives as [`Error::ClosedWithReason`] instead of [`Error::Closed(true)`](Error::Closed).
+    ///
+    /// Other sides using a version of gipc that predates close codes can't make sense of this, and fail to deserialise it instead of closing.
+    /// Like [`close`](Self::close), nothing is sent if [sending a close frame](Self::set_send_close_frame) is disabled.
+    pub async fn close_with_reason(
+        &mut self,
+        code: CloseCode,
+        message: Option<&str>,
+    ) -> Result<()> {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let result = if self.send_close_frame {
+            let message = message.map(str::to_owned);
+            self._send::<()>(Message::ClosingWithReason(code, message))
+                .await
+        } else {
+            Ok(())
+        };
+        self._close(CloseReason::Local).await;
+        result
+    }
+
+    /// Gets the ID of this connection, which is unique among the connections created by this process.
+    /// IDs are assigned in increasing order the first time they're needed, which makes them useful for telling connections apart in logs.
+    pub fn id(&self) -> u64 {
+        *self.id.get_or_init(next_connection_id)
+    }
+    /// Describes how this connection is set up: the codec it currently uses, the kind of transport it runs over, and the name of the socket it was made through.
+    pub fn describe(&self) -> ConnectionDescription {
+        ConnectionDescription {
+            id: self.id(),
+            codec: self.codec.name(),
+            transport: self.internal.inner().transport(),
+            endpoint: self.endpoint.clone(),
+        }
+    }
+
+    /// Check if this connection is closed.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+    /// Gets the reason this connection was closed, or `None` if it isn't closed.
+    ///
+    /// Besides being closed by either side, a connection is closed when receiving from it fails because the other side went away,
+    /// in which case the reason is either [`CloseReason::Dropped`] or [`CloseReason::Error`].
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        self.close_reason
+    }
+    /// Makes this connection usable again after an operation on it failed without breaking the stream, e.g. because it timed out between two messages.
+    /// This clears the failure that [`is_failed`](Self::is_failed) reports, and deadlines that have already passed, as every operation would fail with [`Error::Timeout`] until they are changed otherwise.
+    ///
+    /// This refuses to reset a connection that is out of step with the other side, failing with [`Error::MidFrame`] without changing anything:
+    /// that is the case if part of a frame has been received but not the rest of it, or if sending a message failed after part of its frame had been written.
+    /// Sending through a connection that is left partway through a frame like that fails with [`Error::MidFrame`] as well, so all that is left to do with it is close it.
+    /// A receive that timed out partway through a frame keeps what it read, so the connection can be reset once that frame has been received in full.
+    /// Fails with [`Error::Closed(false)`](Error::Closed) if this connection is closed, since closed connections can't be used again.
+    pub fn reset(&mut self) -> Result<()> {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        if self.failure == Some(Failure::PartialWrite) || !self.internal.at_frame_boundary() {
+            return Err(Error::MidFrame);
+        }
+        let now = Instant::now();
+        if self.read_deadline.is_some_and(|deadline| deadline <= now) {
+            self.set_read_deadline(None);
+        }
+        if self.write_deadline.is_some_and(|deadline| deadline <= now) {
+            self.set_write_deadline(None);
+        }
+        self.failure = None;
+        Ok(())
+    }
+    /// Checks whether reading from or writing to this connection has failed without closing it since it was created or last [reset](Self::reset),
+    /// e.g. because a deadline passed. Messages that can't be deserialised and signals received instead of messages don't count, since they leave the connection as usable as it was.
+    pub fn is_failed(&self) -> bool {
+        self.failure.is_some()
+    }
+
+    /// Sets the deadline for receiving messages, or clears it if `deadline` is `None`.
+    /// Once the deadline has passed, receiving fails with [`Error::Timeout`] until the deadline is changed or cleared.
+    ///
+    /// A message that has only been partially read when the deadline passes is kept, and the next receive continues reading it.
+    pub fn set_read_deadline(&mut self, deadline: Option<Instant>) {
+        self.read_deadline = deadline;
+    }
+    /// Sets the deadline for sending messages, or clears it if `deadline` is `None`.
+    /// Once the deadline has passed, sending fails with [`Error::Timeout`] until the deadline is changed or cleared.
+    ///
+    /// A message that has only been partially written when the deadline passes leaves the connection unusable.
+    pub fn set_write_deadline(&mut self, deadline: Option<Instant>) {
+        self.write_deadline = deadline;
+    }
+    /// Sets how long this connection may go without sending or receiving anything before it is closed, or disables this if `timeout` is `None`, which is the default.
+    /// Once the timeout runs out, the connection is closed with [`CloseReason::Idle`] by the next operation,
+    /// or by a receive that is already waiting, which then fails with [`Error::Closed(true)`](Error::Closed).
+    /// This is useful for servers, so that connections to clients that are gone but never closed them don't stay open forever.
+    ///
+    /// Any message sent or received counts as activity, except for messages sent using [`send_iter`](Self::send_iter).
+    /// If nothing has been sent or received yet, the connection has been idle since the timeout was set.
+    /// The other side isn't told that the connection was closed.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        if self.last_activity.get().is_none() {
+            self.last_activity.set(Some(Instant::now()));
+        }
+        self.idle_timeout = timeout;
+    }
+    /// Gets how long this connection may go without sending or receiving anything before it is closed, as set by [`set_idle_timeout`](Self::set_idle_timeout).
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+    /// Sets the largest message this connection accepts receiving, in bytes, or removes the limit if `max` is `None`, which is the default.
+    /// Receiving a frame whose length prefix claims a larger message fails with [`Error::ProtocolDesync`] before anything is allocated for it,
+    /// since a prefix like that usually means that the two sides are out of sync.
+    /// Even without a limit, prefixes claiming messages too large to be allocated are treated like this.
+    ///
+    /// The limit applies to the message as serialised, not including its length prefix.
+    pub fn set_max_message_size(&mut self, max: Option<usize>) {
+        self.internal.set_max_message_size(max);
+    }
+    /// Gets the largest message this connection accepts receiving, as set by [`set_max_message_size`](Self::set_max_message_size).
+    pub fn max_message_size(&self) -> Option<usize> {
+        self.internal.max_message_size()
+    }
+    /// Tells the other side of this connection the largest message this side accepts receiving, as set by [`set_max_message_size`](Self::set_max_message_size),
+    /// and learns the other side's limit in return, which is returned and kept as [`peer_max_message_size`](Self::peer_max_message_size).
+    /// From then on, sending a message larger than the other side's limit fails with [`Error::MessageTooLarge`] before anything is sent, rather than the other side closing the connection partway through.
+    ///
+    /// Both sides have to call this at the same point, usually right after connecting and [authenticating](Self::authenticate), since each side sends its limit and then waits for the other's.
+    /// Limits set afterwards aren't passed on, so set the maximum message size first.
+    /// Fails in the same way as [`send`](Self::send) and [`receive`](Self::receive).
+    pub async fn exchange_max_message_sizes(&mut self) -> Result<Option<u64>> {
+        let own = self.max_message_size().map(|max| max as u64);
+        self.send(own).await?;
+        let peer = self.receive::<Option<u64>>().await?;
+        self.peer_max_message_size = peer;
+        Ok(peer)
+    }
+    /// Gets the largest message the other side of this connection accepts receiving, as learnt by [`exchange_max_message_sizes`](Self::exchange_max_message_sizes),
+    /// or `None` if the other side has no limit or the limits haven't been exchanged.
+    ///
+    /// Messages streamed using [`send_iter`](Self::send_iter) aren't checked against this, since their size isn't known until they have been sent.
+    pub fn peer_max_message_size(&self) -> Option<u64> {
+        self.peer_max_message_size
+    }
+    /// Limits how fast this connection sends messages, or removes the limit if `limit` is `None`, which is the default.
+    /// Sending awaits whilst the limit is hit, unless that would take until after the [write deadline](Self::set_write_deadline), in which case it fails with [`Error::Timeout`].
+    ///
+    /// Every message sent through this connection counts towards the limit, including signals and the message sent when closing it.
+    /// Setting a limit starts it afresh, with a full second's worth of sending available.
+    pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+        self.rate_limiter = limit.map(RateLimiter::new);
+    }
+    /// Gets the limit on how fast this connection sends messages, as set by [`set_rate_limit`](Self::set_rate_limit).
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        self.rate_limiter.as_ref().map(RateLimiter::limit)
+    }
+
+    /// Reserves space for messages of at least `capacity` bytes in the buffer that messages are serialised into before being sent.
+    /// The buffer is reused between messages and grows as needed, so this is only useful to avoid reallocations the first time a large message is sent.
+    pub fn set_serialize_capacity_hint(&mut self, capacity: usize) {
+        self.encode_buffer.clear();
+        self.encode_buffer.reserve(capacity);
+    }
+
+    /// Sets whether [`close`](Self::close) sends a message telling the other side that the connection is closing, which it does by default.
+    /// Disabling this means closing the connection writes nothing, which is useful when the other side isn't a gipc program and wouldn't understand the message.
+    ///
+    /// Without that message, the other side can't tell a clean close apart from this side crashing - both look like the stream ending.
+    pub fn set_send_close_frame(&mut self, send_close_frame: bool) {
+        self.send_close_frame = send_close_frame;
+    }
+
+    /// Sets whether messages sent through this connection are stamped with when they were sent, which they aren't by default.
+    /// The other side can get the timestamp using [`receive_with_timestamp`](Self::receive_with_timestamp), and receives the messages as normal otherwise.
+    /// Stamping a message makes it around 13 bytes larger with the default codec.
+    pub fn set_send_timestamps(&mut self, send_timestamps: bool) {
+        self.send_timestamps = send_timestamps;
+    }
+
+    /// Sets a function that observes every message sent or received through this connection, replacing any previous observer.
+    /// The observer is given the serialised message (without its length prefix) after it has been sent, or before it is deserialised when it is received.
+    /// Messages discarded by [`drain_pending`](Self::drain_pending) are observed as received too.
+    ///
+    /// The observer only gets to look at the data, so it can't alter what's sent or received - this is intended for things like audit logging.
+    pub fn set_observer(&mut self, observer: Observer) {
+        self.observer = Some(observer);
+    }
+    /// Removes the observer set by [`set_observer`](Self::set_observer), if any.
+    pub fn clear_observer(&mut self) {
+        self.observer = None;
+    }
+    /// Sets a function that is told the type and serialised size of every message sent or received through this connection, replacing any previous hook.
+    /// This is meant for profiling, e.g. to find out which types of messages take up the most bandwidth. There is no hook by default, which costs nothing.
+    ///
+    /// Only messages whose type is known are counted, i.e. those sent or received by methods like [`send`](Self::send) and [`receive`](Self::receive) that take a message type;
+    /// signals, envelopes, closing messages and messages sent using [`send_iter`](Self::send_iter) aren't. Received messages are only counted if they could be deserialised.
+    /// The type name is that of the value given to the method, so e.g. sending a reference to a message using [`send`](Self::send) reports a reference type.
+    pub fn set_message_hook(&mut self, hook: MessageHook) {
+        self.message_hook = Some(hook);
+    }
+    /// Removes the hook set by [`set_message_hook`](Self::set_message_hook), if any.
+    pub fn clear_message_hook(&mut self) {
+        self.message_hook = None;
+    }
+
+    /// Gets the credentials of the process on the other side of this connection.
+    /// Requires the underlying [`ConnectionImpl`] to [support it](ConnectionImpl::peer_credentials),
+    /// which local sockets do on Linux, Android, macOS, iOS and the BSDs.
+    ///
+    /// The credentials can't change whilst the connection is open, so they are only queried the first time they're needed - often when the connection is accepted -
+    /// and kept for later calls. Use [`refresh_peer_credentials`](Self::refresh_peer_credentials) to query them again.
+    pub fn peer_credentials(&mut self) -> Result<PeerCredentials> {
+        match self.peer_credentials {
+            Some(credentials) => Ok(credentials),
+            None => self.refresh_peer_credentials(),
+        }
+    }
+    /// Queries the credentials of the process on the other side of this connection again, instead of using the ones kept by [`peer_credentials`](Self::peer_credentials),
+    /// and keeps the result for later calls.
+    pub fn refresh_peer_credentials(&mut self) -> Result<PeerCredentials> {
+        let credentials = self.internal.inner_mut().peer_credentials()?;
+        self.peer_credentials = Some(credentials);
+        Ok(credentials)
+    }
+
+    /// Sets the size of the kernel's buffer for data received through this connection to `size` bytes, i.e. `SO_RCVBUF`.
+    /// Requires the underlying [`ConnectionImpl`] to [support it](ConnectionImpl::set_socket_buffer_size), which local sockets do on Unix;
+    /// otherwise this fails with an [`Unsupported`](std::io::ErrorKind::Unsupported) I/O error.
+    ///
+    /// The kernel treats `size` as a hint - it may round it, clamp it to system-wide limits, or (like Linux does) double it to leave room for bookkeeping,
+    /// so [`recv_buffer_size`](Self::recv_buffer_size) may not return exactly `size` afterwards.
+    pub fn set_recv_buffer_size(&mut self, size: usize) -> Result<()> {
+        self.internal
+            .inner_mut()
+            .set_socket_buffer_size(Direction::Received, size)
+    }
+    /// Gets the size of the kernel's buffer for data received through this connection, in bytes.
+    /// See [`set_recv_buffer_size`](Self::set_recv_buffer_size).
+    pub fn recv_buffer_size(&mut self) -> Result<usize> {
+        self.internal
+            .inner_mut()
+            .socket_buffer_size(Direction::Received)
+    }
+    /// Sets the size of the kernel's buffer for data sent through this connection to `size` bytes, i.e. `SO_SNDBUF`.
+    /// This works like [`set_recv_buffer_size`](Self::set_recv_buffer_size).
+    pub fn set_send_buffer_size(&mut self, size: usize) -> Result<()> {
+        self.internal
+            .inner_mut()
+            .set_socket_buffer_size(Direction::Sent, size)
+    }
+    /// Gets the size of the kernel's buffer for data sent through this connection, in bytes.
+    /// See [`set_send_buffer_size`](Self::set_send_buffer_size).
+    pub fn send_buffer_size(&mut self) -> Result<usize> {
+        self.internal
+            .inner_mut()
+            .socket_buffer_size(Direction::Sent)
+    }
+
+    /// Wraps this connection in a [`VersionedConnection`] using the schema version `version`.
+    pub fn versioned(self, version: u16) -> VersionedConnection {
+        VersionedConnection::new(self, version)
+    }
+    /// Wraps this connection in a [`TypedConnection`], which detects messages being received as a different type than they were sent as.
+    #[cfg(feature = "type-check")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "type-check")))]
+    pub fn typed(self) -> TypedConnection {
+        TypedConnection::new(self)
+    }
+    /// Wraps this connection in a [`ReadOnlyConnection`], for connections that are only ever received from.
+    pub fn read_only(self) -> ReadOnlyConnection {
+        ReadOnlyConnection { connection: self }
+    }
+    /// Wraps this connection in a [`WriteOnlyConnection`], for connections that are only ever sent through.
+    pub fn write_only(self) -> WriteOnlyConnection {
+        WriteOnlyConnection { connection: self }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        if !self.closed {
+            report_unclosed_drop(format_args!(
+                "async connection {} was dropped without being closed - call `close().await` first",
+                self.id()
+            ));
+        }
+    }
+}
+
+/// How long [`Connection::flush_and_wait_drain`] waits between checking how much data is unsent.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Runs `future` until it completes or `deadline` passes, in which case it fails with [`Error::Timeout`].
+async fn with_deadline<F, R>(deadline: Option<Instant>, future: F) -> Result<R>
+where
+    F: std::future::Future<Output = Result<R>>,
+{
+    match deadline {
+        Some(deadline) if deadline <= Instant::now() => Err(Error::Timeout),
+        Some(deadline) => tokio::time::timeout_at(deadline.into(), future)
+            .await
+            .map_err(|_| Error::Timeout)?,
+        None => future.await,
+    }
+}
+
+/// Checks that connections work in this environment by listening on a socket with a unique name,
+/// connecting to it, sending a message both ways, and closing everything again.
+/// This is useful as a health check, e.g. to find out whether the runtime directory is misconfigured.
+///
+/// Any error that occurs during the test is returned.
+pub async fn self_test() -> Result<()> {
+    let name = super::interprocess::unique_name("gipc-self-test");
+    let mut listener = Listener::listen_as_socket(&name, false)?;
+    let mut client = Connection::connect_to_socket(&name, false).await?;
+    let mut server = listener.accept().await?;
+
+    client.send("ping").await?;
+    let ping: String = server.receive().await?;
+    server.send(ping).await?;
+    let pong: String = client.receive().await?;
+    if pong != "ping" {
+        return Err(Error::Deserialise(
+            format!("self-test received {:?} instead of \"ping\"", pong).into(),
+        ));
+    }
+
+    client.close().await;
+    server.close().await;
+    listener.close().await
+}
+
+/// How many bytes an in-memory connection holds in each direction before sending waits for the other side to receive.
+const IN_MEMORY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Creates two connections that are connected to each other in memory, without any socket.
+/// This is useful for testing code that uses gipc without having to set up real sockets.
+///
+/// Each direction holds up to 64 KiB that hasn't been received yet, after which sending waits for the other side to receive.
+/// Like any other asynchronous connection, both connections have to be closed using [`Connection::close`].
+pub fn in_memory_pair() -> (Connection, Connection) {
+    let (a, b) = tokio::io::duplex(IN_MEMORY_BUFFER_SIZE);
+    (Connection::from(a), Connection::from(b))
+}
+
+/// Creates a fully connected mesh of `n` nodes connected in memory, like [`in_memory_pair`], for testing distributed protocols without real sockets.
+///
+/// The returned vector has one entry per node, holding that node's connections to every other node in order of the other node's index,
+/// skipping the node itself. So node `i` reaches node `j` through `mesh[i][j]` if `j < i` and through `mesh[i][j - 1]` if `j > i`,
+/// and the other end of that connection is the one node `j` has to node `i`.
+pub fn in_memory_mesh(n: usize) -> Vec<Vec<Connection>> {
+    let mut mesh: Vec<Vec<Connection>> = (0..n)
+        .map(|_| Vec::with_capacity(n.saturating_sub(1)))
+        .collect();
+    // connecting the nodes in order of the other node's index means every list comes out sorted
+    for i in 0..n {
+        for j in i + 1..n {
+            let (a, b) = in_memory_pair();
+            mesh[i].push(a);
+            mesh[j].push(b);
+        }
+    }
+    mesh
+}
+
+/// A thin wrapper around a [`Connection`] that tags every message with a schema version.
+/// Received messages with a different schema version are rejected with [`Error::SchemaMismatch`].
+///
+/// Both sides of the connection have to use a `VersionedConnection`, as the version is part of the message data.
+pub struct VersionedConnection {
+    connection: Connection,
+    version: u16,
+}
+
+impl VersionedConnection {
+    /// Creates a new versioned connection that sends messages with, and expects messages to have, `version`.
+    pub const fn new(connection: Connection, version: u16) -> Self {
+        Self {
+            connection,
+            version,
+        }
+    }
+
+    /// Gets the schema version of this connection.
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// Send a message tagged with this connection's schema version.
+    /// Fails in the same way as [`Connection::send`].
+    pub async fn send<T>(&mut self, message_data: T) -> Result<()>
+    where
+        T: Serialize,
+    {
+        self.connection.send((self.version, message_data)).await
+    }
+    /// Receive a message, checking that it was sent with this connection's schema version.
+    /// Fails with [`Error::SchemaMismatch`] if the versions differ, and otherwise in the same way as [`Connection::receive`].
+    pub async fn receive<T>(&mut self) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let (got, value): (u16, ciborium::value::Value) = self.connection.receive().await?;
+        if got != self.version {
+            return Err(Error::SchemaMismatch {
+                expected: self.version,
+                got,
+            });
+        }
+        value
+            .deserialized()
+            .map_err(|v| Error::Deserialise(v.into()))
+    }
+
+    /// Gets the underlying connection.
+    pub fn connection(&mut self) -> &mut Connection {
+        &mut self.connection
+    }
+    /// Unwraps this versioned connection, returning the underlying connection.
+    pub fn into_inner(self) -> Connection {
+        self.connection
+    }
+}
+
+/// A thin wrapper around a [`Connection`] that tags every message with a [fingerprint] of its type.
+/// Received messages that were sent as a different type are rejected with [`Error::TypeMismatch`],
+/// instead of possibly being deserialised into the wrong data when the types happen to look alike.
+///
+/// Both sides of the connection have to use a `TypedConnection`, as the fingerprint is part of the message data:
+/// every message is sent as a `(u64, T)` tuple of the fingerprint and the data, which is also what an untyped receive of it gets.
+///
+/// By default the fingerprint is based on the name of the type as returned by [`std::any::type_name`], so both sides have to use the same type with the same path.
+/// That name isn't guaranteed to be stable and may change between compiler versions, so this is only reliable when both sides are built with the same compiler.
+/// [`send_as`](Self::send_as) and [`receive_as`](Self::receive_as) take a schema ID chosen by the user instead, which is stable,
+/// and should be used whenever both sides are built separately or define the type separately.
+#[cfg(feature = "type-check")]
+#[cfg_attr(docsrs, doc(cfg(feature = "type-check")))]
+pub struct TypedConnection {
+    connection: Connection,
+}
+
+#[cfg(feature = "type-check")]
+impl TypedConnection {
+    /// Creates a new typed connection.
+    pub const fn new(connection: Connection) -> Self {
+        Self { connection }
+    }
+
+    /// Send a message tagged with a fingerprint of the name of its type, which may differ between compiler versions (see [`TypedConnection`]).
+    /// Fails in the same way as [`Connection::send`].
+    pub async fn send<T>(&mut self, message_data: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.send_as(std::any::type_name::<T>(), message_data).await
+    }
+    /// Send a message tagged with a fingerprint of `schema`, instead of one of its type.
+    /// Fails in the same way as [`Connection::send`].
+    pub async fn send_as<T>(&mut self, schema: &str, message_data: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.connection
+            .send(&(fingerprint(schema), message_data))
+            .await
+    }
+    /// Receive a message, checking that it was sent as a `T` by [`send`](Self::send), which relies on both sides agreeing on the name of `T` (see [`TypedConnection`]).
+    /// Fails with [`Error::TypeMismatch`] if it wasn't, and otherwise in the same way as [`Connection::receive`].
+    pub async fn receive<T>(&mut self) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.receive_as(std::any::type_name::<T>()).await
+    }
+    /// Receive a message, checking that it was sent using [`send_as`](Self::send_as) with `schema`.
+    /// Fails with [`Error::TypeMismatch`] if it wasn't, and otherwise in the same way as [`Connection::receive`].
+    pub async fn receive_as<T>(&mut self, schema: &str) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let expected = fingerprint(schema);
+        let (got, value): (u64, ciborium::value::Value) = self.connection.receive().await?;
+        if got != expected {
+            return Err(Error::TypeMismatch { expected, got });
+        }
+        value
+            .deserialized()
+            .map_err(|v| Error::Deserialise(v.into()))
+    }
+
+    /// Gets the underlying connection.
+    pub fn connection(&mut self) -> &mut Connection {
+        &mut self.connection
+    }
+    /// Unwraps this typed connection, returning the underlying connection.
+    pub fn into_inner(self) -> Connection {
+        self.connection
+    }
+
+    /// Gets the underlying connection, to send or receive a message without a fingerprint in the middle of a typed conversation.
+    /// The other side has to send or receive that message untyped as well. Nothing that has already been read is lost, so typed messages can be received again afterwards.
+    pub fn as_untyped(&mut self) -> &mut Connection {
+        &mut self.connection
+    }
+    /// Unwraps this typed connection to go back to untyped messages for good, keeping everything that has already been read.
+    pub fn into_untyped(self) -> Connection {
+        self.connection
+    }
+}
+
+/// A [`Connection`] that can only be received from, as returned by [`Connection::read_only`].
+/// This makes sure at compile time that nothing is sent through a connection that is meant to only go one way.
+///
+/// Messages are still acknowledged and the connection is still closed as usual, so the other side can use the connection like any other.
+///
+/// Sending through a read-only connection doesn't compile:
+/// ```compile_fail,E0599
+/// # async fn send(connection: gipc::connection::async_tokio::Connection) -> gipc::Result<()> {
+/// connection.read_only().send(&1u8).await
+/// # }
+/// ```
+pub struct ReadOnlyConnection {
+    connection: Connection,
+}
+
+impl ReadOnlyConnection {
+    /// Receive a message.
+    /// Fails in the same way as [`Connection::receive`].
+    pub async fn receive<T>(&mut self) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.connection.receive().await
+    }
+    /// Receive a message, deserialising it using `codec`.
+    /// Fails in the same way as [`Connection::receive_with`].
+    pub async fn receive_with<C, T>(&mut self, codec: &C) -> Result<T>
+    where
+        C: Codec + ?Sized,
+        T: DeserializeOwned,
+    {
+        self.connection.receive_with(codec).await
+    }
+    /// Receive a message, returning `None` if it can't be deserialised.
+    /// Fails in the same way as [`Connection::receive_or_skip`].
+    pub async fn receive_or_skip<T>(&mut self) -> Result<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        self.connection.receive_or_skip().await
+    }
+    /// Gets the kind of the next message without receiving it, like [`Connection::peek_variant`].
+    pub async fn peek_variant(&mut self) -> Result<MessageKind> {
+        self.connection.peek_variant().await
+    }
+
+    /// Check if the connection is closed.
+    pub fn is_closed(&self) -> bool {
+        self.connection.is_closed()
+    }
+    /// Closes the connection if it isn't already closed, like [`Connection::close`].
+    pub async fn close(&mut self) {
+        self.connection.close().await;
+    }
+
+    /// Unwraps this read-only connection, returning the underlying connection.
+    pub fn into_inner(self) -> Connection {
+        self.connection
+    }
+}
+
+/// A [`Connection`] that can only be sent through, as returned by [`Connection::write_only`].
+/// This makes sure at compile time that nothing is received from a connection that is meant to only go one way.
+///
+/// Receiving from a write-only connection doesn't compile:
+/// ```compile_fail,E0599
+/// # async fn receive(connection: gipc::connection::async_tokio::Connection) -> gipc::Result<u8> {
+/// connection.write_only().receive().await
+/// # }
+/// ```
+pub struct WriteOnlyConnection {
+    connection: Connection,
+}
+
+impl WriteOnlyConnection {
+    /// Send a message.
+    /// Fails in the same way as [`Connection::send`].
+    pub async fn send<T>(&mut self, message_data: T) -> Result<()>
+    where
+        T: Serialize,
+    {
+        self.connection.send(message_data).await
+    }
+    /// Send a message, serialising it using `codec`.
+    /// Fails in the same way as [`Connection::send_with`].
+    pub async fn send_with<C, T>(&mut self, codec: &C, message_data: T) -> Result<()>
+    where
+        C: Codec + ?Sized,
+        T: Serialize,
+    {
+        self.connection.send_with(codec, message_data).await
+    }
+    /// Send a signal with `code`.
+    /// Fails in the same way as [`Connection::send_signal`].
+    pub async fn send_signal(&mut self, code: u8) -> Result<()> {
+        self.connection.send_signal(code).await
+    }
+
+    /// Check if the connection is closed.
+    pub fn is_closed(&self) -> bool {
+        self.connection.is_closed()
+    }
+    /// Closes the connection if it isn't already closed, like [`Connection::close`].
+    pub async fn close(&mut self) {
+        self.connection.close().await;
+    }
+
+    /// Unwraps this write-only connection, returning the underlying connection.
+    pub fn into_inner(self) -> Connection {
+        self.connection
+    }
+}
+
+/// Listener implementation.
+#[async_trait]
+pub trait ListenerImpl: Send + Unpin {
+    /// Accept a new connection.
+    /// This function should return when a connection can be established.
+    async fn accept(&mut self) -> Result<Connection>;
+    /// Closes this listener implementation.
+    /// After this function is called, no more functions will be called from the implementation.
+    async fn close(&mut self) -> Result<()>;
+    /// Whether [`accept`](Self::accept) is cancel safe, i.e. whether dropping its future before it completes never loses a connection.
+    /// [`Listener::accept_many`] and [`Listener::close_graceful`] only pick up already pending connections from listeners that are.
+    ///
+    /// Defaults to `false`.
+    fn is_accept_cancel_safe(&self) -> bool {
+        false
+    }
+}
+
+#[async_trait]
+impl ListenerImpl for LocalSocketListener {
+    async fn accept(&mut self) -> Result<Connection> {
+        Ok(Connection::from(LocalSocketListener::accept(self).await?))
+    }
+    async fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+    fn is_accept_cancel_safe(&self) -> bool {
+        // these are Unix domain sockets on Unix, but named pipes on Windows
+        cfg!(unix)
+    }
+}
+
+#[cfg(unix)]
+#[cfg_attr(docsrs, doc(cfg(unix)))]
+#[async_trait]
+impl ListenerImpl for tokio::net::UnixListener {
+    async fn accept(&mut self) -> Result<Connection> {
+        let (stream, _) = tokio::net::UnixListener::accept(self).await?;
+        Ok(Connection::from(stream))
+    }
+    async fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+    fn is_accept_cancel_safe(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(unix)]
+#[cfg_attr(docsrs, doc(cfg(unix)))]
+impl From<tokio::net::UnixListener> for Listener {
+    fn from(value: tokio::net::UnixListener) -> Self {
+        Self::new(Box::new(value))
+    }
+}
+
+/// A named pipe that [`Listener::listen_on_pipe`] listens on.
+/// There is always one instance of the pipe waiting for a client, so that clients can connect between accepts.
+#[cfg(windows)]
+struct PipeServer {
+    config: PipeConfig,
+    waiting: NamedPipeServer,
+}
+
+#[cfg(windows)]
+impl PipeServer {
+    fn create(options: &PipeOptions) -> std::io::Result<Self> {
+        let config = PipeConfig::new(options)?;
+        let waiting = Self::create_instance(&config, true)?;
+        Ok(Self { config, waiting })
+    }
+    /// Creates an instance of the pipe for a client to connect to, which has to be the first one if `first` is true.
+    fn create_instance(config: &PipeConfig, first: bool) -> std::io::Result<NamedPipeServer> {
+        use tokio::net::windows::named_pipe::{PipeMode, ServerOptions};
+        let mode = match config.message_mode() {
+            true => PipeMode::Message,
+            false => PipeMode::Byte,
+        };
+        let mut attributes = config.security_attributes();
+        let attributes = match &mut attributes {
+            Some(attributes) => std::ptr::from_mut(attributes).cast(),
+            None => std::ptr::null_mut(),
+        };
+        // SAFETY: the attributes are either null or valid, and point to a descriptor that outlives the call
+        let instance = unsafe {
+            ServerOptions::new()
+                .first_pipe_instance(first)
+                .pipe_mode(mode)
+                .create_with_security_attributes_raw(config.path(), attributes)?
+        };
+        // message-mode pipes are created to be read from in message mode as well, but gipc reads them as bytes
+        super::pipe::set_mode(&instance, false)?;
+        Ok(instance)
+    }
+}
+
+#[cfg(windows)]
+#[async_trait]
+impl ListenerImpl for PipeServer {
+    async fn accept(&mut self) -> Result<Connection> {
+        use tokio_util::compat::TokioAsyncReadCompatExt;
+        self.waiting.connect().await?;
+        let next = Self::create_instance(&self.config, false)?;
+        let connected = std::mem::replace(&mut self.waiting, next);
+        Ok(Connection::new(Box::new(connected.compat())))
+    }
+    async fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+    fn is_accept_cancel_safe(&self) -> bool {
+        // connecting pipes is cancel safe, and the instance a client connected to is kept until it's accepted
+        true
+    }
+}
+
+/// Gets the full path of the named pipe called `name`.
+#[cfg(windows)]
+fn pipe_path(name: &std::ffi::OsStr) -> ResolvedName {
+    let mut path = std::ffi::OsString::from(r"\\.\pipe\");
+    path.push(name);
+    ResolvedName::Path(path.into())
+}
+
+/// Internal implementation for a [`Connection`].
+#[async_trait]
+pub trait ConnectionImpl: AsyncRead + AsyncWrite + Send + Unpin {
     /// Closes this connection implementation.
     /// After this function is called, no more functions will be called from the implementation.
     async fn close(&mut self);
+
+    /// Gets a short, human-readable name for the kind of transport this connection implementation runs over, as reported by [`Connection::describe`].
+    /// The default implementation uses the name of the type implementing this trait.
+    fn transport(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Gets the number of bytes that have been written to this connection implementation but haven't left the kernel's send buffer yet.
+    ///
+    /// Not every implementation has to support this - the default implementation fails with [`Unsupported`](std::io::ErrorKind::Unsupported).
+    fn unsent_bytes(&mut self) -> Result<usize> {
+        Err(Error::Io(std::io::ErrorKind::Unsupported.into()))
+    }
+
+    /// Gets the size of the kernel's buffer for data travelling through this connection implementation in `direction`, in bytes.
+    ///
+    /// Not every implementation has to support this - the default implementation fails with [`Unsupported`](std::io::ErrorKind::Unsupported).
+    fn socket_buffer_size(&mut self, direction: Direction) -> Result<usize> {
+        let _ = direction;
+        Err(Error::Io(std::io::ErrorKind::Unsupported.into()))
+    }
+
+    /// Sets the size of the kernel's buffer for data travelling through this connection implementation in `direction` to `size` bytes.
+    ///
+    /// Not every implementation has to support this - the default implementation fails with [`Unsupported`](std::io::ErrorKind::Unsupported).
+    fn set_socket_buffer_size(&mut self, direction: Direction, size: usize) -> Result<()> {
+        let _ = (direction, size);
+        Err(Error::Io(std::io::ErrorKind::Unsupported.into()))
+    }
+
+    /// Gets the credentials of the process on the other side of this connection implementation.
+    ///
+    /// Not every implementation has to support this - the default implementation fails with [`Unsupported`](std::io::ErrorKind::Unsupported).
+    fn peer_credentials(&mut self) -> Result<PeerCredentials> {
+        Err(Error::Io(std::io::ErrorKind::Unsupported.into()))
+    }
 }
 
 #[async_trait]
@@ -205,6 +2569,67 @@ impl ConnectionImpl for LocalSocketStream {
     async fn close(&mut self) {
         // Once again, do nothing
     }
+
+    fn transport(&self) -> &'static str {
+        "local socket"
+    }
+
+    #[cfg(unix)]
+    fn unsent_bytes(&mut self) -> Result<usize> {
+        use std::os::unix::io::AsRawFd;
+        Ok(super::interprocess::unsent_bytes(self.as_raw_fd())?)
+    }
+
+    #[cfg(unix)]
+    fn socket_buffer_size(&mut self, direction: Direction) -> Result<usize> {
+        use std::os::unix::io::AsRawFd;
+        Ok(super::interprocess::socket_buffer_size(
+            self.as_raw_fd(),
+            direction,
+        )?)
+    }
+
+    #[cfg(unix)]
+    fn set_socket_buffer_size(&mut self, direction: Direction, size: usize) -> Result<()> {
+        use std::os::unix::io::AsRawFd;
+        Ok(super::interprocess::set_socket_buffer_size(
+            self.as_raw_fd(),
+            direction,
+            size,
+        )?)
+    }
+
+    #[cfg(unix)]
+    fn peer_credentials(&mut self) -> Result<PeerCredentials> {
+        use std::os::unix::io::AsRawFd;
+        Ok(super::interprocess::peer_credentials(self.as_raw_fd())?)
+    }
+}
+
+#[cfg(windows)]
+#[cfg_attr(docsrs, doc(cfg(windows)))]
+#[async_trait]
+impl ConnectionImpl for DuplexBytePipeStream {
+    async fn close(&mut self) {
+        // The pipe is disconnected when it's dropped
+    }
+
+    fn transport(&self) -> &'static str {
+        "named pipe"
+    }
+}
+
+#[cfg(windows)]
+#[cfg_attr(docsrs, doc(cfg(windows)))]
+#[async_trait]
+impl ConnectionImpl for tokio_util::compat::Compat<NamedPipeServer> {
+    async fn close(&mut self) {
+        // The pipe is disconnected when it's dropped
+    }
+
+    fn transport(&self) -> &'static str {
+        "named pipe"
+    }
 }
 
 impl From<LocalSocketStream> for Connection {
@@ -212,3 +2637,110 @@ impl From<LocalSocketStream> for Connection {
         Connection::new(Box::new(value))
     }
 }
+
+/// Tokio's Unix streams implement Tokio's own I/O traits rather than the [`futures_io`] ones gipc uses,
+/// so they have to be wrapped in a [`Compat`](tokio_util::compat::Compat) adapter to be used as a [`ConnectionImpl`].
+/// Converting a [`UnixStream`](tokio::net::UnixStream) into a [`Connection`] does this automatically.
+#[cfg(unix)]
+#[cfg_attr(docsrs, doc(cfg(unix)))]
+#[async_trait]
+impl ConnectionImpl for tokio_util::compat::Compat<tokio::net::UnixStream> {
+    async fn close(&mut self) {
+        // The stream is shut down when it's dropped
+    }
+
+    fn transport(&self) -> &'static str {
+        "unix socket"
+    }
+
+    fn unsent_bytes(&mut self) -> Result<usize> {
+        use std::os::unix::io::AsRawFd;
+        Ok(super::interprocess::unsent_bytes(self.get_ref().as_raw_fd())?)
+    }
+
+    fn socket_buffer_size(&mut self, direction: Direction) -> Result<usize> {
+        use std::os::unix::io::AsRawFd;
+        Ok(super::interprocess::socket_buffer_size(
+            self.get_ref().as_raw_fd(),
+            direction,
+        )?)
+    }
+
+    fn set_socket_buffer_size(&mut self, direction: Direction, size: usize) -> Result<()> {
+        use std::os::unix::io::AsRawFd;
+        Ok(super::interprocess::set_socket_buffer_size(
+            self.get_ref().as_raw_fd(),
+            direction,
+            size,
+        )?)
+    }
+
+    fn peer_credentials(&mut self) -> Result<PeerCredentials> {
+        use std::os::unix::io::AsRawFd;
+        Ok(super::interprocess::peer_credentials(
+            self.get_ref().as_raw_fd(),
+        )?)
+    }
+}
+
+#[async_trait]
+impl ConnectionImpl for tokio_util::compat::Compat<tokio::io::DuplexStream> {
+    async fn close(&mut self) {
+        // The other side sees the stream end when it's dropped
+    }
+
+    fn transport(&self) -> &'static str {
+        "in-memory"
+    }
+}
+
+/// Takes the place of the connection implementation of a connection that is being closed in the background.
+struct Detached;
+
+impl AsyncRead for Detached {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        _: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Poll::Ready(Ok(0))
+    }
+}
+
+impl AsyncWrite for Detached {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Poll::Ready(Ok(buf.len()))
+    }
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[async_trait]
+impl ConnectionImpl for Detached {
+    async fn close(&mut self) {}
+}
+
+/// Converting a [`DuplexStream`](tokio::io::DuplexStream) into a [`Connection`] wraps it in a [`Compat`](tokio_util::compat::Compat) adapter, like for Unix streams.
+impl From<tokio::io::DuplexStream> for Connection {
+    fn from(value: tokio::io::DuplexStream) -> Self {
+        use tokio_util::compat::TokioAsyncReadCompatExt;
+        Connection::new(Box::new(value.compat()))
+    }
+}
+
+#[cfg(unix)]
+#[cfg_attr(docsrs, doc(cfg(unix)))]
+impl From<tokio::net::UnixStream> for Connection {
+    fn from(value: tokio::net::UnixStream) -> Self {
+        use tokio_util::compat::TokioAsyncReadCompatExt;
+        Connection::new(Box::new(value.compat()))
+    }
+}
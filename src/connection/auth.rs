@@ -0,0 +1,37 @@
+//! The shared-secret handshake used by `Connection::authenticate` and `Connection::authenticate_peer`.
+//!
+//! The side that checks the other sends a random nonce, the side being checked answers with `HMAC-SHA256(secret, nonce)`,
+//! and the checking side answers with whether that matched before closing the connection if it didn't.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::Duration;
+
+/// The length of the nonces sent by the checking side.
+const NONCE_LEN: usize = 32;
+
+/// How long `Listener::accept` waits for a client to answer the handshake before giving up on it.
+pub(crate) const LISTENER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Creates a new random nonce.
+pub(crate) fn nonce() -> std::io::Result<[u8; NONCE_LEN]> {
+    let mut nonce = [0; NONCE_LEN];
+    getrandom::getrandom(&mut nonce).map_err(std::io::Error::from)?;
+    Ok(nonce)
+}
+
+fn mac(secret: &[u8], nonce: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(nonce);
+    mac
+}
+
+/// Gets the answer to `nonce` for `secret`.
+pub(crate) fn respond(secret: &[u8], nonce: &[u8]) -> Vec<u8> {
+    mac(secret, nonce).finalize().into_bytes().to_vec()
+}
+
+/// Checks whether `response` is the answer to `nonce` for `secret`, in constant time.
+pub(crate) fn verify(secret: &[u8], nonce: &[u8], response: &[u8]) -> bool {
+    mac(secret, nonce).verify_slice(response).is_ok()
+}
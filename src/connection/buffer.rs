@@ -0,0 +1,407 @@
+//! Buffering of bytes that have been read from a connection but not yet consumed as part of a message.
+
+use crate::message::{claimed_length, declared_length, frame_length};
+use crate::{Error, Result};
+#[cfg(feature = "async-tokio")]
+use futures_io::{AsyncRead, AsyncWrite};
+use std::io;
+#[cfg(feature = "sync")]
+use std::io::{Read, Write};
+use std::mem::size_of;
+#[cfg(feature = "async-tokio")]
+use std::pin::Pin;
+#[cfg(feature = "async-tokio")]
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+/// The most that is read from the stream at once whilst reading a frame.
+const FRAME_READ_SIZE: usize = 64 * 1024;
+/// The most that is reserved in the buffer up front for the rest of a frame.
+/// Larger frames make the buffer grow as their bytes arrive, so a length prefix alone can't make it allocate more than this.
+const MAX_FRAME_RESERVATION: usize = 1024 * 1024;
+
+/// Gets a waker that does nothing when woken, for polling something once to see whether it is ready without waiting for it.
+#[cfg(feature = "async-tokio")]
+pub(crate) fn noop_waker() -> Waker {
+    const VTABLE: RawWakerVTable = RawWakerVTable::new(|_| RAW, |_| {}, |_| {}, |_| {});
+    const RAW: RawWaker = RawWaker::new(std::ptr::null(), &VTABLE);
+    // SAFETY: none of the functions in the vtable use the data pointer, so it being null is never a problem
+    unsafe { Waker::from_raw(RAW) }
+}
+
+/// A stream that reads from its internal buffer before reading from the stream itself.
+/// Writes are passed through directly.
+pub(crate) struct BufferedStream<S> {
+    inner: S,
+    buffer: Vec<u8>,
+    max_message_size: Option<usize>,
+    written: u64,
+}
+
+impl<S> BufferedStream<S> {
+    /// Creates a new buffered stream with an empty buffer.
+    pub const fn new(inner: S) -> Self {
+        Self {
+            inner,
+            buffer: Vec::new(),
+            max_message_size: None,
+            written: 0,
+        }
+    }
+
+    /// Creates a new buffered stream whose buffer starts out holding `buffer`.
+    #[cfg(all(feature = "sync", feature = "async-tokio"))]
+    pub const fn with_buffer(inner: S, buffer: Vec<u8>) -> Self {
+        Self {
+            inner,
+            buffer,
+            max_message_size: None,
+            written: 0,
+        }
+    }
+
+    /// Splits this buffered stream into the underlying stream and the bytes that are still in the buffer.
+    #[cfg(all(feature = "sync", feature = "async-tokio"))]
+    pub fn into_parts(self) -> (S, Vec<u8>) {
+        (self.inner, self.buffer)
+    }
+
+    /// Gets the underlying stream.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+    /// Gets the underlying stream mutably.
+    /// Reading from it directly skips the buffer, which will desynchronise the stream.
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Sets the largest message that frames may claim to hold, or removes the limit if `max` is `None`.
+    pub fn set_max_message_size(&mut self, max: Option<usize>) {
+        self.max_message_size = max;
+    }
+    /// Gets the largest message that frames may claim to hold, as set by [`set_max_message_size`](Self::set_max_message_size).
+    pub fn max_message_size(&self) -> Option<usize> {
+        self.max_message_size
+    }
+    /// Gets the number of bytes that have been written through this buffered stream, which tells whether a failed write got anywhere.
+    pub fn bytes_written(&self) -> u64 {
+        self.written
+    }
+    /// Makes room in the buffer for the rest of the first frame, if its length is known, up to [`MAX_FRAME_RESERVATION`] bytes.
+    /// Fails with [`Error::ProtocolDesync`] if the frame claims to hold a message larger than the maximum message size or too large to ever be allocated,
+    /// which usually means that the bytes read as its length prefix aren't one.
+    fn reserve_frame(&mut self) -> Result<()> {
+        let Some(claimed) = claimed_length(&self.buffer) else {
+            return Ok(());
+        };
+        let too_large = match self.max_message_size {
+            Some(max) => claimed > max as u64,
+            None => claimed > (isize::MAX as usize - size_of::<u64>()) as u64,
+        };
+        if too_large {
+            return Err(Error::ProtocolDesync(claimed));
+        }
+        if let Some(length) = frame_length(&self.buffer) {
+            let rest = length.saturating_sub(self.buffer.len());
+            self.buffer
+                .try_reserve(rest.min(MAX_FRAME_RESERVATION))
+                .map_err(|_| Error::ProtocolDesync(claimed))?;
+        }
+        Ok(())
+    }
+
+    /// Gets how many bytes of the payload of the first frame have been read and how long the payload is,
+    /// or `None` if its length isn't known yet because its length prefix hasn't been read in its entirety or it is streamed.
+    fn frame_progress(&self) -> Option<(u64, u64)> {
+        let total = declared_length(&self.buffer)?;
+        let read = (self.buffer.len() - size_of::<u64>()) as u64;
+        Some((read.min(total), total))
+    }
+
+    /// Removes the first frame in the buffer if it has been read in its entirety, returning it with its length prefix.
+    /// The frame keeps the buffer's allocation, so only what was read after the frame is copied.
+    pub fn take_frame(&mut self) -> Option<Vec<u8>> {
+        let length = frame_length(&self.buffer)?;
+        if self.buffer.len() < length {
+            return None;
+        }
+        let rest = self.buffer.split_off(length);
+        Some(std::mem::replace(&mut self.buffer, rest))
+    }
+
+    /// Removes the first frame in the buffer that has been read in its entirety and that `predicate` returns `true` for,
+    /// returning it with its length prefix. The frames before it are left in place.
+    /// Like [`take_frame`](Self::take_frame), the first frame isn't copied, but any frame after it is.
+    pub fn take_frame_matching(
+        &mut self,
+        mut predicate: impl FnMut(&[u8]) -> bool,
+    ) -> Option<Vec<u8>> {
+        let mut start = 0;
+        while let Some(length) = frame_length(&self.buffer[start..]) {
+            let end = start + length;
+            if self.buffer.len() < end {
+                return None;
+            }
+            if predicate(&self.buffer[start..end]) {
+                if start == 0 {
+                    return self.take_frame();
+                }
+                return Some(self.buffer.drain(start..end).collect());
+            }
+            start = end;
+        }
+        None
+    }
+
+    /// Checks whether the buffer holds nothing but frames that have been read in their entirety,
+    /// which means that reading from the stream stopped at the boundary between two frames.
+    pub fn at_frame_boundary(&self) -> bool {
+        let mut start = 0;
+        while start < self.buffer.len() {
+            let end =
+                frame_length(&self.buffer[start..]).and_then(|length| start.checked_add(length));
+            match end {
+                Some(end) if end <= self.buffer.len() => start = end,
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /// Puts a frame taken using [`take_frame`](Self::take_frame) back at the front of the buffer.
+    pub fn restore_frame(&mut self, frame: Vec<u8>) {
+        self.buffer.splice(..0, frame);
+    }
+
+    /// Grows the buffer by however many bytes should be read next when reading a frame,
+    /// returning the offset the new bytes start at.
+    fn grow_for_frame(&mut self) -> usize {
+        let start = self.buffer.len();
+        let wanted = match frame_length(&self.buffer) {
+            Some(length) => (length - start).min(FRAME_READ_SIZE),
+            None => FRAME_READ_SIZE,
+        };
+        self.buffer.resize(start + wanted, 0);
+        start
+    }
+
+    fn read_buffered(&mut self, buf: &mut [u8]) -> usize {
+        let length = buf.len().min(self.buffer.len());
+        buf[..length].copy_from_slice(&self.buffer[..length]);
+        self.buffer.drain(..length);
+        length
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<S> BufferedStream<S>
+where
+    S: Read,
+{
+    /// Reads from the stream until the buffer contains an entire frame, then removes it and returns it with its length prefix.
+    ///
+    /// Everything that has been read is kept in the buffer if this fails, for example because a read timed out,
+    /// so calling this again resumes reading the same frame.
+    /// Fails with [`UnexpectedEof`](io::ErrorKind::UnexpectedEof) if the stream ends first,
+    /// and with [`Error::ProtocolDesync`] if the frame claims to be larger than the maximum message size.
+    ///
+    /// Whenever more of the frame has been read, including once it has been read in its entirety, `progress` is called with how many bytes of its payload have been read and how long the payload is.
+    /// It isn't called for streamed frames, as their length isn't known until they have been read.
+    ///
+    /// `before_read` is called with the stream before each read from it, which allows e.g. its timeout to be adjusted to the time that is left;
+    /// if it fails, reading stops with its error.
+    pub fn read_frame(
+        &mut self,
+        mut before_read: impl FnMut(&mut S) -> Result<()>,
+        mut progress: impl FnMut(u64, u64),
+    ) -> Result<Vec<u8>> {
+        loop {
+            self.reserve_frame()?;
+            if let Some((read, total)) = self.frame_progress() {
+                progress(read, total);
+            }
+            if let Some(frame) = self.take_frame() {
+                return Ok(frame);
+            }
+            before_read(&mut self.inner)?;
+            let start = self.grow_for_frame();
+            let result = self.inner.read(&mut self.buffer[start..]);
+            self.buffer
+                .truncate(start + result.as_ref().map_or(0, |read| *read));
+            match result {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                Ok(_) => {}
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+
+    /// Reads everything that can be read from the stream into the buffer, returning whether the end of the stream was reached.
+    /// The stream is expected to be non-blocking - this function stops at the first [`WouldBlock`](io::ErrorKind::WouldBlock) or end of stream.
+    pub fn fill_available(&mut self) -> io::Result<bool> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            match self.inner.read(&mut chunk) {
+                Ok(0) => return Ok(true),
+                Ok(read) => self.buffer.extend_from_slice(&chunk[..read]),
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<S> Read for BufferedStream<S>
+where
+    S: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffer.is_empty() {
+            return self.inner.read(buf);
+        }
+        Ok(self.read_buffered(buf))
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<S> Write for BufferedStream<S>
+where
+    S: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        let written = self.inner.write_vectored(bufs)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(feature = "async-tokio")]
+impl<S> BufferedStream<S>
+where
+    S: AsyncRead + Unpin,
+{
+    /// Reads from the stream asynchronously until the buffer contains an entire frame, then removes it and returns it with its length prefix.
+    ///
+    /// Everything that has been read is kept in the buffer if this fails or the future is dropped before completing,
+    /// for example because a deadline passed, so calling this again resumes reading the same frame.
+    /// Fails with [`UnexpectedEof`](io::ErrorKind::UnexpectedEof) if the stream ends first,
+    /// and with [`Error::ProtocolDesync`] if the frame claims to be larger than the maximum message size.
+    ///
+    /// Whenever more of the frame has been read, including once it has been read in its entirety, `progress` is called with how many bytes of its payload have been read and how long the payload is.
+    /// It isn't called for streamed frames, as their length isn't known until they have been read.
+    pub async fn read_frame_async(
+        &mut self,
+        mut progress: impl FnMut(u64, u64),
+    ) -> Result<Vec<u8>> {
+        loop {
+            self.reserve_frame()?;
+            if let Some((read, total)) = self.frame_progress() {
+                progress(read, total);
+            }
+            if let Some(frame) = self.take_frame() {
+                return Ok(frame);
+            }
+            // the buffer is grown and shrunk again within a single poll, so the future can be dropped at any point
+            let read = std::future::poll_fn(|cx| {
+                let start = self.grow_for_frame();
+                let result = Pin::new(&mut self.inner).poll_read(cx, &mut self.buffer[start..]);
+                let read = match &result {
+                    Poll::Ready(Ok(read)) => *read,
+                    _ => 0,
+                };
+                self.buffer.truncate(start + read);
+                result
+            })
+            .await;
+            match read {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                Ok(_) => {}
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+
+    /// Reads everything that can be read from the stream without waiting into the buffer, returning whether the end of the stream was reached.
+    /// This function stops as soon as the stream would have to wait for more data, or at the end of the stream.
+    pub fn fill_available_async(&mut self) -> io::Result<bool> {
+        let waker = noop_waker();
+        let mut context = Context::from_waker(&waker);
+        let mut chunk = [0u8; 4096];
+        loop {
+            match Pin::new(&mut self.inner).poll_read(&mut context, &mut chunk) {
+                Poll::Ready(Ok(0)) => return Ok(true),
+                Poll::Pending => return Ok(false),
+                Poll::Ready(Ok(read)) => self.buffer.extend_from_slice(&chunk[..read]),
+                Poll::Ready(Err(error)) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Poll::Ready(Err(error)) => return Err(error),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async-tokio")]
+impl<S> AsyncRead for BufferedStream<S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.buffer.is_empty() {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+        Poll::Ready(Ok(this.read_buffered(buf)))
+    }
+}
+
+#[cfg(feature = "async-tokio")]
+impl<S> AsyncWrite for BufferedStream<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            this.written += written as u64;
+        }
+        result
+    }
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(written)) = result {
+            this.written += written as u64;
+        }
+        result
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_close(cx)
+    }
+}
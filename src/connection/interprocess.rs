@@ -1,33 +1,550 @@
+use crate::message::fnv1a;
+use crate::{Error, Result};
+use std::fmt::{self, Display, Formatter};
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+/// The name a socket actually resolved to.
+/// Which kind of name is used depends on what the platform supports.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ResolvedName {
+    /// A namespaced name, without the leading `@`.
+    Namespaced(String),
+    /// A path on the filesystem.
+    Path(PathBuf),
+}
+
+impl Display for ResolvedName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolvedName::Namespaced(name) => write!(f, "@{}", name),
+            ResolvedName::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Which kinds of socket names the platform supports, as returned by [`name_support`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum NameSupport {
+    /// Only paths on the filesystem are supported, as on macOS and the BSDs.
+    OnlyPaths,
+    /// Only namespaced names are supported, as on Windows.
+    OnlyNamespaced,
+    /// Both paths and namespaced names are supported, as on Linux.
+    Both,
+}
+
+impl NameSupport {
+    /// Checks whether paths on the filesystem are supported.
+    pub const fn paths(self) -> bool {
+        matches!(self, NameSupport::OnlyPaths | NameSupport::Both)
+    }
+    /// Checks whether namespaced names are supported.
+    pub const fn namespaced(self) -> bool {
+        matches!(self, NameSupport::OnlyNamespaced | NameSupport::Both)
+    }
+}
+
+/// Gets which kinds of socket names the platform supports.
+///
+/// Socket names are resolved to namespaced names wherever they are supported, unless [`NameScheme::Paths`] is [set](set_name_scheme),
+/// and to paths in the [runtime directory](runtime_dir) otherwise - so on platforms with [`NameSupport::OnlyPaths`], connecting to or listening as a socket
+/// fails with [`Error::NoRuntimeDir`] if no runtime directory can be found.
+pub fn name_support() -> NameSupport {
+    use interprocess::local_socket::NameTypeSupport;
+    match NameTypeSupport::query() {
+        NameTypeSupport::OnlyPaths => NameSupport::OnlyPaths,
+        NameTypeSupport::OnlyNamespaced => NameSupport::OnlyNamespaced,
+        NameTypeSupport::Both => NameSupport::Both,
+    }
+}
+
+/// The maximum length of a resolved name, excluding the leading `@` of namespaced names.
+#[cfg(target_os = "linux")]
+const MAX_NAME_LENGTH: usize = 107;
+#[cfg(all(unix, not(target_os = "linux")))]
+const MAX_NAME_LENGTH: usize = 103;
+#[cfg(not(unix))]
+const MAX_NAME_LENGTH: usize = 247;
+
+impl ResolvedName {
+    fn len(&self) -> usize {
+        match self {
+            ResolvedName::Namespaced(name) => name.len(),
+            ResolvedName::Path(path) => path.as_os_str().len(),
+        }
+    }
+}
+
+/// The directory set using [`set_runtime_dir`].
+static RUNTIME_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// Sets the directory that non-global sockets are created in on platforms that only support socket paths,
+/// or goes back to the default if `dir` is `None`.
+///
+/// By default, the user's runtime directory is used, falling back to their local data directory
+/// (see [`dirs::runtime_dir`] and [`dirs::data_local_dir`]).
+/// Setting a directory is useful in environments where neither can be found, such as minimal containers.
+///
+/// This affects every listener and connection created afterwards, so both sides of a connection have to agree on it.
+/// Platforms that support namespaced sockets, such as Linux and Windows, don't use this directory.
+pub fn set_runtime_dir(dir: Option<PathBuf>) {
+    *RUNTIME_DIR
+        .write()
+        .unwrap_or_else(|error| error.into_inner()) = dir;
+}
+
+/// Gets the directory that non-global sockets are created in on platforms that only support socket paths.
+/// This is the directory set using [`set_runtime_dir`] if there is one, and otherwise the default.
+/// Fails with [`Error::NoRuntimeDir`] if no directory is set and none of the defaults can be found.
+pub fn runtime_dir() -> Result<PathBuf> {
+    let set = RUNTIME_DIR
+        .read()
+        .unwrap_or_else(|error| error.into_inner())
+        .clone();
+    first_runtime_dir(set, [dirs::runtime_dir, dirs::data_local_dir])
+}
+
+/// Picks the runtime directory out of the one that is `set` and the `defaults` to fall back to, in order.
+fn first_runtime_dir(
+    set: Option<PathBuf>,
+    defaults: [fn() -> Option<PathBuf>; 2],
+) -> Result<PathBuf> {
+    set.or_else(|| defaults.into_iter().find_map(|default| default()))
+        .ok_or(Error::NoRuntimeDir)
+}
+
+/// The suffix set using [`set_socket_suffix`].
+static SOCKET_SUFFIX: RwLock<Option<String>> = RwLock::new(None);
+
+/// Sets the suffix that is appended to the names of sockets to get their actual name,
+/// or goes back to the default if `suffix` is `None`.
+///
+/// By default, namespaced names end in `-gipc.sock` and paths end in `.sock`.
+/// A suffix set using this function is used for both, which allows connecting to and listening as sockets of programs that don't use gipc,
+/// for example with an empty suffix to use names exactly as they are given.
+///
+/// This affects every listener and connection created afterwards, so both sides of a connection have to agree on it.
+pub fn set_socket_suffix(suffix: Option<String>) {
+    *SOCKET_SUFFIX
+        .write()
+        .unwrap_or_else(|error| error.into_inner()) = suffix;
+}
+
+/// Which kind of name socket names are resolved to, as set using [`set_name_scheme`].
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub enum NameScheme {
+    /// Use namespaced names where the platform supports them, and paths otherwise.
+    /// This means that the same name resolves to a namespaced name on Linux, but to a path on macOS and the BSDs.
+    #[default]
+    Auto,
+    /// Always use paths, even where namespaced names are supported,
+    /// so that a name resolves to a path in the same way on every Unix platform.
+    /// Windows doesn't support paths, so this has no effect there.
+    Paths,
+}
+
+/// The scheme set using [`set_name_scheme`].
+static NAME_SCHEME: RwLock<NameScheme> = RwLock::new(NameScheme::Auto);
+
+/// Sets which kind of name socket names are resolved to.
+///
+/// With [`NameScheme::Paths`], non-global sockets are created in the [runtime directory](runtime_dir) and global sockets in `/run`,
+/// named after the name they were given followed by the [suffix](set_socket_suffix), which is `.sock` by default.
+/// Names that would be too long are replaced by a hash of themselves, in the same way on every platform.
+///
+/// This affects every listener and connection created afterwards, so both sides of a connection have to agree on it.
+pub fn set_name_scheme(scheme: NameScheme) {
+    *NAME_SCHEME
+        .write()
+        .unwrap_or_else(|error| error.into_inner()) = scheme;
+}
+
+/// Generates a name that no other socket should be using, starting with `prefix`.
+pub(crate) fn unique_name(prefix: &str) -> String {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::{SystemTime, UNIX_EPOCH};
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as u64);
+    format!(
+        "{}-{}-{:x}-{}",
+        prefix,
+        std::process::id(),
+        time,
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Hashes `name` into a short identifier using 64-bit FNV-1a.
+/// This has to stay the same across versions, as both sides of a connection have to agree on it.
+fn hash_name(name: &str) -> String {
+    format!("gipc-{:016x}", fnv1a(name.as_bytes()))
+}
+
+/// Resolves `name` to the actual name of the socket, depending on what the platform supports.
+///
+/// If the resolved name would be longer than the platform allows, `name` is replaced by a deterministic hash of itself,
+/// so that the same `name` keeps resolving to the same socket.
+/// Fails with [`Error::NoRuntimeDir`] if the name has to be a path and no [runtime directory](runtime_dir) can be found,
+/// and with [`Error::NameTooLong`] if even the hashed name is too long, because the runtime directory or suffix is.
+pub(crate) fn resolve_name(name: &str, global: bool) -> Result<ResolvedName> {
+    let resolved = resolve_name_exact(name, global)?;
+    if resolved.len() <= MAX_NAME_LENGTH {
+        return Ok(resolved);
+    }
+    let hashed = resolve_name_exact(&hash_name(name), global)?;
+    if hashed.len() > MAX_NAME_LENGTH {
+        return Err(Error::NameTooLong(hashed.to_string()));
+    }
+    Ok(hashed)
+}
+
+fn resolve_name_exact(name: &str, global: bool) -> Result<ResolvedName> {
+    let suffix = SOCKET_SUFFIX
+        .read()
+        .unwrap_or_else(|error| error.into_inner())
+        .clone();
+    let with_suffix = |default: &str| format!("{}{}", name, suffix.as_deref().unwrap_or(default));
+    let scheme = *NAME_SCHEME
+        .read()
+        .unwrap_or_else(|error| error.into_inner());
+    let support = match scheme {
+        NameScheme::Paths if cfg!(unix) => NameSupport::OnlyPaths,
+        _ => name_support(),
+    };
+    Ok(match support {
+        NameSupport::Both | NameSupport::OnlyNamespaced => {
+            ResolvedName::Namespaced(with_suffix("-gipc.sock"))
+        }
+        NameSupport::OnlyPaths => {
+            let path = if global {
+                #[cfg(not(target_family = "unix"))]
+                panic!("Non-Linux operating systems do not support global named sockets.");
+                #[cfg(target_family = "unix")]
+                PathBuf::from("/run").join(with_suffix(".sock"))
+            } else {
+                runtime_dir()?.join(with_suffix(".sock"))
+            };
+            ResolvedName::Path(path)
+        }
+    })
+}
+
+/// Binds a listener using `bind`, which is told whether to bind a global socket,
+/// binding a non-global socket instead if `global` and `fallback_to_user` are both `true` and the process isn't permitted to create the global one.
+/// Returns the listener along with whether it fell back to a non-global socket.
+pub(crate) fn bind_with_user_fallback<L>(
+    global: bool,
+    fallback_to_user: bool,
+    mut bind: impl FnMut(bool) -> Result<L>,
+) -> Result<(L, bool)> {
+    match bind(global) {
+        Err(Error::Bind { source, .. })
+            if global
+                && fallback_to_user
+                && source.kind() == std::io::ErrorKind::PermissionDenied =>
+        {
+            Ok((bind(false)?, true))
+        }
+        result => result.map(|listener| (listener, false)),
+    }
+}
+
+/// Resolves a name using [`resolve_name`] and calls `$func` with it,
+/// evaluating to a tuple of the resolved name and the result of `$func`.
+/// Returns early from the surrounding function if the name can't be resolved.
 macro_rules! name_onto {
     (await $func:path; $name:expr, $global:expr) => {
         name_onto!($func, ., await; $name, $global)
     };
     ($func:path $(, $suffix:tt )*; $name:expr, $global:expr) => {{
-        use interprocess::local_socket::NameTypeSupport::{self, *};
-        use std::path::PathBuf;
-        let name = $name.as_ref();
-        let global = $global;
-        match NameTypeSupport::query() {
-            Both | OnlyNamespaced => {
-                $func(format!("@{}-gipc.sock", name))$( $suffix )*
-            }
-            OnlyPaths => {
-                let path = if global {
-                    #[cfg(not(target_family = "unix"))]
-                    panic!("Non-Linux operating systems do not support global named sockets.");
-                    #[cfg(target_family = "unix")]
-                    PathBuf::from(
-                        format!("/run/{}.sock", name)
-                    )
-                } else {
-                    dirs::runtime_dir()
-                        .or(dirs::data_local_dir())
-                        .map(|v| v.join(format!("{}.sock", name)))
-                        .expect("No valid path can be used")
-                };
-                $func(path)$( $suffix )*
-            }
-        }
+        use $crate::connection::interprocess::{resolve_name, ResolvedName};
+        let resolved = resolve_name($name.as_ref(), $global)?;
+        let result = match &resolved {
+            ResolvedName::Namespaced(name) => $func(format!("@{}", name))$( $suffix )*,
+            ResolvedName::Path(path) => $func(path.clone())$( $suffix )*,
+        };
+        (resolved, result)
     }};
 }
 pub(crate) use name_onto;
+
+/// Gets the path that `listener` is bound to, if it is bound to one.
+#[cfg(unix)]
+pub(crate) fn std_listener_name(
+    listener: &std::os::unix::net::UnixListener,
+) -> Option<ResolvedName> {
+    let address = listener.local_addr().ok()?;
+    address
+        .as_pathname()
+        .map(|path| ResolvedName::Path(path.to_owned()))
+}
+
+/// Gets the number of bytes that have been written to the socket `fd` but haven't been sent yet.
+/// For local sockets this generally means bytes that the other side hasn't read yet.
+///
+/// Only supported on Linux, Android, macOS and iOS - fails with [`Unsupported`](std::io::ErrorKind::Unsupported) elsewhere.
+#[cfg(unix)]
+pub(crate) fn unsent_bytes(fd: std::os::unix::io::RawFd) -> std::io::Result<usize> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let mut count: libc::c_int = 0;
+        // SAFETY: TIOCOUTQ writes a single int to the pointer it's given
+        if unsafe { libc::ioctl(fd, libc::TIOCOUTQ, &mut count) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(count as usize)
+    }
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    {
+        let mut count: libc::c_int = 0;
+        let mut length = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // SAFETY: SO_NWRITE writes a single int to the pointer it's given, and `length` is the size of that int
+        let result = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_NWRITE,
+                &mut count as *mut libc::c_int as *mut libc::c_void,
+                &mut length,
+            )
+        };
+        if result < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(count as usize)
+    }
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios"
+    )))]
+    {
+        let _ = fd;
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Gets the socket option that holds the size of the socket's kernel buffer for data travelling in `direction`.
+#[cfg(unix)]
+const fn buffer_size_option(direction: super::Direction) -> libc::c_int {
+    match direction {
+        super::Direction::Sent => libc::SO_SNDBUF,
+        super::Direction::Received => libc::SO_RCVBUF,
+    }
+}
+
+/// Gets the size of the socket `fd`'s kernel buffer for data travelling in `direction`, in bytes.
+#[cfg(unix)]
+pub(crate) fn socket_buffer_size(
+    fd: std::os::unix::io::RawFd,
+    direction: super::Direction,
+) -> std::io::Result<usize> {
+    let mut size: libc::c_int = 0;
+    let mut length = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: SO_SNDBUF and SO_RCVBUF write a single int to the pointer they're given, and `length` is the size of that int
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            buffer_size_option(direction),
+            &mut size as *mut libc::c_int as *mut libc::c_void,
+            &mut length,
+        )
+    };
+    if result < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(size as usize)
+}
+
+/// Sets the size of the socket `fd`'s kernel buffer for data travelling in `direction` to `size` bytes.
+/// Fails with [`InvalidInput`](std::io::ErrorKind::InvalidInput) if `size` doesn't fit in a C `int`.
+#[cfg(unix)]
+pub(crate) fn set_socket_buffer_size(
+    fd: std::os::unix::io::RawFd,
+    direction: super::Direction,
+    size: usize,
+) -> std::io::Result<()> {
+    let size = libc::c_int::try_from(size).map_err(|_| std::io::ErrorKind::InvalidInput)?;
+    // SAFETY: SO_SNDBUF and SO_RCVBUF read a single int from the pointer they're given, and the length is the size of that int
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            buffer_size_option(direction),
+            &size as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Gets the credentials of the process on the other side of the socket `fd`.
+///
+/// Only supported on Linux, Android, macOS, iOS and the BSDs - fails with [`Unsupported`](std::io::ErrorKind::Unsupported) elsewhere.
+/// The process ID is only available on Linux and Android.
+#[cfg(unix)]
+pub(crate) fn peer_credentials(
+    fd: std::os::unix::io::RawFd,
+) -> std::io::Result<super::PeerCredentials> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let mut credentials = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        // SAFETY: SO_PEERCRED writes a single ucred to the pointer it's given, and `length` is the size of that ucred
+        let result = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut credentials as *mut libc::ucred as *mut libc::c_void,
+                &mut length,
+            )
+        };
+        if result < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(super::PeerCredentials {
+            pid: Some(credentials.pid as u32),
+            uid: credentials.uid,
+            gid: credentials.gid,
+        })
+    }
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd",
+        target_os = "dragonfly"
+    ))]
+    {
+        let mut uid = 0;
+        let mut gid = 0;
+        // SAFETY: getpeereid only writes to the two IDs it's given
+        if unsafe { libc::getpeereid(fd, &mut uid, &mut gid) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(super::PeerCredentials {
+            pid: None,
+            uid,
+            gid,
+        })
+    }
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd",
+        target_os = "dragonfly"
+    )))]
+    {
+        let _ = fd;
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn runtime_dir_falls_back_in_order() {
+        let missing = || None;
+        let data = || Some(PathBuf::from("/data"));
+        let runtime = || Some(PathBuf::from("/runtime"));
+        // with no directory set and none of the defaults found, this fails instead of panicking
+        assert!(matches!(
+            first_runtime_dir(None, [missing, missing]),
+            Err(Error::NoRuntimeDir)
+        ));
+        assert_eq!(
+            first_runtime_dir(None, [missing, data]).unwrap(),
+            PathBuf::from("/data")
+        );
+        assert_eq!(
+            first_runtime_dir(None, [runtime, data]).unwrap(),
+            PathBuf::from("/runtime")
+        );
+        assert_eq!(
+            first_runtime_dir(Some(PathBuf::from("/set")), [missing, missing]).unwrap(),
+            PathBuf::from("/set")
+        );
+    }
+
+    #[test]
+    fn name_support_matches_the_platform() {
+        let support = name_support();
+        assert!(support.paths() || support.namespaced());
+        assert_eq!(
+            support == NameSupport::Both,
+            support.paths() && support.namespaced()
+        );
+        #[cfg(target_os = "linux")]
+        assert_eq!(support, NameSupport::Both);
+        #[cfg(target_os = "macos")]
+        assert_eq!(support, NameSupport::OnlyPaths);
+        #[cfg(windows)]
+        assert_eq!(support, NameSupport::OnlyNamespaced);
+    }
+
+    #[test]
+    fn hashed_names_stay_the_same() {
+        // both sides of a connection have to hash a name the same way, whichever version of gipc they use
+        assert_eq!(hash_name("a socket name"), "gipc-d61ea35fd3ead0cc");
+    }
+
+    /// Simulates binding a socket, failing to bind a global one with an error of `kind`.
+    fn bind_failing_global(kind: ErrorKind) -> impl FnMut(bool) -> Result<&'static str> {
+        move |global| match global {
+            true => Err(Error::Bind {
+                name: String::from("/run/test.sock"),
+                source: kind.into(),
+            }),
+            false => Ok("user"),
+        }
+    }
+
+    #[test]
+    fn global_sockets_fall_back_on_permission_errors() {
+        let bind = bind_failing_global(ErrorKind::PermissionDenied);
+        assert_eq!(
+            bind_with_user_fallback(true, true, bind).unwrap(),
+            ("user", true)
+        );
+    }
+
+    #[test]
+    fn global_sockets_only_fall_back_when_asked_to() {
+        let bind = bind_failing_global(ErrorKind::PermissionDenied);
+        assert!(matches!(
+            bind_with_user_fallback(true, false, bind),
+            Err(Error::Bind { .. })
+        ));
+        let bind = bind_failing_global(ErrorKind::AddrInUse);
+        assert!(matches!(
+            bind_with_user_fallback(true, true, bind),
+            Err(Error::Bind { .. })
+        ));
+        let bind = bind_failing_global(ErrorKind::PermissionDenied);
+        assert_eq!(
+            bind_with_user_fallback(false, true, bind).unwrap(),
+            ("user", false)
+        );
+    }
+}
@@ -6,18 +6,483 @@
 //! ## Listeners
 //! Listeners allow programs to accept [connections](#connections) from other programs.
 
+mod buffer;
 pub(crate) mod interprocess;
+#[cfg(all(windows, any(feature = "sync", feature = "async-tokio")))]
+mod pipe;
+#[cfg(all(windows, any(feature = "sync", feature = "async-tokio")))]
+#[cfg_attr(docsrs, doc(cfg(windows)))]
+pub use pipe::PipeOptions;
+
+pub use interprocess::{
+    name_support, runtime_dir, set_name_scheme, set_runtime_dir, set_socket_suffix, NameScheme,
+    NameSupport, ResolvedName,
+};
+
+/// The direction a message travelled through a connection in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// The message was sent through the connection.
+    Sent,
+    /// The message was received through the connection.
+    Received,
+}
+
+/// The lane a message was sent on, as reported by `Connection::receive_any`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Lane {
+    /// The message was sent normally, e.g. using `Connection::send`.
+    Normal,
+    /// The message was sent using `Connection::send_priority`.
+    Priority,
+}
+
+/// The outcome of receiving from a connection using `Connection::receive_outcome`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ReceiveOutcome<T> {
+    /// A message was received.
+    Data(T),
+    /// The other side closed the connection, so there are no more messages to receive.
+    Closed,
+}
+
+/// When a message was sent, as reported by `Connection::receive_with_timestamp`.
+///
+/// On Unix, this is read from the system-wide monotonic clock, so instants from different processes on the same machine can be compared with each other.
+/// Elsewhere the system clock is used instead, which can jump if it's changed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SendInstant(u64);
+
+impl SendInstant {
+    /// Gets the current instant.
+    pub fn now() -> Self {
+        #[cfg(unix)]
+        {
+            let mut time = libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            };
+            // SAFETY: `time` is a valid timespec to write to, and CLOCK_MONOTONIC exists on every Unix we support
+            unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut time) };
+            Self((time.tv_sec as u64) * 1_000_000_000 + time.tv_nsec as u64)
+        }
+        #[cfg(not(unix))]
+        {
+            let since_epoch = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default();
+            Self(since_epoch.as_nanos() as u64)
+        }
+    }
+    /// Creates an instant from a number of nanoseconds, as returned by [`as_nanos`](Self::as_nanos).
+    pub const fn from_nanos(nanos: u64) -> Self {
+        Self(nanos)
+    }
+    /// Gets this instant as a number of nanoseconds since an unspecified point in time, which is the same for every process on the machine.
+    pub const fn as_nanos(&self) -> u64 {
+        self.0
+    }
+
+    /// Gets how much time has passed between `earlier` and this instant, or zero if `earlier` is later.
+    pub fn duration_since(&self, earlier: SendInstant) -> std::time::Duration {
+        std::time::Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+    /// Gets how much time has passed since this instant, e.g. how long ago a message was sent.
+    pub fn elapsed(&self) -> std::time::Duration {
+        Self::now().duration_since(*self)
+    }
+}
+
+/// Why a connection was closed, as reported by `Connection::close_reason`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CloseReason {
+    /// The connection was closed on this side, using `close`.
+    Local,
+    /// The other side closed the connection cleanly.
+    Peer,
+    /// The connection was closed because of an I/O error of the specified kind, e.g. the connection being reset.
+    Error(std::io::ErrorKind),
+    /// The other side went away without closing the connection, for example because its process exited.
+    Dropped,
+    /// The connection was closed on this side because nothing was sent or received through it for longer than its idle timeout.
+    Idle,
+}
+
+impl CloseReason {
+    /// Gets the reason a connection should be closed for after `error` occurred whilst receiving from it,
+    /// if the error means that the other side can't be reached anymore.
+    pub(crate) fn for_error(error: &crate::Error) -> Option<Self> {
+        use std::io::ErrorKind::*;
+        let crate::Error::Io(error) = error else {
+            return None;
+        };
+        match error.kind() {
+            UnexpectedEof => Some(Self::Dropped),
+            kind @ (ConnectionReset | ConnectionAborted | BrokenPipe) => Some(Self::Error(kind)),
+            _ => None,
+        }
+    }
+}
+
+/// A code telling the other side of a connection why it is being closed, as sent by `Connection::close_with_reason`.
+/// The codes are modelled after WebSocket close codes - the constants cover the common ones, but any other code can be used as well.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct CloseCode(pub u16);
+
+impl CloseCode {
+    /// The connection is closing normally, because it has served its purpose.
+    pub const NORMAL: Self = Self(1000);
+    /// The connection is closing because this side is going away, e.g. because its process is shutting down.
+    pub const GOING_AWAY: Self = Self(1001);
+    /// The connection is closing because the other side broke the protocol.
+    pub const PROTOCOL_ERROR: Self = Self(1002);
+    /// The connection is closing because the other side sent a kind of message that this side can't handle.
+    pub const UNSUPPORTED_DATA: Self = Self(1003);
+    /// The connection is closing because the other side sent a message that this side's policy doesn't allow.
+    pub const POLICY_VIOLATION: Self = Self(1008);
+    /// The connection is closing because this side ran into a condition it didn't expect.
+    pub const INTERNAL_ERROR: Self = Self(1011);
+}
+
+/// The credentials of the process on the other side of a connection.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PeerCredentials {
+    /// The ID of the process, if the platform provides it.
+    pub pid: Option<u32>,
+    /// The ID of the user the process is running as.
+    pub uid: u32,
+    /// The ID of the group the process is running as.
+    pub gid: u32,
+}
+
+/// A description of how a connection is set up, as returned by `Connection::describe`.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ConnectionDescription {
+    /// The ID of the connection, as returned by `Connection::id`.
+    pub id: u64,
+    /// The name of the codec the connection currently uses, as given by [`DynCodec::name`](crate::codec::DynCodec::name).
+    pub codec: &'static str,
+    /// The kind of transport the connection runs over, e.g. `"local socket"`, as given by the connection's `ConnectionImpl`.
+    pub transport: &'static str,
+    /// The name of the socket the connection was made through, or `None` if it isn't known, e.g. when the connection was created using `Connection::new`.
+    /// For accepted connections, this is the listener's resolved name.
+    pub endpoint: Option<ResolvedName>,
+}
+
+/// Information about a connection that has just been accepted, as given to the filter set by `Listener::set_accept_filter`.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct AcceptMeta {
+    /// The credentials of the process that connected, or `None` if they aren't available.
+    pub peer_credentials: Option<PeerCredentials>,
+}
+
+/// A function that decides whether a connection is accepted, as set by `Listener::set_accept_filter`.
+pub type AcceptFilter = Box<dyn Fn(&AcceptMeta) -> bool + Send + Sync>;
+
+/// A function that is called before a listener starts accepting a connection, as set by `Listener::set_accept_hook`.
+pub type AcceptHook = Box<dyn FnMut() + Send>;
+
+/// What a listener does when accepting a connection fails with an error that is likely to go away by itself,
+/// such as the process running out of file descriptors.
+/// Other errors are always returned.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AcceptRetryPolicy {
+    /// Return the error.
+    Never,
+    /// Wait for the specified duration, then try accepting again.
+    After(std::time::Duration),
+}
+
+impl AcceptRetryPolicy {
+    pub(crate) const DEFAULT: Self = Self::After(std::time::Duration::from_millis(100));
+}
+
+impl Default for AcceptRetryPolicy {
+    /// Retries after 100 milliseconds.
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// How long to wait between attempts to connect, as used by `Connection::connect_to_socket_backoff`.
+///
+/// The delay starts at `initial_delay` and is multiplied by `multiplier` after every failed attempt, up to `max_delay`.
+/// Each delay is then randomly made up to `jitter` times shorter or longer, so that many clients started at the same time don't all retry at the same time.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BackoffPolicy {
+    /// How long to wait after the first failed attempt.
+    pub initial_delay: std::time::Duration,
+    /// The longest to wait between two attempts, before jitter is applied.
+    pub max_delay: std::time::Duration,
+    /// What the delay is multiplied by after every failed attempt.
+    pub multiplier: f64,
+    /// How much of the delay is randomised, from `0.0` for none to `1.0` for anywhere between no delay and twice the delay.
+    pub jitter: f64,
+    /// How many attempts to make in total, or `None` to keep trying until `timeout` has passed.
+    pub max_attempts: Option<u32>,
+    /// How long to keep trying for, or `None` to keep trying until `max_attempts` attempts have been made.
+    pub timeout: Option<std::time::Duration>,
+}
+
+impl BackoffPolicy {
+    /// Gets how long to wait after `attempt` attempts have failed, including jitter.
+    pub fn delay(&self, attempt: u32) -> std::time::Duration {
+        let exponent = i32::try_from(attempt.saturating_sub(1)).unwrap_or(i32::MAX);
+        let max = self.max_delay.as_secs_f64();
+        let base =
+            (self.initial_delay.as_secs_f64() * self.multiplier.powi(exponent)).clamp(0.0, max);
+        let jitter = self.jitter.clamp(0.0, 1.0) * (random_unit() * 2.0 - 1.0);
+        // a NaN multiplier or jitter can't be turned into a delay, so those just wait for the longest delay
+        std::time::Duration::try_from_secs_f64(base * (1.0 + jitter)).unwrap_or(self.max_delay)
+    }
+
+    /// Gets how long to wait before trying again after `attempt` attempts that started at `started` have failed, the last one with `error`.
+    /// Fails with `error` if no more attempts are allowed, and with [`Error::Timeout`](crate::Error::Timeout) if the timeout has passed.
+    pub(crate) fn retry_delay(
+        &self,
+        attempt: u32,
+        started: std::time::Instant,
+        error: crate::Error,
+    ) -> crate::Result<std::time::Duration> {
+        if matches!(self.max_attempts, Some(max) if attempt >= max) {
+            return Err(error);
+        }
+        let delay = self.delay(attempt);
+        match self.timeout {
+            Some(timeout) => match timeout.checked_sub(started.elapsed()) {
+                Some(remaining) if !remaining.is_zero() => Ok(delay.min(remaining)),
+                _ => Err(crate::Error::Timeout),
+            },
+            None => Ok(delay),
+        }
+    }
+}
+
+impl Default for BackoffPolicy {
+    /// Starts at 10 milliseconds and doubles up to 1 second, with 20% jitter, making at most 10 attempts.
+    fn default() -> Self {
+        Self {
+            initial_delay: std::time::Duration::from_millis(10),
+            max_delay: std::time::Duration::from_secs(1),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: Some(10),
+            timeout: None,
+        }
+    }
+}
+
+/// A limit on how fast a connection sends messages, as set by `Connection::set_rate_limit`.
+///
+/// Each limit is enforced using a token bucket that holds up to one second's worth of sending, so a connection that has been quiet can send a burst of that size straight away.
+/// Once a limit is hit, sending waits until enough time has passed for the message to fit within it.
+/// Rates of zero are treated as one, so that sending is slowed down rather than stopped.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct RateLimit {
+    /// The most messages to send per second, or `None` for no limit on the number of messages.
+    pub messages_per_second: Option<u32>,
+    /// The most bytes of messages to send per second, not counting their length prefixes, or `None` for no limit on the number of bytes.
+    pub bytes_per_second: Option<u64>,
+}
+
+/// The token buckets that enforce a [`RateLimit`].
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    messages: f64,
+    bytes: f64,
+    refilled_at: std::time::Instant,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        let mut limiter = Self {
+            limit,
+            messages: 0.0,
+            bytes: 0.0,
+            refilled_at: std::time::Instant::now(),
+        };
+        limiter.messages = limiter.message_rate().unwrap_or(0.0);
+        limiter.bytes = limiter.byte_rate().unwrap_or(0.0);
+        limiter
+    }
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    fn message_rate(&self) -> Option<f64> {
+        self.limit
+            .messages_per_second
+            .map(|rate| f64::from(rate.max(1)))
+    }
+    fn byte_rate(&self) -> Option<f64> {
+        self.limit.bytes_per_second.map(|rate| rate.max(1) as f64)
+    }
+    fn refill(&mut self) {
+        let now = std::time::Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.refilled_at = now;
+        if let Some(rate) = self.message_rate() {
+            self.messages = (self.messages + elapsed * rate).min(rate);
+        }
+        if let Some(rate) = self.byte_rate() {
+            self.bytes = (self.bytes + elapsed * rate).min(rate);
+        }
+    }
+
+    /// Gets how long to wait before the next message may be sent.
+    /// Since a message's size is only known once it has been serialised, a message that goes over the byte limit is sent anyway
+    /// and the deficit is made up for by the messages after it.
+    pub fn delay(&mut self) -> std::time::Duration {
+        self.refill();
+        let messages = match self.message_rate() {
+            Some(rate) if self.messages < 1.0 => (1.0 - self.messages) / rate,
+            _ => 0.0,
+        };
+        let bytes = match self.byte_rate() {
+            Some(rate) if self.bytes < 0.0 => -self.bytes / rate,
+            _ => 0.0,
+        };
+        std::time::Duration::from_secs_f64(messages.max(bytes))
+    }
+    /// Takes a message of `length` bytes that has just been sent out of the buckets.
+    pub fn record(&mut self, length: usize) {
+        if self.message_rate().is_some() {
+            self.messages -= 1.0;
+        }
+        if self.byte_rate().is_some() {
+            self.bytes -= length as f64;
+        }
+    }
+}
+
+/// When sending and receiving is retried by the connection returned by `Connection::with_retry`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// How long to wait between attempts, and how many attempts to make in total.
+    pub backoff: BackoffPolicy,
+    /// The kinds of I/O errors that are retried. Any other error, including serialisation and protocol errors, is returned straight away.
+    pub transient_kinds: Vec<std::io::ErrorKind>,
+}
+
+impl RetryPolicy {
+    /// Checks whether `error` is one of the I/O errors that this policy retries.
+    pub fn is_transient(&self, error: &crate::Error) -> bool {
+        matches!(error, crate::Error::Io(error) if self.transient_kinds.contains(&error.kind()))
+    }
+}
+
+impl Default for RetryPolicy {
+    /// Retries [`WouldBlock`](std::io::ErrorKind::WouldBlock), [`Interrupted`](std::io::ErrorKind::Interrupted)
+    /// and [`TimedOut`](std::io::ErrorKind::TimedOut) errors using the default [`BackoffPolicy`].
+    fn default() -> Self {
+        use std::io::ErrorKind::*;
+        Self {
+            backoff: BackoffPolicy::default(),
+            transient_kinds: vec![WouldBlock, Interrupted, TimedOut],
+        }
+    }
+}
+
+/// Gets a random number between 0 and 1, using the random keys of the standard library's hasher so that no extra dependency is needed.
+fn random_unit() -> f64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// How an operation on a connection failed without closing it, which the connection keeps track of until `Connection::reset` is called.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Failure {
+    /// Reading or writing failed, e.g. because it timed out, without leaving the other side partway through a frame.
+    /// Receiving may have stopped partway through a frame, but what was read is kept for the next receive to continue.
+    Io,
+    /// Sending failed after part of a frame had been written, so the other side is waiting for the rest of a frame that is never coming.
+    PartialWrite,
+}
+
+/// How long `Connection::connect_to_socket_wait` waits between attempts to connect.
+pub(crate) const CONNECT_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(10);
+
+/// How many accepts in a row `Listener::close_graceful` lets fail before it stops looking for pending connections.
+/// Failed accepts are skipped, but one that keeps failing, e.g. because the process ran out of file descriptors, would otherwise never end the close.
+pub(crate) const MAX_GRACEFUL_ACCEPT_FAILURES: usize = 8;
+
+/// Whether `error`, returned when connecting to a socket, means that nothing was listening on the socket (yet).
+pub(crate) fn is_not_listening(error: &crate::Error) -> bool {
+    use std::io::ErrorKind::*;
+    matches!(
+        error,
+        crate::Error::Connect { source, .. } if matches!(source.kind(), NotFound | ConnectionRefused)
+    )
+}
+
+/// Gets a new connection ID, as returned by `Connection::id`.
+/// IDs are shared between sync and async connections, so they are unique within the process.
+pub(crate) fn next_connection_id() -> u64 {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// A function that observes the serialised form of messages, as set by `Connection::set_observer`.
+pub type Observer = Box<dyn Fn(Direction, &[u8]) + Send + Sync>;
+
+/// A function that is told the type and serialised size of messages, as set by `Connection::set_message_hook`.
+/// It is given the direction the message travelled in, the name of its type as returned by [`std::any::type_name`], and its length in bytes without its length prefix.
+pub type MessageHook = Box<dyn Fn(Direction, &str, usize) + Send + Sync>;
+
+pub mod registry;
+pub use registry::{ConnectionInfo, ConnectionRegistry};
+
+#[cfg(feature = "auth")]
+mod auth;
 
 #[cfg(feature = "sync")]
 #[cfg_attr(docsrs, doc(cfg(feature = "sync")))]
 pub mod sync;
+#[cfg(all(feature = "sync", feature = "type-check"))]
+#[cfg_attr(docsrs, doc(cfg(all(feature = "sync", feature = "type-check"))))]
+pub use sync::TypedConnection;
+#[cfg(feature = "sync")]
+#[cfg_attr(docsrs, doc(cfg(feature = "sync")))]
+pub use sync::{
+    self_test, ConnectHook, Connection, Listener, ReadOnlyConnection, RetryingConnection,
+    VersionedConnection, WriteOnlyConnection,
+};
 #[cfg(feature = "sync")]
 #[cfg_attr(docsrs, doc(cfg(feature = "sync")))]
-pub use sync::{Connection, Listener};
+pub mod topic;
+#[cfg(feature = "sync")]
+#[cfg_attr(docsrs, doc(cfg(feature = "sync")))]
+pub use topic::{Publisher, Subscriber};
 
 #[cfg(feature = "async-tokio")]
 #[cfg_attr(docsrs, doc(cfg(feature = "async-tokio")))]
 pub mod async_tokio;
+#[cfg(all(feature = "async-tokio", feature = "type-check"))]
+#[cfg_attr(docsrs, doc(cfg(all(feature = "async-tokio", feature = "type-check"))))]
+pub use async_tokio::TypedConnection as AsyncTypedConnection;
+#[cfg(feature = "async-tokio")]
+#[cfg_attr(docsrs, doc(cfg(feature = "async-tokio")))]
+pub use async_tokio::{
+    in_memory_mesh, in_memory_pair, self_test as self_test_async, ConnectHook as AsyncConnectHook,
+    Connection as AsyncConnection, Listener as AsyncListener, PauseHandle,
+    ReadOnlyConnection as AsyncReadOnlyConnection, VersionedConnection as AsyncVersionedConnection,
+    WriteOnlyConnection as AsyncWriteOnlyConnection,
+};
+#[cfg(feature = "async-tokio")]
+#[cfg_attr(docsrs, doc(cfg(feature = "async-tokio")))]
+pub mod pool;
 #[cfg(feature = "async-tokio")]
 #[cfg_attr(docsrs, doc(cfg(feature = "async-tokio")))]
-pub use async_tokio::{Connection as AsyncConnection, Listener as AsyncListener};
+pub use pool::{ConnectionPool, PooledConnection};
+#[cfg(feature = "tower")]
+#[cfg_attr(docsrs, doc(cfg(feature = "tower")))]
+pub mod service;
+#[cfg(feature = "tower")]
+#[cfg_attr(docsrs, doc(cfg(feature = "tower")))]
+pub use service::GipcService;
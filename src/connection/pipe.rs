@@ -0,0 +1,193 @@
+//! Windows named pipes created by gipc itself, so that they can be given a security descriptor, which [`interprocess`] doesn't support.
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::AsRawHandle;
+#[cfg(feature = "sync")]
+use std::os::windows::io::{FromRawHandle, OwnedHandle};
+use std::ptr;
+use windows_sys::Win32::Foundation::LocalFree;
+#[cfg(feature = "sync")]
+use windows_sys::Win32::Foundation::{
+    ERROR_NO_DATA, ERROR_PIPE_CONNECTED, ERROR_PIPE_LISTENING, INVALID_HANDLE_VALUE,
+};
+use windows_sys::Win32::Security::Authorization::{
+    ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+};
+use windows_sys::Win32::Security::{PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES};
+#[cfg(feature = "sync")]
+use windows_sys::Win32::Storage::FileSystem::{FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX};
+#[cfg(feature = "sync")]
+use windows_sys::Win32::System::Pipes::{
+    ConnectNamedPipe, CreateNamedPipeW, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE,
+    PIPE_TYPE_MESSAGE, PIPE_UNLIMITED_INSTANCES,
+};
+use windows_sys::Win32::System::Pipes::{
+    SetNamedPipeHandleState, PIPE_NOWAIT, PIPE_READMODE_BYTE, PIPE_WAIT,
+};
+
+/// Options for creating a Windows named pipe to listen on, as used by `Listener::listen_on_pipe`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct PipeOptions {
+    /// The name of the pipe without the `\\.\pipe\` prefix, which is added automatically.
+    pub name: OsString,
+    /// Whether the pipe itself keeps the boundaries between writes, rather than being a plain stream of bytes.
+    /// Connections are always read from in byte mode either way, since gipc's framing already keeps messages apart.
+    pub message_mode: bool,
+    /// The security descriptor that decides who may connect to the pipe, in the [security descriptor definition language](https://learn.microsoft.com/en-us/windows/win32/secauthz/security-descriptor-string-format),
+    /// or `None` for the default descriptor, which lets other users on the machine connect.
+    /// For example, `D:P(A;;GA;;;OW)` only lets the user that owns the pipe connect to it.
+    pub security_descriptor: Option<String>,
+}
+
+/// A security descriptor converted from its string form, which is freed when this is dropped.
+struct SecurityDescriptor(PSECURITY_DESCRIPTOR);
+
+// SAFETY: the descriptor is only read from once it has been created, and it belongs to this alone
+unsafe impl Send for SecurityDescriptor {}
+unsafe impl Sync for SecurityDescriptor {}
+
+impl SecurityDescriptor {
+    /// Converts `sddl` into a security descriptor, failing if it isn't a valid security descriptor string.
+    fn parse(sddl: &str) -> io::Result<Self> {
+        let wide = wide(OsStr::new(sddl));
+        let mut descriptor: PSECURITY_DESCRIPTOR = ptr::null_mut();
+        // SAFETY: `wide` is a nul-terminated string, and `descriptor` is only used if the conversion succeeds
+        let converted = unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                wide.as_ptr(),
+                SDDL_REVISION_1,
+                &mut descriptor,
+                ptr::null_mut(),
+            )
+        };
+        match converted {
+            0 => Err(io::Error::last_os_error()),
+            _ => Ok(Self(descriptor)),
+        }
+    }
+}
+
+impl Drop for SecurityDescriptor {
+    fn drop(&mut self) {
+        // SAFETY: the descriptor was allocated by ConvertStringSecurityDescriptorToSecurityDescriptorW, which has to be freed using LocalFree
+        unsafe {
+            LocalFree(self.0);
+        }
+    }
+}
+
+/// Everything needed to create instances of a named pipe, converted from [`PipeOptions`] once.
+pub(crate) struct PipeConfig {
+    path: OsString,
+    message_mode: bool,
+    descriptor: Option<SecurityDescriptor>,
+}
+
+impl PipeConfig {
+    /// Prepares to create instances of the pipe described by `options`, failing if its security descriptor is invalid.
+    pub fn new(options: &PipeOptions) -> io::Result<Self> {
+        let descriptor = options
+            .security_descriptor
+            .as_deref()
+            .map(SecurityDescriptor::parse)
+            .transpose()?;
+        let mut path = OsString::from(r"\\.\pipe\");
+        path.push(&options.name);
+        Ok(Self {
+            path,
+            message_mode: options.message_mode,
+            descriptor,
+        })
+    }
+    /// Gets the full path of the pipe.
+    #[cfg(feature = "async-tokio")]
+    pub fn path(&self) -> &OsStr {
+        &self.path
+    }
+    /// Gets the security attributes to create instances of the pipe with, which point into this configuration.
+    pub fn security_attributes(&self) -> Option<SECURITY_ATTRIBUTES> {
+        let descriptor = self.descriptor.as_ref()?;
+        Some(SECURITY_ATTRIBUTES {
+            nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+            lpSecurityDescriptor: descriptor.0,
+            bInheritHandle: 0,
+        })
+    }
+    /// Gets whether the pipe keeps the boundaries between writes.
+    #[cfg(feature = "async-tokio")]
+    pub fn message_mode(&self) -> bool {
+        self.message_mode
+    }
+    /// Creates an instance of the pipe for a client to connect to, which has to be the first one if `first` is true.
+    #[cfg(feature = "sync")]
+    pub fn create_instance(&self, first: bool, nonblocking: bool) -> io::Result<OwnedHandle> {
+        let mut open_mode = PIPE_ACCESS_DUPLEX;
+        if first {
+            open_mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+        }
+        let mut pipe_mode = match self.message_mode {
+            true => PIPE_TYPE_MESSAGE,
+            false => PIPE_TYPE_BYTE,
+        };
+        pipe_mode |= PIPE_READMODE_BYTE | PIPE_REJECT_REMOTE_CLIENTS;
+        if nonblocking {
+            pipe_mode |= PIPE_NOWAIT;
+        }
+        let attributes = self.security_attributes();
+        let path = wide(&self.path);
+        // SAFETY: `path` is a nul-terminated string, and the security attributes point to a descriptor that outlives the call
+        let handle = unsafe {
+            CreateNamedPipeW(
+                path.as_ptr(),
+                open_mode,
+                pipe_mode,
+                PIPE_UNLIMITED_INSTANCES,
+                0,
+                0,
+                0,
+                attributes.as_ref().map_or(ptr::null(), ptr::from_ref),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the handle was just created, so nothing else owns it
+        Ok(unsafe { OwnedHandle::from_raw_handle(handle) })
+    }
+}
+
+/// Waits for a client to connect to `instance`, or fails with [`WouldBlock`](io::ErrorKind::WouldBlock) if there is none and the instance is in non-blocking mode.
+/// A client that already connected, or that connected and left again, counts as connected.
+#[cfg(feature = "sync")]
+pub(crate) fn connect(instance: &OwnedHandle) -> io::Result<()> {
+    // SAFETY: the handle is valid for as long as `instance` is borrowed
+    if unsafe { ConnectNamedPipe(instance.as_raw_handle(), ptr::null_mut()) } != 0 {
+        return Ok(());
+    }
+    let error = io::Error::last_os_error();
+    match error.raw_os_error().map(|code| code as u32) {
+        Some(ERROR_PIPE_CONNECTED | ERROR_NO_DATA) => Ok(()),
+        Some(ERROR_PIPE_LISTENING) => Err(io::ErrorKind::WouldBlock.into()),
+        _ => Err(error),
+    }
+}
+
+/// Sets `instance` to be read from in byte mode, waiting for data if `nonblocking` is false and failing instead if it is true.
+pub(crate) fn set_mode(instance: &impl AsRawHandle, nonblocking: bool) -> io::Result<()> {
+    let mode = match nonblocking {
+        true => PIPE_READMODE_BYTE | PIPE_NOWAIT,
+        false => PIPE_READMODE_BYTE | PIPE_WAIT,
+    };
+    let handle = instance.as_raw_handle();
+    // SAFETY: the handle is valid for as long as `instance` is borrowed, and `mode` outlives the call
+    match unsafe { SetNamedPipeHandleState(handle, &mode, ptr::null(), ptr::null()) } {
+        0 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Converts `string` into a nul-terminated UTF-16 string for the Windows API.
+fn wide(string: &OsStr) -> Vec<u16> {
+    string.encode_wide().chain(Some(0)).collect()
+}
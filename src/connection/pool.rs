@@ -0,0 +1,162 @@
+//! Pooling of asynchronous connections, for clients that repeatedly connect to the same sockets.
+
+use super::async_tokio::Connection;
+use crate::Result;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+type Key = (String, bool);
+
+struct Idle {
+    connection: Connection,
+    since: Instant,
+}
+
+struct PoolInner {
+    idle: Mutex<HashMap<Key, Vec<Idle>>>,
+    max_idle: usize,
+    idle_timeout: Duration,
+}
+
+/// A pool of asynchronous [`Connection`]s, keyed by the name of the socket they're connected to.
+///
+/// Connections taken from the pool using [`get`](Self::get) are returned to it when the [`PooledConnection`] is dropped,
+/// so that later calls to `get` can reuse them instead of connecting again.
+/// Cloning a pool gives you another handle to the same pool.
+#[derive(Clone)]
+pub struct ConnectionPool {
+    inner: Arc<PoolInner>,
+}
+
+impl ConnectionPool {
+    /// Creates a new, empty pool that keeps at most `max_idle` idle connections per socket,
+    /// and discards connections that have been idle for longer than `idle_timeout`.
+    pub fn new(max_idle: usize, idle_timeout: Duration) -> Self {
+        Self {
+            inner: Arc::new(PoolInner {
+                idle: Mutex::new(HashMap::new()),
+                max_idle,
+                idle_timeout,
+            }),
+        }
+    }
+
+    /// Gets a connection to the socket with a name based on `name`.
+    /// An idle connection is reused if there is one, otherwise a new connection is made using [`Connection::connect_to_socket`].
+    ///
+    /// Idle connections that have expired, or that the other side has closed, are closed and discarded.
+    /// Any messages that arrived on an idle connection whilst it was in the pool are discarded too.
+    pub async fn get<S>(&self, name: S, global: bool) -> Result<PooledConnection>
+    where
+        S: AsRef<str>,
+    {
+        let key = (name.as_ref().to_string(), global);
+        while let Some(Idle {
+            mut connection,
+            since,
+        }) = self.take_idle(&key)
+        {
+            if since.elapsed() <= self.inner.idle_timeout {
+                // draining also detects whether the other side has gone away
+                if connection.drain_pending().await.is_ok() && !connection.is_closed() {
+                    return Ok(PooledConnection::new(self.clone(), key, connection));
+                }
+            }
+            connection.close().await;
+        }
+
+        let connection = Connection::connect_to_socket(&key.0, global).await?;
+        Ok(PooledConnection::new(self.clone(), key, connection))
+    }
+
+    /// Gets the number of idle connections in this pool, across all sockets.
+    pub fn idle_count(&self) -> usize {
+        self.lock_idle().values().map(Vec::len).sum()
+    }
+
+    fn lock_idle(&self) -> std::sync::MutexGuard<'_, HashMap<Key, Vec<Idle>>> {
+        // the map is never left in an inconsistent state, so a poisoned lock is still usable
+        self.inner
+            .idle
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    fn take_idle(&self, key: &Key) -> Option<Idle> {
+        self.lock_idle().get_mut(key)?.pop()
+    }
+    /// Returns `connection` to the pool, or gives it back if the pool already has as many idle connections to the socket as it's allowed to.
+    fn put_idle(&self, key: Key, connection: Connection) -> Option<Connection> {
+        let mut idle = self.lock_idle();
+        let connections = idle.entry(key).or_default();
+        if connections.len() < self.inner.max_idle {
+            connections.push(Idle {
+                connection,
+                since: Instant::now(),
+            });
+            None
+        } else {
+            Some(connection)
+        }
+    }
+}
+
+/// A connection taken from a [`ConnectionPool`].
+/// It dereferences to the underlying [`Connection`], and is returned to the pool when dropped unless it was closed.
+///
+/// If the pool is already holding as many idle connections as it's allowed to, the connection is closed instead,
+/// on a task spawned onto the current Tokio runtime. Outside of a runtime, it is closed without telling the other side.
+pub struct PooledConnection {
+    pool: ConnectionPool,
+    key: Key,
+    connection: Option<Connection>,
+}
+
+impl PooledConnection {
+    fn new(pool: ConnectionPool, key: Key, connection: Connection) -> Self {
+        Self {
+            pool,
+            key,
+            connection: Some(connection),
+        }
+    }
+
+    /// Takes the connection out of the pool for good, so that it isn't returned to the pool when dropped.
+    pub fn detach(mut self) -> Connection {
+        self.connection
+            .take()
+            .expect("connection is only taken when detaching or dropping")
+    }
+}
+
+impl Deref for PooledConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.connection
+            .as_ref()
+            .expect("connection is only taken when detaching or dropping")
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.connection
+            .as_mut()
+            .expect("connection is only taken when detaching or dropping")
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            if connection.is_closed() {
+                return;
+            }
+            if let Some(mut overflow) = self.pool.put_idle(self.key.clone(), connection) {
+                overflow.close_detached();
+            }
+        }
+    }
+}
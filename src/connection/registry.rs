@@ -0,0 +1,137 @@
+//! Tracking of the connections a listener has accepted, for servers that want to know which connections are open.
+
+use super::{Direction, PeerCredentials};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+struct Entry {
+    peer_credentials: Option<PeerCredentials>,
+    accepted_at: Instant,
+    counters: Arc<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+/// A registry of the connections that are currently open, as accepted by the listeners it is set on using `Listener::set_registry`.
+///
+/// Connections are added when they are accepted, and removed when they are closed or dropped.
+/// Cloning a registry gives you another handle to the same registry, which can be shared between several listeners.
+#[derive(Clone, Default)]
+pub struct ConnectionRegistry {
+    entries: Arc<Mutex<HashMap<u64, Entry>>>,
+}
+
+/// Information about a connection in a [`ConnectionRegistry`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ConnectionInfo {
+    /// The ID of the connection, as returned by `Connection::id`.
+    pub id: u64,
+    /// The credentials of the process on the other side of the connection, or `None` if they aren't available.
+    pub peer_credentials: Option<PeerCredentials>,
+    /// When the connection was accepted.
+    pub accepted_at: Instant,
+    /// The number of bytes of messages sent through the connection, not counting their length prefixes.
+    /// Like the connection's observer, this doesn't include messages sent using `send_iter`.
+    pub bytes_sent: u64,
+    /// The number of bytes of messages received through the connection, not counting their length prefixes.
+    pub bytes_received: u64,
+}
+
+impl ConnectionInfo {
+    /// Gets how long ago the connection was accepted.
+    pub fn age(&self) -> Duration {
+        self.accepted_at.elapsed()
+    }
+}
+
+impl ConnectionRegistry {
+    /// Creates a new, empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets information about every connection in this registry, in no particular order.
+    /// This is a snapshot - connections accepted or closed afterwards aren't reflected in it.
+    pub fn iter(&self) -> impl Iterator<Item = ConnectionInfo> {
+        let infos: Vec<_> = self
+            .lock_entries()
+            .iter()
+            .map(|(&id, entry)| ConnectionInfo {
+                id,
+                peer_credentials: entry.peer_credentials,
+                accepted_at: entry.accepted_at,
+                bytes_sent: entry.counters.sent.load(Ordering::Relaxed),
+                bytes_received: entry.counters.received.load(Ordering::Relaxed),
+            })
+            .collect();
+        infos.into_iter()
+    }
+    /// Gets the number of connections in this registry.
+    pub fn len(&self) -> usize {
+        self.lock_entries().len()
+    }
+    /// Checks whether this registry has no connections in it.
+    pub fn is_empty(&self) -> bool {
+        self.lock_entries().is_empty()
+    }
+
+    /// Adds the connection with the ID `id` to this registry, until the returned registration is dropped.
+    pub(crate) fn register(
+        &self,
+        id: u64,
+        peer_credentials: Option<PeerCredentials>,
+    ) -> Registration {
+        let counters = Arc::new(Counters::default());
+        self.lock_entries().insert(
+            id,
+            Entry {
+                peer_credentials,
+                accepted_at: Instant::now(),
+                counters: counters.clone(),
+            },
+        );
+        Registration {
+            registry: self.clone(),
+            id,
+            counters,
+        }
+    }
+
+    fn lock_entries(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Entry>> {
+        // the map is never left in an inconsistent state, so a poisoned lock is still usable
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// A connection's place in a [`ConnectionRegistry`], which removes the connection from the registry when dropped.
+pub(crate) struct Registration {
+    registry: ConnectionRegistry,
+    id: u64,
+    counters: Arc<Counters>,
+}
+
+impl Registration {
+    /// Counts a message of `length` bytes that travelled through the connection in `direction`.
+    pub fn record(&self, direction: Direction, length: usize) {
+        let counter = match direction {
+            Direction::Sent => &self.counters.sent,
+            Direction::Received => &self.counters.received,
+        };
+        counter.fetch_add(length as u64, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.registry.lock_entries().remove(&self.id);
+    }
+}
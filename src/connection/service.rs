@@ -0,0 +1,107 @@
+//! Using asynchronous connections as a [`tower_service::Service`], so that gipc can be the transport under `tower` middleware.
+
+use super::async_tokio::Connection;
+use crate::{Error, Result};
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::Serialize;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::sync::Mutex;
+
+struct Inner {
+    connection: Connection,
+    /// How many responses are still on their way for requests whose calls were cancelled after the request was sent.
+    stale: usize,
+}
+
+/// A [`Service`](tower_service::Service) that sends every request through an asynchronous [`Connection`] and receives the response to it,
+/// like [`Connection::send_and_receive`] does.
+///
+/// Requests are sent one at a time, so a call waits for the calls before it to finish before sending its request.
+/// If a call is cancelled (e.g. by a timeout) after its request was sent, the response to it is discarded when it arrives.
+/// Cancelling a call whilst its request is still being sent leaves the connection unusable, though.
+/// Cloning a service gives you another handle to the same connection.
+pub struct GipcService<Req, Resp> {
+    inner: Arc<Mutex<Inner>>,
+    _messages: PhantomData<fn(Req) -> Resp>,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        self.connection.close_detached();
+    }
+}
+
+impl<Req, Resp> GipcService<Req, Resp> {
+    /// Creates a service that sends requests through `connection`.
+    /// The other side has to answer every request with exactly one response.
+    pub fn new(connection: Connection) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                connection,
+                stale: 0,
+            })),
+            _messages: PhantomData,
+        }
+    }
+
+    /// Closes the connection, waiting for every call in progress to finish first.
+    pub async fn close(&self) {
+        self.inner.lock().await.connection.close().await;
+    }
+    /// Gets the connection back, or gives this service back if there are other handles to it.
+    pub fn into_inner(self) -> std::result::Result<Connection, Self> {
+        match Arc::try_unwrap(self.inner) {
+            Ok(inner) => Ok(inner.into_inner().connection.take()),
+            Err(inner) => Err(Self {
+                inner,
+                _messages: PhantomData,
+            }),
+        }
+    }
+}
+
+impl<Req, Resp> Clone for GipcService<Req, Resp> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            _messages: PhantomData,
+        }
+    }
+}
+
+impl<Req, Resp> tower_service::Service<Req> for GipcService<Req, Resp>
+where
+    Req: Serialize + Send + Sync + 'static,
+    Resp: DeserializeOwned + Send + 'static,
+{
+    type Response = Resp;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Resp>> + Send>>;
+
+    /// Always ready - calls wait for each other instead.
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Req) -> Self::Future {
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            let mut inner = inner.lock().await;
+            while inner.stale > 0 {
+                // a late response that fails to be received has still been received, so it doesn't count as on its way anymore
+                let late = inner.connection.receive::<IgnoredAny>().await;
+                inner.stale -= 1;
+                late?;
+            }
+            inner.connection.send(request).await?;
+            inner.stale += 1;
+            let response = inner.connection.receive().await;
+            inner.stale -= 1;
+            response
+        })
+    }
+}
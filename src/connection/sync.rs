@@ -10,18 +10,54 @@
 //!
 //! See the [sync example directory](https://github.com/tecc/gipc/tree/dev/examples/sync) for both an example client and listener.
 
-use super::interprocess::name_onto;
-use crate::message::Message;
+#[cfg(feature = "async-tokio")]
+use super::async_tokio::{Connection as AsyncConnection, ConnectionImpl as AsyncConnectionImpl};
+#[cfg(feature = "auth")]
+use super::auth;
+use super::buffer::BufferedStream;
+use super::interprocess::{bind_with_user_fallback, name_onto};
+#[cfg(windows)]
+use super::pipe::{PipeConfig, PipeOptions};
+use super::registry::Registration;
+use super::{
+    is_not_listening, next_connection_id, AcceptHook, BackoffPolicy, CloseCode, CloseReason,
+    ConnectionDescription, ConnectionRegistry, Direction, Failure, Lane, MessageHook, Observer,
+    PeerCredentials, RateLimit, RateLimiter, ReceiveOutcome, ResolvedName, RetryPolicy,
+    SendInstant, CONNECT_POLL_INTERVAL, MAX_GRACEFUL_ACCEPT_FAILURES,
+};
+use crate::codec::{ActiveCodec, Codec, DynCodec};
+#[cfg(feature = "type-check")]
+use crate::message::fingerprint;
+use crate::message::{
+    frame_payload, framed_len, is_priority_frame, ping_or_pong, write_envelope, write_stream,
+    Envelope, Message, MessageKind, Payload, MAX_PING_LEN,
+};
 use crate::{Error, Result};
 use interprocess::local_socket::{LocalSocketListener, LocalSocketStream};
-use serde::de::DeserializeOwned;
+use serde::de::{DeserializeOwned, IgnoredAny};
 use serde::Serialize;
+#[cfg(windows)]
+use interprocess::os::windows::named_pipe::DuplexBytePipeStream;
+use std::cell::{Cell, OnceCell};
 use std::io::{Read, Write};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::time::{Duration, Instant};
+
+/// A function that is called with every connection a listener accepts, right before it is returned, as set by [`Listener::set_connect_hook`].
+pub type ConnectHook = Box<dyn Fn(&Connection) + Send + Sync>;
 
 /// Listeners allow you to wait until new [`Connection`s](Connection) can be established.
 pub struct Listener {
     internal: Box<dyn ListenerImpl>,
     closed: bool,
+    user_fallback: bool,
+    resolved_name: Option<ResolvedName>,
+    registry: Option<ConnectionRegistry>,
+    accept_hook: Option<AcceptHook>,
+    connect_hook: Option<ConnectHook>,
+    #[cfg(feature = "auth")]
+    auth_secret: Option<Vec<u8>>,
 }
 
 impl Listener {
@@ -31,17 +67,132 @@ impl Listener {
         Self {
             internal,
             closed: false,
+            user_fallback: false,
+            resolved_name: None,
+            registry: None,
+            accept_hook: None,
+            connect_hook: None,
+            #[cfg(feature = "auth")]
+            auth_secret: None,
         }
     }
 
     /// Listens to a socket on the local machine with a name based on `name`.
     /// The actual name used is generated internally.
-    pub fn listen_as_socket<'a, S>(name: S, global: bool) -> Result<Self>
+    /// Names that would be too long for the platform are replaced by a hash of themselves, failing with [`Error::NameTooLong`] if even that is too long.
+    /// Fails with [`Error::Bind`] if the socket can't be bound to.
+    pub fn listen_as_socket<S>(name: S, global: bool) -> Result<Self>
+    where
+        S: AsRef<str>,
+    {
+        let (resolved, bound) = name_onto!(LocalSocketListener::bind; name, global);
+        let bound = bound.map_err(|source| Error::Bind {
+            name: resolved.to_string(),
+            source,
+        })?;
+        let mut listener = Self::new(Box::new(bound));
+        listener.resolved_name = Some(resolved);
+        Ok(listener)
+    }
+
+    /// Listens to a Unix domain socket at exactly `path`, instead of at a path generated from a name like [`listen_as_socket`](Self::listen_as_socket) does.
+    /// This is useful when the location of the socket is decided elsewhere, such as in a configuration file or by another program.
+    /// Fails with [`Error::Bind`] if the socket can't be bound to, e.g. because a file already exists at `path`.
+    #[cfg(unix)]
+    #[cfg_attr(docsrs, doc(cfg(unix)))]
+    pub fn listen_on_path<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let resolved = ResolvedName::Path(path.as_ref().to_owned());
+        let bound = LocalSocketListener::bind(path.as_ref()).map_err(|source| Error::Bind {
+            name: resolved.to_string(),
+            source,
+        })?;
+        let mut listener = Self::new(Box::new(bound));
+        listener.resolved_name = Some(resolved);
+        Ok(listener)
+    }
+
+    /// Listens to a socket like [`listen_as_socket`](Self::listen_as_socket),
+    /// but if `global` and `fallback_to_user` are both `true` and the process isn't permitted to create the global socket,
+    /// the socket is created as a non-global socket instead. [`is_user_fallback`](Self::is_user_fallback) tells you whether that happened.
+    ///
+    /// Note that non-global sockets are scoped to the current user, so processes running as other users won't be able to connect to the listener.
+    /// Clients have to connect with `global` set to `false` to reach a listener that fell back.
+    pub fn listen_as_socket_with_fallback<S>(
+        name: S,
+        global: bool,
+        fallback_to_user: bool,
+    ) -> Result<Self>
+    where
+        S: AsRef<str>,
+    {
+        let (mut listener, fell_back) =
+            bind_with_user_fallback(global, fallback_to_user, |global| {
+                Self::listen_as_socket(name.as_ref(), global)
+            })?;
+        listener.user_fallback = fell_back;
+        Ok(listener)
+    }
+
+    /// Listens to a socket like [`listen_as_socket`](Self::listen_as_socket),
+    /// retrying up to `retries` times with `delay` between attempts if binding fails with a transient I/O error
+    /// (for example if the directory the socket is in doesn't exist yet).
+    /// Errors that won't go away by retrying are returned immediately, as is the last error if all attempts fail.
+    pub fn listen_as_socket_retry<S>(
+        name: S,
+        global: bool,
+        retries: usize,
+        delay: Duration,
+    ) -> Result<Self>
     where
         S: AsRef<str>,
     {
-        let bound = name_onto!(LocalSocketListener::bind; name, global)?;
-        Ok(Self::new(Box::new(bound)))
+        let mut attempt = 0;
+        loop {
+            match Self::listen_as_socket(name.as_ref(), global) {
+                Err(error) if error.is_transient() && attempt < retries => {
+                    attempt += 1;
+                    std::thread::sleep(delay);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Listens on a Windows named pipe created using `options`,
+    /// which allows you to control pipe-specific options that [`listen_as_socket`](Self::listen_as_socket) doesn't expose, such as who may connect.
+    /// Clients connect to it using [`Connection::connect_to_pipe`] with the same name.
+    /// Fails with [`Error::Bind`] if the pipe can't be created, including when another pipe with the same name exists or the security descriptor is invalid.
+    ///
+    /// # Security
+    /// Unless `options` has a [security descriptor](PipeOptions::security_descriptor), the pipe gets the default one, which lets other users on the machine connect to it.
+    /// Connections from other machines are always rejected.
+    #[cfg(windows)]
+    #[cfg_attr(docsrs, doc(cfg(windows)))]
+    pub fn listen_on_pipe(options: &PipeOptions) -> Result<Self> {
+        let resolved = pipe_path(&options.name);
+        let bound = PipeServer::create(options).map_err(|source| Error::Bind {
+            name: resolved.to_string(),
+            source,
+        })?;
+        let mut listener = Self::new(Box::new(bound));
+        listener.resolved_name = Some(resolved);
+        Ok(listener)
+    }
+
+    /// Wraps `listener`, a Unix domain socket that is already listening, such as one handed to the process by a service manager using socket activation.
+    ///
+    /// The returned listener takes ownership of the socket's file descriptor and closes it when it's dropped,
+    /// but never removes the socket's file - that is left to whoever bound the socket.
+    #[cfg(unix)]
+    #[cfg_attr(docsrs, doc(cfg(unix)))]
+    pub fn from_std(listener: UnixListener) -> Self {
+        let resolved = super::interprocess::std_listener_name(&listener);
+        let mut listener = Self::from(listener);
+        listener.resolved_name = resolved;
+        listener
     }
 
     /// Accept a new connection.
@@ -49,7 +200,163 @@ impl Listener {
         if self.closed {
             return Err(Error::Closed(false));
         }
-        self.internal.accept()
+        self.run_accept_hook();
+        let mut connection = self.internal.accept()?;
+        #[cfg(feature = "auth")]
+        self.authenticate(&mut connection)?;
+        self.register(&mut connection);
+        self.run_connect_hook(&connection);
+        Ok(connection)
+    }
+    /// Accept a new connection, waiting at most `timeout` for one to arrive.
+    /// Returns `Ok(None)` if no connection arrived in time, in which case the listener can still be used as normal.
+    ///
+    /// Requires the underlying [`ListenerImpl`] to support [non-blocking mode](ListenerImpl::set_nonblocking).
+    pub fn accept_timeout(&mut self, timeout: Duration) -> Result<Option<Connection>> {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        self.run_accept_hook();
+        let deadline = Instant::now() + timeout;
+        self.internal.set_nonblocking(true)?;
+        let result = loop {
+            match self.internal.accept() {
+                Err(Error::Io(error)) if error.kind() == std::io::ErrorKind::WouldBlock => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break Ok(None);
+                    }
+                    std::thread::sleep(ACCEPT_POLL_INTERVAL.min(deadline - now));
+                }
+                result => break result.map(Some),
+            }
+        };
+        self.internal.set_nonblocking(false)?;
+        let mut connection = result?;
+        if let Some(connection) = &mut connection {
+            // on some platforms, connections accepted by a non-blocking listener are non-blocking as well
+            match connection.internal.inner_mut().set_nonblocking(false) {
+                Err(Error::Io(error)) if error.kind() == std::io::ErrorKind::Unsupported => {}
+                result => result?,
+            }
+            #[cfg(feature = "auth")]
+            self.authenticate(connection)?;
+            self.register(connection);
+            self.run_connect_hook(connection);
+        }
+        Ok(connection)
+    }
+    /// Accept a new connection like [`accept`](Self::accept), then accept up to `max` connections in total by also accepting those that are already pending,
+    /// without waiting for more to arrive. This is useful for working through a burst of connections, such as when many clients connect at startup.
+    /// Returns no connections if `max` is 0.
+    ///
+    /// Errors that occur after the first connection has been accepted end the batch early instead of being returned, so that no accepted connection is lost.
+    /// Requires the underlying [`ListenerImpl`] to support [non-blocking mode](ListenerImpl::set_nonblocking).
+    pub fn accept_many(&mut self, max: usize) -> Result<Vec<Connection>> {
+        let mut connections = Vec::new();
+        if max == 0 {
+            return Ok(connections);
+        }
+        connections.push(self.accept()?);
+        while connections.len() < max {
+            match self.accept_timeout(Duration::ZERO) {
+                Ok(Some(connection)) => connections.push(connection),
+                Ok(None) | Err(_) => break,
+            }
+        }
+        Ok(connections)
+    }
+    /// Sets the registry that connections accepted by this listener are added to, replacing any previous registry.
+    /// Connections that were accepted before this is called aren't added to the new registry.
+    pub fn set_registry(&mut self, registry: ConnectionRegistry) {
+        self.registry = Some(registry);
+    }
+    /// Removes the registry set by [`set_registry`](Self::set_registry), if any.
+    /// Connections already in the registry stay there until they are closed.
+    pub fn clear_registry(&mut self) {
+        self.registry = None;
+    }
+    /// Sets a function that is called every time [`accept`](Self::accept) or [`accept_timeout`](Self::accept_timeout) is called, before waiting for a connection,
+    /// replacing any previous hook. There is no hook by default.
+    ///
+    /// In a server that accepts connections in a loop, this runs between accepts, which can be used for periodic work on the same thread,
+    /// or to let other threads run by setting it to [`std::thread::yield_now`].
+    pub fn set_accept_hook(&mut self, hook: AcceptHook) {
+        self.accept_hook = Some(hook);
+    }
+    /// Removes the hook set by [`set_accept_hook`](Self::set_accept_hook), if any.
+    pub fn clear_accept_hook(&mut self) {
+        self.accept_hook = None;
+    }
+    fn run_accept_hook(&mut self) {
+        if let Some(hook) = &mut self.accept_hook {
+            hook();
+        }
+    }
+    /// Sets a function that is called with every connection this listener accepts, replacing any previous hook. There is no hook by default.
+    /// It is called right before the connection is returned, once it has been authenticated and added to the [registry](Self::set_registry), so it sees the connection's final [ID](Connection::id).
+    ///
+    /// This is a single place for per-connection setup, such as adding the connection to a map or counting it in a metric.
+    pub fn set_connect_hook(&mut self, hook: ConnectHook) {
+        self.connect_hook = Some(hook);
+    }
+    /// Removes the hook set by [`set_connect_hook`](Self::set_connect_hook), if any.
+    pub fn clear_connect_hook(&mut self) {
+        self.connect_hook = None;
+    }
+    fn run_connect_hook(&self, connection: &Connection) {
+        if let Some(hook) = &self.connect_hook {
+            hook(connection);
+        }
+    }
+    /// Sets a secret that every connection has to prove it knows before it is accepted, replacing any previous secret.
+    /// Once this is set, accepting a connection also runs the handshake described by [`Connection::authenticate_peer`],
+    /// so clients have to call [`Connection::authenticate`] with the same secret right after connecting.
+    ///
+    /// If a client gives the wrong answer, accepting it fails with [`Error::AuthFailed`];
+    /// if it doesn't answer within 5 seconds, accepting it fails with [`Error::Timeout`].
+    /// Either way, the client's connection is closed and the listener can still be used.
+    ///
+    /// # Blocking
+    /// The handshake runs inside `accept`, so until a client has answered (or the 5 seconds have passed), no other connection is accepted.
+    /// A client that connects and then stays silent holds up every client behind it.
+    /// If processes that aren't trusted can reach the socket, leave this unset and call [`Connection::authenticate_peer`]
+    /// on each accepted connection instead, away from the accept loop.
+    #[cfg(feature = "auth")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "auth")))]
+    pub fn set_auth_secret<B>(&mut self, secret: B)
+    where
+        B: Into<Vec<u8>>,
+    {
+        self.auth_secret = Some(secret.into());
+    }
+    /// Removes the secret set by [`set_auth_secret`](Self::set_auth_secret), if any.
+    #[cfg(feature = "auth")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "auth")))]
+    pub fn clear_auth_secret(&mut self) {
+        self.auth_secret = None;
+    }
+    #[cfg(feature = "auth")]
+    fn authenticate(&self, connection: &mut Connection) -> Result<()> {
+        if let Some(secret) = &self.auth_secret {
+            let deadline = Instant::now() + auth::LISTENER_TIMEOUT;
+            let result = connection
+                .set_read_deadline(Some(deadline))
+                .and_then(|()| connection.authenticate_peer(secret))
+                .and_then(|()| connection.set_read_deadline(None));
+            if result.is_err() {
+                connection.close();
+            }
+            return result;
+        }
+        Ok(())
+    }
+    fn register(&self, connection: &mut Connection) {
+        connection.endpoint = self.resolved_name.clone();
+        if let Some(registry) = &self.registry {
+            let peer_credentials = connection.peer_credentials().ok();
+            connection.registration = Some(registry.register(connection.id(), peer_credentials));
+        }
     }
     /// Closes this listener, returning any error that occurred whilst closing it.
     /// After calling this function, all other methods will immediately return [`Error::Closed(false)`](Error::Closed) if called.
@@ -60,11 +367,49 @@ impl Listener {
         self.closed = true; // we set it to closed either way
         self.internal.close()
     }
+    /// Closes this listener like [`close`](Self::close), but first accepts the connections that clients have already made and are waiting to be accepted,
+    /// returning them so that they can be served before shutting down. As with [`close`](Self::close), no connections are accepted afterwards.
+    ///
+    /// Pending connections are authenticated like any other, and those that fail to authenticate or fail to be accepted at all are left out rather than failing the whole close.
+    /// Requires the underlying [`ListenerImpl`] to support [non-blocking mode](ListenerImpl::set_nonblocking).
+    pub fn close_graceful(&mut self) -> Result<Vec<Connection>> {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let mut connections = Vec::new();
+        let mut failures = 0;
+        loop {
+            match self.accept_timeout(Duration::ZERO) {
+                Ok(Some(connection)) => {
+                    failures = 0;
+                    connections.push(connection);
+                }
+                Ok(None) => break,
+                // a connection that fails to authenticate or to be accepted has nothing left to serve, so it is simply skipped
+                Err(_) if failures < MAX_GRACEFUL_ACCEPT_FAILURES => failures += 1,
+                Err(_) => break,
+            }
+        }
+        self.close()?;
+        Ok(connections)
+    }
 
     /// Check if this listener is closed.
     pub fn is_closed(&self) -> bool {
         self.closed
     }
+
+    /// Gets the name that this listener's socket actually resolved to.
+    /// Returns `None` if this listener wasn't created from a name, e.g. when it was created using [`new`](Self::new).
+    pub fn resolved_name(&self) -> Option<&ResolvedName> {
+        self.resolved_name.as_ref()
+    }
+
+    /// Check if this listener was created as a non-global socket because it wasn't permitted to create a global one.
+    /// See [`listen_as_socket_with_fallback`](Self::listen_as_socket_with_fallback).
+    pub fn is_user_fallback(&self) -> bool {
+        self.user_fallback
+    }
 }
 
 impl Drop for Listener {
@@ -74,9 +419,31 @@ impl Drop for Listener {
 }
 
 /// Connections represent a two-way bidirectional stream that you can send and receive messages through.
+///
+/// Every message is flushed as soon as it has been written, so nothing that has been sent is held back in a buffer on this side.
+/// Dropping a connection closes it using [`close`](Self::close), which tells the other side that the connection is closing unless [disabled](Self::set_send_close_frame),
+/// then [closes](ConnectionImpl::close) the underlying implementation, which the implementations gipc provides flush once more.
 pub struct Connection {
-    internal: Box<dyn ConnectionImpl>,
+    internal: BufferedStream<Box<dyn ConnectionImpl>>,
+    encode_buffer: Vec<u8>,
+    codec: ActiveCodec,
+    read_deadline: Option<Instant>,
+    write_deadline: Option<Instant>,
+    observer: Option<Observer>,
+    message_hook: Option<MessageHook>,
     closed: bool,
+    close_reason: Option<CloseReason>,
+    send_close_frame: bool,
+    send_timestamps: bool,
+    idle_timeout: Option<Duration>,
+    last_activity: Cell<Option<Instant>>,
+    id: OnceCell<u64>,
+    registration: Option<Registration>,
+    endpoint: Option<ResolvedName>,
+    rate_limiter: Option<RateLimiter>,
+    peer_credentials: Option<PeerCredentials>,
+    failure: Option<Failure>,
+    peer_max_message_size: Option<u64>,
 }
 
 impl Connection {
@@ -84,141 +451,2313 @@ impl Connection {
     /// Generally, you won't call this directly unless you're extending gipc.
     pub const fn new(internal: Box<dyn ConnectionImpl>) -> Self {
         Self {
-            internal,
+            internal: BufferedStream::new(internal),
+            encode_buffer: Vec::new(),
+            codec: ActiveCodec::Default,
+            read_deadline: None,
+            write_deadline: None,
+            observer: None,
+            message_hook: None,
             closed: false,
+            close_reason: None,
+            send_close_frame: true,
+            send_timestamps: false,
+            idle_timeout: None,
+            last_activity: Cell::new(None),
+            id: OnceCell::new(),
+            registration: None,
+            endpoint: None,
+            rate_limiter: None,
+            peer_credentials: None,
+            failure: None,
+            peer_max_message_size: None,
         }
     }
     /// Connects to a socket using a name based on `name`.
     /// The actual name used is generated internally.
+    /// Names that would be too long for the platform are replaced by a hash of themselves, failing with [`Error::NameTooLong`] if even that is too long.
+    /// Fails with [`Error::Connect`] if the socket can't be connected to.
     pub fn connect_to_socket<S>(name: S, global: bool) -> Result<Self>
     where
         S: AsRef<str>,
     {
-        let bound = name_onto!(LocalSocketStream::connect; name, global)?;
-        Ok(Self::new(Box::new(bound)))
+        Self::connect_to_socket_detailed(name, global).map(|(connection, _)| connection)
+    }
+    /// Connects to a socket like [`connect_to_socket`](Self::connect_to_socket),
+    /// but if nothing is listening on the socket yet, keeps trying until something is or until `timeout` has passed.
+    /// This lets a client be started before the listener it connects to, without having to guess how long the listener takes to start.
+    ///
+    /// Errors other than the socket not existing or refusing the connection are returned immediately.
+    /// If the socket still can't be connected to once `timeout` has passed, the last [`Error::Connect`] is returned.
+    pub fn connect_to_socket_wait<S>(name: S, global: bool, timeout: Duration) -> Result<Self>
+    where
+        S: AsRef<str>,
+    {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match Self::connect_to_socket(name.as_ref(), global) {
+                Err(error) if is_not_listening(&error) && Instant::now() < deadline => {
+                    std::thread::sleep(CONNECT_POLL_INTERVAL.min(deadline - Instant::now()));
+                }
+                result => return result,
+            }
+        }
+    }
+    /// Connects to a socket like [`connect_to_socket`](Self::connect_to_socket),
+    /// but if nothing is listening on the socket yet, keeps trying with increasing delays as described by `policy`.
+    /// This is useful for clients that start whilst the service they connect to may still be starting.
+    ///
+    /// Errors other than the socket not existing or refusing the connection are returned immediately.
+    /// Once the policy's `max_attempts` have been made this fails with the last [`Error::Connect`], and once its `timeout` has passed this fails with [`Error::Timeout`].
+    pub fn connect_to_socket_backoff<S>(
+        name: S,
+        global: bool,
+        policy: &BackoffPolicy,
+    ) -> Result<Self>
+    where
+        S: AsRef<str>,
+    {
+        let started = Instant::now();
+        let mut attempt = 0;
+        loop {
+            match Self::connect_to_socket(name.as_ref(), global) {
+                Err(error) if is_not_listening(&error) => {
+                    attempt += 1;
+                    let delay = policy.retry_delay(attempt, started, error)?;
+                    std::thread::sleep(delay);
+                }
+                result => return result,
+            }
+        }
+    }
+    /// Connects to a socket like [`connect_to_socket`](Self::connect_to_socket),
+    /// but also returns the name that the socket actually resolved to.
+    pub fn connect_to_socket_detailed<S>(name: S, global: bool) -> Result<(Self, ResolvedName)>
+    where
+        S: AsRef<str>,
+    {
+        let (resolved, bound) = name_onto!(LocalSocketStream::connect; name, global);
+        let bound = bound.map_err(|source| Error::Connect {
+            name: resolved.to_string(),
+            source,
+        })?;
+        let mut connection = Self::new(Box::new(bound));
+        connection.endpoint = Some(resolved.clone());
+        Ok((connection, resolved))
+    }
+
+    /// Connects to a Unix domain socket at exactly `path`, instead of at a path generated from a name like [`connect_to_socket`](Self::connect_to_socket) does.
+    /// This is meant for connecting to sockets whose location is decided elsewhere, including ones created using [`Listener::listen_on_path`].
+    /// Fails with [`Error::Connect`] if the socket can't be connected to.
+    #[cfg(unix)]
+    #[cfg_attr(docsrs, doc(cfg(unix)))]
+    pub fn connect_to_path<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let stream =
+            LocalSocketStream::connect(path.as_ref()).map_err(|source| Error::Connect {
+                name: ResolvedName::Path(path.as_ref().to_owned()).to_string(),
+                source,
+            })?;
+        let mut connection = Self::new(Box::new(stream));
+        connection.endpoint = Some(ResolvedName::Path(path.as_ref().to_owned()));
+        Ok(connection)
+    }
+
+    /// Connects to a Windows named pipe with the name `name`, without the `\\.\pipe\` prefix.
+    /// This is meant for connecting to listeners created using [`Listener::listen_on_pipe`].
+    /// Fails with [`Error::Connect`] if the pipe can't be connected to.
+    #[cfg(windows)]
+    #[cfg_attr(docsrs, doc(cfg(windows)))]
+    pub fn connect_to_pipe<S>(name: S) -> Result<Self>
+    where
+        S: AsRef<std::ffi::OsStr>,
+    {
+        let stream = DuplexBytePipeStream::connect(name.as_ref()).map_err(|source| {
+            Error::Connect {
+                name: pipe_path(name.as_ref()).to_string(),
+                source,
+            }
+        })?;
+        let mut connection = Self::new(Box::new(stream));
+        connection.endpoint = Some(pipe_path(name.as_ref()));
+        Ok(connection)
     }
 
     fn _send<T>(&mut self, message: Message<T>) -> Result<()>
     where
         T: Serialize,
     {
-        message.write_to(&mut self.internal)
+        self._send_with(&self.codec.clone(), &message)
+    }
+    fn _send_with<C, E>(&mut self, codec: &C, envelope: &E) -> Result<()>
+    where
+        C: Codec + ?Sized,
+        E: Serialize + ?Sized,
+    {
+        self.check_idle()?;
+        self.throttle()?;
+        if let Some(timeout) = remaining(self.write_deadline)? {
+            self.internal.inner_mut().set_write_timeout(Some(timeout))?;
+        }
+        self._check_writable()?;
+        let written = self.internal.bytes_written();
+        write_envelope(
+            &mut self.internal,
+            codec,
+            envelope,
+            &mut self.encode_buffer,
+            self.peer_max_message_size,
+        )
+        .map_err(|error| self._write_failed(written, error))?;
+        self.observe(Direction::Sent, &self.encode_buffer);
+        if let Some(limiter) = &mut self.rate_limiter {
+            limiter.record(self.encode_buffer.len());
+        }
+        Ok(())
+    }
+    /// Converts `error` from a failed write like [`deadline_error`] does,
+    /// noting that the other side has been left partway through a frame if anything was written since `written` bytes had been.
+    fn _write_failed(&mut self, written: u64, error: Error) -> Error {
+        if self.internal.bytes_written() != written {
+            self._fail(Failure::PartialWrite);
+        } else if let Error::Io(_) | Error::Timeout = error {
+            self._fail(Failure::Io);
+        }
+        deadline_error(error, self.write_deadline)
+    }
+    /// Notes that an operation on this connection failed with `failure` without closing it, for [`reset`](Self::reset) to check.
+    /// A partial write can't be recovered from, so it is never replaced by a lesser failure.
+    fn _fail(&mut self, failure: Failure) {
+        if self.failure != Some(Failure::PartialWrite) {
+            self.failure = Some(failure);
+        }
+    }
+    /// Fails with [`Error::MidFrame`] if sending has failed partway through a frame before,
+    /// since the other side would take whatever is sent next for the rest of that frame.
+    fn _check_writable(&self) -> Result<()> {
+        match self.failure {
+            Some(Failure::PartialWrite) => Err(Error::MidFrame),
+            _ => Ok(()),
+        }
+    }
+    fn _receive_with<C, E>(&mut self, codec: &C) -> Result<E>
+    where
+        C: Codec + ?Sized,
+        E: DeserializeOwned,
+    {
+        let payload = self._receive_payload()?;
+        codec.deserialise(&payload)
     }
-    fn _receive<T>(&mut self) -> Result<Message<T>>
+    fn _receive_message<C, T>(&mut self, codec: &C) -> Result<Message<T>>
     where
+        C: Codec + ?Sized,
         T: DeserializeOwned,
     {
-        Message::<T>::read_from(&mut self.internal)
+        let payload = self._receive_payload()?;
+        let message = codec.deserialise::<Message<T>>(&payload)?;
+        if message.carries_data() {
+            self.report::<T>(Direction::Received, payload.len());
+        }
+        Ok(message)
+    }
+    fn _receive_payload(&mut self) -> Result<Payload> {
+        let payload = self._read_payload()?;
+        self.observe(Direction::Received, &payload);
+        Ok(payload)
+    }
+    /// Reads the next payload, answering pings and skipping pongs along the way.
+    fn _read_payload(&mut self) -> Result<Payload> {
+        self._read_payload_with_progress(|_, _| {})
+    }
+    /// Reads the next payload like [`_read_payload`](Self::_read_payload), calling `progress` as more of it is read and once it has been read in its entirety.
+    fn _read_payload_with_progress(
+        &mut self,
+        mut progress: impl FnMut(u64, u64),
+    ) -> Result<Payload> {
+        let mut last = None;
+        loop {
+            let payload = self._read_any_payload_with_progress(|read, total| {
+                // pings and pongs are never longer than this, so they don't show up as progress on the message that follows them
+                if total > MAX_PING_LEN as u64 {
+                    last = Some((read, total));
+                    progress(read, total);
+                }
+            })?;
+            match ping_or_pong(&self.codec, &payload) {
+                Some(kind) => self._answer_ping(kind, &payload),
+                None => {
+                    let length = payload.len() as u64;
+                    if last != Some((length, length)) {
+                        progress(length, length);
+                    }
+                    return Ok(payload);
+                }
+            }
+        }
+    }
+    /// Answers a ping of kind `kind`, or does nothing more than observe it if it is a pong.
+    fn _answer_ping(&mut self, kind: MessageKind, payload: &[u8]) {
+        self.observe(Direction::Received, payload);
+        if kind == MessageKind::Ping {
+            // like acknowledgements, a failure to answer is left for the next operation to run into
+            let _ = self._send::<()>(Message::Pong);
+        }
+    }
+    fn _read_any_payload(&mut self) -> Result<Payload> {
+        self._read_any_payload_with_progress(|_, _| {})
+    }
+    fn _read_any_payload_with_progress(
+        &mut self,
+        progress: impl FnMut(u64, u64),
+    ) -> Result<Payload> {
+        self.check_idle()?;
+        let deadline = [self.read_deadline, self.idle_deadline()]
+            .into_iter()
+            .flatten()
+            .min();
+        remaining(deadline)?;
+        // the timeout is set before every read, so that a frame arriving bit by bit can't keep reading going past the deadline
+        let set_timeout = |inner: &mut Box<dyn ConnectionImpl>| match remaining(deadline)? {
+            Some(timeout) => inner.set_read_timeout(Some(timeout)),
+            None => Ok(()),
+        };
+        let frame = self.internal.read_frame(set_timeout, progress);
+        let payload = match frame.and_then(frame_payload) {
+            Ok(payload) => payload,
+            Err(error) => {
+                match CloseReason::for_error(&error) {
+                    Some(reason) => self._close(reason),
+                    None => self._fail(Failure::Io),
+                }
+                let error = deadline_error(error, deadline);
+                if let Error::Timeout = error {
+                    self.check_idle()?;
+                }
+                return Err(error);
+            }
+        };
+        Ok(payload)
+    }
+    fn _unwrap_message<T>(&mut self, message: Message<T>) -> Result<T> {
+        match message {
+            Message::ClosingConnection => {
+                self._close(CloseReason::Peer);
+                Err(Error::Closed(true))
+            }
+            Message::ClosingWithReason(code, message) => {
+                self._close(CloseReason::Peer);
+                Err(Error::ClosedWithReason { code, message })
+            }
+            Message::Data(data) | Message::Priority(data) | Message::Stamped(_, data) => Ok(data),
+            Message::Signal(code) => Err(Error::Signal(code)),
+            Message::Acked(data) => {
+                // the message has been received either way, so a failure to acknowledge it is left for the next operation to run into
+                let _ = self._send::<()>(Message::Ack);
+                Ok(data)
+            }
+            Message::Ack => Err(Error::UnexpectedAck),
+            Message::Ping | Message::Pong => {
+                unreachable!("pings and pongs are handled whilst reading payloads")
+            }
+        }
+    }
+    /// Tells the message hook, if any, about a message of `length` bytes of type `T`.
+    fn report<T: ?Sized>(&self, direction: Direction, length: usize) {
+        if let Some(hook) = &self.message_hook {
+            hook(direction, std::any::type_name::<T>(), length);
+        }
+    }
+    fn observe(&self, direction: Direction, payload: &[u8]) {
+        self.last_activity.set(Some(Instant::now()));
+        if let Some(observer) = &self.observer {
+            observer(direction, payload);
+        }
+        if let Some(registration) = &self.registration {
+            registration.record(direction, payload.len());
+        }
     }
 
     /// Send a message through this connection.
-    /// Will immediately fail with [`Error::Closed(false)`] if this connection is already closed.
+    /// Will immediately fail with [`Error::Closed(false)`] if this connection is already closed,
+    /// and with [`Error::MessageTooLarge`] if the message is larger than the other side [accepts receiving](Self::peer_max_message_size).
     pub fn send<T>(&mut self, message_data: &T) -> Result<()>
     where
-        T: Serialize,
+        T: Serialize + ?Sized,
     {
-        if self.closed {
-            return Err(Error::Closed(false));
-        }
-        let message = Message::Data(message_data);
-        self._send(message)
+        self.send_with(&self.codec.clone(), message_data)
+    }
+    /// Send a message given as a [`Serialize`](erased_serde::Serialize) trait object, for when the type of the message isn't known where it is sent.
+    /// The message is serialised using this connection's codec, so it can be received as its concrete type using [`receive`](Self::receive).
+    /// Fails in the same way as [`send`](Self::send).
+    #[cfg(feature = "erased-serde")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "erased-serde")))]
+    pub fn send_erased(&mut self, value: &dyn erased_serde::Serialize) -> Result<()> {
+        self.send(value)
     }
     /// Receive a message from this connection.
     /// Will immediately fail with [`Error::Closed(false)`] if this connection is already closed,
     /// or fail with [`Error::Closed(true)`] if this connection was closed whilst trying to read the message.
+    /// If a signal sent using [`send_signal`](Self::send_signal) is received instead of a message, fails with [`Error::Signal`].
+    /// If the message can't be deserialised, this fails with [`Error::Deserialise`], but the message is still consumed so that the next one can be received.
     pub fn receive<T>(&mut self) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.receive_with(&self.codec.clone())
+    }
+    /// Receive a message from this connection like [`receive`](Self::receive), but return [`ReceiveOutcome::Closed`] instead of failing once the other side has closed the connection.
+    /// This is useful for loops that consume messages until the other side is done, where the end of the stream isn't an error.
+    /// Once the connection is closed, further calls fail with [`Error::Closed(false)`](Error::Closed) like [`receive`](Self::receive) does.
+    pub fn receive_outcome<T>(&mut self) -> Result<ReceiveOutcome<T>>
+    where
+        T: DeserializeOwned,
+    {
+        match self.receive() {
+            Ok(message_data) => Ok(ReceiveOutcome::Data(message_data)),
+            Err(Error::Closed(true) | Error::ClosedWithReason { .. }) => Ok(ReceiveOutcome::Closed),
+            Err(error) => Err(error),
+        }
+    }
+    /// Receive a message from this connection like [`receive`](Self::receive), calling `progress` with how many bytes of it have been read and how many bytes it has in total as it arrives.
+    /// This is meant for large messages, so that e.g. a progress bar can be shown whilst they are read - the message is only deserialised once it has been read in its entirety.
+    ///
+    /// The amount read never decreases, and the last call is always with both amounts equal, once the whole message has been read.
+    /// Messages sent using [`send_iter`](Self::send_iter) don't declare their length up front, so `progress` is only called once for them, after they have been read.
+    /// Fails in the same way as [`receive`](Self::receive).
+    pub fn receive_with_progress<T>(&mut self, progress: impl FnMut(u64, u64)) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let payload = self._read_payload_with_progress(progress)?;
+        self.observe(Direction::Received, &payload);
+        let message = self.codec.clone().deserialise::<Message<T>>(&payload)?;
+        if message.carries_data() {
+            self.report::<T>(Direction::Received, payload.len());
+        }
+        self._unwrap_message(message)
+    }
+    /// Changes the codec that this connection uses for all messages from now on, including signals and the message sent when closing it.
+    /// [`send_with`](Self::send_with) and [`receive_with`](Self::receive_with) still use the codec they're given.
+    ///
+    /// Both sides have to switch to the same codec at the same point - usually right after a message that tells the other side which codec to use.
+    /// Messages that have been sent with one codec can't be received with another.
+    pub fn set_codec(&mut self, codec: Box<dyn DynCodec>) {
+        self.codec = ActiveCodec::Dyn(codec.into());
+    }
+    /// Changes the codec that this connection uses back to the default, [`CborCodec`](crate::codec::CborCodec).
+    pub fn reset_codec(&mut self) {
+        self.codec = ActiveCodec::Default;
+    }
+
+    /// Send a message through this connection, serialising it using `codec` instead of the codec this connection uses.
+    /// Fails in the same way as [`send`](Self::send).
+    pub fn send_with<C, T>(&mut self, codec: &C, message_data: &T) -> Result<()>
+    where
+        C: Codec + ?Sized,
+        T: Serialize + ?Sized,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        if self.send_timestamps {
+            let message = Message::Stamped(SendInstant::now().as_nanos(), message_data);
+            self._send_with(codec, &message)?;
+        } else {
+            self._send_with(codec, &Message::Data(message_data))?;
+        }
+        self.report::<T>(Direction::Sent, self.encode_buffer.len());
+        Ok(())
+    }
+    /// Receive a message from this connection, deserialising it using `codec` instead of the codec this connection uses.
+    /// Fails in the same way as [`receive`](Self::receive).
+    pub fn receive_with<C, T>(&mut self, codec: &C) -> Result<T>
+    where
+        C: Codec + ?Sized,
+        T: DeserializeOwned,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let message = self._receive_message(codec)?;
+        self._unwrap_message(message)
+    }
+    /// Receive a message from this connection along with when it was sent, if the other side has [timestamps enabled](Self::set_send_timestamps).
+    /// Messages sent without a timestamp are returned with `None`.
+    /// Fails in the same way as [`receive`](Self::receive).
+    ///
+    /// Comparing the timestamp to [`SendInstant::now`] gives how long the message took to arrive, including how long it waited to be received.
+    pub fn receive_with_timestamp<T>(&mut self) -> Result<(T, Option<SendInstant>)>
     where
         T: DeserializeOwned,
     {
         if self.closed {
             return Err(Error::Closed(false));
         }
-        let message = self._receive()?;
+        let message = self._receive_message(&self.codec.clone())?;
         match message {
-            Message::ClosingConnection => {
-                self._close();
-                Err(Error::Closed(true))
-            }
-            Message::Data(data) => Ok(data),
+            Message::Stamped(nanos, data) => Ok((data, Some(SendInstant::from_nanos(nanos)))),
+            message => Ok((self._unwrap_message(message)?, None)),
         }
     }
-    /// Shorthand for calling [`send`](Self::send) and [`receive`](Self::receive) after one another.
-    pub fn send_and_receive<A, B>(&mut self, data: &A) -> Result<B>
+    /// Receive a message from this connection like [`receive`](Self::receive), but return `None` instead of failing with [`Error::Deserialise`] if it can't be deserialised.
+    /// Messages are framed independently of their contents, so a message that can't be deserialised is still received in its entirety and discarded,
+    /// and the next message can be received as usual.
+    pub fn receive_or_skip<T>(&mut self) -> Result<Option<T>>
     where
-        A: Serialize,
-        B: DeserializeOwned,
+        T: DeserializeOwned,
     {
-        self.send(data)?;
-        self.receive()
+        match self.receive() {
+            Err(Error::Deserialise(_)) => Ok(None),
+            result => result.map(Some),
+        }
     }
-
-    fn _close(&mut self) {
-        self.internal.close();
-        self.closed = true;
+    /// Gets the kind of the next message on this connection without receiving it, waiting for it to arrive if necessary.
+    /// The message is kept so that it is received as usual afterwards, which lets close frames and signals be told apart from data without deserialising the data.
+    /// Fails with [`Error::Deserialise`] if the message isn't a gipc message at all, in which case it is still kept, and otherwise in the same way as [`receive`](Self::receive).
+    ///
+    /// The message is only passed to the [observer](Self::set_observer) once it is received.
+    pub fn peek_variant(&mut self) -> Result<MessageKind> {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let payload = self._read_payload()?;
+        let kind = self.codec.deserialise::<Message<IgnoredAny>>(&payload);
+        self.internal.restore_frame(payload.into_frame());
+        kind.map(|message| message.kind())
     }
-
-    /// Closes this connection if it isn't already closed.
-    /// This operation can never fail.
-    pub fn close(&mut self) {
+    /// Receive a message from this connection like [`receive`](Self::receive), but if it can't be deserialised as a `T`,
+    /// keep it so that it can be received again, for example by calling this again with a different type.
+    /// This is useful when a message can be one of several types that don't share an enum.
+    /// If all types the message can be are known up front, receiving an enum that derives [`Deserialize`](serde::Deserialize) is simpler.
+    ///
+    /// The message is passed to the [observer](Self::set_observer) every time it is received.
+    pub fn try_receive_as<T>(&mut self) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
         if self.closed {
-            return;
+            return Err(Error::Closed(false));
+        }
+        let payload = self._receive_payload()?;
+        match self.codec.deserialise::<Message<T>>(&payload) {
+            Ok(message) => {
+                if message.carries_data() {
+                    self.report::<T>(Direction::Received, payload.len());
+                }
+                self._unwrap_message(message)
+            }
+            Err(error) => {
+                self.internal.restore_frame(payload.into_frame());
+                Err(error)
+            }
         }
-        // ignore the results of this - it doesn't matter since we're closing it either way
-        let _ = self._send::<()>(Message::ClosingConnection);
-        self._close();
     }
-
-    /// Check if this connection is closed.
-    pub fn is_closed(&self) -> bool {
-        self.closed
+    /// Send every item in `items` through this connection as a single message containing a sequence of them, without collecting them first.
+    /// The other side receives this like any other message, e.g. as a `Vec` of the items using [`receive`](Self::receive).
+    ///
+    /// The message is sent in chunks whilst it's being serialised, so `items` never has to be held in memory all at once.
+    /// The downside is that the size of the message isn't known until it has been sent in its entirety,
+    /// and the other side still has to buffer the entire message before deserialising it.
+    /// A receiver with a [maximum message size](Self::set_max_message_size) can therefore only reject the message once enough of its chunks have arrived to exceed it.
+    /// If serialising an item fails, the message has only been sent in part and the connection can't be used anymore.
+    ///
+    /// Messages sent like this are always encoded as CBOR, so this fails with [`Error::Serialise`] if [a different codec](Self::set_codec) is used.
+    /// They also aren't passed to the [observer](Self::set_observer) when they're sent.
+    pub fn send_iter<I>(&mut self, items: I) -> Result<()>
+    where
+        I: IntoIterator,
+        I::Item: Serialize,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        if !self.codec.is_default() {
+            return Err(Error::Serialise(
+                "streamed messages can only be sent using the default codec".into(),
+            ));
+        }
+        if let Some(timeout) = remaining(self.write_deadline)? {
+            self.internal.inner_mut().set_write_timeout(Some(timeout))?;
+        }
+        self._check_writable()?;
+        let written = self.internal.bytes_written();
+        write_stream(&mut self.internal, items, &mut self.encode_buffer)
+            .map_err(|error| self._write_failed(written, error))
     }
-}
-
-impl Drop for Connection {
-    fn drop(&mut self) {
-        self.close();
+    /// Send `envelope` through this connection as-is, instead of wrapping data in a [`Message`].
+    /// This allows protocols to define their own control frames - see [`Envelope`] for what envelopes need to look like.
+    /// Fails in the same way as [`send`](Self::send).
+    pub fn send_envelope<E>(&mut self, envelope: &E) -> Result<()>
+    where
+        E: Envelope,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        self._send_with(&self.codec.clone(), envelope)
     }
-}
-
-/// Internal implementation for a [`Listener`].
-pub trait ListenerImpl {
-    /// Accept a new connection.
-    /// This function should _block_ until a connection can be established.
-    fn accept(&mut self) -> Result<Connection>;
-
-    /// Closes this listener implementation.
-    /// After this function is called, no more functions will be called from the implementation.
-    fn close(&mut self) -> Result<()>;
-}
-
-impl ListenerImpl for LocalSocketListener {
-    fn accept(&mut self) -> Result<Connection> {
-        Ok(Connection::from(LocalSocketListener::accept(self)?))
+    /// Receive an envelope sent using [`send_envelope`](Self::send_envelope) from this connection.
+    /// If the envelope [indicates that the connection is closing](Envelope::is_closing), this connection is closed and this fails with [`Error::Closed(true)`](Error::Closed);
+    /// otherwise, it fails in the same way as [`receive`](Self::receive).
+    pub fn receive_envelope<E>(&mut self) -> Result<E>
+    where
+        E: Envelope,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let envelope: E = self._receive_with(&self.codec.clone())?;
+        if envelope.is_closing() {
+            self._close(CloseReason::Peer);
+            return Err(Error::Closed(true));
+        }
+        Ok(envelope)
     }
-
-    fn close(&mut self) -> Result<()> {
-        // LocalSocketListener doesn't need to do anything when closing
+    /// Send a message through this connection on the priority lane.
+    /// The other side receives it like any other message, but [`receive_any`](Self::receive_any) returns it ahead of normal messages that have already arrived.
+    ///
+    /// Messages aren't split up whilst they're being sent, so a priority message can't overtake a normal message that is still being sent,
+    /// only normal messages that are waiting to be received.
+    /// Will immediately fail with [`Error::Closed(false)`](Error::Closed) if this connection is already closed.
+    pub fn send_priority<T>(&mut self, message_data: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        self._send(Message::Priority(message_data))?;
+        self.report::<T>(Direction::Sent, self.encode_buffer.len());
         Ok(())
     }
-}
-
-impl From<LocalSocketListener> for Listener {
-    fn from(value: LocalSocketListener) -> Self {
-        Self::new(Box::new(value))
-    }
-}
-
+    /// Receive a message from this connection along with the lane it was sent on.
+    /// If a message sent using [`send_priority`](Self::send_priority) has already arrived in its entirety, it is received first,
+    /// even if normal messages arrived before it; otherwise, the next message is received in order.
+    /// Fails in the same way as [`receive`](Self::receive).
+    ///
+    /// Requires the underlying [`ConnectionImpl`] to support [non-blocking mode](ConnectionImpl::set_nonblocking).
+    pub fn receive_any<T>(&mut self) -> Result<(Lane, T)>
+    where
+        T: DeserializeOwned,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        self.internal.inner_mut().set_nonblocking(true)?;
+        let filled = self.internal.fill_available();
+        self.internal.inner_mut().set_nonblocking(false)?;
+        filled?;
+        let priority = self
+            .internal
+            .take_frame_matching(|frame| is_priority_frame(&self.codec, frame));
+        let payload = match priority {
+            Some(frame) => {
+                let payload = frame_payload(frame)?;
+                self.observe(Direction::Received, &payload);
+                payload
+            }
+            None => self._receive_payload()?,
+        };
+        let message = self.codec.deserialise::<Message<T>>(&payload)?;
+        if message.carries_data() {
+            self.report::<T>(Direction::Received, payload.len());
+        }
+        match message {
+            Message::Priority(data) => Ok((Lane::Priority, data)),
+            message => Ok((Lane::Normal, self._unwrap_message(message)?)),
+        }
+    }
+    /// Gets how many bytes sending `message_data` using [`send`](Self::send) would write to this connection, including the length prefix, without sending anything.
+    /// This serialises the message using this connection's codec, so it costs about as much as serialising it for sending.
+    /// Fails with [`Error::MessageTooLarge`] if the message is too large to be sent at all.
+    pub fn serialised_len<T>(&self, message_data: &T) -> Result<usize>
+    where
+        T: Serialize + ?Sized,
+    {
+        framed_len(&self.codec, &Message::Data(message_data))
+    }
+    /// Send a signal with `code` through this connection.
+    /// Signals are lightweight notifications that don't carry any data,
+    /// and are surfaced on the other side as [`Error::Signal`] by [`receive`](Self::receive).
+    /// Will immediately fail with [`Error::Closed(false)`](Error::Closed) if this connection is already closed.
+    pub fn send_signal(&mut self, code: u8) -> Result<()> {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        self._send::<()>(Message::Signal(code))
+    }
+
+    /// Send raw bytes through this connection.
+    /// The bytes are encoded as a byte string, which is far more compact than sending a `Vec<u8>` through [`send`](Self::send).
+    /// Will immediately fail with [`Error::Closed(false)`](Error::Closed) if this connection is already closed.
+    pub fn send_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.send(serde_bytes::Bytes::new(bytes))
+    }
+    /// Receive raw bytes sent using [`send_bytes`](Self::send_bytes) from this connection.
+    /// Fails in the same way as [`receive`](Self::receive).
+    pub fn receive_bytes(&mut self) -> Result<Vec<u8>> {
+        self.receive().map(serde_bytes::ByteBuf::into_vec)
+    }
+    /// Send the bytes in `buf` through this connection, like [`send_bytes`](Self::send_bytes) does.
+    /// Will immediately fail with [`Error::Closed(false)`](Error::Closed) if this connection is already closed.
+    #[cfg(feature = "bytes")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "bytes")))]
+    pub fn send_bytes_buf(&mut self, buf: &bytes::Bytes) -> Result<()> {
+        self.send_bytes(buf)
+    }
+    /// Receive raw bytes sent using [`send_bytes`](Self::send_bytes) or [`send_bytes_buf`](Self::send_bytes_buf) from this connection.
+    /// With the default codec, the returned [`Bytes`](bytes::Bytes) shares its storage with the payload of the frame the bytes were received in, rather than the bytes being copied out of it.
+    /// Other codecs deserialise the bytes like any other message, which copies them.
+    /// Fails in the same way as [`receive`](Self::receive).
+    #[cfg(feature = "bytes")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "bytes")))]
+    pub fn receive_bytes_buf(&mut self) -> Result<bytes::Bytes> {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let payload = self._receive_payload()?;
+        if self.codec.is_default() {
+            if let Some(range) = crate::message::data_bytes_range(&payload) {
+                self.report::<serde_bytes::ByteBuf>(Direction::Received, payload.len());
+                return Ok(payload.into_bytes(range));
+            }
+        }
+        let message = self.codec.deserialise::<Message<serde_bytes::ByteBuf>>(&payload)?;
+        if message.carries_data() {
+            self.report::<serde_bytes::ByteBuf>(Direction::Received, payload.len());
+        }
+        self._unwrap_message(message)
+            .map(|bytes| bytes::Bytes::from(bytes.into_vec()))
+    }
+    /// Send bytes that `write` produces, like [`send_bytes`](Self::send_bytes) does, for formats that don't fit the [`Codec`] trait, like hand-written binary layouts.
+    /// `write` is given an empty buffer to append its encoding of the message to.
+    ///
+    /// This is a helper around [`send_bytes`](Self::send_bytes), not a way of writing frames directly:
+    /// the bytes are still wrapped in a byte-string message in the usual envelope, so that signals, acknowledgements and closing the connection keep working,
+    /// and the other side has to receive them using [`receive_bytes_with`](Self::receive_bytes_with) or [`receive_bytes`](Self::receive_bytes).
+    /// Fails with whatever `write` fails with, in which case nothing is sent, and otherwise in the same way as [`send_bytes`](Self::send_bytes).
+    pub fn send_bytes_with<F>(&mut self, write: F) -> Result<()>
+    where
+        F: FnOnce(&mut Vec<u8>) -> Result<()>,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let mut payload = Vec::new();
+        write(&mut payload)?;
+        self.send_bytes(&payload)
+    }
+    /// Receive bytes sent using [`send_bytes_with`](Self::send_bytes_with) or [`send_bytes`](Self::send_bytes), decoding them using `read`.
+    /// `read` is given the bytes that were sent, and what it returns is returned from this.
+    /// Fails in the same way as [`receive_bytes`](Self::receive_bytes), or with whatever `read` fails with, in which case the message is still consumed.
+    pub fn receive_bytes_with<F, T>(&mut self, read: F) -> Result<T>
+    where
+        F: FnOnce(&[u8]) -> Result<T>,
+    {
+        read(&self.receive_bytes()?)
+    }
+    /// Send a dynamically-typed CBOR value through this connection.
+    /// This is useful when the type of a message isn't known at compile time, e.g. when forwarding messages between other processes.
+    /// Will immediately fail with [`Error::Closed(false)`](Error::Closed) if this connection is already closed.
+    pub fn send_value(&mut self, value: &ciborium::value::Value) -> Result<()> {
+        self.send(value)
+    }
+    /// Receive a message from this connection as a dynamically-typed CBOR value, regardless of what type it was sent as.
+    /// Fails in the same way as [`receive`](Self::receive).
+    pub fn receive_value(&mut self) -> Result<ciborium::value::Value> {
+        self.receive()
+    }
+    /// Reads and discards every message that has already arrived in its entirety, returning how many were discarded.
+    /// This never waits for more data - a message that has only partially arrived is kept to be received later.
+    /// If one of the messages indicates that the connection is closing, this connection is closed and draining stops there.
+    /// This connection is also closed if the stream has ended, i.e. the other side has gone away without closing the connection.
+    /// Messages that can't be deserialised are discarded and counted like any other, so one malformed message doesn't stop the rest from being drained.
+    ///
+    /// Requires the underlying [`ConnectionImpl`] to support [non-blocking mode](ConnectionImpl::set_nonblocking).
+    pub fn drain_pending(&mut self) -> Result<usize> {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        self.internal.inner_mut().set_nonblocking(true)?;
+        let filled = self.internal.fill_available();
+        self.internal.inner_mut().set_nonblocking(false)?;
+        let ended = filled?;
+
+        let mut count = 0;
+        while let Some(frame) = self.internal.take_frame() {
+            let payload = frame_payload(frame)?;
+            self.observe(Direction::Received, &payload);
+            match self.codec.deserialise::<Message<IgnoredAny>>(&payload) {
+                Ok(Message::ClosingConnection | Message::ClosingWithReason(..)) => {
+                    self._close(CloseReason::Peer);
+                    break;
+                }
+                // a frame that can't be deserialised is still a whole message, so it is discarded like the rest
+                Err(_)
+                | Ok(
+                    Message::Data(_)
+                    | Message::Signal(_)
+                    | Message::Priority(_)
+                    | Message::Stamped(..),
+                ) => count += 1,
+                Ok(Message::Acked(_)) => {
+                    let _ = self._send::<()>(Message::Ack);
+                    count += 1;
+                }
+                Ok(Message::Ping) => {
+                    let _ = self._send::<()>(Message::Pong);
+                }
+                Ok(Message::Ack | Message::Pong) => {}
+            }
+        }
+        if ended && !self.closed {
+            self._close(CloseReason::Dropped);
+        }
+        Ok(count)
+    }
+    /// Receives up to `max` messages that have already arrived in their entirety, without waiting for more data.
+    /// This returns early with fewer messages (possibly none) if no more complete messages are available,
+    /// so that one busy connection can't keep the caller from serving others.
+    ///
+    /// A signal, a closing message or a message that can't be deserialised stops the batch.
+    /// If it is the first message, the error [`receive`](Self::receive) would give is returned;
+    /// otherwise the messages before it are returned, and it is left for the next call.
+    /// If the stream has ended, this connection is closed once every complete message has been received.
+    ///
+    /// Requires the underlying [`ConnectionImpl`] to support [non-blocking mode](ConnectionImpl::set_nonblocking).
+    pub fn receive_batch<T>(&mut self, max: usize) -> Result<Vec<T>>
+    where
+        T: DeserializeOwned,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        if max == 0 {
+            return Ok(Vec::new());
+        }
+        self.internal.inner_mut().set_nonblocking(true)?;
+        let filled = self.internal.fill_available();
+        self.internal.inner_mut().set_nonblocking(false)?;
+        let ended = filled?;
+
+        let mut messages = Vec::new();
+        while messages.len() < max {
+            let frame = match self.internal.take_frame() {
+                Some(frame) => frame,
+                None => {
+                    if ended {
+                        self._close(CloseReason::Dropped);
+                    }
+                    break;
+                }
+            };
+            let payload = frame_payload(frame)?;
+            match self.codec.deserialise::<Message<T>>(&payload) {
+                Ok(Message::Data(data) | Message::Priority(data) | Message::Stamped(_, data)) => {
+                    self.observe(Direction::Received, &payload);
+                    self.report::<T>(Direction::Received, payload.len());
+                    messages.push(data);
+                }
+                Ok(Message::Ping) => self._answer_ping(MessageKind::Ping, &payload),
+                Ok(Message::Pong) => self._answer_ping(MessageKind::Pong, &payload),
+                _ if !messages.is_empty() => {
+                    self.internal.restore_frame(payload.into_frame());
+                    break;
+                }
+                result => {
+                    self.observe(Direction::Received, &payload);
+                    return self._unwrap_message(result?).map(|data| vec![data]);
+                }
+            }
+        }
+        Ok(messages)
+    }
+    /// Flushes this connection, then waits until everything sent through it has left the kernel's send buffer, or until `timeout` has passed.
+    /// For local sockets, data generally only leaves the send buffer once the other side has read it.
+    /// Fails with [`Error::Timeout`] if there is still unsent data after `timeout`.
+    ///
+    /// This is best-effort: only Linux, Android, macOS and iOS let gipc see how much data is unsent.
+    /// On other platforms, and for [`ConnectionImpl`]s that don't [support it](ConnectionImpl::unsent_bytes), this only flushes the connection.
+    pub fn flush_and_wait_drain(&mut self, timeout: Duration) -> Result<()> {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        self.internal.flush()?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.internal.inner_mut().unsent_bytes() {
+                Ok(0) => return Ok(()),
+                Ok(_) if Instant::now() >= deadline => return Err(Error::Timeout),
+                Ok(_) => std::thread::sleep(DRAIN_POLL_INTERVAL),
+                Err(Error::Io(error)) if error.kind() == std::io::ErrorKind::Unsupported => {
+                    return Ok(())
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+    /// Proves to the other side of this connection that this side knows `secret`,
+    /// as checked by [`authenticate_peer`](Self::authenticate_peer) or by a listener with an [authentication secret](Listener::set_auth_secret).
+    /// This has to happen before any other messages are sent through the connection.
+    /// Fails with [`Error::AuthFailed`] if the other side doesn't accept the answer, and otherwise in the same way as [`receive`](Self::receive).
+    /// This connection is closed whenever the handshake fails, since it can't be told where the handshake stopped.
+    ///
+    /// The handshake keeps processes that don't know the secret from using the connection, but it doesn't encrypt anything,
+    /// and only the side calling this is checked - a process listening in place of the expected one isn't detected.
+    #[cfg(feature = "auth")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "auth")))]
+    pub fn authenticate(&mut self, secret: &[u8]) -> Result<()> {
+        let result = self._authenticate(secret);
+        if result.is_err() {
+            self.close();
+        }
+        result
+    }
+    #[cfg(feature = "auth")]
+    fn _authenticate(&mut self, secret: &[u8]) -> Result<()> {
+        let nonce = self.receive_bytes()?;
+        self.send_bytes(&auth::respond(secret, &nonce))?;
+        match self.receive::<bool>()? {
+            true => Ok(()),
+            false => Err(Error::AuthFailed),
+        }
+    }
+    /// Checks that the other side of this connection knows `secret`, by sending it a random nonce that it has to answer with `HMAC-SHA256(secret, nonce)`
+    /// using [`authenticate`](Self::authenticate).
+    /// This has to happen before any other messages are sent through the connection.
+    /// Fails with [`Error::AuthFailed`] if the answer is wrong, and otherwise in the same way as [`receive`](Self::receive).
+    /// Like with [`authenticate`](Self::authenticate), this connection is closed whenever the handshake fails.
+    #[cfg(feature = "auth")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "auth")))]
+    pub fn authenticate_peer(&mut self, secret: &[u8]) -> Result<()> {
+        let result = self._authenticate_peer(secret);
+        if result.is_err() {
+            self.close();
+        }
+        result
+    }
+    #[cfg(feature = "auth")]
+    fn _authenticate_peer(&mut self, secret: &[u8]) -> Result<()> {
+        let nonce = auth::nonce()?;
+        self.send_bytes(&nonce)?;
+        let accepted = match self.receive_bytes() {
+            Ok(response) => auth::verify(secret, &nonce, &response),
+            Err(Error::Deserialise(_)) => false,
+            Err(error) => return Err(error),
+        };
+        self.send(&accepted)?;
+        match accepted {
+            true => Ok(()),
+            false => Err(Error::AuthFailed),
+        }
+    }
+    /// Shorthand for calling [`send`](Self::send) and [`receive`](Self::receive) after one another.
+    pub fn send_and_receive<A, B>(&mut self, data: &A) -> Result<B>
+    where
+        A: Serialize,
+        B: DeserializeOwned,
+    {
+        self.send(data)?;
+        self.receive()
+    }
+    /// Sends `data` and receives the response like [`send_and_receive`](Self::send_and_receive), but gives up once `timeout` has passed since this was called,
+    /// failing with [`Error::Timeout`]. The timeout covers sending and receiving together, and any earlier deadlines that are already set still apply.
+    ///
+    /// Timing out closes this connection, since a request that may have been sent could still be answered, and that answer would be mistaken for the response to the next request.
+    /// The other side isn't told that the connection was closed, and its close reason is [`CloseReason::Error`] with [`TimedOut`](std::io::ErrorKind::TimedOut).
+    /// Afterwards the deadlines are set back to what they were, and if that fails, the error is returned unless sending or receiving already failed.
+    pub fn send_and_receive_timeout<A, B>(&mut self, data: &A, timeout: Duration) -> Result<B>
+    where
+        A: Serialize,
+        B: DeserializeOwned,
+    {
+        let deadline = Instant::now() + timeout;
+        let (read_deadline, write_deadline) = (self.read_deadline, self.write_deadline);
+        let earliest =
+            |current: Option<Instant>| Some(current.map_or(deadline, |d| d.min(deadline)));
+        self.set_write_deadline(earliest(write_deadline))?;
+        if let Err(error) = self.set_read_deadline(earliest(read_deadline)) {
+            let _ = self.set_write_deadline(write_deadline);
+            return Err(error);
+        }
+        let result = self.send_and_receive(data);
+        if let Err(Error::Timeout) = result {
+            self._close(CloseReason::Error(std::io::ErrorKind::TimedOut));
+            // the implementation is closed, so the deadlines are set back without touching it
+            (self.read_deadline, self.write_deadline) = (read_deadline, write_deadline);
+            return result;
+        }
+        let restored_write = self.set_write_deadline(write_deadline);
+        let restored_read = self.set_read_deadline(read_deadline);
+        let value = result?;
+        restored_write?;
+        restored_read?;
+        Ok(value)
+    }
+    /// Answers requests on this connection until the other side closes it: receives a request, passes it to `handler`, sends the response `handler` returns, and repeats.
+    /// This is the server side of [`send_and_receive`](Self::send_and_receive).
+    ///
+    /// Returns `Ok(())` once the other side has closed the connection cleanly.
+    /// Any other error stops serving and is returned, including the other side going away without closing the connection,
+    /// in which case the connection is closed as usual for that error.
+    pub fn serve<Req, Resp, F>(&mut self, mut handler: F) -> Result<()>
+    where
+        Req: DeserializeOwned,
+        Resp: Serialize,
+        F: FnMut(Req) -> Resp,
+    {
+        loop {
+            let request = match self.receive() {
+                Err(Error::Closed(true) | Error::ClosedWithReason { .. }) => return Ok(()),
+                result => result?,
+            };
+            let response = handler(request);
+            self.send(&response)?;
+        }
+    }
+    /// Send a message through this connection, then wait until the other side has received it.
+    /// The other side acknowledges the message automatically as soon as [`receive`](Self::receive) (or any other way of receiving it) has returned it,
+    /// which is a stronger guarantee than [`flush_and_wait_drain`](Self::flush_and_wait_drain) gives.
+    ///
+    /// This costs a full round trip per message, so it is far slower than [`send`](Self::send) when sending many messages.
+    /// Messages that the other side sends whilst this is waiting are kept to be received afterwards,
+    /// but the other side can't receive anything whilst it's waiting for an acknowledgement itself,
+    /// so both sides calling this at the same time waits forever unless a [read deadline](Self::set_read_deadline) is set.
+    /// If waiting fails, the acknowledgement may still arrive later, in which case it is received as [`Error::UnexpectedAck`].
+    pub fn send_acked<T>(&mut self, message_data: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        self._send(Message::Acked(message_data))?;
+        self.report::<T>(Direction::Sent, self.encode_buffer.len());
+        self._wait_for(MessageKind::Ack)
+    }
+    /// Primes this connection by sending a tiny ping and waiting for the other side to answer it, so that a latency-sensitive first request doesn't pay for cold buffers and lazy setup in the OS.
+    /// The other side answers automatically whilst it is receiving, without either side's messages being affected, and messages that it sends in the meantime are kept to be received afterwards.
+    ///
+    /// Other sides using a version of gipc that predates pings can't make sense of them, and fail to deserialise them instead of answering,
+    /// so this waits forever unless a [read deadline](Self::set_read_deadline) is set.
+    /// Will immediately fail with [`Error::Closed(false)`](Error::Closed) if this connection is already closed.
+    pub fn warm_up(&mut self) -> Result<()> {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        self._send::<()>(Message::Ping)?;
+        self._wait_for(MessageKind::Pong)
+    }
+
+    /// Waits for a message of kind `kind` that answers one this connection has just sent, keeping the messages that arrive in the meantime to be received afterwards.
+    fn _wait_for(&mut self, kind: MessageKind) -> Result<()> {
+        let mut skipped = Vec::new();
+        let result = loop {
+            let payload = match self._read_any_payload() {
+                Ok(payload) => payload,
+                Err(error) => break Err(error),
+            };
+            match self.codec.deserialise::<Message<IgnoredAny>>(&payload) {
+                Ok(message) if message.kind() == kind => {
+                    self.observe(Direction::Received, &payload);
+                    break Ok(());
+                }
+                Ok(Message::Ping) => self._answer_ping(MessageKind::Ping, &payload),
+                Ok(Message::Pong) => self._answer_ping(MessageKind::Pong, &payload),
+                Ok(Message::ClosingConnection) => {
+                    self.observe(Direction::Received, &payload);
+                    self._close(CloseReason::Peer);
+                    break Err(Error::Closed(true));
+                }
+                Ok(Message::ClosingWithReason(code, message)) => {
+                    self.observe(Direction::Received, &payload);
+                    self._close(CloseReason::Peer);
+                    break Err(Error::ClosedWithReason { code, message });
+                }
+                _ => skipped.push(payload),
+            }
+        };
+        for payload in skipped.into_iter().rev() {
+            self.internal.restore_frame(payload.into_frame());
+        }
+        result
+    }
+
+    /// Gets when this connection's idle timeout runs out if nothing is sent or received before then.
+    fn idle_deadline(&self) -> Option<Instant> {
+        self.idle_timeout
+            .and_then(|timeout| self.last_activity.get()?.checked_add(timeout))
+    }
+    /// Closes this connection if its idle timeout has run out, failing with [`Error::Closed(true)`](Error::Closed) if it did.
+    fn check_idle(&mut self) -> Result<()> {
+        match self.idle_deadline() {
+            Some(deadline) if deadline <= Instant::now() => {
+                self._close(CloseReason::Idle);
+                Err(Error::Closed(true))
+            }
+            _ => Ok(()),
+        }
+    }
+    /// Waits until this connection's rate limit allows another message to be sent,
+    /// failing with [`Error::Timeout`] straight away if that would take until after the write deadline.
+    fn throttle(&mut self) -> Result<()> {
+        let Some(limiter) = &mut self.rate_limiter else {
+            return Ok(());
+        };
+        let delay = limiter.delay();
+        if !delay.is_zero() {
+            if matches!(self.write_deadline, Some(deadline) if Instant::now() + delay > deadline) {
+                return Err(Error::Timeout);
+            }
+            std::thread::sleep(delay);
+        }
+        Ok(())
+    }
+    fn _close(&mut self, reason: CloseReason) {
+        self.internal.inner_mut().close();
+        self.closed = true;
+        self.close_reason = Some(reason);
+        self.registration = None;
+    }
+
+    /// Closes this connection if it isn't already closed.
+    /// Unless [disabled](Self::set_send_close_frame), the other side is told that the connection is closing first.
+    /// This operation can never fail - use [`try_close`](Self::try_close) to find out whether the other side could be told.
+    pub fn close(&mut self) {
+        if self.closed {
+            return;
+        }
+        // ignore the results of this - it doesn't matter since we're closing it either way
+        let _ = self.try_close();
+    }
+    /// Closes this connection like [`close`](Self::close), but returns the error that occurred whilst telling the other side that the connection is closing, if any.
+    /// The connection is closed even if this fails.
+    /// Fails with [`Error::Closed(false)`](Error::Closed) if this connection is already closed.
+    pub fn try_close(&mut self) -> Result<()> {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let result = if self.send_close_frame {
+            self._send::<()>(Message::ClosingConnection)
+        } else {
+            Ok(())
+        };
+        self._close(CloseReason::Local);
+        result
+    }
+    /// Closes this connection like [`try_close`](Self::try_close), but first receives the messages the other side sent before it saw that the connection is closing, returning them.
+    ///
+    /// Closing races with the other side: it may send more messages after this side has decided to close, but before the close frame reaches it,
+    /// and [`close`](Self::close) silently drops them. This instead tells the other side that the connection is closing, then keeps receiving messages as `T`
+    /// until the other side closes the connection or goes away, or until `timeout` has passed, whichever comes first.
+    /// Signals are skipped, and a message that can't be deserialised as `T` stops receiving and fails with [`Error::Deserialise`].
+    ///
+    /// The connection is closed afterwards either way, even if sending the close frame or receiving fails, in which case the first error is returned.
+    /// Fails with [`Error::Closed(false)`](Error::Closed) if the connection is already closed.
+    pub fn close_draining<T>(&mut self, timeout: Duration) -> Result<Vec<T>>
+    where
+        T: DeserializeOwned,
+    {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let mut result = if self.send_close_frame {
+            self._send::<()>(Message::ClosingConnection)
+        } else {
+            Ok(())
+        };
+        let mut messages = Vec::new();
+        let read_deadline = self.read_deadline;
+        let deadline = Instant::now() + timeout;
+        // even if the close frame couldn't be sent, the messages that have already arrived can still be received
+        if !self.closed {
+            let draining = Some(read_deadline.map_or(deadline, |d| d.min(deadline)));
+            match self.set_read_deadline(draining) {
+                Ok(()) => loop {
+                    match self.receive() {
+                        Ok(message_data) => messages.push(message_data),
+                        Err(Error::Signal(_)) => {}
+                        // receiving closes the connection once the other side has closed it or gone away
+                        Err(_) if self.closed => break,
+                        Err(Error::Timeout) => break,
+                        Err(error) => {
+                            result = result.and(Err(error));
+                            break;
+                        }
+                    }
+                },
+                Err(error) => result = result.and(Err(error)),
+            }
+        }
+        if !self.closed {
+            let restored = self.set_read_deadline(read_deadline);
+            result = result.and(restored);
+            self._close(CloseReason::Local);
+        }
+        result.map(|()| messages)
+    }
+    /// Closes this connection like [`try_close`](Self::try_close), but tells the other side why using `code` and optionally `message`,
+    /// which it receives as [`Error::ClosedWithReason`] instead of [`Error::Closed(true)`](Error::Closed).
+    ///
+    /// Other sides using a version of gipc that predates close codes can't make sense of this, and fail to deserialise it instead of closing.
+    /// Like [`close`](Self::close), nothing is sent if [sending a close frame](Self::set_send_close_frame) is disabled.
+    pub fn close_with_reason(&mut self, code: CloseCode, message: Option<&str>) -> Result<()> {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let result = if self.send_close_frame {
+            let message = message.map(str::to_owned);
+            self._send::<()>(Message::ClosingWithReason(code, message))
+        } else {
+            Ok(())
+        };
+        self._close(CloseReason::Local);
+        result
+    }
+
+    /// Gets the ID of this connection, which is unique among the connections created by this process.
+    /// IDs are assigned in increasing order the first time they're needed, which makes them useful for telling connections apart in logs.
+    pub fn id(&self) -> u64 {
+        *self.id.get_or_init(next_connection_id)
+    }
+    /// Describes how this connection is set up: the codec it currently uses, the kind of transport it runs over, and the name of the socket it was made through.
+    pub fn describe(&self) -> ConnectionDescription {
+        ConnectionDescription {
+            id: self.id(),
+            codec: self.codec.name(),
+            transport: self.internal.inner().transport(),
+            endpoint: self.endpoint.clone(),
+        }
+    }
+
+    /// Check if this connection is closed.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+    /// Gets the reason this connection was closed, or `None` if it isn't closed.
+    ///
+    /// Besides being closed by either side, a connection is closed when receiving from it fails because the other side went away,
+    /// in which case the reason is either [`CloseReason::Dropped`] or [`CloseReason::Error`].
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        self.close_reason
+    }
+    /// Makes this connection usable again after an operation on it failed without breaking the stream, e.g. because it timed out between two messages.
+    /// This clears the failure that [`is_failed`](Self::is_failed) reports, and deadlines that have already passed, as every operation would fail with [`Error::Timeout`] until they are changed otherwise.
+    ///
+    /// This refuses to reset a connection that is out of step with the other side, failing with [`Error::MidFrame`] without changing anything:
+    /// that is the case if part of a frame has been received but not the rest of it, or if sending a message failed after part of its frame had been written.
+    /// Sending through a connection that is left partway through a frame like that fails with [`Error::MidFrame`] as well, so all that is left to do with it is close it.
+    /// A receive that timed out partway through a frame keeps what it read, so the connection can be reset once that frame has been received in full.
+    /// Fails with [`Error::Closed(false)`](Error::Closed) if this connection is closed, since closed connections can't be used again.
+    pub fn reset(&mut self) -> Result<()> {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        if self.failure == Some(Failure::PartialWrite) || !self.internal.at_frame_boundary() {
+            return Err(Error::MidFrame);
+        }
+        let now = Instant::now();
+        if self.read_deadline.is_some_and(|deadline| deadline <= now) {
+            self.set_read_deadline(None)?;
+        }
+        if self.write_deadline.is_some_and(|deadline| deadline <= now) {
+            self.set_write_deadline(None)?;
+        }
+        self.failure = None;
+        Ok(())
+    }
+    /// Checks whether reading from or writing to this connection has failed without closing it since it was created or last [reset](Self::reset),
+    /// e.g. because a deadline passed. Messages that can't be deserialised and signals received instead of messages don't count, since they leave the connection as usable as it was.
+    pub fn is_failed(&self) -> bool {
+        self.failure.is_some()
+    }
+
+    /// Sets the deadline for receiving messages, or clears it if `deadline` is `None`.
+    /// Once the deadline has passed, receiving fails with [`Error::Timeout`] until the deadline is changed or cleared.
+    ///
+    /// A message that has only been partially read when the deadline passes is kept, and the next receive continues reading it.
+    /// Requires the underlying [`ConnectionImpl`] to support [read timeouts](ConnectionImpl::set_read_timeout).
+    pub fn set_read_deadline(&mut self, deadline: Option<Instant>) -> Result<()> {
+        if deadline.is_none() {
+            self.internal.inner_mut().set_read_timeout(None)?;
+        }
+        self.read_deadline = deadline;
+        Ok(())
+    }
+    /// Sets the deadline for sending messages, or clears it if `deadline` is `None`.
+    /// Once the deadline has passed, sending fails with [`Error::Timeout`] until the deadline is changed or cleared.
+    ///
+    /// A message that has only been partially written when the deadline passes leaves the connection unusable.
+    /// Requires the underlying [`ConnectionImpl`] to support [write timeouts](ConnectionImpl::set_write_timeout).
+    pub fn set_write_deadline(&mut self, deadline: Option<Instant>) -> Result<()> {
+        if deadline.is_none() {
+            self.internal.inner_mut().set_write_timeout(None)?;
+        }
+        self.write_deadline = deadline;
+        Ok(())
+    }
+    /// Sets how long this connection may go without sending or receiving anything before it is closed, or disables this if `timeout` is `None`, which is the default.
+    /// Once the timeout runs out, the connection is closed with [`CloseReason::Idle`] by the next operation,
+    /// or by a receive that is already waiting, which then fails with [`Error::Closed(true)`](Error::Closed).
+    /// This is useful for servers, so that connections to clients that are gone but never closed them don't stay open forever.
+    ///
+    /// Any message sent or received counts as activity, except for messages sent using [`send_iter`](Self::send_iter).
+    /// If nothing has been sent or received yet, the connection has been idle since the timeout was set.
+    /// The other side isn't told that the connection was closed.
+    /// Requires the underlying [`ConnectionImpl`] to support [read timeouts](ConnectionImpl::set_read_timeout).
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        if timeout.is_none() && self.read_deadline.is_none() {
+            self.internal.inner_mut().set_read_timeout(None)?;
+        }
+        if self.last_activity.get().is_none() {
+            self.last_activity.set(Some(Instant::now()));
+        }
+        self.idle_timeout = timeout;
+        Ok(())
+    }
+    /// Gets how long this connection may go without sending or receiving anything before it is closed, as set by [`set_idle_timeout`](Self::set_idle_timeout).
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+    /// Sets the largest message this connection accepts receiving, in bytes, or removes the limit if `max` is `None`, which is the default.
+    /// Receiving a frame whose length prefix claims a larger message fails with [`Error::ProtocolDesync`] before anything is allocated for it,
+    /// since a prefix like that usually means that the two sides are out of sync.
+    /// Even without a limit, prefixes claiming messages too large to be allocated are treated like this.
+    ///
+    /// The limit applies to the message as serialised, not including its length prefix.
+    pub fn set_max_message_size(&mut self, max: Option<usize>) {
+        self.internal.set_max_message_size(max);
+    }
+    /// Gets the largest message this connection accepts receiving, as set by [`set_max_message_size`](Self::set_max_message_size).
+    pub fn max_message_size(&self) -> Option<usize> {
+        self.internal.max_message_size()
+    }
+    /// Tells the other side of this connection the largest message this side accepts receiving, as set by [`set_max_message_size`](Self::set_max_message_size),
+    /// and learns the other side's limit in return, which is returned and kept as [`peer_max_message_size`](Self::peer_max_message_size).
+    /// From then on, sending a message larger than the other side's limit fails with [`Error::MessageTooLarge`] before anything is sent, rather than the other side closing the connection partway through.
+    ///
+    /// Both sides have to call this at the same point, usually right after connecting and [authenticating](Self::authenticate), since each side sends its limit and then waits for the other's.
+    /// Limits set afterwards aren't passed on, so set the maximum message size first.
+    /// Fails in the same way as [`send`](Self::send) and [`receive`](Self::receive).
+    pub fn exchange_max_message_sizes(&mut self) -> Result<Option<u64>> {
+        let own = self.max_message_size().map(|max| max as u64);
+        self.send(&own)?;
+        let peer = self.receive::<Option<u64>>()?;
+        self.peer_max_message_size = peer;
+        Ok(peer)
+    }
+    /// Gets the largest message the other side of this connection accepts receiving, as learnt by [`exchange_max_message_sizes`](Self::exchange_max_message_sizes),
+    /// or `None` if the other side has no limit or the limits haven't been exchanged.
+    ///
+    /// Messages streamed using [`send_iter`](Self::send_iter) aren't checked against this, since their size isn't known until they have been sent.
+    pub fn peer_max_message_size(&self) -> Option<u64> {
+        self.peer_max_message_size
+    }
+    /// Limits how fast this connection sends messages, or removes the limit if `limit` is `None`, which is the default.
+    /// Sending sleeps whilst the limit is hit, unless that would take until after the [write deadline](Self::set_write_deadline), in which case it fails with [`Error::Timeout`].
+    ///
+    /// Every message sent through this connection counts towards the limit, including signals and the message sent when closing it.
+    /// Setting a limit starts it afresh, with a full second's worth of sending available.
+    pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+        self.rate_limiter = limit.map(RateLimiter::new);
+    }
+    /// Gets the limit on how fast this connection sends messages, as set by [`set_rate_limit`](Self::set_rate_limit).
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        self.rate_limiter.as_ref().map(RateLimiter::limit)
+    }
+
+    /// Reserves space for messages of at least `capacity` bytes in the buffer that messages are serialised into before being sent.
+    /// The buffer is reused between messages and grows as needed, so this is only useful to avoid reallocations the first time a large message is sent.
+    pub fn set_serialize_capacity_hint(&mut self, capacity: usize) {
+        self.encode_buffer.clear();
+        self.encode_buffer.reserve(capacity);
+    }
+
+    /// Sets whether [`close`](Self::close) sends a message telling the other side that the connection is closing, which it does by default.
+    /// Disabling this means closing the connection writes nothing, which is useful when the other side isn't a gipc program and wouldn't understand the message.
+    ///
+    /// Without that message, the other side can't tell a clean close apart from this side crashing - both look like the stream ending.
+    pub fn set_send_close_frame(&mut self, send_close_frame: bool) {
+        self.send_close_frame = send_close_frame;
+    }
+
+    /// Sets whether messages sent through this connection are stamped with when they were sent, which they aren't by default.
+    /// The other side can get the timestamp using [`receive_with_timestamp`](Self::receive_with_timestamp), and receives the messages as normal otherwise.
+    /// Stamping a message makes it around 13 bytes larger with the default codec.
+    pub fn set_send_timestamps(&mut self, send_timestamps: bool) {
+        self.send_timestamps = send_timestamps;
+    }
+
+    /// Sets a function that observes every message sent or received through this connection, replacing any previous observer.
+    /// The observer is given the serialised message (without its length prefix) after it has been sent, or before it is deserialised when it is received.
+    /// Messages discarded by [`drain_pending`](Self::drain_pending) are observed as received too.
+    ///
+    /// The observer only gets to look at the data, so it can't alter what's sent or received - this is intended for things like audit logging.
+    pub fn set_observer(&mut self, observer: Observer) {
+        self.observer = Some(observer);
+    }
+    /// Removes the observer set by [`set_observer`](Self::set_observer), if any.
+    pub fn clear_observer(&mut self) {
+        self.observer = None;
+    }
+    /// Sets a function that is told the type and serialised size of every message sent or received through this connection, replacing any previous hook.
+    /// This is meant for profiling, e.g. to find out which types of messages take up the most bandwidth. There is no hook by default, which costs nothing.
+    ///
+    /// Only messages whose type is known are counted, i.e. those sent or received by methods like [`send`](Self::send) and [`receive`](Self::receive) that take a message type;
+    /// signals, envelopes, closing messages and messages sent using [`send_iter`](Self::send_iter) aren't. Received messages are only counted if they could be deserialised.
+    pub fn set_message_hook(&mut self, hook: MessageHook) {
+        self.message_hook = Some(hook);
+    }
+    /// Removes the hook set by [`set_message_hook`](Self::set_message_hook), if any.
+    pub fn clear_message_hook(&mut self) {
+        self.message_hook = None;
+    }
+
+    /// Gets the credentials of the process on the other side of this connection.
+    /// Requires the underlying [`ConnectionImpl`] to [support it](ConnectionImpl::peer_credentials),
+    /// which local sockets do on Linux, Android, macOS, iOS and the BSDs.
+    ///
+    /// The credentials can't change whilst the connection is open, so they are only queried the first time they're needed - often when the connection is accepted -
+    /// and kept for later calls. Use [`refresh_peer_credentials`](Self::refresh_peer_credentials) to query them again.
+    pub fn peer_credentials(&mut self) -> Result<PeerCredentials> {
+        match self.peer_credentials {
+            Some(credentials) => Ok(credentials),
+            None => self.refresh_peer_credentials(),
+        }
+    }
+    /// Queries the credentials of the process on the other side of this connection again, instead of using the ones kept by [`peer_credentials`](Self::peer_credentials),
+    /// and keeps the result for later calls.
+    pub fn refresh_peer_credentials(&mut self) -> Result<PeerCredentials> {
+        let credentials = self.internal.inner_mut().peer_credentials()?;
+        self.peer_credentials = Some(credentials);
+        Ok(credentials)
+    }
+
+    /// Sets the size of the kernel's buffer for data received through this connection to `size` bytes, i.e. `SO_RCVBUF`.
+    /// Requires the underlying [`ConnectionImpl`] to [support it](ConnectionImpl::set_socket_buffer_size), which local sockets do on Unix;
+    /// otherwise this fails with an [`Unsupported`](std::io::ErrorKind::Unsupported) I/O error.
+    ///
+    /// The kernel treats `size` as a hint - it may round it, clamp it to system-wide limits, or (like Linux does) double it to leave room for bookkeeping,
+    /// so [`recv_buffer_size`](Self::recv_buffer_size) may not return exactly `size` afterwards.
+    pub fn set_recv_buffer_size(&mut self, size: usize) -> Result<()> {
+        self.internal
+            .inner_mut()
+            .set_socket_buffer_size(Direction::Received, size)
+    }
+    /// Gets the size of the kernel's buffer for data received through this connection, in bytes.
+    /// See [`set_recv_buffer_size`](Self::set_recv_buffer_size).
+    pub fn recv_buffer_size(&mut self) -> Result<usize> {
+        self.internal
+            .inner_mut()
+            .socket_buffer_size(Direction::Received)
+    }
+    /// Sets the size of the kernel's buffer for data sent through this connection to `size` bytes, i.e. `SO_SNDBUF`.
+    /// This works like [`set_recv_buffer_size`](Self::set_recv_buffer_size).
+    pub fn set_send_buffer_size(&mut self, size: usize) -> Result<()> {
+        self.internal
+            .inner_mut()
+            .set_socket_buffer_size(Direction::Sent, size)
+    }
+    /// Gets the size of the kernel's buffer for data sent through this connection, in bytes.
+    /// See [`set_send_buffer_size`](Self::set_send_buffer_size).
+    pub fn send_buffer_size(&mut self) -> Result<usize> {
+        self.internal
+            .inner_mut()
+            .socket_buffer_size(Direction::Sent)
+    }
+
+    /// Creates another connection through the same underlying stream, so that e.g. one thread can receive messages whilst another sends them.
+    /// The underlying [`ConnectionImpl`] has to [support this](ConnectionImpl::try_clone), which local sockets and Unix domain sockets do on Unix.
+    ///
+    /// The clone starts out with the same codec, maximum message size, known maximum message size of the other side, rate limit and setting for sending a close frame as this connection, and shares its known peer credentials, but it has a new ID, and it isn't in any registry.
+    /// Framing state isn't shared, so the two connections have to coordinate so that they don't both send or both receive at the same time - usually by only ever sending through one of them and receiving through the other.
+    /// Read and write timeouts, and therefore deadlines, are set on the shared socket, so setting them on one connection affects the other.
+    ///
+    /// Each connection sends a close frame when it is closed or dropped unless told [not to](Self::set_send_close_frame),
+    /// and the other side treats the first one it receives as the end of the connection, so disable it on the connection that is closed first.
+    pub fn try_clone(&self) -> Result<Self> {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let mut connection = Self::new(self.internal.inner().try_clone()?);
+        connection.codec = self.codec.clone();
+        connection.send_close_frame = self.send_close_frame;
+        connection.endpoint = self.endpoint.clone();
+        connection.peer_credentials = self.peer_credentials;
+        connection.set_max_message_size(self.max_message_size());
+        connection.peer_max_message_size = self.peer_max_message_size;
+        connection.set_rate_limit(self.rate_limit());
+        Ok(connection)
+    }
+
+    /// Turns this connection into an asynchronous one that continues where this one left off, so that a connection made synchronously can be used from async code.
+    /// This has to be called from within a Tokio runtime.
+    ///
+    /// Bytes that have already been read but not yet received as messages are carried over, so no messages are lost or cut in half,
+    /// as are the codec, observer, message hook, deadlines, idle timeout, maximum message sizes of both sides, whether a close frame is sent, the peer credentials, the ID and the connection's place in a [registry](ConnectionRegistry).
+    /// The underlying [`ConnectionImpl`] has to [support this](ConnectionImpl::into_async), which Unix domain sockets do;
+    /// if it doesn't, this fails with an [`Unsupported`](std::io::ErrorKind::Unsupported) I/O error, and the connection is closed.
+    #[cfg(feature = "async-tokio")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "async-tokio")))]
+    pub fn into_async(mut self) -> Result<AsyncConnection> {
+        if self.closed {
+            return Err(Error::Closed(false));
+        }
+        let max_message_size = self.internal.max_message_size();
+        let detached = BufferedStream::new(Box::new(Detached) as Box<dyn ConnectionImpl>);
+        let (internal, buffer) = std::mem::replace(&mut self.internal, detached).into_parts();
+        // the socket now belongs to the new connection, so dropping this one mustn't send anything
+        self.closed = true;
+        let mut connection = AsyncConnection::from_sync(
+            internal.into_async()?,
+            buffer,
+            self.codec.clone(),
+            self.id(),
+            self.registration.take(),
+            self.endpoint.take(),
+            self.peer_credentials,
+        );
+        if let Some(observer) = self.observer.take() {
+            connection.set_observer(observer);
+        }
+        if let Some(hook) = self.message_hook.take() {
+            connection.set_message_hook(hook);
+        }
+        connection.set_read_deadline(self.read_deadline);
+        connection.set_write_deadline(self.write_deadline);
+        connection.set_send_close_frame(self.send_close_frame);
+        connection.set_send_timestamps(self.send_timestamps);
+        connection.set_idle_timeout(self.idle_timeout);
+        connection.set_max_message_size(max_message_size);
+        connection.set_peer_max_message_size(self.peer_max_message_size);
+        connection.set_rate_limit(self.rate_limit());
+        Ok(connection)
+    }
+
+    /// Wraps this connection in a [`VersionedConnection`] using the schema version `version`.
+    pub fn versioned(self, version: u16) -> VersionedConnection {
+        VersionedConnection::new(self, version)
+    }
+    /// Wraps this connection in a [`TypedConnection`], which detects messages being received as a different type than they were sent as.
+    #[cfg(feature = "type-check")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "type-check")))]
+    pub fn typed(self) -> TypedConnection {
+        TypedConnection::new(self)
+    }
+    /// Wraps this connection in a [`ReadOnlyConnection`], for connections that are only ever received from.
+    pub fn read_only(self) -> ReadOnlyConnection {
+        ReadOnlyConnection { connection: self }
+    }
+    /// Wraps this connection in a [`RetryingConnection`], which retries sending and receiving after transient I/O errors as described by `policy`.
+    pub fn with_retry(self, policy: RetryPolicy) -> RetryingConnection {
+        RetryingConnection::new(self, policy)
+    }
+    /// Wraps this connection in a [`WriteOnlyConnection`], for connections that are only ever sent through.
+    pub fn write_only(self) -> WriteOnlyConnection {
+        WriteOnlyConnection { connection: self }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Takes the place of the connection implementation of a connection that has been turned into an asynchronous one.
+#[cfg(feature = "async-tokio")]
+struct Detached;
+
+#[cfg(feature = "async-tokio")]
+impl Read for Detached {
+    fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+        Ok(0)
+    }
+}
+
+#[cfg(feature = "async-tokio")]
+impl Write for Detached {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async-tokio")]
+impl ConnectionImpl for Detached {
+    fn close(&mut self) {}
+}
+
+/// How long [`Listener::accept_timeout`] waits between checking for new connections.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long [`Connection::flush_and_wait_drain`] waits between checking how much data is unsent.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Gets the time remaining until `deadline`, failing with [`Error::Timeout`] if it has already passed.
+fn remaining(deadline: Option<Instant>) -> Result<Option<Duration>> {
+    match deadline {
+        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+            Some(remaining) if !remaining.is_zero() => Ok(Some(remaining)),
+            _ => Err(Error::Timeout),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Converts errors caused by a socket timeout into [`Error::Timeout`] if there is a deadline.
+fn deadline_error(error: Error, deadline: Option<Instant>) -> Error {
+    use std::io::ErrorKind::{TimedOut, WouldBlock};
+    match error {
+        Error::Io(error) if deadline.is_some() && matches!(error.kind(), WouldBlock | TimedOut) => {
+            Error::Timeout
+        }
+        error => error,
+    }
+}
+
+/// Checks that connections work in this environment by listening on a socket with a unique name,
+/// connecting to it, sending a message both ways, and closing everything again.
+/// This is useful as a health check, e.g. to find out whether the runtime directory is misconfigured.
+///
+/// Any error that occurs during the test is returned.
+pub fn self_test() -> Result<()> {
+    let name = super::interprocess::unique_name("gipc-self-test");
+    let mut listener = Listener::listen_as_socket(&name, false)?;
+    let mut client = Connection::connect_to_socket(&name, false)?;
+    let mut server = listener.accept()?;
+
+    client.send("ping")?;
+    let ping: String = server.receive()?;
+    server.send(&ping)?;
+    let pong: String = client.receive()?;
+    if pong != "ping" {
+        return Err(Error::Deserialise(
+            format!("self-test received {:?} instead of \"ping\"", pong).into(),
+        ));
+    }
+
+    client.close();
+    server.close();
+    listener.close()
+}
+
+/// A thin wrapper around a [`Connection`] that tags every message with a schema version.
+/// Received messages with a different schema version are rejected with [`Error::SchemaMismatch`].
+///
+/// Both sides of the connection have to use a `VersionedConnection`, as the version is part of the message data.
+pub struct VersionedConnection {
+    connection: Connection,
+    version: u16,
+}
+
+impl VersionedConnection {
+    /// Creates a new versioned connection that sends messages with, and expects messages to have, `version`.
+    pub const fn new(connection: Connection, version: u16) -> Self {
+        Self {
+            connection,
+            version,
+        }
+    }
+
+    /// Gets the schema version of this connection.
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// Send a message tagged with this connection's schema version.
+    /// Fails in the same way as [`Connection::send`].
+    pub fn send<T>(&mut self, message_data: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.connection.send(&(self.version, message_data))
+    }
+    /// Receive a message, checking that it was sent with this connection's schema version.
+    /// Fails with [`Error::SchemaMismatch`] if the versions differ, and otherwise in the same way as [`Connection::receive`].
+    pub fn receive<T>(&mut self) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let (got, value): (u16, ciborium::value::Value) = self.connection.receive()?;
+        if got != self.version {
+            return Err(Error::SchemaMismatch {
+                expected: self.version,
+                got,
+            });
+        }
+        value
+            .deserialized()
+            .map_err(|v| Error::Deserialise(v.into()))
+    }
+
+    /// Gets the underlying connection.
+    pub fn connection(&mut self) -> &mut Connection {
+        &mut self.connection
+    }
+    /// Unwraps this versioned connection, returning the underlying connection.
+    pub fn into_inner(self) -> Connection {
+        self.connection
+    }
+}
+
+/// A thin wrapper around a [`Connection`] that tags every message with a [fingerprint] of its type.
+/// Received messages that were sent as a different type are rejected with [`Error::TypeMismatch`],
+/// instead of possibly being deserialised into the wrong data when the types happen to look alike.
+///
+/// Both sides of the connection have to use a `TypedConnection`, as the fingerprint is part of the message data:
+/// every message is sent as a `(u64, T)` tuple of the fingerprint and the data, which is also what an untyped receive of it gets.
+///
+/// By default the fingerprint is based on the name of the type as returned by [`std::any::type_name`], so both sides have to use the same type with the same path.
+/// That name isn't guaranteed to be stable and may change between compiler versions, so this is only reliable when both sides are built with the same compiler.
+/// [`send_as`](Self::send_as) and [`receive_as`](Self::receive_as) take a schema ID chosen by the user instead, which is stable,
+/// and should be used whenever both sides are built separately or define the type separately.
+#[cfg(feature = "type-check")]
+#[cfg_attr(docsrs, doc(cfg(feature = "type-check")))]
+pub struct TypedConnection {
+    connection: Connection,
+}
+
+#[cfg(feature = "type-check")]
+impl TypedConnection {
+    /// Creates a new typed connection.
+    pub const fn new(connection: Connection) -> Self {
+        Self { connection }
+    }
+
+    /// Send a message tagged with a fingerprint of the name of its type, which may differ between compiler versions (see [`TypedConnection`]).
+    /// Fails in the same way as [`Connection::send`].
+    pub fn send<T>(&mut self, message_data: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.send_as(std::any::type_name::<T>(), message_data)
+    }
+    /// Send a message tagged with a fingerprint of `schema`, instead of one of its type.
+    /// Fails in the same way as [`Connection::send`].
+    pub fn send_as<T>(&mut self, schema: &str, message_data: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.connection.send(&(fingerprint(schema), message_data))
+    }
+    /// Receive a message, checking that it was sent as a `T` by [`send`](Self::send), which relies on both sides agreeing on the name of `T` (see [`TypedConnection`]).
+    /// Fails with [`Error::TypeMismatch`] if it wasn't, and otherwise in the same way as [`Connection::receive`].
+    pub fn receive<T>(&mut self) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.receive_as(std::any::type_name::<T>())
+    }
+    /// Receive a message, checking that it was sent using [`send_as`](Self::send_as) with `schema`.
+    /// Fails with [`Error::TypeMismatch`] if it wasn't, and otherwise in the same way as [`Connection::receive`].
+    pub fn receive_as<T>(&mut self, schema: &str) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let expected = fingerprint(schema);
+        let (got, value): (u64, ciborium::value::Value) = self.connection.receive()?;
+        if got != expected {
+            return Err(Error::TypeMismatch { expected, got });
+        }
+        value
+            .deserialized()
+            .map_err(|v| Error::Deserialise(v.into()))
+    }
+
+    /// Gets the underlying connection.
+    pub fn connection(&mut self) -> &mut Connection {
+        &mut self.connection
+    }
+    /// Unwraps this typed connection, returning the underlying connection.
+    pub fn into_inner(self) -> Connection {
+        self.connection
+    }
+
+    /// Gets the underlying connection, to send or receive a message without a fingerprint in the middle of a typed conversation.
+    /// The other side has to send or receive that message untyped as well. Nothing that has already been read is lost, so typed messages can be received again afterwards.
+    pub fn as_untyped(&mut self) -> &mut Connection {
+        &mut self.connection
+    }
+    /// Unwraps this typed connection to go back to untyped messages for good, keeping everything that has already been read.
+    pub fn into_untyped(self) -> Connection {
+        self.connection
+    }
+}
+
+/// A [`Connection`] that can only be received from, as returned by [`Connection::read_only`].
+/// This makes sure at compile time that nothing is sent through a connection that is meant to only go one way.
+///
+/// Messages are still acknowledged and the connection is still closed as usual, so the other side can use the connection like any other.
+///
+/// Sending through a read-only connection doesn't compile:
+/// ```compile_fail,E0599
+/// # fn send(connection: gipc::connection::sync::Connection) -> gipc::Result<()> {
+/// connection.read_only().send(&1u8)
+/// # }
+/// ```
+pub struct ReadOnlyConnection {
+    connection: Connection,
+}
+
+impl ReadOnlyConnection {
+    /// Receive a message.
+    /// Fails in the same way as [`Connection::receive`].
+    pub fn receive<T>(&mut self) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.connection.receive()
+    }
+    /// Receive a message, deserialising it using `codec`.
+    /// Fails in the same way as [`Connection::receive_with`].
+    pub fn receive_with<C, T>(&mut self, codec: &C) -> Result<T>
+    where
+        C: Codec + ?Sized,
+        T: DeserializeOwned,
+    {
+        self.connection.receive_with(codec)
+    }
+    /// Receive a message, returning `None` if it can't be deserialised.
+    /// Fails in the same way as [`Connection::receive_or_skip`].
+    pub fn receive_or_skip<T>(&mut self) -> Result<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        self.connection.receive_or_skip()
+    }
+    /// Gets the kind of the next message without receiving it, like [`Connection::peek_variant`].
+    pub fn peek_variant(&mut self) -> Result<MessageKind> {
+        self.connection.peek_variant()
+    }
+
+    /// Check if the connection is closed.
+    pub fn is_closed(&self) -> bool {
+        self.connection.is_closed()
+    }
+    /// Closes the connection if it isn't already closed, like [`Connection::close`].
+    pub fn close(&mut self) {
+        self.connection.close();
+    }
+
+    /// Unwraps this read-only connection, returning the underlying connection.
+    pub fn into_inner(self) -> Connection {
+        self.connection
+    }
+}
+
+/// A [`Connection`] that can only be sent through, as returned by [`Connection::write_only`].
+/// This makes sure at compile time that nothing is received from a connection that is meant to only go one way.
+///
+/// Receiving from a write-only connection doesn't compile:
+/// ```compile_fail,E0599
+/// # fn receive(connection: gipc::connection::sync::Connection) -> gipc::Result<u8> {
+/// connection.write_only().receive()
+/// # }
+/// ```
+pub struct WriteOnlyConnection {
+    connection: Connection,
+}
+
+impl WriteOnlyConnection {
+    /// Send a message.
+    /// Fails in the same way as [`Connection::send`].
+    pub fn send<T>(&mut self, message_data: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.connection.send(message_data)
+    }
+    /// Send a message, serialising it using `codec`.
+    /// Fails in the same way as [`Connection::send_with`].
+    pub fn send_with<C, T>(&mut self, codec: &C, message_data: &T) -> Result<()>
+    where
+        C: Codec + ?Sized,
+        T: Serialize + ?Sized,
+    {
+        self.connection.send_with(codec, message_data)
+    }
+    /// Send a signal with `code`.
+    /// Fails in the same way as [`Connection::send_signal`].
+    pub fn send_signal(&mut self, code: u8) -> Result<()> {
+        self.connection.send_signal(code)
+    }
+
+    /// Check if the connection is closed.
+    pub fn is_closed(&self) -> bool {
+        self.connection.is_closed()
+    }
+    /// Closes the connection if it isn't already closed, like [`Connection::close`].
+    pub fn close(&mut self) {
+        self.connection.close();
+    }
+
+    /// Unwraps this write-only connection, returning the underlying connection.
+    pub fn into_inner(self) -> Connection {
+        self.connection
+    }
+}
+
+/// A [`Connection`] that retries sending and receiving when they fail with a transient I/O error, as returned by [`Connection::with_retry`].
+/// Which errors count as transient, how long to wait between attempts and how many attempts to make are decided by its [`RetryPolicy`].
+///
+/// Receiving is safe to retry, since the part of a message that was read before the error is kept for the next attempt.
+/// Anything is only retried if nothing was written to the connection during the failed attempt, however -
+/// once part of a message has been written, writing it again would desynchronise the connection, so the error is returned instead.
+/// Once the policy runs out of attempts, the last error is returned, and once its timeout has passed, this fails with [`Error::Timeout`].
+pub struct RetryingConnection {
+    connection: Connection,
+    policy: RetryPolicy,
+}
+
+impl RetryingConnection {
+    /// Wraps `connection` so that sending and receiving are retried according to `policy`.
+    pub fn new(connection: Connection, policy: RetryPolicy) -> Self {
+        Self { connection, policy }
+    }
+
+    /// Sends a message like [`Connection::send`], retrying after transient errors.
+    pub fn send<T>(&mut self, message_data: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.retry(|connection| connection.send(message_data))
+    }
+    /// Receives a message like [`Connection::receive`], retrying after transient errors.
+    pub fn receive<T>(&mut self) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.retry(Connection::receive)
+    }
+    fn retry<R>(&mut self, mut operation: impl FnMut(&mut Connection) -> Result<R>) -> Result<R> {
+        let started = Instant::now();
+        let mut attempt = 0;
+        loop {
+            let written = self.connection.internal.bytes_written();
+            match operation(&mut self.connection) {
+                Err(error)
+                    if self.policy.is_transient(&error)
+                        && self.connection.internal.bytes_written() == written =>
+                {
+                    attempt += 1;
+                    let delay = self.policy.backoff.retry_delay(attempt, started, error)?;
+                    std::thread::sleep(delay);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Gets the policy that decides when sending and receiving are retried.
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+    /// Gets the underlying connection.
+    pub fn connection(&mut self) -> &mut Connection {
+        &mut self.connection
+    }
+    /// Unwraps this retrying connection, returning the underlying connection.
+    pub fn into_inner(self) -> Connection {
+        self.connection
+    }
+}
+
+/// Internal implementation for a [`Listener`].
+pub trait ListenerImpl {
+    /// Accept a new connection.
+    /// This function should _block_ until a connection can be established.
+    fn accept(&mut self) -> Result<Connection>;
+
+    /// Closes this listener implementation.
+    /// After this function is called, no more functions will be called from the implementation.
+    fn close(&mut self) -> Result<()>;
+
+    /// Enables or disables non-blocking mode for this listener implementation.
+    /// In non-blocking mode, [`accept`](Self::accept) fails with [`WouldBlock`](std::io::ErrorKind::WouldBlock) if there is no connection to accept.
+    ///
+    /// Not every implementation has to support this - the default implementation fails with [`Unsupported`](std::io::ErrorKind::Unsupported).
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        let _ = nonblocking;
+        Err(Error::Io(std::io::ErrorKind::Unsupported.into()))
+    }
+}
+
+impl ListenerImpl for LocalSocketListener {
+    fn accept(&mut self) -> Result<Connection> {
+        Ok(Connection::from(LocalSocketListener::accept(self)?))
+    }
+
+    fn close(&mut self) -> Result<()> {
+        // LocalSocketListener doesn't need to do anything when closing
+        Ok(())
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        Ok(LocalSocketListener::set_nonblocking(self, nonblocking)?)
+    }
+}
+
+impl From<LocalSocketListener> for Listener {
+    fn from(value: LocalSocketListener) -> Self {
+        Self::new(Box::new(value))
+    }
+}
+
+#[cfg(unix)]
+#[cfg_attr(docsrs, doc(cfg(unix)))]
+impl ListenerImpl for UnixListener {
+    fn accept(&mut self) -> Result<Connection> {
+        let (stream, _) = UnixListener::accept(self)?;
+        Ok(Connection::from(stream))
+    }
+
+    fn close(&mut self) -> Result<()> {
+        // The socket is closed when it's dropped
+        Ok(())
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        Ok(UnixListener::set_nonblocking(self, nonblocking)?)
+    }
+}
+
+#[cfg(unix)]
+#[cfg_attr(docsrs, doc(cfg(unix)))]
+impl From<UnixListener> for Listener {
+    fn from(value: UnixListener) -> Self {
+        Self::new(Box::new(value))
+    }
+}
+
+/// A named pipe that [`Listener::listen_on_pipe`] listens on.
+/// There is always one instance of the pipe waiting for a client, so that clients can connect between accepts.
+#[cfg(windows)]
+struct PipeServer {
+    config: PipeConfig,
+    waiting: std::os::windows::io::OwnedHandle,
+    nonblocking: bool,
+}
+
+#[cfg(windows)]
+impl PipeServer {
+    fn create(options: &PipeOptions) -> std::io::Result<Self> {
+        let config = PipeConfig::new(options)?;
+        let waiting = config.create_instance(true, false)?;
+        Ok(Self {
+            config,
+            waiting,
+            nonblocking: false,
+        })
+    }
+}
+
+#[cfg(windows)]
+impl ListenerImpl for PipeServer {
+    fn accept(&mut self) -> Result<Connection> {
+        use std::os::windows::io::{FromRawHandle, IntoRawHandle};
+        super::pipe::connect(&self.waiting)?;
+        let next = self.config.create_instance(false, self.nonblocking)?;
+        let connected = std::mem::replace(&mut self.waiting, next);
+        // SAFETY: the handle is a server instance of a byte-mode duplex pipe, and it isn't owned by anything else anymore
+        let stream = unsafe { DuplexBytePipeStream::from_raw_handle(connected.into_raw_handle()) };
+        Ok(Connection::new(Box::new(stream)))
+    }
+
+    fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        super::pipe::set_mode(&self.waiting, nonblocking)?;
+        self.nonblocking = nonblocking;
+        Ok(())
+    }
+}
+
+/// Gets the full path of the named pipe called `name`.
+#[cfg(windows)]
+fn pipe_path(name: &std::ffi::OsStr) -> ResolvedName {
+    let mut path = std::ffi::OsString::from(r"\\.\pipe\");
+    path.push(name);
+    ResolvedName::Path(path.into())
+}
+
 /// Internal implementation for a [`Connection`].
-pub trait ConnectionImpl: Read + Write {
+///
+/// Implementations have to be [`Send`], so that connections can be moved to other threads,
+/// which is what [`Connection::try_clone`] is for, and matches what the asynchronous `ConnectionImpl` requires.
+pub trait ConnectionImpl: Read + Write + Send {
     /// Closes this connection implementation.
     /// After this function is called, no more functions will be called from the implementation.
     fn close(&mut self);
+
+    /// Gets a short, human-readable name for the kind of transport this connection implementation runs over, as reported by [`Connection::describe`].
+    /// The default implementation uses the name of the type implementing this trait.
+    fn transport(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Enables or disables non-blocking mode for this connection implementation.
+    /// In non-blocking mode, reads and writes that can't complete immediately fail with [`WouldBlock`](std::io::ErrorKind::WouldBlock).
+    ///
+    /// Not every implementation has to support this - the default implementation fails with [`Unsupported`](std::io::ErrorKind::Unsupported).
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        let _ = nonblocking;
+        Err(Error::Io(std::io::ErrorKind::Unsupported.into()))
+    }
+
+    /// Sets the timeout for reads from this connection implementation, or removes it if `timeout` is `None`.
+    /// Reads that time out fail with [`WouldBlock`](std::io::ErrorKind::WouldBlock) or [`TimedOut`](std::io::ErrorKind::TimedOut).
+    ///
+    /// Not every implementation has to support this - the default implementation fails with [`Unsupported`](std::io::ErrorKind::Unsupported).
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        let _ = timeout;
+        Err(Error::Io(std::io::ErrorKind::Unsupported.into()))
+    }
+
+    /// Sets the timeout for writes to this connection implementation, or removes it if `timeout` is `None`.
+    /// Writes that time out fail with [`WouldBlock`](std::io::ErrorKind::WouldBlock) or [`TimedOut`](std::io::ErrorKind::TimedOut).
+    ///
+    /// Not every implementation has to support this - the default implementation fails with [`Unsupported`](std::io::ErrorKind::Unsupported).
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        let _ = timeout;
+        Err(Error::Io(std::io::ErrorKind::Unsupported.into()))
+    }
+
+    /// Gets the number of bytes that have been written to this connection implementation but haven't left the kernel's send buffer yet.
+    ///
+    /// Not every implementation has to support this - the default implementation fails with [`Unsupported`](std::io::ErrorKind::Unsupported).
+    fn unsent_bytes(&mut self) -> Result<usize> {
+        Err(Error::Io(std::io::ErrorKind::Unsupported.into()))
+    }
+
+    /// Gets the size of the kernel's buffer for data travelling through this connection implementation in `direction`, in bytes.
+    ///
+    /// Not every implementation has to support this - the default implementation fails with [`Unsupported`](std::io::ErrorKind::Unsupported).
+    fn socket_buffer_size(&mut self, direction: Direction) -> Result<usize> {
+        let _ = direction;
+        Err(Error::Io(std::io::ErrorKind::Unsupported.into()))
+    }
+
+    /// Sets the size of the kernel's buffer for data travelling through this connection implementation in `direction` to `size` bytes.
+    ///
+    /// Not every implementation has to support this - the default implementation fails with [`Unsupported`](std::io::ErrorKind::Unsupported).
+    fn set_socket_buffer_size(&mut self, direction: Direction, size: usize) -> Result<()> {
+        let _ = (direction, size);
+        Err(Error::Io(std::io::ErrorKind::Unsupported.into()))
+    }
+
+    /// Gets the credentials of the process on the other side of this connection implementation.
+    ///
+    /// Not every implementation has to support this - the default implementation fails with [`Unsupported`](std::io::ErrorKind::Unsupported).
+    fn peer_credentials(&mut self) -> Result<PeerCredentials> {
+        Err(Error::Io(std::io::ErrorKind::Unsupported.into()))
+    }
+
+    /// Creates another connection implementation that reads from and writes to the same underlying stream, as used by [`Connection::try_clone`].
+    ///
+    /// Not every implementation has to support this - the default implementation fails with [`Unsupported`](std::io::ErrorKind::Unsupported).
+    fn try_clone(&self) -> Result<Box<dyn ConnectionImpl>> {
+        Err(Error::Io(std::io::ErrorKind::Unsupported.into()))
+    }
+
+    /// Turns this connection implementation into an asynchronous one, as used by [`Connection::into_async`].
+    /// This has to be called from within a Tokio runtime.
+    ///
+    /// Not every implementation has to support this - the default implementation fails with [`Unsupported`](std::io::ErrorKind::Unsupported).
+    #[cfg(feature = "async-tokio")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "async-tokio")))]
+    fn into_async(self: Box<Self>) -> Result<Box<dyn AsyncConnectionImpl>> {
+        let _ = self;
+        Err(Error::Io(std::io::ErrorKind::Unsupported.into()))
+    }
 }
 
 impl ConnectionImpl for LocalSocketStream {
     fn close(&mut self) {
         let _ = self.flush();
     }
+
+    fn transport(&self) -> &'static str {
+        "local socket"
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        Ok(LocalSocketStream::set_nonblocking(self, nonblocking)?)
+    }
+
+    #[cfg(unix)]
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        Ok(as_unix_stream(self, |stream| {
+            stream.set_read_timeout(timeout)
+        })?)
+    }
+
+    #[cfg(unix)]
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        Ok(as_unix_stream(self, |stream| {
+            stream.set_write_timeout(timeout)
+        })?)
+    }
+
+    #[cfg(unix)]
+    fn unsent_bytes(&mut self) -> Result<usize> {
+        use std::os::unix::io::AsRawFd;
+        Ok(super::interprocess::unsent_bytes(self.as_raw_fd())?)
+    }
+
+    #[cfg(unix)]
+    fn socket_buffer_size(&mut self, direction: Direction) -> Result<usize> {
+        use std::os::unix::io::AsRawFd;
+        Ok(super::interprocess::socket_buffer_size(
+            self.as_raw_fd(),
+            direction,
+        )?)
+    }
+
+    #[cfg(unix)]
+    fn set_socket_buffer_size(&mut self, direction: Direction, size: usize) -> Result<()> {
+        use std::os::unix::io::AsRawFd;
+        Ok(super::interprocess::set_socket_buffer_size(
+            self.as_raw_fd(),
+            direction,
+            size,
+        )?)
+    }
+
+    #[cfg(unix)]
+    fn peer_credentials(&mut self) -> Result<PeerCredentials> {
+        use std::os::unix::io::AsRawFd;
+        Ok(super::interprocess::peer_credentials(self.as_raw_fd())?)
+    }
+
+    #[cfg(unix)]
+    fn try_clone(&self) -> Result<Box<dyn ConnectionImpl>> {
+        use std::os::unix::io::{FromRawFd, IntoRawFd};
+        let stream = as_unix_stream(self, std::os::unix::net::UnixStream::try_clone)?;
+        // SAFETY: the descriptor was just duplicated from a connected local socket, and nothing else owns it
+        Ok(Box::new(unsafe {
+            LocalSocketStream::from_raw_fd(stream.into_raw_fd())
+        }))
+    }
+
+    #[cfg(all(unix, feature = "async-tokio"))]
+    fn into_async(self: Box<Self>) -> Result<Box<dyn AsyncConnectionImpl>> {
+        use std::os::unix::io::{FromRawFd, IntoRawFd};
+        // SAFETY: the descriptor comes from a connected Unix domain socket that we own
+        let stream = unsafe { UnixStream::from_raw_fd(self.into_raw_fd()) };
+        Box::new(stream).into_async()
+    }
+}
+
+#[cfg(unix)]
+#[cfg_attr(docsrs, doc(cfg(unix)))]
+impl ConnectionImpl for UnixStream {
+    fn close(&mut self) {
+        let _ = self.flush();
+    }
+
+    fn transport(&self) -> &'static str {
+        "unix socket"
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        Ok(UnixStream::set_nonblocking(self, nonblocking)?)
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        Ok(UnixStream::set_read_timeout(self, timeout)?)
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        Ok(UnixStream::set_write_timeout(self, timeout)?)
+    }
+
+    fn unsent_bytes(&mut self) -> Result<usize> {
+        use std::os::unix::io::AsRawFd;
+        Ok(super::interprocess::unsent_bytes(self.as_raw_fd())?)
+    }
+
+    fn socket_buffer_size(&mut self, direction: Direction) -> Result<usize> {
+        use std::os::unix::io::AsRawFd;
+        Ok(super::interprocess::socket_buffer_size(
+            self.as_raw_fd(),
+            direction,
+        )?)
+    }
+
+    fn set_socket_buffer_size(&mut self, direction: Direction, size: usize) -> Result<()> {
+        use std::os::unix::io::AsRawFd;
+        Ok(super::interprocess::set_socket_buffer_size(
+            self.as_raw_fd(),
+            direction,
+            size,
+        )?)
+    }
+
+    fn peer_credentials(&mut self) -> Result<PeerCredentials> {
+        use std::os::unix::io::AsRawFd;
+        Ok(super::interprocess::peer_credentials(self.as_raw_fd())?)
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn ConnectionImpl>> {
+        Ok(Box::new(UnixStream::try_clone(self)?))
+    }
+
+    #[cfg(feature = "async-tokio")]
+    fn into_async(self: Box<Self>) -> Result<Box<dyn AsyncConnectionImpl>> {
+        use tokio_util::compat::TokioAsyncReadCompatExt;
+        self.set_nonblocking(true)?;
+        Ok(Box::new(tokio::net::UnixStream::from_std(*self)?.compat()))
+    }
+}
+
+#[cfg(unix)]
+#[cfg_attr(docsrs, doc(cfg(unix)))]
+impl From<UnixStream> for Connection {
+    fn from(value: UnixStream) -> Self {
+        Connection::new(Box::new(value))
+    }
+}
+
+/// Calls `f` with a [`UnixStream`](std::os::unix::net::UnixStream) that shares `stream`'s socket,
+/// to make use of socket options that [`LocalSocketStream`] doesn't expose.
+#[cfg(unix)]
+fn as_unix_stream<R>(
+    stream: &LocalSocketStream,
+    f: impl FnOnce(&std::os::unix::net::UnixStream) -> std::io::Result<R>,
+) -> std::io::Result<R> {
+    use std::mem::ManuallyDrop;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use std::os::unix::net::UnixStream;
+    // SAFETY: the descriptor stays open for as long as `stream` is borrowed,
+    // and wrapping it in ManuallyDrop prevents it from being closed when we're done
+    let unix_stream = ManuallyDrop::new(unsafe { UnixStream::from_raw_fd(stream.as_raw_fd()) });
+    f(&unix_stream)
+}
+
+#[cfg(windows)]
+#[cfg_attr(docsrs, doc(cfg(windows)))]
+impl ConnectionImpl for DuplexBytePipeStream {
+    fn close(&mut self) {
+        let _ = self.flush();
+    }
+
+    fn transport(&self) -> &'static str {
+        "named pipe"
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        Ok(DuplexBytePipeStream::set_nonblocking(self, nonblocking)?)
+    }
 }
 
 impl From<LocalSocketStream> for Connection {
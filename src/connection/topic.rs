@@ -0,0 +1,131 @@
+//! Publishing messages to every process that subscribes to a socket.
+
+use super::sync::{Connection, Listener};
+use crate::{Error, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+/// The publishing side of a topic, which sends every message to all of its [`Subscriber`]s.
+///
+/// Subscribers that have connected are picked up whenever a message is published, so a subscriber receives every message published after it connected,
+/// but none of the messages published before.
+/// Subscribers that can't be sent to anymore, e.g. because their process has exited, are dropped.
+///
+/// Messages are sent to one subscriber after another, so a subscriber that doesn't keep up with receiving them holds up publishing once its connection's buffer is full.
+pub struct Publisher<T> {
+    listener: Listener,
+    subscribers: Vec<Connection>,
+    _message: PhantomData<fn(&T)>,
+}
+
+impl<T> Publisher<T>
+where
+    T: Serialize,
+{
+    /// Creates a publisher that subscribers can reach through a socket with a name based on `name`,
+    /// as created by [`Listener::listen_as_socket`].
+    pub fn listen_as_socket<S>(name: S, global: bool) -> Result<Self>
+    where
+        S: AsRef<str>,
+    {
+        Ok(Self::new(Listener::listen_as_socket(name, global)?))
+    }
+    /// Creates a publisher that accepts subscribers using `listener`.
+    ///
+    /// Requires the underlying [`ListenerImpl`](super::sync::ListenerImpl) to support [non-blocking mode](super::sync::ListenerImpl::set_nonblocking).
+    pub fn new(listener: Listener) -> Self {
+        Self {
+            listener,
+            subscribers: Vec::new(),
+            _message: PhantomData,
+        }
+    }
+
+    /// Sends `message` to every subscriber, returning how many subscribers it was sent to.
+    /// Subscribers that connected since the last message are added first, and subscribers that the message couldn't be sent to are dropped.
+    pub fn publish(&mut self, message: &T) -> Result<usize> {
+        self.accept_pending()?;
+        self.subscribers
+            .retain_mut(|subscriber| subscriber.send(message).is_ok());
+        Ok(self.subscribers.len())
+    }
+    /// Adds every subscriber that has connected but hasn't been added yet, without waiting for more, returning how many were added.
+    /// This is done automatically by [`publish`](Self::publish).
+    pub fn accept_pending(&mut self) -> Result<usize> {
+        let mut count = 0;
+        while let Some(subscriber) = self.listener.accept_timeout(Duration::ZERO)? {
+            self.subscribers.push(subscriber);
+            count += 1;
+        }
+        Ok(count)
+    }
+    /// Gets the number of subscribers that messages are currently sent to.
+    /// Subscribers that have connected since the last message was published aren't included.
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    /// Gets the listener that subscribers are accepted from.
+    pub fn listener(&mut self) -> &mut Listener {
+        &mut self.listener
+    }
+}
+
+/// The subscribing side of a topic, which receives the messages sent by a [`Publisher`].
+pub struct Subscriber<T> {
+    connection: Connection,
+    _message: PhantomData<fn() -> T>,
+}
+
+impl<T> Subscriber<T>
+where
+    T: DeserializeOwned,
+{
+    /// Subscribes to the publisher using the socket with a name based on `name`, as connected to by [`Connection::connect_to_socket`].
+    /// Only messages that are published from now on are received.
+    pub fn connect_to_socket<S>(name: S, global: bool) -> Result<Self>
+    where
+        S: AsRef<str>,
+    {
+        Ok(Self::new(Connection::connect_to_socket(name, global)?))
+    }
+    /// Creates a subscriber that receives messages through `connection`.
+    pub fn new(connection: Connection) -> Self {
+        Self {
+            connection,
+            _message: PhantomData,
+        }
+    }
+
+    /// Receives the next message from the publisher, waiting for it to be published if necessary.
+    /// Fails with [`Error::Closed(true)`](Error::Closed) once the publisher has gone away, and otherwise in the same way as [`Connection::receive`].
+    pub fn receive(&mut self) -> Result<T> {
+        self.connection.receive()
+    }
+
+    /// Gets the connection to the publisher.
+    pub fn connection(&mut self) -> &mut Connection {
+        &mut self.connection
+    }
+    /// Gets the connection to the publisher, consuming this subscriber.
+    pub fn into_inner(self) -> Connection {
+        self.connection
+    }
+}
+
+impl<T> Iterator for Subscriber<T>
+where
+    T: DeserializeOwned,
+{
+    type Item = Result<T>;
+
+    /// Receives the next message, ending once the publisher has gone away.
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.receive() {
+            Err(Error::Closed(_) | Error::ClosedWithReason { .. }) => None,
+            result => Some(result),
+        }
+    }
+}
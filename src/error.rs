@@ -11,12 +11,38 @@ pub enum Error {
         #[source]
         std::io::Error,
     ),
+    /// Indicates that a listener couldn't bind to a socket.
+    #[error("couldn't bind to {name}: {source}")]
+    Bind {
+        /// The resolved name of the socket.
+        name: String,
+        /// The I/O error that occurred.
+        #[source]
+        source: std::io::Error,
+    },
+    /// Indicates that a connection couldn't connect to a socket.
+    #[error("couldn't connect to {name}: {source}")]
+    Connect {
+        /// The resolved name of the socket.
+        name: String,
+        /// The I/O error that occurred.
+        #[source]
+        source: std::io::Error,
+    },
     /// Indicates that there was a serialisation error.
     #[error("serialisation: {0}")]
-    Serialise(#[doc = "The message of the internal error."] String),
+    Serialise(
+        #[doc = "The internal error, usually from the codec."]
+        #[source]
+        BoxError,
+    ),
     /// Indicates that there was a deserialisation error.
     #[error("deserialisation: {0}")]
-    Deserialise(#[doc = "The message of the internal error."] String),
+    Deserialise(
+        #[doc = "The internal error, usually from the codec."]
+        #[source]
+        BoxError,
+    ),
     /// Indicates that tokio could not join a task.
     #[cfg(feature = "async-tokio")]
     #[error("tokio join failed: {0}")]
@@ -25,12 +51,160 @@ pub enum Error {
         #[from]
         tokio::task::JoinError,
     ),
+    /// Indicates that a message was sent with a different schema version than the one expected.
+    #[error("schema mismatch: expected version {expected}, got version {got}")]
+    SchemaMismatch {
+        /// The schema version that the receiver expected.
+        expected: u16,
+        /// The schema version that the message was sent with.
+        got: u16,
+    },
+    /// Indicates that a message was sent as a different type than the one it was received as, as detected by a `TypedConnection`.
+    #[cfg(feature = "type-check")]
+    #[error("type mismatch: expected fingerprint {expected:#018x}, got fingerprint {got:#018x}")]
+    TypeMismatch {
+        /// The [fingerprint](crate::message::fingerprint) of the type or schema that the receiver expected.
+        expected: u64,
+        /// The [fingerprint](crate::message::fingerprint) of the type or schema that the message was sent as.
+        got: u64,
+    },
+    /// Indicates that a signal was received instead of a message.
+    #[error("received signal {0}")]
+    Signal(#[doc = "The code of the signal."] u8),
+    /// Indicates that an acknowledgement was received that nothing was waiting for,
+    /// e.g. because `send_acked` stopped waiting for it before it arrived.
+    #[error("received an acknowledgement that wasn't waited for")]
+    UnexpectedAck,
+    /// Indicates that a message is too large to be sent, because its length can't be represented by the length prefix of its frame,
+    /// or because it is larger than the other side accepts receiving according to `peer_max_message_size` on connections.
+    #[error("message of {0} bytes is too large to be sent")]
+    MessageTooLarge(#[doc = "The length of the serialised message in bytes."] usize),
+    /// Indicates that a frame was received whose length prefix claims a message larger than the connection's maximum message size or too large to be allocated,
+    /// which usually means that the two sides are out of sync and the bytes being read as a length prefix aren't one.
+    /// The connection can't be received from anymore, so it should be closed.
+    #[error("received a length prefix of {0} bytes, so the stream is likely out of sync")]
+    ProtocolDesync(
+        #[doc = "The length the prefix claims, or how much of the frame had been read if it is streamed."]
+        u64,
+    ),
+    /// Indicates that a connection couldn't be reset because it is partway through a frame,
+    /// either because part of a frame has been received but not the rest of it, or because sending a message failed after part of its frame had been written.
+    #[error("the connection is partway through a frame")]
+    MidFrame,
+    /// Indicates that no directory could be found to create a non-global socket in.
+    /// A directory can be set using [`set_runtime_dir`](crate::connection::set_runtime_dir).
+    #[error("no runtime directory could be found for the socket")]
+    NoRuntimeDir,
+    /// Indicates that the name a socket resolved to is too long for the platform, even after the name it was given was replaced by a hash.
+    /// This happens when the [runtime directory](crate::connection::set_runtime_dir) or [suffix](crate::connection::set_socket_suffix) alone is too long.
+    #[error("socket name {0} is too long for this platform")]
+    NameTooLong(#[doc = "The name the socket resolved to."] String),
+    /// Indicates that the other side of a connection failed the shared-secret handshake, or that this side did.
+    #[cfg(feature = "auth")]
+    #[error("authentication failed")]
+    AuthFailed,
+    /// Indicates that an operation didn't complete before its deadline.
+    #[error("deadline exceeded")]
+    Timeout,
     /// Indicates that something is closed.
     #[error("{}", if *.0 { "was closed by operation" } else { "already closed" })]
     Closed(
         #[doc = "Whether it was closed by the operation (`true`) or was already closed (`false`)"]
         bool,
     ),
+    /// Indicates that the other side closed the connection using `close_with_reason`, saying why it did.
+    /// Like [`Error::Closed(true)`](Error::Closed), the connection has been closed by the operation that fails with this.
+    #[error("was closed by the other side with code {}", .code.0)]
+    ClosedWithReason {
+        /// The code the other side closed the connection with.
+        code: crate::connection::CloseCode,
+        /// The message the other side closed the connection with, if any.
+        message: Option<String>,
+    },
 }
+
+impl Error {
+    /// Whether this error is likely to go away if the operation that caused it is retried.
+    /// Only I/O errors can be transient, and only some kinds of them are.
+    pub(crate) fn is_transient(&self) -> bool {
+        use std::io::ErrorKind::*;
+        match self {
+            Error::Io(error)
+            | Error::Bind { source: error, .. }
+            | Error::Connect { source: error, .. } => matches!(
+                error.kind(),
+                NotFound | AddrInUse | Interrupted | WouldBlock
+            ),
+            _ => false,
+        }
+    }
+
+    /// Whether this error, returned when accepting a connection, is likely to go away if accepting is retried.
+    /// This covers running out of file descriptors or memory, and connections that were aborted before they could be accepted.
+    #[cfg(feature = "async-tokio")]
+    pub(crate) fn is_transient_accept(&self) -> bool {
+        use std::io::ErrorKind::*;
+        let Error::Io(error) = self else {
+            return false;
+        };
+        if matches!(
+            error.kind(),
+            ConnectionAborted | ConnectionReset | Interrupted | WouldBlock | TimedOut | OutOfMemory
+        ) {
+            return true;
+        }
+        #[cfg(unix)]
+        if let Some(code) = error.raw_os_error() {
+            return matches!(
+                code,
+                libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM
+            );
+        }
+        false
+    }
+}
+
+/// A boxed error of any type, as held by [`Error::Serialise`] and [`Error::Deserialise`].
+/// Plain messages can be turned into one using [`Into`].
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
 /// Result type for this library. Shorthand for [`std::result::Result<T, Error>`].
 pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn only_temporary_io_errors_are_transient() {
+        for kind in [
+            ErrorKind::NotFound,
+            ErrorKind::AddrInUse,
+            ErrorKind::Interrupted,
+            ErrorKind::WouldBlock,
+        ] {
+            assert!(Error::Io(kind.into()).is_transient(), "{:?}", kind);
+        }
+        for kind in [
+            ErrorKind::PermissionDenied,
+            ErrorKind::InvalidInput,
+            ErrorKind::AddrNotAvailable,
+        ] {
+            assert!(!Error::Io(kind.into()).is_transient(), "{:?}", kind);
+        }
+        assert!(!Error::NoRuntimeDir.is_transient());
+        assert!(!Error::Timeout.is_transient());
+    }
+
+    #[test]
+    fn codec_errors_are_kept_as_sources() {
+        use crate::codec::{CborCodec, Codec};
+        use std::error::Error as _;
+        let error = CborCodec.deserialise::<String>(&[0xff]).unwrap_err();
+        assert!(matches!(error, Error::Deserialise(_)));
+        let source = error.source().expect("the codec error is the source");
+        assert!(source.is::<ciborium::de::Error<std::io::Error>>());
+        assert_eq!(error.to_string(), format!("deserialisation: {}", source));
+    }
+}
@@ -9,10 +9,12 @@
 //! ## Crate structure
 //!
 //! The [`connection`] module handles all things related to receiving and sending data between your programs.
-//! This is aided by the [`message`] module, which describes the communication protocol that it uses.
+//! This is aided by the [`message`] module, which describes the communication protocol that it uses,
+//! and the [`codec`] module, which describes how the messages themselves are encoded.
 //!
 //! Any errors the crate can return are in the [`error`] module.
 
+pub mod codec;
 pub mod connection;
 pub mod error;
 pub mod message;
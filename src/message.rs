@@ -4,9 +4,12 @@
 #[cfg(feature = "async-tokio")]
 use futures_io::{AsyncRead, AsyncWrite};
 use serde::{de::DeserializeOwned, Deserialize, Serialize}; // NOTE(tecc): Keeping Deserialize allows compatibility with older versions of Ciborium
+#[cfg(feature = "sync")]
 use std::io::{Read, Write};
 
-use crate::{Error, Result};
+use crate::codec::{CborCodec, Codec};
+use crate::connection::CloseCode;
+use crate::Result;
 
 type Endian = byteorder::BigEndian;
 
@@ -15,39 +18,190 @@ type Endian = byteorder::BigEndian;
 /// It is also not exposed as this module does not have any relation to any users of gipc.
 mod raw {
     use super::Endian;
-    use crate::Result;
+    use crate::{Error, Result};
     #[cfg(feature = "async-tokio")]
     use futures_io::{AsyncRead, AsyncWrite};
+    use std::io::Read;
     #[cfg(feature = "sync")]
-    use std::io::{Read, Write};
+    use std::io::Write;
     use std::mem::size_of;
     #[cfg(feature = "async-tokio")]
     use tokio::io::{AsyncReadExt, AsyncWriteExt};
     #[cfg(feature = "async-tokio")]
     use tokio_util::compat::Compat;
 
-    fn serialised_vec(data: &Vec<u8>) -> Result<Vec<u8>> {
+    /// The length prefix of streamed frames.
+    /// Instead of being sent all at once, the payload of a streamed frame is sent in chunks that are each prefixed with their length,
+    /// and the end of the payload is marked by an empty chunk.
+    pub const STREAMED: u64 = u64::MAX;
+
+    /// Gets the length prefix for a payload or chunk of `length` bytes.
+    /// Fails with [`Error::MessageTooLarge`] if the length can't be represented by the prefix,
+    /// which includes the length that is reserved for [`STREAMED`] frames.
+    pub fn length_prefix(length: usize) -> Result<u64> {
+        match u64::try_from(length) {
+            Ok(prefix) if prefix != STREAMED => Ok(prefix),
+            _ => Err(Error::MessageTooLarge(length)),
+        }
+    }
+
+    /// Gets the length of the first frame in `bytes`, including its length prefix.
+    /// Returns `None` if `bytes` doesn't contain the entire length prefix yet,
+    /// or if the frame is streamed and `bytes` doesn't contain all of its chunks yet.
+    pub fn frame_length(bytes: &[u8]) -> Option<usize> {
+        use byteorder::ByteOrder;
+        let length = Endian::read_u64(bytes.get(..size_of::<u64>())?);
+        if length != STREAMED {
+            return (length as usize).checked_add(size_of::<u64>());
+        }
+        let mut offset = size_of::<u64>();
+        loop {
+            let end = offset.checked_add(size_of::<u64>())?;
+            let chunk = usize::try_from(Endian::read_u64(bytes.get(offset..end)?)).ok()?;
+            offset = end;
+            if chunk == 0 {
+                return Some(offset);
+            }
+            offset = offset.checked_add(chunk)?;
+        }
+    }
+
+    /// Gets the length of the payload of the first frame in `bytes` as declared by its length prefix.
+    /// Returns `None` if `bytes` doesn't contain the entire length prefix yet, or if the frame is streamed and so has no declared length.
+    pub fn declared_length(bytes: &[u8]) -> Option<u64> {
+        use byteorder::ByteOrder;
+        let length = Endian::read_u64(bytes.get(..size_of::<u64>())?);
+        (length != STREAMED).then_some(length)
+    }
+
+    /// Gets the length of the payload of the first frame in `bytes` according to its length prefix,
+    /// or how much of the frame `bytes` contains after the length prefix if the frame is streamed.
+    /// Returns `None` if `bytes` doesn't contain the entire length prefix yet.
+    pub fn claimed_length(bytes: &[u8]) -> Option<u64> {
+        use byteorder::ByteOrder;
+        let length = Endian::read_u64(bytes.get(..size_of::<u64>())?);
+        if length == STREAMED {
+            return Some((bytes.len() - size_of::<u64>()) as u64);
+        }
+        Some(length)
+    }
+
+    /// The size from which payloads are written together with their length prefix using a vectored write,
+    /// rather than by copying both into one buffer first.
+    /// Below this, the copy is cheap enough that it isn't worth risking an extra write on writers without vectored writes.
+    pub const VECTORED_WRITE_THRESHOLD: usize = 4096;
+
+    /// Frames `data` by prefixing it with its length.
+    pub fn serialised_vec(data: &Vec<u8>) -> Result<Vec<u8>> {
         use byteorder::WriteBytesExt;
         #[cfg(not(feature = "sync"))]
         use std::io::Write;
         let mut complete = Vec::new();
         complete.reserve_exact(data.len() + size_of::<u64>());
-        WriteBytesExt::write_u64::<Endian>(&mut complete, data.len() as u64)?;
+        WriteBytesExt::write_u64::<Endian>(&mut complete, length_prefix(data.len())?)?;
         Write::write_all(&mut complete, data.as_slice())?;
         Ok(complete)
     }
 
-    #[cfg(feature = "sync")]
+    /// Turns the streamed frame in `frame` into an ordinary frame holding the same payload, by moving its chunks together in place.
+    /// Fails with [`Error::ProtocolDesync`] if a chunk claims to extend past the end of `frame`.
+    pub fn unchunk(frame: &mut Vec<u8>) -> Result<()> {
+        use byteorder::ByteOrder;
+        let prefix = size_of::<u64>();
+        let desync = Error::ProtocolDesync(frame.len().saturating_sub(prefix) as u64);
+        let (mut read, mut written) = (prefix, prefix);
+        loop {
+            let Some(end) = read.checked_add(prefix).filter(|end| *end <= frame.len()) else {
+                return Err(desync);
+            };
+            let chunk = Endian::read_u64(&frame[read..end]);
+            read = end;
+            if chunk == 0 {
+                break;
+            }
+            let Some(end) = usize::try_from(chunk)
+                .ok()
+                .and_then(|chunk| read.checked_add(chunk))
+                .filter(|end| *end <= frame.len())
+            else {
+                return Err(desync);
+            };
+            frame.copy_within(read..end, written);
+            written += end - read;
+            read = end;
+        }
+        frame.truncate(written);
+        Endian::write_u64(&mut frame[..prefix], (written - prefix) as u64);
+        Ok(())
+    }
+
+    /// Checks that another `length` bytes can be added to the payload in `vector`, failing with [`Error::ProtocolDesync`]
+    /// if that makes the payload longer than `max_len` or too large to ever be allocated.
+    fn check_payload(vector: &[u8], length: u64, max_len: Option<usize>) -> Result<()> {
+        let total = (vector.len() as u64).saturating_add(length);
+        let too_large = match max_len {
+            Some(max) => total > max as u64,
+            None => total > isize::MAX as u64,
+        };
+        match too_large {
+            true => Err(Error::ProtocolDesync(total)),
+            false => Ok(()),
+        }
+    }
+    /// Reads another `length` bytes of the payload of a frame from `reader` onto the end of `vector`.
+    /// The vector grows as the bytes arrive rather than by `length` up front,
+    /// so a length that the other side never sends the bytes for doesn't allocate anything for them.
+    /// Fails with [`UnexpectedEof`](std::io::ErrorKind::UnexpectedEof) if `reader` ends first.
+    fn read_payload<R>(reader: &mut R, vector: &mut Vec<u8>, length: u64) -> Result<()>
+    where
+        R: Read,
+    {
+        let read = reader.take(length).read_to_end(vector)?;
+        if (read as u64) < length {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        Ok(())
+    }
+    /// Reads another `length` bytes of the payload of a frame from `reader` onto the end of `vector` asynchronously,
+    /// growing it as the bytes arrive like [`read_payload`] does.
+    #[cfg(feature = "async-tokio")]
+    async fn read_payload_async<R>(
+        reader: &mut Compat<R>,
+        vector: &mut Vec<u8>,
+        length: u64,
+    ) -> Result<()>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let read = reader.take(length).read_to_end(vector).await?;
+        if (read as u64) < length {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        Ok(())
+    }
+
     /// Reads a message from `reader`.
-    pub fn read_from<R>(reader: &mut R) -> Result<Vec<u8>>
+    /// Fails with [`Error::ProtocolDesync`] if its payload is longer than `max_len`, which for streamed frames is checked for every chunk before it is read.
+    pub fn read_from<R>(reader: &mut R, max_len: Option<usize>) -> Result<Vec<u8>>
     where
         R: Read,
     {
         use byteorder::ReadBytesExt;
-        let size = reader.read_u64::<Endian>()? as usize;
-        let mut vector = vec![0u8; size];
-        reader.read_exact(vector.as_mut_slice())?;
-        Ok(vector)
+        let size = reader.read_u64::<Endian>()?;
+        let mut vector = Vec::new();
+        if size != STREAMED {
+            check_payload(&vector, size, max_len)?;
+            read_payload(reader, &mut vector, size)?;
+            return Ok(vector);
+        }
+        loop {
+            let chunk = reader.read_u64::<Endian>()?;
+            if chunk == 0 {
+                return Ok(vector);
+            }
+            check_payload(&vector, chunk, max_len)?;
+            read_payload(reader, &mut vector, chunk)?;
+        }
     }
     /// Writes bytes to `writer` asynchronously.
     #[cfg(feature = "sync")]
@@ -55,21 +209,73 @@ mod raw {
     where
         W: Write,
     {
-        let complete = serialised_vec(data)?;
-        writer.write_all(complete.as_slice())?;
+        use std::io::{ErrorKind, IoSlice};
+        if data.len() < VECTORED_WRITE_THRESHOLD {
+            let complete = serialised_vec(data)?;
+            writer.write_all(complete.as_slice())?;
+            return Ok(());
+        }
+        let prefix = length_prefix(data.len())?.to_be_bytes();
+        let mut written = 0;
+        while written < prefix.len() {
+            match writer.write_vectored(&[IoSlice::new(&prefix[written..]), IoSlice::new(data)]) {
+                Ok(0) => return Err(std::io::Error::from(ErrorKind::WriteZero).into()),
+                Ok(count) => written += count,
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => return Err(error.into()),
+            }
+        }
+        writer.write_all(&data[written - prefix.len()..])?;
+        Ok(())
+    }
+    /// Writes the length prefix of a streamed frame to `writer`.
+    #[cfg(feature = "sync")]
+    pub fn write_stream_start<W>(writer: &mut W) -> Result<()>
+    where
+        W: Write,
+    {
+        use byteorder::WriteBytesExt;
+        writer.write_u64::<Endian>(STREAMED)?;
+        Ok(())
+    }
+    /// Writes `data` to `writer` as a chunk of a streamed frame.
+    /// Writing an empty chunk ends the frame.
+    #[cfg(feature = "sync")]
+    pub fn write_chunk<W>(writer: &mut W, data: &[u8]) -> Result<()>
+    where
+        W: Write,
+    {
+        use byteorder::WriteBytesExt;
+        writer.write_u64::<Endian>(length_prefix(data.len())?)?;
+        writer.write_all(data)?;
         Ok(())
     }
 
     /// Reads bytes from `reader` asynchronously.
+    /// Fails in the same way as [`read_from`].
     #[cfg(feature = "async-tokio")]
-    pub async fn read_from_async<R>(reader: &mut Compat<R>) -> Result<Vec<u8>>
+    pub async fn read_from_async<R>(
+        reader: &mut Compat<R>,
+        max_len: Option<usize>,
+    ) -> Result<Vec<u8>>
     where
         R: AsyncRead + Unpin,
     {
-        let size = reader.read_u64().await? as usize;
-        let mut vector = vec![0u8; size];
-        reader.read_exact(vector.as_mut_slice()).await?;
-        Ok(vector)
+        let size = reader.read_u64().await?;
+        let mut vector = Vec::new();
+        if size != STREAMED {
+            check_payload(&vector, size, max_len)?;
+            read_payload_async(reader, &mut vector, size).await?;
+            return Ok(vector);
+        }
+        loop {
+            let chunk = reader.read_u64().await?;
+            if chunk == 0 {
+                return Ok(vector);
+            }
+            check_payload(&vector, chunk, max_len)?;
+            read_payload_async(reader, &mut vector, chunk).await?;
+        }
     }
     /// Write `data` to `writer` asynchronously.
     #[cfg(feature = "async-tokio")]
@@ -77,24 +283,499 @@ mod raw {
     where
         W: AsyncWrite + Unpin,
     {
-        let vec = serialised_vec(data)?;
-        writer.write_all(vec.as_slice()).await?;
+        use std::io::{ErrorKind, IoSlice};
+        if data.len() < VECTORED_WRITE_THRESHOLD {
+            let vec = serialised_vec(data)?;
+            writer.write_all(vec.as_slice()).await?;
+            return Ok(());
+        }
+        let prefix = length_prefix(data.len())?.to_be_bytes();
+        let mut written = 0;
+        while written < prefix.len() {
+            let slices = [IoSlice::new(&prefix[written..]), IoSlice::new(data)];
+            match writer.write_vectored(&slices).await {
+                Ok(0) => return Err(std::io::Error::from(ErrorKind::WriteZero).into()),
+                Ok(count) => written += count,
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => return Err(error.into()),
+            }
+        }
+        writer.write_all(&data[written - prefix.len()..]).await?;
         Ok(())
     }
+    /// Writes the length prefix of a streamed frame to `writer` asynchronously.
+    #[cfg(feature = "async-tokio")]
+    pub async fn write_stream_start_async<W>(writer: &mut Compat<W>) -> Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        writer.write_u64(STREAMED).await?;
+        Ok(())
+    }
+    /// Writes `data` to `writer` as a chunk of a streamed frame asynchronously.
+    /// Writing an empty chunk ends the frame.
+    #[cfg(feature = "async-tokio")]
+    pub async fn write_chunk_async<W>(writer: &mut Compat<W>, data: &[u8]) -> Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        writer.write_u64(length_prefix(data.len())?).await?;
+        writer.write_all(data).await?;
+        Ok(())
+    }
+}
+
+pub(crate) use raw::{claimed_length, declared_length, frame_length};
+
+/// Reads the payload of a frame from `reader`, however long the frame claims it is.
+#[cfg(feature = "sync")]
+fn read_payload<R>(reader: &mut R) -> Result<Vec<u8>>
+where
+    R: Read,
+{
+    raw::read_from(reader, None)
+}
+
+/// Reads the payload of a frame from `reader` asynchronously, however long the frame claims it is.
+#[cfg(feature = "async-tokio")]
+async fn read_payload_async<R>(reader: R) -> Result<Vec<u8>>
+where
+    R: AsyncRead + Unpin,
+{
+    use tokio_util::compat::FuturesAsyncReadCompatExt;
+    raw::read_from_async(&mut reader.compat(), None).await
+}
+
+/// The payload of a frame read by a connection, which is kept in the frame it was read in rather than being copied out of it.
+/// It dereferences to the payload without its length prefix.
+pub(crate) struct Payload {
+    /// A frame that isn't streamed, holding nothing but its length prefix and the payload.
+    frame: Vec<u8>,
+}
+
+impl Payload {
+    /// Gets the frame holding this payload, e.g. to put it back into a connection's read buffer.
+    pub fn into_frame(self) -> Vec<u8> {
+        self.frame
+    }
+    /// Gets `range` of this payload as [`Bytes`](bytes::Bytes) that share their storage with the frame.
+    #[cfg(feature = "bytes")]
+    pub fn into_bytes(self, range: std::ops::Range<usize>) -> bytes::Bytes {
+        let prefix = std::mem::size_of::<u64>();
+        bytes::Bytes::from(self.frame).slice(prefix + range.start..prefix + range.end)
+    }
+}
+
+impl std::ops::Deref for Payload {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.frame[std::mem::size_of::<u64>()..]
+    }
+}
+
+/// Gets the payload of `frame`, a frame including its length prefix, as read by a connection.
+/// The payload is left where it is in `frame`, apart from the chunks of streamed frames, which are moved together in place.
+/// Fails with [`Error::ProtocolDesync`](crate::Error::ProtocolDesync) if the frame claims to be longer than it is.
+pub(crate) fn frame_payload(mut frame: Vec<u8>) -> Result<Payload> {
+    match declared_length(&frame) {
+        Some(length) => {
+            let end = sized_frame_end(&frame).ok_or(crate::Error::ProtocolDesync(length))?;
+            frame.truncate(end);
+        }
+        None => raw::unchunk(&mut frame)?,
+    }
+    Ok(Payload { frame })
+}
+
+/// Gets where the payload of `frame` ends if the frame isn't streamed and holds its entire payload.
+fn sized_frame_end(frame: &[u8]) -> Option<usize> {
+    let length = usize::try_from(declared_length(frame)?).ok()?;
+    length
+        .checked_add(std::mem::size_of::<u64>())
+        .filter(|end| *end <= frame.len())
+}
+
+/// Checks whether `frame`, a frame including its length prefix, holds a [`Message::Priority`] when deserialised using `codec`.
+pub(crate) fn is_priority_frame<C>(codec: &C, frame: &[u8]) -> bool
+where
+    C: Codec + ?Sized,
+{
+    let deserialise = |payload: &[u8]| codec.deserialise::<Message<serde::de::IgnoredAny>>(payload);
+    let message = match sized_frame_end(frame) {
+        Some(end) => deserialise(&frame[std::mem::size_of::<u64>()..end]),
+        // streamed frames are rare, so they are simply copied to put their chunks together
+        None => match frame_payload(frame.to_vec()) {
+            Ok(payload) => deserialise(&payload),
+            Err(_) => return false,
+        },
+    };
+    matches!(message, Ok(Message::Priority(_)))
+}
+
+/// The longest payload that [`ping_or_pong`] deserialises - pings and pongs are far shorter than this with any sensible codec.
+pub(crate) const MAX_PING_LEN: usize = 16;
+
+/// Checks whether `payload` holds a [`Message::Ping`] or a [`Message::Pong`] when deserialised using `codec`, returning which.
+/// Only short payloads are deserialised, so this costs next to nothing for messages that carry data.
+pub(crate) fn ping_or_pong<C>(codec: &C, payload: &[u8]) -> Option<MessageKind>
+where
+    C: Codec + ?Sized,
+{
+    if payload.len() > MAX_PING_LEN {
+        return None;
+    }
+    match codec.deserialise::<Message<serde::de::IgnoredAny>>(payload) {
+        Ok(Message::Ping) => Some(MessageKind::Ping),
+        Ok(Message::Pong) => Some(MessageKind::Pong),
+        _ => None,
+    }
+}
+
+/// Gets the length of the frame that `envelope` is written as when serialised using `codec`, including its length prefix.
+/// Fails with [`Error::MessageTooLarge`](crate::Error::MessageTooLarge) if it is too large to be framed.
+pub(crate) fn framed_len<C, E>(codec: &C, envelope: &E) -> Result<usize>
+where
+    C: Codec + ?Sized,
+    E: Serialize + ?Sized,
+{
+    let mut buffer = Vec::new();
+    codec.serialise(envelope, &mut buffer)?;
+    raw::length_prefix(buffer.len())?;
+    Ok(buffer.len() + std::mem::size_of::<u64>())
+}
+
+/// Fails with [`Error::MessageTooLarge`](crate::Error::MessageTooLarge) if `payload` is larger than `max_size` bytes.
+#[cfg(any(feature = "sync", feature = "async-tokio"))]
+fn check_size(payload: &[u8], max_size: Option<u64>) -> Result<()> {
+    match max_size {
+        Some(max) if payload.len() as u64 > max => {
+            Err(crate::Error::MessageTooLarge(payload.len()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Writes `envelope` to `writer` as a frame, serialising it into `buffer` using `codec` first.
+/// `buffer` is cleared beforehand, which allows its allocation to be reused between messages.
+/// Fails with [`Error::MessageTooLarge`](crate::Error::MessageTooLarge) without writing anything if the serialised envelope is larger than `max_size` bytes.
+#[cfg(feature = "sync")]
+pub(crate) fn write_envelope<C, E, W>(
+    writer: &mut W,
+    codec: &C,
+    envelope: &E,
+    buffer: &mut Vec<u8>,
+    max_size: Option<u64>,
+) -> Result<()>
+where
+    C: Codec + ?Sized,
+    E: Serialize + ?Sized,
+    W: Write,
+{
+    buffer.clear();
+    codec.serialise(envelope, buffer)?;
+    check_size(buffer, max_size)?;
+    raw::write_to(writer, buffer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes `envelope` to `writer` as a frame asynchronously, serialising it into `buffer` using `codec` first.
+/// `buffer` is cleared beforehand, which allows its allocation to be reused between messages.
+/// Fails with [`Error::MessageTooLarge`](crate::Error::MessageTooLarge) without writing anything if the serialised envelope is larger than `max_size` bytes.
+#[cfg(feature = "async-tokio")]
+pub(crate) async fn write_envelope_async<C, E, W>(
+    writer: W,
+    codec: &C,
+    envelope: &E,
+    buffer: &mut Vec<u8>,
+    max_size: Option<u64>,
+) -> Result<()>
+where
+    C: Codec + ?Sized,
+    E: Serialize + ?Sized,
+    W: AsyncWrite + Unpin,
+{
+    use tokio::io::AsyncWriteExt;
+    use tokio_util::compat::FuturesAsyncWriteCompatExt;
+    let mut writer = writer.compat_write();
+    buffer.clear();
+    codec.serialise(envelope, buffer)?;
+    check_size(buffer, max_size)?;
+    raw::write_to_async(&mut writer, buffer).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// The start of a CBOR-encoded [`Message::Data`]: a map with one entry, whose key is the text string "Data".
+const DATA_PREFIX: &[u8] = &[0xa1, 0x64, b'D', b'a', b't', b'a'];
+
+/// Gets the range of `payload` that contains the bytes of a CBOR-encoded [`Message::Data`] holding a byte string,
+/// as sent by `send_bytes` on connections.
+/// Returns `None` if `payload` is anything else, or if the byte string isn't encoded in a single chunk.
+#[cfg(feature = "bytes")]
+pub(crate) fn data_bytes_range(payload: &[u8]) -> Option<std::ops::Range<usize>> {
+    let rest = payload.strip_prefix(DATA_PREFIX)?;
+    let (&initial, rest) = rest.split_first()?;
+    if initial >> 5 != 2 {
+        // not a byte string
+        return None;
+    }
+    let (length, header) = match initial & 0x1f {
+        length @ 0..=23 => (u64::from(length), 0),
+        24 => (u64::from(*rest.first()?), 1),
+        25 => (u64::from(u16::from_be_bytes(rest.get(..2)?.try_into().ok()?)), 2),
+        26 => (u64::from(u32::from_be_bytes(rest.get(..4)?.try_into().ok()?)), 4),
+        27 => (u64::from_be_bytes(rest.get(..8)?.try_into().ok()?), 8),
+        _ => return None,
+    };
+    let start = DATA_PREFIX.len() + 1 + header;
+    let end = start.checked_add(usize::try_from(length).ok()?)?;
+    (end == payload.len()).then_some(start..end)
+}
+
+/// The size that the chunks of streamed messages are split into, apart from the last chunk.
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Writes a streamed [`Message::Data`] containing every item in `items` as a sequence to `writer`, encoded as CBOR.
+/// Items are serialised into `buffer` and written whenever it reaches the chunk size, so `items` is never held in memory all at once.
+#[cfg(feature = "sync")]
+pub(crate) fn write_stream<I, W>(writer: &mut W, items: I, buffer: &mut Vec<u8>) -> Result<()>
+where
+    I: IntoIterator,
+    I::Item: Serialize,
+    W: Write,
+{
+    raw::write_stream_start(writer)?;
+    buffer.clear();
+    buffer.extend_from_slice(DATA_PREFIX);
+    buffer.push(CBOR_INDEFINITE_ARRAY);
+    for item in items {
+        CborCodec.serialise(&item, buffer)?;
+        if buffer.len() >= STREAM_CHUNK_SIZE {
+            raw::write_chunk(writer, buffer)?;
+            buffer.clear();
+        }
+    }
+    buffer.push(CBOR_BREAK);
+    raw::write_chunk(writer, buffer)?;
+    raw::write_chunk(writer, &[])?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes a streamed [`Message::Data`] containing every item in `items` as a sequence to `writer` asynchronously, encoded as CBOR.
+/// Items are serialised into `buffer` and written whenever it reaches the chunk size, so `items` is never held in memory all at once.
+#[cfg(feature = "async-tokio")]
+pub(crate) async fn write_stream_async<I, W>(
+    writer: W,
+    items: I,
+    buffer: &mut Vec<u8>,
+) -> Result<()>
+where
+    I: IntoIterator,
+    I::Item: Serialize,
+    W: AsyncWrite + Unpin,
+{
+    use tokio::io::AsyncWriteExt;
+    use tokio_util::compat::FuturesAsyncWriteCompatExt;
+    let mut writer = writer.compat_write();
+    raw::write_stream_start_async(&mut writer).await?;
+    buffer.clear();
+    buffer.extend_from_slice(DATA_PREFIX);
+    buffer.push(CBOR_INDEFINITE_ARRAY);
+    for item in items {
+        CborCodec.serialise(&item, buffer)?;
+        if buffer.len() >= STREAM_CHUNK_SIZE {
+            raw::write_chunk_async(&mut writer, buffer).await?;
+            buffer.clear();
+        }
+    }
+    buffer.push(CBOR_BREAK);
+    raw::write_chunk_async(&mut writer, buffer).await?;
+    raw::write_chunk_async(&mut writer, &[]).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// The CBOR header of an array whose length isn't known upfront.
+const CBOR_INDEFINITE_ARRAY: u8 = 0x9f;
+/// The CBOR marker for the end of an [indefinite-length array](CBOR_INDEFINITE_ARRAY).
+const CBOR_BREAK: u8 = 0xff;
+
+/// A type that data is wrapped in when it's sent through a connection.
+/// [`Message`] is the envelope gipc uses, but protocols that need additional control frames (like flow-control updates)
+/// can define their own and send it using `send_envelope` and `receive_envelope` on connections.
+///
+/// When a connection is closed, gipc sends [`Message::ClosingConnection`] regardless of which envelope is used.
+/// Envelopes should therefore have a unit variant named `ClosingConnection` as their first variant,
+/// so that the closing frame is understood by both ends no matter which codec is used.
+pub trait Envelope: Serialize + DeserializeOwned {
+    /// Whether this envelope indicates that the connection is about to be closed.
+    fn is_closing(&self) -> bool;
+}
+
+impl<T> Envelope for Message<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn is_closing(&self) -> bool {
+        matches!(
+            self,
+            Message::ClosingConnection | Message::ClosingWithReason(..)
+        )
+    }
 }
 
 /// The core of gipc's protocol.
 /// This is primarily for internal use.
-#[derive(Debug, Copy, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum Message<T> {
     /// Indicates that the connection is about to be closed.
     ClosingConnection,
     /// Container for user-defined data.
     /// This is the main variant used for communication using [`Connection`s and `Listener`s](crate::connection).
     Data(T),
+    /// A lightweight signal that carries a code instead of user-defined data.
+    Signal(u8),
+    /// Container for user-defined data that the receiver acknowledges with [`Message::Ack`] as soon as it has received it.
+    /// This is sent by `send_acked`.
+    Acked(T),
+    /// Acknowledges that a [`Message::Acked`] has been received.
+    Ack,
+    /// Container for user-defined data that is received ahead of [`Message::Data`] that has already arrived.
+    /// This is sent by `send_priority`.
+    Priority(T),
+    /// Container for user-defined data along with when it was sent, in nanoseconds as read by `SendInstant::now`.
+    /// This is sent instead of [`Message::Data`] by connections that have timestamps enabled.
+    Stamped(u64, T),
+    /// Indicates that the connection is about to be closed, along with a code and optionally a message saying why.
+    /// This is sent by `close_with_reason` instead of [`Message::ClosingConnection`].
+    ClosingWithReason(CloseCode, Option<String>),
+    /// Asks the receiver to reply with [`Message::Pong`], which connections do by themselves whilst receiving, without surfacing either message.
+    /// This is sent by `warm_up`.
+    Ping,
+    /// Replies to a [`Message::Ping`].
+    Pong,
+}
+
+/// Gets the fingerprint of `schema`, which is either the name of a type as returned by [`std::any::type_name`] or a schema ID chosen by the user.
+/// This is what `TypedConnection` tags messages with, and is the same for the same `schema` in every process and build.
+/// Note that the names returned by `type_name` aren't guaranteed to be: they may change between compiler versions, unlike a schema ID.
+#[cfg(feature = "type-check")]
+#[cfg_attr(docsrs, doc(cfg(feature = "type-check")))]
+pub const fn fingerprint(schema: &str) -> u64 {
+    fnv1a(schema.as_bytes())
+}
+
+/// Hashes `bytes` using 64-bit FNV-1a, which is stable and good enough to tell a handful of names apart.
+/// Both sides of a connection have to agree on the hashes, so this has to stay the same across versions.
+pub(crate) const fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+        i += 1;
+    }
+    hash
+}
+
+/// Creates a [`LengthDelimitedCodec`](tokio_util::codec::LengthDelimitedCodec) that frames data the way gipc does,
+/// so that a [`Framed`](tokio_util::codec::Framed) stream can talk to a gipc connection on the other end.
+///
+/// Every frame is an unsigned 64-bit big-endian length prefix, which doesn't count itself, followed by that many bytes of payload.
+/// The payload is an [`Envelope`] serialised using the connection's codec, which by default is a [`Message`] encoded as CBOR -
+/// i.e. what [`Message::to_bytes`] returns without its length prefix, as the codec adds and removes that itself.
+/// Streamed messages, as sent by `send_iter`, are split into chunks that this codec can't put back together; their length prefix is `u64::MAX`,
+/// which the returned codec rejects as too long since it keeps tokio-util's default maximum frame length of 8 MiB.
+/// Use [`set_max_frame_length`](tokio_util::codec::LengthDelimitedCodec::set_max_frame_length) to allow larger messages.
+#[cfg(feature = "codec")]
+#[cfg_attr(docsrs, doc(cfg(feature = "codec")))]
+pub fn length_delimited_codec() -> tokio_util::codec::LengthDelimitedCodec {
+    tokio_util::codec::LengthDelimitedCodec::builder()
+        .length_field_length(std::mem::size_of::<u64>())
+        .big_endian()
+        .new_codec()
+}
+
+/// The kind of a [`Message`], i.e. its variant without the data it carries.
+/// This is returned by `peek_variant`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum MessageKind {
+    /// A [`Message::ClosingConnection`].
+    ClosingConnection,
+    /// A [`Message::Data`].
+    Data,
+    /// A [`Message::Signal`].
+    Signal,
+    /// A [`Message::Acked`].
+    Acked,
+    /// A [`Message::Ack`].
+    Ack,
+    /// A [`Message::Priority`].
+    Priority,
+    /// A [`Message::Stamped`].
+    Stamped,
+    /// A [`Message::ClosingWithReason`].
+    ClosingWithReason,
+    /// A [`Message::Ping`].
+    Ping,
+    /// A [`Message::Pong`].
+    Pong,
 }
 
 impl<T> Message<T> {
+    /// Gets the kind of this [`Message`].
+    pub const fn kind(&self) -> MessageKind {
+        match self {
+            Message::ClosingConnection => MessageKind::ClosingConnection,
+            Message::Data(_) => MessageKind::Data,
+            Message::Signal(_) => MessageKind::Signal,
+            Message::Acked(_) => MessageKind::Acked,
+            Message::Ack => MessageKind::Ack,
+            Message::Priority(_) => MessageKind::Priority,
+            Message::Stamped(..) => MessageKind::Stamped,
+            Message::ClosingWithReason(..) => MessageKind::ClosingWithReason,
+            Message::Ping => MessageKind::Ping,
+            Message::Pong => MessageKind::Pong,
+        }
+    }
+    /// Checks whether this [`Message`] carries user-defined data.
+    pub(crate) const fn carries_data(&self) -> bool {
+        matches!(
+            self,
+            Message::Data(_) | Message::Acked(_) | Message::Priority(_) | Message::Stamped(..)
+        )
+    }
+    /// Serialises this [`Message`] into a frame, exactly as it would be written to a connection.
+    /// This includes the length prefix.
+    pub fn to_bytes(&self) -> Result<Vec<u8>>
+    where
+        T: Serialize,
+    {
+        let mut payload = Vec::new();
+        CborCodec.serialise(self, &mut payload)?;
+        raw::serialised_vec(&payload)
+    }
+    /// Gets the length of the frame this [`Message`] is serialised into, i.e. the length of what [`to_bytes`](Self::to_bytes) returns,
+    /// without keeping the serialised frame around.
+    pub fn serialised_len(&self) -> Result<usize>
+    where
+        T: Serialize,
+    {
+        framed_len(&CborCodec, self)
+    }
+    /// Deserialises a [`Message`] from the frame at the start of `bytes`, as produced by [`to_bytes`](Self::to_bytes).
+    /// Fails with an [`UnexpectedEof`](std::io::ErrorKind::UnexpectedEof) I/O error if `bytes` doesn't contain the entire frame.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self>
+    where
+        T: DeserializeOwned,
+    {
+        let payload = raw::read_from(&mut &bytes[..], Some(bytes.len()))?;
+        CborCodec.deserialise(&payload)
+    }
+
     /// Reads a [`Message`] from `reader`.
     #[cfg(feature = "sync")]
     #[cfg_attr(docsrs, doc(cfg(feature = "sync")))]
@@ -103,11 +784,19 @@ impl<T> Message<T> {
         T: DeserializeOwned,
         R: Read,
     {
-        let raw = raw::read_from(reader)?;
-        let deserialised: Self = ciborium::de::from_reader(raw.as_slice())
-            .map_err(|v| Error::Deserialise(v.to_string()))?;
-
-        Ok(deserialised)
+        Self::read_from_with_codec(reader, &CborCodec)
+    }
+    /// Reads a [`Message`] from `reader`, deserialising it using `codec`.
+    #[cfg(feature = "sync")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "sync")))]
+    pub fn read_from_with_codec<C, R>(reader: &mut R, codec: &C) -> Result<Self>
+    where
+        T: DeserializeOwned,
+        C: Codec + ?Sized,
+        R: Read,
+    {
+        let raw = read_payload(reader)?;
+        codec.deserialise(raw.as_slice())
     }
     /// Writes this [`Message`] to `writer`.
     #[cfg(feature = "sync")]
@@ -117,12 +806,35 @@ impl<T> Message<T> {
         T: Serialize,
         W: Write,
     {
-        let mut serialised = Vec::new();
-        ciborium::ser::into_writer(self, &mut serialised)
-            .map_err(|v| Error::Serialise(v.to_string()))?;
-        raw::write_to(writer, &serialised)?;
-        writer.flush()?;
-        Ok(())
+        self.write_to_with_buffer(writer, &mut Vec::new())
+    }
+    /// Writes this [`Message`] to `writer`, serialising it into `buffer` first.
+    /// `buffer` is cleared beforehand, which allows its allocation to be reused between messages.
+    #[cfg(feature = "sync")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "sync")))]
+    pub fn write_to_with_buffer<W>(&self, writer: &mut W, buffer: &mut Vec<u8>) -> Result<()>
+    where
+        T: Serialize,
+        W: Write,
+    {
+        self.write_to_with_codec(writer, &CborCodec, buffer)
+    }
+    /// Writes this [`Message`] to `writer`, serialising it into `buffer` using `codec` first.
+    /// `buffer` is cleared beforehand, which allows its allocation to be reused between messages.
+    #[cfg(feature = "sync")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "sync")))]
+    pub fn write_to_with_codec<C, W>(
+        &self,
+        writer: &mut W,
+        codec: &C,
+        buffer: &mut Vec<u8>,
+    ) -> Result<()>
+    where
+        T: Serialize,
+        C: Codec + ?Sized,
+        W: Write,
+    {
+        write_envelope(writer, codec, self, buffer, None)
     }
 
     /// Reads a [`Message`] from `reader` asynchronously.
@@ -133,13 +845,20 @@ impl<T> Message<T> {
         T: DeserializeOwned,
         R: AsyncRead + Unpin,
     {
-        use tokio_util::compat::FuturesAsyncReadCompatExt;
-        let mut reader = reader.compat();
-        let raw = raw::read_from_async(&mut reader).await?;
-        let deserialised: Self = ciborium::de::from_reader(raw.as_slice())
-            .map_err(|v| Error::Deserialise(v.to_string()))?;
+        Self::read_from_async_with_codec(reader, &CborCodec).await
+    }
 
-        Ok(deserialised)
+    /// Reads a [`Message`] from `reader` asynchronously, deserialising it using `codec`.
+    #[cfg(feature = "async-tokio")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "async-tokio")))]
+    pub async fn read_from_async_with_codec<C, R>(reader: R, codec: &C) -> Result<Self>
+    where
+        T: DeserializeOwned,
+        C: Codec + ?Sized,
+        R: AsyncRead + Unpin,
+    {
+        let raw = read_payload_async(reader).await?;
+        codec.deserialise(raw.as_slice())
     }
 
     /// Writes this [`Message`] to `writer` asynchronously.
@@ -150,14 +869,190 @@ impl<T> Message<T> {
         T: Serialize,
         W: AsyncWrite + Unpin + Send,
     {
-        use tokio::io::AsyncWriteExt;
-        use tokio_util::compat::FuturesAsyncWriteCompatExt;
-        let mut serialised = Vec::new();
-        let mut writer = writer.compat_write();
-        ciborium::ser::into_writer(self, &mut serialised)
-            .map_err(|v| Error::Serialise(v.to_string()))?;
-        raw::write_to_async(&mut writer, &serialised).await?;
-        writer.flush().await?;
-        Ok(())
+        self.write_to_async_with_buffer(writer, &mut Vec::new())
+            .await
+    }
+
+    /// Writes this [`Message`] to `writer` asynchronously, serialising it into `buffer` first.
+    /// `buffer` is cleared beforehand, which allows its allocation to be reused between messages.
+    #[cfg(feature = "async-tokio")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "async-tokio")))]
+    pub async fn write_to_async_with_buffer<W>(&self, writer: W, buffer: &mut Vec<u8>) -> Result<()>
+    where
+        T: Serialize,
+        W: AsyncWrite + Unpin + Send,
+    {
+        self.write_to_async_with_codec(writer, &CborCodec, buffer)
+            .await
+    }
+
+    /// Writes this [`Message`] to `writer` asynchronously, serialising it into `buffer` using `codec` first.
+    /// `buffer` is cleared beforehand, which allows its allocation to be reused between messages.
+    #[cfg(feature = "async-tokio")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "async-tokio")))]
+    pub async fn write_to_async_with_codec<C, W>(
+        &self,
+        writer: W,
+        codec: &C,
+        buffer: &mut Vec<u8>,
+    ) -> Result<()>
+    where
+        T: Serialize,
+        C: Codec + ?Sized,
+        W: AsyncWrite + Unpin + Send,
+    {
+        write_envelope_async(writer, codec, self, buffer, None).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    #[test]
+    fn bytes_round_trip() {
+        let data = Message::Data(vec![String::from("a"), String::from("b")]);
+        let bytes = data.to_bytes().unwrap();
+        assert_eq!(bytes.len(), data.serialised_len().unwrap());
+        let Message::Data(decoded) = Message::<Vec<String>>::from_bytes(&bytes).unwrap() else {
+            panic!("expected a data message");
+        };
+        assert_eq!(decoded, ["a", "b"]);
+
+        let closing = Message::<()>::ClosingConnection.to_bytes().unwrap();
+        assert!(matches!(
+            Message::<()>::from_bytes(&closing).unwrap(),
+            Message::ClosingConnection
+        ));
+    }
+
+    /// Builds a streamed frame out of the given chunk length prefixes and `chunks`, which are appended as they are.
+    fn streamed(prefixes: &[u64], chunks: &[u8]) -> Vec<u8> {
+        let mut frame = raw::STREAMED.to_be_bytes().to_vec();
+        for prefix in prefixes {
+            frame.extend_from_slice(&prefix.to_be_bytes());
+        }
+        frame.extend_from_slice(chunks);
+        frame
+    }
+
+    #[test]
+    fn lengths_the_prefix_cannot_hold_are_rejected() {
+        assert_eq!(raw::length_prefix(0).unwrap(), 0);
+        assert_eq!(raw::length_prefix(1 << 20).unwrap(), 1 << 20);
+        // on 64-bit targets, the largest length is the one reserved for streamed frames
+        #[cfg(target_pointer_width = "64")]
+        {
+            assert!(matches!(
+                raw::length_prefix(usize::MAX),
+                Err(Error::MessageTooLarge(usize::MAX))
+            ));
+            assert_eq!(raw::length_prefix(usize::MAX - 1).unwrap(), u64::MAX - 1);
+        }
+    }
+
+    #[test]
+    fn frame_length_of_streamed_frames() {
+        let mut frame = streamed(&[2], b"ab");
+        assert_eq!(frame_length(&frame), None);
+        frame.extend_from_slice(&0u64.to_be_bytes());
+        assert_eq!(frame_length(&frame), Some(frame.len()));
+        // a chunk that would end past the largest possible offset doesn't overflow
+        assert_eq!(frame_length(&streamed(&[u64::MAX - 3], &[])), None);
+        assert_eq!(
+            frame_length(&streamed(&[usize::MAX as u64 - 16], &[])),
+            None
+        );
+    }
+
+    #[test]
+    fn streamed_payloads_are_capped_before_being_read() {
+        let mut frame = raw::STREAMED.to_be_bytes().to_vec();
+        for chunk in [b"abc", b"def"] {
+            frame.extend_from_slice(&3u64.to_be_bytes());
+            frame.extend_from_slice(chunk);
+        }
+        frame.extend_from_slice(&0u64.to_be_bytes());
+        assert_eq!(raw::read_from(&mut &frame[..], Some(6)).unwrap(), b"abcdef");
+        assert!(matches!(
+            raw::read_from(&mut &frame[..], Some(5)),
+            Err(Error::ProtocolDesync(6))
+        ));
+        // chunks claiming to be absurdly long are rejected instead of being allocated
+        assert!(matches!(
+            raw::read_from(&mut &streamed(&[u64::MAX - 1], &[])[..], None),
+            Err(Error::ProtocolDesync(_))
+        ));
+        assert!(matches!(
+            frame_payload(streamed(&[1 << 40], b"abc")),
+            Err(Error::ProtocolDesync(_))
+        ));
+    }
+
+    #[test]
+    fn payloads_are_taken_in_place() {
+        let frame = raw::serialised_vec(&b"sized".to_vec()).unwrap();
+        let mut longer = frame.clone();
+        longer.extend_from_slice(b"next");
+        let payload = frame_payload(longer).unwrap();
+        assert_eq!(&*payload, b"sized");
+        assert_eq!(payload.into_frame(), frame);
+
+        let mut streamed = raw::STREAMED.to_be_bytes().to_vec();
+        for chunk in [&b"str"[..], b"eam", b"ed"] {
+            streamed.extend_from_slice(&(chunk.len() as u64).to_be_bytes());
+            streamed.extend_from_slice(chunk);
+        }
+        streamed.extend_from_slice(&0u64.to_be_bytes());
+        let payload = frame_payload(streamed).unwrap();
+        assert_eq!(&*payload, b"streamed");
+        // the frame is rewritten as an ordinary one, so that it can be put back into a read buffer
+        let frame = payload.into_frame();
+        assert_eq!(frame, raw::serialised_vec(&b"streamed".to_vec()).unwrap());
+        assert!(matches!(
+            frame_payload(frame[..frame.len() - 1].to_vec()),
+            Err(Error::ProtocolDesync(8))
+        ));
+    }
+
+    /// A writer that accepts at most a few bytes per write, and so only ever writes part of the first buffer of a vectored write.
+    #[cfg(feature = "sync")]
+    struct Trickle(Vec<u8>);
+
+    #[cfg(feature = "sync")]
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let length = buf.len().min(3);
+            self.0.extend_from_slice(&buf[..length]);
+            Ok(length)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn vectored_writes_match_concatenated_frames() {
+        for length in [0, 10, raw::VECTORED_WRITE_THRESHOLD, 100_000] {
+            let data = (0..length).map(|i| i as u8).collect::<Vec<_>>();
+            let expected = raw::serialised_vec(&data).unwrap();
+            let mut vectored = Vec::new();
+            raw::write_to(&mut vectored, &data).unwrap();
+            assert_eq!(vectored, expected, "{} bytes", length);
+            let mut trickle = Trickle(Vec::new());
+            raw::write_to(&mut trickle, &data).unwrap();
+            assert_eq!(trickle.0, expected, "{} bytes written bit by bit", length);
+        }
+    }
+
+    #[test]
+    fn from_bytes_needs_the_entire_frame() {
+        let bytes = Message::Data(5u8).to_bytes().unwrap();
+        let Err(Error::Io(error)) = Message::<u8>::from_bytes(&bytes[..bytes.len() - 1]) else {
+            panic!("deserialised a truncated frame");
+        };
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
     }
 }
@@ -0,0 +1,132 @@
+//! Counts the allocations made whilst sending and receiving messages, using a global allocator that keeps track of them.
+//! The counts are kept per thread, so tests running at the same time don't affect each other's counts.
+#![cfg(feature = "sync")]
+
+use gipc::message::Message;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+struct CountingAllocator;
+
+thread_local! {
+    static REALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Adds `amount` to `counter` on the current thread, unless the thread is being torn down.
+fn count(counter: &'static std::thread::LocalKey<Cell<usize>>, amount: usize) {
+    let _ = counter.try_with(|counter| counter.set(counter.get() + amount));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count(&ALLOCATED, layout.size());
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count(&REALLOCATIONS, 1);
+        count(&ALLOCATED, new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Runs `f`, returning what it returns along with how many reallocations and how many bytes of allocations it made on the current thread.
+fn counting<R>(f: impl FnOnce() -> R) -> (R, usize, usize) {
+    let reallocations = REALLOCATIONS.with(Cell::get);
+    let allocated = ALLOCATED.with(Cell::get);
+    let result = f();
+    (
+        result,
+        REALLOCATIONS.with(Cell::get) - reallocations,
+        ALLOCATED.with(Cell::get) - allocated,
+    )
+}
+
+/// Counts the reallocations made whilst writing `message` using `buffer`.
+fn reallocations_writing(message: &Message<serde_bytes::ByteBuf>, buffer: &mut Vec<u8>) -> usize {
+    counting(|| {
+        message
+            .write_to_with_buffer(&mut std::io::sink(), buffer)
+            .unwrap()
+    })
+    .1
+}
+
+#[test]
+fn reserved_buffers_avoid_reallocations() {
+    let message = Message::Data(serde_bytes::ByteBuf::from(vec![7u8; 1024 * 1024]));
+
+    let growing = reallocations_writing(&message, &mut Vec::new());
+    assert!(growing > 0);
+
+    let mut reserved = Vec::with_capacity(1024 * 1024 + 64);
+    assert_eq!(reallocations_writing(&message, &mut reserved), 0);
+    // the buffer is cleared and reused, so later messages don't reallocate either
+    assert_eq!(reallocations_writing(&message, &mut reserved), 0);
+
+    let mut reused = Vec::new();
+    reallocations_writing(&message, &mut reused);
+    assert_eq!(reallocations_writing(&message, &mut reused), 0);
+}
+
+#[cfg(all(unix, feature = "bytes"))]
+#[test]
+fn received_bytes_share_the_payload() {
+    use gipc::connection::sync::Connection;
+    use std::os::unix::net::UnixStream;
+
+    const LENGTH: usize = 1024 * 1024;
+    let (a, b) = UnixStream::pair().unwrap();
+    let (mut sender, mut receiver) = (Connection::from(a), Connection::from(b));
+    // the frame is larger than the socket's buffer, so it has to be sent whilst it is being received
+    let sending = std::thread::spawn(move || {
+        sender
+            .send_bytes_buf(&bytes::Bytes::from(vec![9u8; LENGTH]))
+            .unwrap();
+        sender
+    });
+    let (received, _, allocated) = counting(|| receiver.receive_bytes_buf().unwrap());
+    assert_eq!(received, vec![9u8; LENGTH]);
+    // the frame is read into a single allocation of its size that the bytes are then sliced out of; copying the payload or deserialising the bytes would take another
+    assert!(
+        allocated < LENGTH * 3 / 2,
+        "{} bytes were allocated to receive {} bytes",
+        allocated,
+        LENGTH
+    );
+    sending.join().unwrap();
+}
+
+#[test]
+fn length_prefixes_alone_allocate_nothing_for_their_payload() {
+    // a prefix claiming 64 GiB, which could be allocated, followed by a few bytes of garbage
+    let mut frame = (1u64 << 36).to_be_bytes().to_vec();
+    frame.extend_from_slice(b"garbage");
+    let (result, _, allocated) = counting(|| Message::<u8>::read_from(&mut &frame[..]));
+    assert!(result.is_err());
+    assert!(allocated < 64 * 1024, "{} bytes were allocated", allocated);
+
+    #[cfg(unix)]
+    {
+        use gipc::connection::sync::Connection;
+        use std::io::Write;
+        use std::os::unix::net::UnixStream;
+        let (a, mut raw) = UnixStream::pair().unwrap();
+        let mut connection = Connection::from(a);
+        raw.write_all(&frame).unwrap();
+        drop(raw);
+        let (result, _, allocated) = counting(|| connection.receive::<u8>());
+        assert!(result.is_err());
+        assert!(
+            allocated < 4 * 1024 * 1024,
+            "{} bytes were allocated",
+            allocated
+        );
+    }
+}
@@ -0,0 +1,1372 @@
+//! Behaviour of asynchronous connections and listeners.
+#![cfg(feature = "async-tokio")]
+
+use gipc::connection::async_tokio::{in_memory_pair, Connection};
+
+#[cfg(unix)]
+#[tokio::test]
+async fn wraps_a_tokio_unix_stream() {
+    let (a, b) = tokio::net::UnixStream::pair().unwrap();
+    let (mut a, mut b) = (Connection::from(a), Connection::from(b));
+    a.send(String::from("ping")).await.unwrap();
+    assert_eq!(b.receive::<String>().await.unwrap(), "ping");
+    b.send(42u32).await.unwrap();
+    assert_eq!(a.receive::<u32>().await.unwrap(), 42);
+    a.close().await;
+    assert!(matches!(
+        b.receive::<u32>().await,
+        Err(gipc::Error::Closed(true))
+    ));
+}
+
+#[tokio::test]
+async fn drain_pending_counts_arrived_messages() {
+    let (mut sender, mut receiver) = in_memory_pair();
+    for i in 0..5u8 {
+        sender.send(i).await.unwrap();
+    }
+    assert_eq!(receiver.drain_pending().await.unwrap(), 5);
+    assert_eq!(receiver.drain_pending().await.unwrap(), 0);
+    sender.close().await;
+    receiver.close().await;
+}
+
+#[tokio::test]
+async fn past_deadlines_fail_immediately() {
+    use std::time::{Duration, Instant};
+    let (mut a, mut b) = in_memory_pair();
+    b.send(1u8).await.unwrap();
+    let past = Instant::now() - Duration::from_secs(1);
+    a.set_read_deadline(Some(past));
+    assert!(matches!(a.receive::<u8>().await, Err(gipc::Error::Timeout)));
+    a.set_write_deadline(Some(past));
+    assert!(matches!(a.send(2u8).await, Err(gipc::Error::Timeout)));
+    a.set_read_deadline(None);
+    assert_eq!(a.receive::<u8>().await.unwrap(), 1);
+    a.close().await;
+    b.close().await;
+}
+
+#[cfg(unix)]
+fn socket_name(test: &str) -> String {
+    format!("gipc-test-{}-{}", test, std::process::id())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn pool_reuses_connections_across_requests() {
+    use gipc::connection::async_tokio::Listener;
+    use gipc::connection::ConnectionPool;
+    use std::time::Duration;
+
+    let name = socket_name("pool-reuse");
+    let mut listener = Listener::listen_as_socket(&name, false).unwrap();
+    let server = tokio::spawn(async move {
+        // only one connection is ever accepted, so both requests have to share it
+        let mut connection = listener.accept().await.unwrap();
+        for _ in 0..2 {
+            let request = connection.receive::<u32>().await.unwrap();
+            connection.send(request + 1).await.unwrap();
+        }
+        assert!(matches!(
+            connection.receive::<u32>().await,
+            Err(gipc::Error::Closed(true))
+        ));
+        connection.close().await;
+        listener.close().await.unwrap();
+    });
+
+    let pool = ConnectionPool::new(4, Duration::from_secs(60));
+    let mut first = pool.get(&name, false).await.unwrap();
+    first.send(1u32).await.unwrap();
+    assert_eq!(first.receive::<u32>().await.unwrap(), 2);
+    let id = first.id();
+    drop(first);
+    assert_eq!(pool.idle_count(), 1);
+
+    let mut second = pool.get(&name, false).await.unwrap();
+    assert_eq!(second.id(), id);
+    second.send(2u32).await.unwrap();
+    assert_eq!(second.receive::<u32>().await.unwrap(), 3);
+    second.detach().close().await;
+    server.await.unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn pool_closes_connections_it_has_no_room_for() {
+    use gipc::connection::async_tokio::Listener;
+    use gipc::connection::ConnectionPool;
+    use std::time::Duration;
+
+    let name = socket_name("pool-overflow");
+    let mut listener = Listener::listen_as_socket(&name, false).unwrap();
+    let pool = ConnectionPool::new(1, Duration::from_secs(60));
+    let first = pool.get(&name, false).await.unwrap();
+    let mut kept = listener.accept().await.unwrap();
+    let second = pool.get(&name, false).await.unwrap();
+    let mut overflow = listener.accept().await.unwrap();
+
+    drop(first);
+    drop(second);
+    assert_eq!(pool.idle_count(), 1);
+    assert!(matches!(
+        overflow.receive::<u32>().await,
+        Err(gipc::Error::Closed(true))
+    ));
+
+    pool.get(&name, false).await.unwrap().detach().close().await;
+    assert!(matches!(
+        kept.receive::<u32>().await,
+        Err(gipc::Error::Closed(true))
+    ));
+    kept.close().await;
+    overflow.close().await;
+    listener.close().await.unwrap();
+}
+
+/// A listener that fails with each of `errors` in turn, then hands out in-memory connections.
+struct FlakyListener {
+    errors: Vec<std::io::Error>,
+    peers: Vec<Connection>,
+}
+
+#[async_trait::async_trait]
+impl gipc::connection::async_tokio::ListenerImpl for FlakyListener {
+    async fn accept(&mut self) -> gipc::Result<Connection> {
+        if !self.errors.is_empty() {
+            return Err(self.errors.remove(0).into());
+        }
+        let (connection, peer) = in_memory_pair();
+        self.peers.push(peer);
+        Ok(connection)
+    }
+    async fn close(&mut self) -> gipc::Result<()> {
+        for peer in &mut self.peers {
+            peer.close().await;
+        }
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn accept_retries_transient_errors() {
+    use gipc::connection::async_tokio::Listener;
+    use gipc::connection::AcceptRetryPolicy;
+    use std::io::{Error, ErrorKind};
+    use std::time::Duration;
+
+    let errors = vec![
+        Error::from(ErrorKind::ConnectionAborted),
+        #[cfg(unix)]
+        Error::from_raw_os_error(24), // EMFILE
+    ];
+    let mut listener = Listener::new(Box::new(FlakyListener {
+        errors,
+        peers: Vec::new(),
+    }));
+    listener.set_accept_retry_policy(AcceptRetryPolicy::After(Duration::from_millis(1)));
+    listener.accept().await.unwrap().close().await;
+    listener.close().await.unwrap();
+
+    let mut strict = Listener::new(Box::new(FlakyListener {
+        errors: vec![Error::from(ErrorKind::ConnectionAborted)],
+        peers: Vec::new(),
+    }));
+    strict.set_accept_retry_policy(AcceptRetryPolicy::Never);
+    assert!(matches!(strict.accept().await, Err(gipc::Error::Io(_))));
+    strict.accept().await.unwrap().close().await;
+    strict.close().await.unwrap();
+
+    // errors that won't go away by themselves are returned even when retrying
+    let mut fatal = Listener::new(Box::new(FlakyListener {
+        errors: vec![Error::from(ErrorKind::PermissionDenied)],
+        peers: Vec::new(),
+    }));
+    assert!(matches!(fatal.accept().await, Err(gipc::Error::Io(_))));
+    fatal.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn close_reason_is_peer_when_the_other_side_closes() {
+    use gipc::connection::CloseReason;
+    let (mut a, mut b) = in_memory_pair();
+    assert_eq!(a.close_reason(), None);
+    b.close().await;
+    assert_eq!(b.close_reason(), Some(CloseReason::Local));
+    assert!(matches!(
+        a.receive::<u8>().await,
+        Err(gipc::Error::Closed(true))
+    ));
+    assert_eq!(a.close_reason(), Some(CloseReason::Peer));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn accept_filter_drops_disallowed_peers() {
+    use gipc::connection::async_tokio::Listener;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    let name = socket_name("accept-filter");
+    let mut listener = Listener::listen_as_socket(&name, false).unwrap();
+    // no real process runs as this user, so the first connection is from a disallowed uid
+    let allowed = Arc::new(AtomicU32::new(u32::MAX - 1));
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let (filter_allowed, filter_seen) = (Arc::clone(&allowed), Arc::clone(&seen));
+    listener.set_accept_filter(Box::new(move |meta| {
+        let uid = meta
+            .peer_credentials
+            .as_ref()
+            .map(|credentials| credentials.uid);
+        filter_seen.lock().unwrap().push(uid);
+        uid == Some(filter_allowed.load(Ordering::SeqCst))
+    }));
+    let server = tokio::spawn(async move {
+        let mut connection = listener.accept().await.unwrap();
+        connection.send(String::from("welcome")).await.unwrap();
+        connection.close().await;
+        listener.close().await.unwrap();
+    });
+
+    let mut rejected = Connection::connect_to_socket(&name, false).await.unwrap();
+    assert!(matches!(
+        rejected.receive::<String>().await,
+        Err(gipc::Error::Closed(true))
+    ));
+    let uid = seen.lock().unwrap()[0].expect("credentials are available on Unix");
+    allowed.store(uid, Ordering::SeqCst);
+
+    let mut accepted = Connection::connect_to_socket(&name, false).await.unwrap();
+    assert_eq!(accepted.receive::<String>().await.unwrap(), "welcome");
+    server.await.unwrap();
+    assert_eq!(seen.lock().unwrap().len(), 2);
+    rejected.close().await;
+    accepted.close().await;
+}
+
+#[tokio::test]
+async fn self_test_passes() {
+    gipc::connection::async_tokio::self_test().await.unwrap();
+}
+
+#[tokio::test]
+async fn receive_batch_returns_early() {
+    let (mut sender, mut receiver) = in_memory_pair();
+    for i in 0..3u8 {
+        sender.send(i).await.unwrap();
+    }
+    assert_eq!(receiver.receive_batch::<u8>(2).await.unwrap(), [0, 1]);
+    assert_eq!(receiver.receive_batch::<u8>(10).await.unwrap(), [2]);
+    assert!(receiver.receive_batch::<u8>(10).await.unwrap().is_empty());
+    sender.close().await;
+    receiver.close().await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn large_frames_are_written_like_concatenated_ones() {
+    use gipc::message::Message;
+    use tokio::io::AsyncReadExt;
+    let (a, mut raw) = tokio::net::UnixStream::pair().unwrap();
+    let mut connection = Connection::from(a);
+    let data = (0..100_000u32).map(|i| i as u8).collect::<Vec<_>>();
+    let expected = Message::Data(serde_bytes::ByteBuf::from(data.clone()))
+        .to_bytes()
+        .unwrap();
+    let reading = tokio::spawn(async move {
+        let mut written = vec![0; expected.len()];
+        raw.read_exact(&mut written).await.unwrap();
+        assert_eq!(written, expected);
+        raw
+    });
+    connection.send_bytes(&data).await.unwrap();
+    let _raw = reading.await.unwrap();
+    connection.close().await;
+}
+
+#[tokio::test]
+async fn connections_get_distinct_ids() {
+    let (mut a, mut b) = in_memory_pair();
+    assert_ne!(a.id(), b.id());
+    assert_eq!(a.id(), a.id());
+    a.close().await;
+    b.close().await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn interrupted_reads_resume_the_same_frame() {
+    use gipc::message::Message;
+    use std::time::{Duration, Instant};
+    use tokio::io::AsyncWriteExt;
+    let (a, mut raw) = tokio::net::UnixStream::pair().unwrap();
+    let mut connection = Connection::from(a);
+    let expected = "resumed".repeat(1000);
+    let bytes = Message::Data(expected.clone()).to_bytes().unwrap();
+    let (first, rest) = bytes.split_at(bytes.len() / 2);
+    raw.write_all(first).await.unwrap();
+    connection.set_read_deadline(Some(Instant::now() + Duration::from_millis(50)));
+    assert!(matches!(
+        connection.receive::<String>().await,
+        Err(gipc::Error::Timeout)
+    ));
+    connection.set_read_deadline(None);
+    // dropping a receive that is underway leaves what it read buffered as well
+    let dropped = tokio::time::timeout(Duration::from_millis(50), connection.receive::<String>());
+    assert!(dropped.await.is_err());
+    raw.write_all(rest).await.unwrap();
+    raw.write_all(&Message::Data(1u8).to_bytes().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(connection.receive::<String>().await.unwrap(), expected);
+    assert_eq!(connection.receive::<u8>().await.unwrap(), 1);
+    connection.close().await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn timed_out_receives_resume_with_fresh_deadlines() {
+    use gipc::message::Message;
+    use std::time::{Duration, Instant};
+    use tokio::io::AsyncWriteExt;
+    let (a, mut raw) = tokio::net::UnixStream::pair().unwrap();
+    let mut connection = Connection::from(a);
+    let expected = "long poll".repeat(100);
+    let bytes = Message::Data(expected.clone()).to_bytes().unwrap();
+    // the length prefix alone, then part of the body, then the rest of it
+    let pieces = [
+        &bytes[..4],
+        &bytes[4..bytes.len() / 2],
+        &bytes[bytes.len() / 2..],
+    ];
+    for piece in &pieces[..2] {
+        raw.write_all(piece).await.unwrap();
+        connection.set_read_deadline(Some(Instant::now() + Duration::from_millis(50)));
+        assert!(matches!(
+            connection.receive::<String>().await,
+            Err(gipc::Error::Timeout)
+        ));
+    }
+    raw.write_all(pieces[2]).await.unwrap();
+    connection.set_read_deadline(Some(Instant::now() + Duration::from_secs(5)));
+    assert_eq!(connection.receive::<String>().await.unwrap(), expected);
+    assert!(!connection.is_closed());
+    connection.close().await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn clients_can_wait_for_listeners_to_start() {
+    use gipc::connection::async_tokio::Listener;
+    use std::time::Duration;
+    let name = socket_name("connect-wait");
+    let client_name = name.clone();
+    let client = tokio::spawn(async move {
+        let mut connection =
+            Connection::connect_to_socket_wait(&client_name, false, Duration::from_secs(5))
+                .await
+                .unwrap();
+        let message = connection.receive::<String>().await.unwrap();
+        connection.close().await;
+        message
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut listener = Listener::listen_as_socket(&name, false).unwrap();
+    let mut accepted = listener.accept().await.unwrap();
+    accepted.send("ready").await.unwrap();
+    assert_eq!(client.await.unwrap(), "ready");
+    accepted.close().await;
+    listener.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn owned_types_are_received_through_deserialize_owned() {
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+    struct Named {
+        name: String,
+    }
+    async fn receive_owned<T: serde::de::DeserializeOwned>(
+        connection: &mut Connection,
+    ) -> gipc::Result<T> {
+        connection.receive().await
+    }
+    let (mut a, mut b) = in_memory_pair();
+    a.send(Named {
+        name: String::from("owned"),
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        receive_owned::<Named>(&mut b).await.unwrap(),
+        Named {
+            name: String::from("owned")
+        }
+    );
+    a.close().await;
+    b.close().await;
+}
+
+#[tokio::test]
+async fn send_acked_waits_for_the_peer_to_receive() {
+    use std::time::Duration;
+    let (mut sender, mut receiver) = in_memory_pair();
+    let sending = tokio::spawn(async move {
+        sender.send_acked("acked").await.unwrap();
+        sender
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!sending.is_finished());
+    assert_eq!(receiver.receive::<String>().await.unwrap(), "acked");
+    let mut sender = sending.await.unwrap();
+    sender.close().await;
+    receiver.close().await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn listeners_wrap_already_bound_std_sockets() {
+    use gipc::connection::async_tokio::Listener;
+    let path = std::env::temp_dir().join(format!(
+        "gipc-test-async-from-std-{}.sock",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    let mut listener =
+        Listener::from_std(std::os::unix::net::UnixListener::bind(&path).unwrap()).unwrap();
+    let mut client = Connection::from(tokio::net::UnixStream::connect(&path).await.unwrap());
+    let mut accepted = listener.accept().await.unwrap();
+    client.send("activated").await.unwrap();
+    assert_eq!(accepted.receive::<String>().await.unwrap(), "activated");
+    client.close().await;
+    accepted.close().await;
+    listener.close().await.unwrap();
+    assert!(path.exists());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn priority_messages_overtake_waiting_messages() {
+    use gipc::connection::Lane;
+    let (mut sender, mut receiver) = in_memory_pair();
+    let large = "x".repeat(50_000);
+    sender.send(&large).await.unwrap();
+    sender.send_priority("urgent").await.unwrap();
+    assert_eq!(
+        receiver.receive_any::<String>().await.unwrap(),
+        (Lane::Priority, String::from("urgent"))
+    );
+    assert_eq!(
+        receiver.receive_any::<String>().await.unwrap(),
+        (Lane::Normal, large)
+    );
+    sender.close().await;
+    receiver.close().await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn malformed_messages_can_be_skipped() {
+    use gipc::message::Message;
+    use tokio::io::AsyncWriteExt;
+    let (a, mut raw) = tokio::net::UnixStream::pair().unwrap();
+    let mut connection = Connection::from(a);
+    let mut malformed = 2u64.to_be_bytes().to_vec();
+    malformed.extend_from_slice(&[0xff, 0xff]);
+    raw.write_all(&Message::Data(1u8).to_bytes().unwrap())
+        .await
+        .unwrap();
+    raw.write_all(&malformed).await.unwrap();
+    raw.write_all(&Message::Data(2u8).to_bytes().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(connection.receive_or_skip::<u8>().await.unwrap(), Some(1));
+    assert_eq!(connection.receive_or_skip::<u8>().await.unwrap(), None);
+    assert_eq!(connection.receive_or_skip::<u8>().await.unwrap(), Some(2));
+    connection.close().await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn backoff_connects_once_the_listener_starts() {
+    use gipc::connection::async_tokio::Listener;
+    use gipc::connection::BackoffPolicy;
+    use std::time::Duration;
+    let policy = BackoffPolicy {
+        initial_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(40),
+        timeout: Some(Duration::from_secs(5)),
+        max_attempts: None,
+        ..BackoffPolicy::default()
+    };
+    let name = socket_name("backoff");
+    let client_name = name.clone();
+    let client = tokio::spawn(async move {
+        let mut connection = Connection::connect_to_socket_backoff(&client_name, false, &policy)
+            .await
+            .unwrap();
+        let message = connection.receive::<String>().await.unwrap();
+        connection.close().await;
+        message
+    });
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    let mut listener = Listener::listen_as_socket(&name, false).unwrap();
+    let mut accepted = listener.accept().await.unwrap();
+    accepted.send("up").await.unwrap();
+    assert_eq!(client.await.unwrap(), "up");
+    accepted.close().await;
+    listener.close().await.unwrap();
+}
+
+#[cfg(feature = "auth")]
+#[tokio::test]
+async fn handshakes_accept_only_the_right_secret() {
+    let (mut server, mut client) = in_memory_pair();
+    let (checked, answered) = tokio::join!(
+        server.authenticate_peer(b"secret"),
+        client.authenticate(b"secret")
+    );
+    checked.unwrap();
+    answered.unwrap();
+    client.send("authenticated").await.unwrap();
+    assert_eq!(server.receive::<String>().await.unwrap(), "authenticated");
+    server.close().await;
+    client.close().await;
+
+    let (mut server, mut client) = in_memory_pair();
+    let (checked, answered) = tokio::join!(
+        server.authenticate_peer(b"secret"),
+        client.authenticate(b"wrong")
+    );
+    assert!(matches!(checked, Err(gipc::Error::AuthFailed)));
+    assert!(matches!(answered, Err(gipc::Error::AuthFailed)));
+    assert!(server.is_closed());
+    assert!(client.is_closed());
+}
+
+#[cfg(all(unix, feature = "auth"))]
+#[tokio::test]
+async fn listeners_reject_clients_with_the_wrong_secret() {
+    use gipc::connection::async_tokio::Listener;
+    let name = socket_name("auth");
+    let mut listener = Listener::listen_as_socket(&name, false).unwrap();
+    listener.set_auth_secret("secret");
+    let connect = |secret: &'static str| {
+        let name = name.clone();
+        tokio::spawn(async move {
+            let mut client = Connection::connect_to_socket(&name, false).await.unwrap();
+            client
+                .authenticate(secret.as_bytes())
+                .await
+                .map(|()| client)
+        })
+    };
+
+    let wrong = connect("wrong");
+    assert!(matches!(
+        listener.accept().await,
+        Err(gipc::Error::AuthFailed)
+    ));
+    assert!(matches!(wrong.await.unwrap(), Err(gipc::Error::AuthFailed)));
+
+    let right = connect("secret");
+    let mut accepted = listener.accept().await.unwrap();
+    let mut client = right.await.unwrap().unwrap();
+    client.send("let in").await.unwrap();
+    assert_eq!(accepted.receive::<String>().await.unwrap(), "let in");
+    client.close().await;
+    accepted.close().await;
+    listener.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn try_close_reports_undelivered_close_frames() {
+    let (mut connection, mut peer) = in_memory_pair();
+    peer.close().await;
+    drop(peer);
+    assert!(connection.try_close().await.is_err());
+    assert!(connection.is_closed());
+    assert!(matches!(
+        connection.try_close().await,
+        Err(gipc::Error::Closed(false))
+    ));
+}
+
+#[cfg(feature = "tower")]
+#[tokio::test]
+async fn services_work_under_middleware() {
+    use gipc::connection::GipcService;
+    use std::time::Duration;
+    use tower::{Service, ServiceBuilder, ServiceExt};
+    let (client, mut server) = in_memory_pair();
+    let serving = tokio::spawn(async move {
+        while let Ok(request) = server.receive::<u32>().await {
+            match request {
+                // these are answered too late, once as a signal and once as a response
+                0 => {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    server.send_signal(7).await.unwrap();
+                }
+                1 => {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    server.send(1_000u32).await.unwrap();
+                }
+                request => server.send(request * 2).await.unwrap(),
+            }
+        }
+        server.close().await;
+    });
+    let inner = GipcService::<u32, u32>::new(client);
+    let mut service = ServiceBuilder::new()
+        .timeout(Duration::from_millis(50))
+        .service(inner.clone());
+    assert_eq!(service.ready().await.unwrap().call(21).await.unwrap(), 42);
+
+    assert!(service.ready().await.unwrap().call(1).await.is_err());
+    // the timeout covers discarding late responses, so this waits for the late response to arrive first
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(service.ready().await.unwrap().call(5).await.unwrap(), 10);
+
+    // a late signal fails the call that runs into it, but the calls after it work as usual
+    assert!(service.ready().await.unwrap().call(0).await.is_err());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let error = service.ready().await.unwrap().call(3).await.unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<gipc::Error>(),
+        Some(gipc::Error::Signal(7))
+    ));
+    assert_eq!(service.ready().await.unwrap().call(4).await.unwrap(), 8);
+
+    inner.close().await;
+    serving.await.unwrap();
+}
+
+#[tokio::test]
+async fn peek_variant_tells_data_from_close_frames() {
+    use gipc::message::MessageKind;
+    let (mut a, mut b) = in_memory_pair();
+    b.send(1u8).await.unwrap();
+    b.close().await;
+    assert_eq!(a.peek_variant().await.unwrap(), MessageKind::Data);
+    assert_eq!(a.peek_variant().await.unwrap(), MessageKind::Data);
+    assert_eq!(a.receive::<u8>().await.unwrap(), 1);
+    assert_eq!(
+        a.peek_variant().await.unwrap(),
+        MessageKind::ClosingConnection
+    );
+    assert!(matches!(
+        a.receive::<u8>().await,
+        Err(gipc::Error::Closed(true))
+    ));
+}
+
+#[tokio::test]
+async fn idle_connections_are_closed() {
+    use gipc::connection::CloseReason;
+    use std::time::Duration;
+    let (mut a, mut b) = in_memory_pair();
+    a.set_idle_timeout(Some(Duration::from_millis(50)));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(matches!(a.send(1u8).await, Err(gipc::Error::Closed(true))));
+    assert_eq!(a.close_reason(), Some(CloseReason::Idle));
+
+    // a receive that is already waiting is closed on time as well
+    b.set_idle_timeout(Some(Duration::from_millis(50)));
+    let waiting = tokio::time::timeout(Duration::from_millis(500), b.receive::<u8>());
+    assert!(matches!(
+        waiting.await.unwrap(),
+        Err(gipc::Error::Closed(true))
+    ));
+    assert_eq!(b.close_reason(), Some(CloseReason::Idle));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn accept_many_takes_every_pending_connection() {
+    use gipc::connection::async_tokio::Listener;
+    let name = socket_name("accept-many");
+    let mut listener = Listener::listen_as_socket(&name, false).unwrap();
+    let mut clients = Vec::new();
+    for _ in 0..3 {
+        clients.push(Connection::connect_to_socket(&name, false).await.unwrap());
+    }
+    let mut accepted = listener.accept_many(10).await.unwrap();
+    assert_eq!(accepted.len(), 3);
+    for _ in 0..3 {
+        clients.push(Connection::connect_to_socket(&name, false).await.unwrap());
+    }
+    let batch = listener.accept_many(2).await.unwrap();
+    assert_eq!(batch.len(), 2);
+    accepted.extend(batch);
+    accepted.extend(listener.accept_many(2).await.unwrap());
+    assert_eq!(accepted.len(), 6);
+    for mut connection in clients.into_iter().chain(accepted) {
+        connection.close().await;
+    }
+    listener.close().await.unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn implausible_length_prefixes_are_desyncs() {
+    use tokio::io::AsyncWriteExt;
+    let (a, mut raw) = tokio::net::UnixStream::pair().unwrap();
+    let mut connection = Connection::from(a);
+    raw.write_all(&[0xfe; 16]).await.unwrap();
+    assert!(matches!(
+        connection.receive::<u8>().await,
+        Err(gipc::Error::ProtocolDesync(_))
+    ));
+    connection.close().await;
+
+    let (a, mut raw) = tokio::net::UnixStream::pair().unwrap();
+    let mut connection = Connection::from(a);
+    connection.set_max_message_size(Some(1024));
+    raw.write_all(&4096u64.to_be_bytes()).await.unwrap();
+    assert!(matches!(
+        connection.receive::<u8>().await,
+        Err(gipc::Error::ProtocolDesync(4096))
+    ));
+    connection.close().await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn sockets_can_be_bound_to_explicit_paths() {
+    use gipc::connection::async_tokio::Listener;
+    let path =
+        std::env::temp_dir().join(format!("gipc-test-async-path-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut listener = Listener::listen_on_path(&path).unwrap();
+    let mut client = Connection::connect_to_path(&path).await.unwrap();
+    let mut accepted = listener.accept().await.unwrap();
+    client.send("by path").await.unwrap();
+    assert_eq!(accepted.receive::<String>().await.unwrap(), "by path");
+    client.close().await;
+    accepted.close().await;
+    listener.close().await.unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert!(matches!(
+        Connection::connect_to_path(&path).await,
+        Err(gipc::Error::Connect { .. })
+    ));
+}
+
+#[tokio::test]
+async fn serve_answers_requests_until_the_peer_closes() {
+    let (mut server, mut client) = in_memory_pair();
+    let serving = tokio::spawn(async move {
+        let result = server.serve(|request: String| async move { request }).await;
+        server.close().await;
+        result
+    });
+    for request in ["one", "two", "three"] {
+        assert_eq!(
+            client
+                .send_and_receive::<_, String>(&request)
+                .await
+                .unwrap(),
+            request
+        );
+    }
+    client.close().await;
+    serving.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn round_trips_do_not_wait_between_writing_and_reading() {
+    use std::time::{Duration, Instant};
+    // each request goes out in one write, so there's nothing left to flush before reading the response,
+    // and a round trip only costs handing the request over and back rather than waiting for anything to time out
+    let (mut server, mut client) = in_memory_pair();
+    let serving = tokio::spawn(async move {
+        let result = server
+            .serve(|request: u32| async move { request + 1 })
+            .await;
+        server.close().await;
+        result
+    });
+    let started = Instant::now();
+    for request in 0..100u32 {
+        assert_eq!(
+            client.send_and_receive::<_, u32>(&request).await.unwrap(),
+            request + 1
+        );
+    }
+    // a whole second for a hundred round trips leaves plenty of room for slow debug builds
+    let elapsed = started.elapsed();
+    assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+    client.close().await;
+    serving.await.unwrap().unwrap();
+}
+
+#[cfg(feature = "type-check")]
+#[tokio::test]
+async fn typed_connections_reject_messages_of_other_types() {
+    let (a, b) = in_memory_pair();
+    let (mut sender, mut receiver) = (a.typed(), b.typed());
+    sender.send(&1u16).await.unwrap();
+    assert!(matches!(
+        receiver.receive::<u32>().await,
+        Err(gipc::Error::TypeMismatch { .. })
+    ));
+    sender.send_as("id", &2u16).await.unwrap();
+    assert_eq!(receiver.receive_as::<u32>("id").await.unwrap(), 2);
+    sender.into_untyped().close().await;
+    receiver.into_untyped().close().await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn graceful_closes_accept_connections_left_by_cancelled_accepts() {
+    use gipc::connection::async_tokio::Listener;
+    use std::time::Duration;
+    let name = socket_name("close-graceful");
+    let mut listener = Listener::listen_as_socket(&name, false).unwrap();
+    // an accept that is given up on before anyone connects
+    assert!(
+        tokio::time::timeout(Duration::from_millis(20), listener.accept())
+            .await
+            .is_err()
+    );
+    let mut client = Connection::connect_to_socket(&name, false).await.unwrap();
+    let mut accepted = listener.close_graceful().await.unwrap();
+    assert_eq!(accepted.len(), 1);
+    assert!(listener.is_closed());
+    assert!(matches!(
+        listener.accept().await,
+        Err(gipc::Error::Closed(false))
+    ));
+
+    client.send(&"still served").await.unwrap();
+    assert_eq!(
+        accepted[0].receive::<String>().await.unwrap(),
+        "still served"
+    );
+    client.close().await;
+    accepted[0].close().await;
+}
+
+/// A listener that hands out each of `results` in turn, then waits forever for a connection.
+struct QueuedListener {
+    results: Vec<std::io::Result<()>>,
+    cancel_safe: bool,
+    peers: Vec<Connection>,
+}
+
+impl QueuedListener {
+    fn new(results: Vec<std::io::Result<()>>, cancel_safe: bool) -> Self {
+        Self {
+            results,
+            cancel_safe,
+            peers: Vec::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl gipc::connection::async_tokio::ListenerImpl for QueuedListener {
+    async fn accept(&mut self) -> gipc::Result<Connection> {
+        if self.results.is_empty() {
+            std::future::pending::<()>().await;
+        }
+        self.results.remove(0)?;
+        let (connection, peer) = in_memory_pair();
+        self.peers.push(peer);
+        Ok(connection)
+    }
+    async fn close(&mut self) -> gipc::Result<()> {
+        for peer in &mut self.peers {
+            peer.close().await;
+        }
+        Ok(())
+    }
+    fn is_accept_cancel_safe(&self) -> bool {
+        self.cancel_safe
+    }
+}
+
+#[tokio::test]
+async fn graceful_closes_skip_failed_accepts() {
+    use gipc::connection::async_tokio::Listener;
+    use std::io::{Error, ErrorKind};
+    let aborted = || Err(Error::from(ErrorKind::ConnectionAborted));
+
+    let mut listener = Listener::new(Box::new(QueuedListener::new(
+        vec![aborted(), Ok(()), aborted(), aborted(), Ok(())],
+        true,
+    )));
+    let accepted = listener.close_graceful().await.unwrap();
+    assert_eq!(accepted.len(), 2);
+    for mut connection in accepted {
+        connection.close().await;
+    }
+
+    // an accept that keeps failing doesn't keep the close from finishing
+    let mut failing = (0..1000).map(|_| aborted()).collect::<Vec<_>>();
+    failing.push(Ok(()));
+    let mut listener = Listener::new(Box::new(QueuedListener::new(failing, true)));
+    assert!(listener.close_graceful().await.unwrap().is_empty());
+
+    // accepts that might lose a connection when cancelled aren't tried at all
+    let mut listener = Listener::new(Box::new(QueuedListener::new(vec![Ok(())], false)));
+    assert!(listener.close_graceful().await.unwrap().is_empty());
+    assert!(listener.is_closed());
+    let mut listener = Listener::new(Box::new(QueuedListener::new(vec![Ok(()), Ok(())], false)));
+    let mut accepted = listener.accept_many(2).await.unwrap();
+    assert_eq!(accepted.len(), 1);
+    accepted[0].close().await;
+    listener.close().await.unwrap();
+}
+
+#[cfg(all(unix, feature = "codec"))]
+#[tokio::test]
+async fn connections_talk_to_length_delimited_peers() {
+    use futures::{SinkExt, StreamExt};
+    use gipc::message::{length_delimited_codec, Message};
+    let (a, b) = tokio::net::UnixStream::pair().unwrap();
+    let mut connection = Connection::from(a);
+    let mut peer = tokio_util::codec::Framed::new(b, length_delimited_codec());
+
+    connection.send(&"to the peer").await.unwrap();
+    let frame = peer.next().await.unwrap().unwrap();
+    // the codec strips the length prefix, leaving the same payload that gipc frames
+    assert_eq!(
+        &frame[..],
+        &Message::Data("to the peer").to_bytes().unwrap()[8..]
+    );
+
+    let payload = Message::Data(42u32).to_bytes().unwrap()[8..].to_vec();
+    peer.send(bytes::Bytes::from(payload)).await.unwrap();
+    assert_eq!(connection.receive::<u32>().await.unwrap(), 42);
+    connection.close().await;
+}
+
+#[tokio::test]
+async fn socket_buffer_sizes_are_unsupported_in_memory() {
+    let (mut a, mut b) = in_memory_pair();
+    match a.set_recv_buffer_size(1 << 20) {
+        Err(gipc::Error::Io(error)) => assert_eq!(error.kind(), std::io::ErrorKind::Unsupported),
+        other => panic!("expected an unsupported error, got {:?}", other),
+    }
+    assert!(a.send_buffer_size().is_err());
+    a.close().await;
+    b.close().await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn socket_buffer_sizes_can_be_tuned() {
+    let (a, b) = tokio::net::UnixStream::pair().unwrap();
+    let (mut connection, mut peer) = (Connection::from(a), Connection::from(b));
+    let before = connection.send_buffer_size().unwrap();
+    connection.set_send_buffer_size(before * 4).unwrap();
+    assert!(connection.send_buffer_size().unwrap() > before);
+    connection.close().await;
+    peer.close().await;
+}
+
+#[tokio::test]
+async fn connect_hook_sees_every_accepted_connection() {
+    use gipc::connection::async_tokio::Listener;
+    use std::sync::{Arc, Mutex};
+    let mut listener = Listener::new(Box::new(FlakyListener {
+        errors: Vec::new(),
+        peers: Vec::new(),
+    }));
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let recorder = Arc::clone(&seen);
+    listener.set_connect_hook(Box::new(move |connection| {
+        recorder.lock().unwrap().push(connection.id());
+    }));
+
+    let mut accepted = Vec::new();
+    for _ in 0..3 {
+        accepted.push(listener.accept().await.unwrap());
+    }
+    let ids = accepted.iter().map(Connection::id).collect::<Vec<_>>();
+    assert_eq!(*seen.lock().unwrap(), ids);
+    for mut connection in accepted {
+        connection.close().await;
+    }
+    listener.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn custom_encodings_round_trip_as_bytes() {
+    let (mut sender, mut receiver) = in_memory_pair();
+    sender
+        .send_bytes_with(|buffer| {
+            buffer.extend_from_slice(b"GIPC");
+            buffer.push(1);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    let version = receiver
+        .receive_bytes_with(|bytes| match bytes {
+            [b'G', b'I', b'P', b'C', version] => Ok(*version),
+            _ => Err(std::io::Error::from(std::io::ErrorKind::InvalidData).into()),
+        })
+        .await
+        .unwrap();
+    assert_eq!(version, 1);
+    sender.close().await;
+    receiver.close().await;
+}
+
+#[tokio::test]
+async fn close_reasons_reach_the_peer() {
+    use gipc::connection::CloseCode;
+    let (mut a, mut b) = in_memory_pair();
+    a.close_with_reason(CloseCode::GOING_AWAY, None)
+        .await
+        .unwrap();
+    assert!(matches!(
+        b.receive::<u8>().await,
+        Err(gipc::Error::ClosedWithReason {
+            code: CloseCode::GOING_AWAY,
+            message: None
+        })
+    ));
+    assert!(b.is_closed());
+}
+
+#[tokio::test]
+async fn paused_listeners_hold_accepts_until_resumed() {
+    use gipc::connection::async_tokio::Listener;
+    use std::time::Duration;
+    let mut listener = Listener::new(Box::new(FlakyListener {
+        errors: Vec::new(),
+        peers: Vec::new(),
+    }));
+    listener.pause();
+    assert!(listener.is_paused());
+    assert!(
+        tokio::time::timeout(Duration::from_millis(50), listener.accept())
+            .await
+            .is_err()
+    );
+
+    let handle = listener.pause_handle();
+    let accepting = tokio::spawn(async move {
+        let connection = listener.accept().await;
+        (listener, connection)
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(!accepting.is_finished());
+    handle.resume();
+    let (mut listener, connection) = accepting.await.unwrap();
+    assert!(!listener.is_paused());
+    connection.unwrap().close().await;
+    // resuming a listener that isn't paused does nothing
+    listener.resume();
+    listener.accept().await.unwrap().close().await;
+    listener.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn pipelined_requests_are_answered_in_order() {
+    // connections have no lock to split, but a server can still read the next request whilst its last response waits to be read
+    let (mut server, mut client) = in_memory_pair();
+    let serving = tokio::spawn(async move {
+        let result = server
+            .serve(|request: u32| async move { request + 1 })
+            .await;
+        server.close().await;
+        result
+    });
+    for request in 0..3u32 {
+        client.send(&request).await.unwrap();
+    }
+    for request in 0..3u32 {
+        assert_eq!(client.receive::<u32>().await.unwrap(), request + 1);
+    }
+    client.close().await;
+    serving.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn mesh_nodes_reach_every_other_node() {
+    use gipc::connection::async_tokio::in_memory_mesh;
+    let mut mesh = in_memory_mesh(4);
+    assert_eq!(mesh.len(), 4);
+    assert!(mesh.iter().all(|connections| connections.len() == 3));
+
+    // node 0 reaches node 2 at index 2 - 1, and node 2 reaches node 0 at index 0
+    mesh[0][1].send(&"from 0").await.unwrap();
+    assert_eq!(mesh[2][0].receive::<String>().await.unwrap(), "from 0");
+    mesh[2][0].send(&"from 2").await.unwrap();
+    assert_eq!(mesh[0][1].receive::<String>().await.unwrap(), "from 2");
+    // and node 3 reaches node 1 at index 1, whilst node 1 reaches node 3 at index 3 - 1
+    mesh[3][1].send(&3u8).await.unwrap();
+    assert_eq!(mesh[1][2].receive::<u8>().await.unwrap(), 3);
+
+    for connection in mesh.iter_mut().flatten() {
+        connection.close().await;
+    }
+    assert!(in_memory_mesh(0).is_empty());
+    assert!(in_memory_mesh(1)[0].is_empty());
+}
+
+#[tokio::test]
+async fn descriptions_reflect_how_connections_are_set_up() {
+    let (mut a, mut b) = in_memory_pair();
+    let description = a.describe();
+    assert_eq!(description.id, a.id());
+    assert_eq!(description.codec, "CBOR");
+    assert_eq!(description.transport, "in-memory");
+    assert!(description.endpoint.is_none());
+    a.close().await;
+    b.close().await;
+}
+
+#[cfg(feature = "erased-serde")]
+#[tokio::test]
+async fn erased_values_are_received_as_their_concrete_types() {
+    let (mut sender, mut receiver) = in_memory_pair();
+    let value: Box<dyn erased_serde::Serialize + Sync> = Box::new(String::from("erased"));
+    sender.send_erased(value.as_ref()).await.unwrap();
+    assert_eq!(receiver.receive::<String>().await.unwrap(), "erased");
+    sender.close().await;
+    receiver.close().await;
+}
+
+#[tokio::test]
+async fn receive_outcomes_report_closes_without_errors() {
+    use gipc::connection::ReceiveOutcome;
+    let (mut a, mut b) = in_memory_pair();
+    a.send(&"last").await.unwrap();
+    a.close().await;
+    let mut received = Vec::new();
+    while let ReceiveOutcome::Data(message) = b.receive_outcome::<String>().await.unwrap() {
+        received.push(message);
+    }
+    assert_eq!(received, ["last"]);
+    assert!(b.is_closed());
+}
+
+#[tokio::test]
+async fn rate_limits_throttle_sending() {
+    use gipc::connection::RateLimit;
+    use std::time::{Duration, Instant};
+    let (mut sender, mut receiver) = in_memory_pair();
+    sender.set_rate_limit(Some(RateLimit {
+        messages_per_second: None,
+        bytes_per_second: Some(1000),
+    }));
+    let payload = vec![0u8; 400];
+    let started = Instant::now();
+    for _ in 0..4 {
+        sender.send_bytes(&payload).await.unwrap();
+        receiver.receive_bytes().await.unwrap();
+    }
+    // the first 1000 bytes are available straight away, and the third message may overdraw them,
+    // but the fourth has to wait for that deficit of about 200 bytes to be made up
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(150), "{:?}", elapsed);
+    sender.close().await;
+    receiver.close().await;
+}
+
+#[tokio::test]
+async fn unanswered_requests_time_out_and_close_the_connection() {
+    use gipc::connection::CloseReason;
+    use std::time::Duration;
+    let (mut client, mut server) = in_memory_pair();
+    assert!(matches!(
+        client
+            .send_and_receive_timeout::<_, u32>(&1u32, Duration::from_millis(50))
+            .await,
+        Err(gipc::Error::Timeout)
+    ));
+    assert!(client.is_closed());
+    assert_eq!(
+        client.close_reason(),
+        Some(CloseReason::Error(std::io::ErrorKind::TimedOut))
+    );
+    // the request was sent, but the other side isn't told that it won't be waited for
+    assert_eq!(server.receive::<u32>().await.unwrap(), 1);
+    server.close().await;
+}
+
+#[tokio::test]
+async fn draining_closes_surface_messages_that_race_the_close() {
+    use std::time::Duration;
+    let (mut a, mut b) = in_memory_pair();
+    b.send(&"racing").await.unwrap();
+    let peer = tokio::spawn(async move {
+        assert!(matches!(
+            b.receive::<String>().await,
+            Err(gipc::Error::Closed(true))
+        ));
+    });
+    let trailing = a
+        .close_draining::<String>(Duration::from_secs(5))
+        .await
+        .unwrap();
+    assert_eq!(trailing, ["racing"]);
+    assert!(a.is_closed());
+    peer.await.unwrap();
+
+    let (mut a, mut b) = in_memory_pair();
+    b.send(&"not a number").await.unwrap();
+    assert!(matches!(
+        a.close_draining::<u32>(Duration::from_secs(5)).await,
+        Err(gipc::Error::Deserialise(_))
+    ));
+    assert!(a.is_closed());
+    b.close().await;
+
+    // the connection is closed even if the close frame can't be sent
+    let (mut a, mut b) = in_memory_pair();
+    b.close().await;
+    drop(b);
+    assert!(a
+        .close_draining::<u32>(Duration::from_secs(5))
+        .await
+        .is_err());
+    assert!(a.is_closed());
+}
+
+#[tokio::test]
+async fn warming_up_leaves_connections_usable() {
+    let (mut client, mut server) = in_memory_pair();
+    let serving = tokio::spawn(async move {
+        let result = server
+            .serve(|request: u32| async move { request * 2 })
+            .await;
+        server.close().await;
+        result
+    });
+    client.warm_up().await.unwrap();
+    assert_eq!(client.send_and_receive::<_, u32>(&4u32).await.unwrap(), 8);
+    client.close().await;
+    serving.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn progress_is_reported_whilst_large_messages_arrive() {
+    let (mut receiver, mut sender) = in_memory_pair();
+    let sending = tokio::spawn(async move {
+        sender.send(&"x".repeat(1 << 20)).await.unwrap();
+        sender
+    });
+    let mut reports = Vec::new();
+    let received: String = receiver
+        .receive_with_progress(|read, total| reports.push((read, total)))
+        .await
+        .unwrap();
+    assert_eq!(received.len(), 1 << 20);
+    assert!(reports.len() > 1);
+    assert!(reports.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+    let (read, total) = *reports.last().unwrap();
+    assert_eq!(read, total);
+
+    // small messages are reported once, when they are complete
+    let mut sender = sending.await.unwrap();
+    sender.send(&1u8).await.unwrap();
+    let mut calls = 0;
+    receiver
+        .receive_with_progress::<u8>(|read, total| {
+            calls += 1;
+            assert_eq!(read, total);
+        })
+        .await
+        .unwrap();
+    assert_eq!(calls, 1);
+    sender.close().await;
+    receiver.close().await;
+}
+
+#[tokio::test]
+async fn connections_can_be_reset_after_timing_out() {
+    use std::time::{Duration, Instant};
+    let (mut a, mut b) = in_memory_pair();
+    b.set_read_deadline(Some(Instant::now() + Duration::from_millis(20)));
+    assert!(matches!(
+        b.receive::<u32>().await,
+        Err(gipc::Error::Timeout)
+    ));
+    assert!(b.is_failed());
+    b.reset().unwrap();
+    assert!(!b.is_failed());
+    a.send(7u32).await.unwrap();
+    assert_eq!(b.receive::<u32>().await.unwrap(), 7);
+
+    // messages that can't be deserialised leave the connection as it was
+    a.send("not a number").await.unwrap();
+    assert!(b.receive::<u32>().await.is_err());
+    assert!(!b.is_failed());
+
+    a.close().await;
+    b.close().await;
+    assert!(matches!(b.reset(), Err(gipc::Error::Closed(false))));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn connections_that_time_out_mid_frame_are_reset_once_the_frame_arrives() {
+    use gipc::message::Message;
+    use std::time::{Duration, Instant};
+    use tokio::io::AsyncWriteExt;
+    let (a, mut raw) = tokio::net::UnixStream::pair().unwrap();
+    let mut connection = Connection::from(a);
+    let bytes = Message::Data(5u32).to_bytes().unwrap();
+    raw.write_all(&bytes[..2]).await.unwrap();
+    connection.set_read_deadline(Some(Instant::now() + Duration::from_millis(20)));
+    assert!(matches!(
+        connection.receive::<u32>().await,
+        Err(gipc::Error::Timeout)
+    ));
+    assert!(matches!(connection.reset(), Err(gipc::Error::MidFrame)));
+    assert!(connection.is_failed());
+
+    raw.write_all(&bytes[2..]).await.unwrap();
+    connection.set_read_deadline(Some(Instant::now() + Duration::from_secs(5)));
+    assert_eq!(connection.receive::<u32>().await.unwrap(), 5);
+    connection.reset().unwrap();
+    assert!(!connection.is_failed());
+    connection.close().await;
+}
+
+#[tokio::test]
+async fn partial_writes_poison_connections() {
+    use std::time::{Duration, Instant};
+    // more than the other side holds before it has to receive, so sending stalls partway through the frame
+    let (mut a, mut b) = in_memory_pair();
+    a.set_write_deadline(Some(Instant::now() + Duration::from_millis(50)));
+    assert!(matches!(
+        a.send_bytes(&[0; 128 * 1024]).await,
+        Err(gipc::Error::Timeout)
+    ));
+    assert!(a.is_failed());
+    assert!(matches!(a.reset(), Err(gipc::Error::MidFrame)));
+    a.set_write_deadline(None);
+    assert!(matches!(a.send(1u8).await, Err(gipc::Error::MidFrame)));
+    a.close().await;
+    b.close().await;
+}
+
+#[tokio::test]
+async fn messages_larger_than_the_peer_accepts_fail_before_being_sent() {
+    let (mut sender, mut receiver) = in_memory_pair();
+    receiver.set_max_message_size(Some(64));
+    let (sent, received) = tokio::join!(
+        sender.exchange_max_message_sizes(),
+        receiver.exchange_max_message_sizes()
+    );
+    assert_eq!(sent.unwrap(), Some(64));
+    assert_eq!(received.unwrap(), None);
+    assert_eq!(sender.peer_max_message_size(), Some(64));
+
+    let Err(gipc::Error::MessageTooLarge(length)) = sender.send(vec![0u8; 100]).await else {
+        panic!("the message is larger than the receiver accepts");
+    };
+    assert!(length > 64);
+    assert!(!sender.is_failed());
+    sender.send("small").await.unwrap();
+    assert_eq!(receiver.receive::<String>().await.unwrap(), "small");
+    sender.close().await;
+    receiver.close().await;
+}
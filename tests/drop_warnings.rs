@@ -0,0 +1,49 @@
+//! Warnings about asynchronous connections that are dropped without being closed, which only exist in debug builds.
+//! These tests change the process-wide hook that the warnings go to, so they are kept apart from the other tests.
+#![cfg(all(feature = "async-tokio", debug_assertions))]
+
+use gipc::connection::async_tokio::{in_memory_pair, set_unclosed_drop_hook};
+use std::sync::Mutex;
+
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn record(message: &str) {
+    WARNINGS.lock().unwrap().push(message.to_string());
+}
+
+fn take_warnings() -> Vec<String> {
+    std::mem::take(&mut *WARNINGS.lock().unwrap())
+}
+
+#[tokio::test]
+async fn only_connections_dropped_whilst_open_warn() {
+    set_unclosed_drop_hook(Some(record));
+    let (mut closed, open) = in_memory_pair();
+    closed.close().await;
+    drop(closed);
+    assert!(take_warnings().is_empty());
+
+    let id = open.id();
+    drop(open);
+    let warnings = take_warnings();
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].contains(&format!("connection {} ", id)));
+
+    #[cfg(feature = "tower")]
+    {
+        // services close their connection themselves once the last handle to them is gone
+        let (connection, mut peer) = in_memory_pair();
+        drop(gipc::connection::GipcService::<u8, u8>::new(connection));
+        assert!(matches!(
+            peer.receive::<u8>().await,
+            Err(gipc::Error::Closed(true))
+        ));
+        peer.close().await;
+        assert!(take_warnings().is_empty());
+    }
+
+    set_unclosed_drop_hook(None);
+    let (silenced, _) = in_memory_pair();
+    drop(silenced);
+    assert!(take_warnings().is_empty());
+}
@@ -0,0 +1,158 @@
+//! Behaviour that depends on the process-wide socket configuration, such as the runtime directory, name scheme and socket suffix.
+//! These tests change that configuration, so they run one at a time and are kept apart from the other tests.
+#![cfg(all(unix, feature = "sync"))]
+
+use gipc::connection::sync::{Connection, Listener};
+use gipc::connection::{set_name_scheme, set_runtime_dir, set_socket_suffix, NameScheme};
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+static CONFIG: Mutex<()> = Mutex::new(());
+
+/// Takes exclusive use of the process-wide configuration, resetting it to the defaults once the guard is dropped.
+struct Config {
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl Config {
+    fn lock() -> Self {
+        Self {
+            _guard: CONFIG
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+        }
+    }
+}
+
+impl Drop for Config {
+    fn drop(&mut self) {
+        set_runtime_dir(None);
+        set_name_scheme(NameScheme::Auto);
+        set_socket_suffix(None);
+    }
+}
+
+/// Gets a directory under the system's temporary directory that doesn't exist yet.
+fn missing_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gipc-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn listen_retry_succeeds_once_the_directory_exists() {
+    let _config = Config::lock();
+    let dir = missing_dir("retry");
+    set_name_scheme(NameScheme::Paths);
+    set_runtime_dir(Some(dir.clone()));
+    assert!(Listener::listen_as_socket("retry", false).is_err());
+
+    let creator = {
+        let dir = dir.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            std::fs::create_dir_all(dir).unwrap();
+        })
+    };
+    let listener = Listener::listen_as_socket_retry("retry", false, 50, Duration::from_millis(20));
+    creator.join().unwrap();
+    assert!(listener.is_ok());
+    drop(listener);
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn listen_retry_gives_up_on_permanent_errors() {
+    let _config = Config::lock();
+    // a runtime "directory" that is a file can never be bound in
+    let file = missing_dir("permanent");
+    std::fs::write(&file, b"").unwrap();
+    set_name_scheme(NameScheme::Paths);
+    set_runtime_dir(Some(file.clone()));
+
+    let started = Instant::now();
+    let result =
+        Listener::listen_as_socket_retry("permanent", false, 50, Duration::from_millis(100));
+    assert!(result.is_err());
+    assert!(started.elapsed() < Duration::from_secs(1));
+    let _ = std::fs::remove_file(file);
+}
+
+#[test]
+fn long_names_are_hashed() {
+    let _config = Config::lock();
+    let name = format!("{}-{}", "x".repeat(500), std::process::id());
+    let mut listener = Listener::listen_as_socket(&name, false).unwrap();
+    let resolved = listener.resolved_name().unwrap().to_string();
+    assert!(resolved.len() < 110, "{}", resolved);
+    assert!(resolved.contains("gipc-"), "{}", resolved);
+
+    let mut client = Connection::connect_to_socket(&name, false).unwrap();
+    let mut server = listener.accept().unwrap();
+    client.send(&1u8).unwrap();
+    assert_eq!(server.receive::<u8>().unwrap(), 1);
+}
+
+#[test]
+fn names_too_long_even_when_hashed_are_rejected() {
+    let _config = Config::lock();
+    set_name_scheme(NameScheme::Paths);
+    set_runtime_dir(Some(PathBuf::from("/tmp").join("d".repeat(200))));
+    let result = Listener::listen_as_socket("long-dir", false);
+    assert!(matches!(result, Err(gipc::Error::NameTooLong(_))));
+}
+
+#[test]
+fn self_test_passes_and_reports_misconfiguration() {
+    let _config = Config::lock();
+    gipc::connection::sync::self_test().unwrap();
+    set_name_scheme(NameScheme::Paths);
+    set_runtime_dir(Some(missing_dir("self-test")));
+    assert!(gipc::connection::sync::self_test().is_err());
+}
+
+#[test]
+fn custom_suffixes_are_used_for_every_scheme() {
+    let _config = Config::lock();
+    set_socket_suffix(Some(String::from(".custom")));
+    let name = format!("suffix-{}", std::process::id());
+    let dir = missing_dir("suffix");
+    std::fs::create_dir_all(&dir).unwrap();
+    set_runtime_dir(Some(dir.clone()));
+    for scheme in [NameScheme::Auto, NameScheme::Paths] {
+        set_name_scheme(scheme);
+        let listener = Listener::listen_as_socket(&name, false).unwrap();
+        let resolved = listener.resolved_name().unwrap().to_string();
+        assert!(
+            resolved.ends_with(&format!("{}.custom", name)),
+            "{:?}: {}",
+            scheme,
+            resolved
+        );
+        let mut client = Connection::connect_to_socket(&name, false).unwrap();
+        client.send(&1u8).unwrap();
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn forced_path_scheme_resolves_names_the_same_everywhere() {
+    use gipc::connection::ResolvedName;
+    let _config = Config::lock();
+    // even where namespaced names are supported, the name maps to a path like on platforms that only support paths
+    set_name_scheme(NameScheme::Paths);
+    let name = format!("forced-paths-{}", std::process::id());
+    let dir = missing_dir("forced-paths");
+    std::fs::create_dir_all(&dir).unwrap();
+    set_runtime_dir(Some(dir.clone()));
+    let listener = Listener::listen_as_socket(&name, false).unwrap();
+    assert_eq!(
+        listener.resolved_name(),
+        Some(&ResolvedName::Path(dir.join(format!("{}.sock", name))))
+    );
+    let mut client = Connection::connect_to_socket(&name, false).unwrap();
+    client.send(&1u8).unwrap();
+    drop(listener);
+    std::fs::remove_dir_all(dir).unwrap();
+}
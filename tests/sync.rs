@@ -0,0 +1,1925 @@
+//! Behaviour of synchronous connections and listeners, tested over Unix socket pairs.
+#![cfg(all(unix, feature = "sync"))]
+
+use gipc::connection::sync::Connection;
+use std::io::Read;
+use std::os::unix::net::UnixStream;
+
+/// Creates two connections that are connected to each other.
+fn pair() -> (Connection, Connection) {
+    let (a, b) = UnixStream::pair().unwrap();
+    (Connection::from(a), Connection::from(b))
+}
+
+/// Creates a connection and the raw stream on the other side of it.
+fn raw_pair() -> (Connection, UnixStream) {
+    let (a, b) = UnixStream::pair().unwrap();
+    (Connection::from(a), b)
+}
+
+/// Reads everything that has been written to `stream` so far, waiting up to a second for the first bytes.
+fn read_available(stream: &mut UnixStream) -> Vec<u8> {
+    stream
+        .set_read_timeout(Some(std::time::Duration::from_secs(1)))
+        .unwrap();
+    let mut bytes = vec![0u8; 64 * 1024];
+    let read = stream.read(&mut bytes).unwrap();
+    bytes.truncate(read);
+    stream.set_nonblocking(true).unwrap();
+    let mut chunk = [0u8; 4096];
+    while let Ok(read @ 1..) = stream.read(&mut chunk) {
+        bytes.extend_from_slice(&chunk[..read]);
+    }
+    stream.set_nonblocking(false).unwrap();
+    bytes
+}
+
+#[test]
+fn send_bytes_frames_compactly() {
+    let (mut connection, mut raw) = raw_pair();
+    connection.send_bytes(&[0xAB; 1000]).unwrap();
+    let frame = read_available(&mut raw);
+    assert!(frame.len() >= 1000 && frame.len() < 1100, "{}", frame.len());
+
+    let (mut sender, mut receiver) = pair();
+    sender.send_bytes(&[7; 1000]).unwrap();
+    assert_eq!(receiver.receive_bytes().unwrap(), vec![7; 1000]);
+}
+
+#[test]
+fn versioned_connection_rejects_other_versions() {
+    use gipc::connection::sync::VersionedConnection;
+    let (a, b) = pair();
+    let (mut old, mut new) = (
+        VersionedConnection::new(a, 1),
+        VersionedConnection::new(b, 2),
+    );
+    old.send(&"hello").unwrap();
+    match new.receive::<String>() {
+        Err(gipc::Error::SchemaMismatch { expected, got }) => assert_eq!((expected, got), (2, 1)),
+        result => panic!("expected a schema mismatch, got {:?}", result),
+    }
+    new.send(&5u32).unwrap();
+    assert!(matches!(
+        old.receive::<u32>(),
+        Err(gipc::Error::SchemaMismatch {
+            expected: 1,
+            got: 2
+        })
+    ));
+
+    let (a, b) = pair();
+    let (mut sender, mut receiver) = (
+        VersionedConnection::new(a, 3),
+        VersionedConnection::new(b, 3),
+    );
+    sender.send(&"same").unwrap();
+    assert_eq!(receiver.receive::<String>().unwrap(), "same");
+}
+
+/// Frames `payload` with its length prefix, as gipc writes it.
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = (payload.len() as u64).to_be_bytes().to_vec();
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[test]
+fn drain_pending_counts_arrived_messages() {
+    let (mut sender, mut receiver) = pair();
+    for i in 0..5u8 {
+        sender.send(&i).unwrap();
+    }
+    assert_eq!(receiver.drain_pending().unwrap(), 5);
+    assert_eq!(receiver.drain_pending().unwrap(), 0);
+    sender.send(&9u8).unwrap();
+    assert_eq!(receiver.receive::<u8>().unwrap(), 9);
+}
+
+#[test]
+fn drain_pending_keeps_partial_frames_and_skips_malformed_ones() {
+    use std::io::Write;
+    let (mut connection, mut raw) = raw_pair();
+    raw.write_all(&frame(&[0xff, 0xff, 0xff])).unwrap();
+    let message = {
+        let (mut sender, mut peer) = raw_pair();
+        sender.send(&7u8).unwrap();
+        read_available(&mut peer)
+    };
+    raw.write_all(&message).unwrap();
+    raw.write_all(&message[..message.len() - 1]).unwrap();
+    assert_eq!(connection.drain_pending().unwrap(), 2);
+    raw.write_all(&message[message.len() - 1..]).unwrap();
+    assert_eq!(connection.receive::<u8>().unwrap(), 7);
+}
+
+#[test]
+fn socket_errors_name_the_socket() {
+    use gipc::connection::sync::Listener;
+    let name = format!("gipc-test-missing-{}", std::process::id());
+    let Err(error) = Connection::connect_to_socket(&name, false) else {
+        panic!("connected to a socket that doesn't exist");
+    };
+    assert!(matches!(error, gipc::Error::Connect { .. }));
+    assert!(error.to_string().contains(&name), "{}", error);
+
+    let name = format!("gipc-test-taken-{}", std::process::id());
+    let _listener = Listener::listen_as_socket(&name, false).unwrap();
+    let Err(error) = Listener::listen_as_socket(&name, false) else {
+        panic!("bound to a socket that is taken");
+    };
+    assert!(matches!(error, gipc::Error::Bind { .. }));
+    assert!(error.to_string().contains(&name), "{}", error);
+}
+
+#[cfg(feature = "type-check")]
+#[test]
+fn typed_connections_can_switch_to_untyped_messages() {
+    let (a, b) = pair();
+    let (mut sender, mut receiver) = (a.typed(), b.typed());
+    sender.send(&1u32).unwrap();
+    sender.as_untyped().send(&"out of band").unwrap();
+    sender.send(&2u32).unwrap();
+
+    assert_eq!(receiver.receive::<u32>().unwrap(), 1);
+    assert_eq!(
+        receiver.as_untyped().receive::<String>().unwrap(),
+        "out of band"
+    );
+    assert_eq!(receiver.receive::<u32>().unwrap(), 2);
+
+    sender.send(&3u32).unwrap();
+    let mut receiver = receiver.into_untyped();
+    // typed messages are sent as their fingerprint followed by their data, so an untyped receive sees both
+    let (tag, value): (u64, u32) = receiver.receive().unwrap();
+    assert_eq!(
+        tag,
+        gipc::message::fingerprint(std::any::type_name::<u32>())
+    );
+    assert_eq!(value, 3);
+}
+
+#[cfg(feature = "type-check")]
+#[test]
+fn typed_connections_reject_messages_of_other_types() {
+    #[derive(serde::Serialize)]
+    struct Celsius(f64);
+    #[derive(serde::Deserialize, Debug)]
+    struct Fahrenheit(#[allow(dead_code)] f64);
+
+    let (a, b) = pair();
+    let (mut sender, mut receiver) = (a.typed(), b.typed());
+    // an untyped connection would happily decode one as the other, as they look the same on the wire
+    sender.send(&Celsius(20.0)).unwrap();
+    match receiver.receive::<Fahrenheit>() {
+        Err(gipc::Error::TypeMismatch { expected, got }) => {
+            assert_eq!(
+                expected,
+                gipc::message::fingerprint(std::any::type_name::<Fahrenheit>())
+            );
+            assert_eq!(
+                got,
+                gipc::message::fingerprint(std::any::type_name::<Celsius>())
+            );
+        }
+        other => panic!("expected a type mismatch, got {:?}", other),
+    }
+
+    // types defined separately on both sides can share a schema ID instead
+    sender.send_as("temperature", &Celsius(20.0)).unwrap();
+    sender.send_as("pressure", &Celsius(1.0)).unwrap();
+    assert_eq!(receiver.receive_as::<f64>("temperature").unwrap(), 20.0);
+    assert!(matches!(
+        receiver.receive_as::<f64>("temperature"),
+        Err(gipc::Error::TypeMismatch { .. })
+    ));
+    // a mismatch only costs the message itself
+    sender.send(&1u8).unwrap();
+    assert_eq!(receiver.receive::<u8>().unwrap(), 1);
+}
+
+#[test]
+fn signals_are_received_distinctly() {
+    let (mut sender, mut receiver) = pair();
+    sender.send_signal(3).unwrap();
+    sender.send(&"data").unwrap();
+    assert!(matches!(
+        receiver.receive::<String>(),
+        Err(gipc::Error::Signal(3))
+    ));
+    assert_eq!(receiver.receive::<String>().unwrap(), "data");
+}
+
+#[test]
+fn past_deadlines_fail_immediately() {
+    use std::time::{Duration, Instant};
+    let (mut a, mut b) = pair();
+    b.send(&1u8).unwrap();
+    let past = Instant::now() - Duration::from_secs(1);
+    a.set_read_deadline(Some(past)).unwrap();
+    let started = Instant::now();
+    assert!(matches!(a.receive::<u8>(), Err(gipc::Error::Timeout)));
+    assert!(started.elapsed() < Duration::from_millis(100));
+    a.set_write_deadline(Some(past)).unwrap();
+    assert!(matches!(a.send(&2u8), Err(gipc::Error::Timeout)));
+
+    a.set_read_deadline(None).unwrap();
+    a.set_write_deadline(None).unwrap();
+    assert_eq!(a.receive::<u8>().unwrap(), 1);
+    a.send(&2u8).unwrap();
+    assert_eq!(b.receive::<u8>().unwrap(), 2);
+}
+
+#[test]
+fn read_deadlines_hold_against_trickling_peers() {
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+    let (mut connection, mut raw) = raw_pair();
+    let trickler = std::thread::spawn(move || {
+        let bytes = frame(&[0u8; 100]);
+        for byte in bytes {
+            if raw.write_all(&[byte]).is_err() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    });
+    let started = Instant::now();
+    connection
+        .set_read_deadline(Some(started + Duration::from_millis(150)))
+        .unwrap();
+    assert!(matches!(
+        connection.receive::<u8>(),
+        Err(gipc::Error::Timeout)
+    ));
+    assert!(started.elapsed() < Duration::from_millis(500));
+    connection.close();
+    drop(connection);
+    trickler.join().unwrap();
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn codecs_can_be_chosen_per_message() {
+    use gipc::codec::{CborCodec, JsonCodec};
+    let (mut sender, mut receiver) = pair();
+    sender.send_with(&JsonCodec, &"json").unwrap();
+    sender.send_with(&CborCodec, &"cbor").unwrap();
+    sender.send(&"default").unwrap();
+    assert_eq!(
+        receiver.receive_with::<_, String>(&JsonCodec).unwrap(),
+        "json"
+    );
+    assert_eq!(
+        receiver.receive_with::<_, String>(&CborCodec).unwrap(),
+        "cbor"
+    );
+    assert_eq!(receiver.receive::<String>().unwrap(), "default");
+
+    sender.send_with(&JsonCodec, &1u8).unwrap();
+    assert!(matches!(
+        receiver.receive::<u8>(),
+        Err(gipc::Error::Deserialise(_))
+    ));
+}
+
+#[test]
+fn detailed_connects_report_the_resolved_endpoint() {
+    use gipc::connection::sync::Listener;
+    use gipc::connection::{name_support, ResolvedName};
+    let name = format!("gipc-test-detailed-{}", std::process::id());
+    let listener = Listener::listen_as_socket(&name, false).unwrap();
+    let (_connection, endpoint) = Connection::connect_to_socket_detailed(&name, false).unwrap();
+    assert_eq!(Some(&endpoint), listener.resolved_name());
+    match endpoint {
+        ResolvedName::Namespaced(resolved) => {
+            assert!(name_support().namespaced());
+            assert!(resolved.starts_with(&name));
+        }
+        ResolvedName::Path(path) => {
+            assert!(!name_support().namespaced());
+            assert!(path.to_string_lossy().contains(&name));
+        }
+    }
+}
+
+#[test]
+fn observer_sees_sent_and_received_payloads() {
+    use gipc::connection::Direction;
+    use std::sync::{Arc, Mutex};
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let (mut a, mut raw) = raw_pair();
+    let log = Arc::clone(&seen);
+    a.set_observer(Box::new(move |direction, payload| {
+        log.lock().unwrap().push((direction, payload.to_vec()))
+    }));
+
+    a.send(&"outgoing").unwrap();
+    let written = read_available(&mut raw);
+    use std::io::Write;
+    raw.write_all(&written).unwrap();
+    assert_eq!(a.receive::<String>().unwrap(), "outgoing");
+
+    let seen = seen.lock().unwrap();
+    assert_eq!(seen.len(), 2);
+    assert!(matches!(seen[0].0, Direction::Sent));
+    assert!(matches!(seen[1].0, Direction::Received));
+    // the observer is given exactly the payload that went over the wire, without its length prefix
+    assert_eq!(frame(&seen[0].1), written);
+    assert_eq!(seen[0].1, seen[1].1);
+}
+
+#[test]
+fn flush_and_wait_drain_waits_for_the_peer_to_read() {
+    use std::time::Duration;
+    let (mut connection, mut raw) = raw_pair();
+    connection.send_bytes(&[1; 4096]).unwrap();
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios"
+    ))]
+    assert!(matches!(
+        connection.flush_and_wait_drain(Duration::from_millis(20)),
+        Err(gipc::Error::Timeout)
+    ));
+    read_available(&mut raw);
+    connection
+        .flush_and_wait_drain(Duration::from_secs(1))
+        .unwrap();
+}
+
+#[test]
+fn close_reason_is_peer_when_the_other_side_closes() {
+    use gipc::connection::CloseReason;
+    let (mut a, mut b) = pair();
+    assert_eq!(a.close_reason(), None);
+    b.close();
+    assert_eq!(b.close_reason(), Some(CloseReason::Local));
+    assert!(matches!(a.receive::<u8>(), Err(gipc::Error::Closed(true))));
+    assert_eq!(a.close_reason(), Some(CloseReason::Peer));
+
+    // dropping a connection closes it cleanly, so only a raw stream can go away without closing
+    let (mut a, raw) = raw_pair();
+    drop(raw);
+    assert!(a.receive::<u8>().is_err());
+    assert_eq!(a.close_reason(), Some(CloseReason::Dropped));
+}
+
+#[test]
+fn dynamic_values_round_trip() {
+    use ciborium::value::Value;
+    let value = Value::Map(vec![
+        (Value::Text("name".into()), Value::Text("plugin".into())),
+        (
+            Value::Text("args".into()),
+            Value::Array(vec![
+                Value::Integer(1.into()),
+                Value::Map(vec![(Value::Bool(true), Value::Null)]),
+                Value::Bytes(vec![1, 2, 3]),
+            ]),
+        ),
+    ]);
+    let (mut a, mut b) = pair();
+    a.send_value(&value).unwrap();
+    assert_eq!(b.receive_value().unwrap(), value);
+
+    // values can be forwarded without knowing the type they were sent as
+    a.send(&(5u8, "five")).unwrap();
+    let forwarded = b.receive_value().unwrap();
+    b.send_value(&forwarded).unwrap();
+    assert_eq!(a.receive::<(u8, String)>().unwrap(), (5, "five".into()));
+}
+
+#[test]
+fn accept_timeout_returns_none_when_no_client_connects() {
+    use gipc::connection::sync::Listener;
+    use std::time::{Duration, Instant};
+    let name = format!("gipc-test-accept-timeout-{}", std::process::id());
+    let mut listener = Listener::listen_as_socket(&name, false).unwrap();
+    let start = Instant::now();
+    assert!(listener
+        .accept_timeout(Duration::from_millis(50))
+        .unwrap()
+        .is_none());
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    // the listener is still usable, and what it accepts is blocking as usual
+    let mut client = Connection::connect_to_socket(&name, false).unwrap();
+    let mut server = listener
+        .accept_timeout(Duration::from_secs(1))
+        .unwrap()
+        .expect("the client connected");
+    client.send(&3u8).unwrap();
+    assert_eq!(server.receive::<u8>().unwrap(), 3);
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn bytes_buffers_round_trip() {
+    use std::sync::{Arc, Mutex};
+    let (mut a, mut b) = pair();
+    let reported = Arc::new(Mutex::new(Vec::new()));
+    let log = Arc::clone(&reported);
+    b.set_message_hook(Box::new(move |_, name, length| {
+        log.lock().unwrap().push((name.to_owned(), length))
+    }));
+
+    let sent = bytes::Bytes::from_static(b"some binary payload");
+    a.send_bytes_buf(&sent).unwrap();
+    let received = b.receive_bytes_buf().unwrap();
+    assert_eq!(received, sent);
+
+    let reported = reported.lock().unwrap();
+    assert_eq!(reported.len(), 1);
+    assert!(reported[0].0.contains("ByteBuf"), "{}", reported[0].0);
+}
+
+#[test]
+fn frames_with_trailing_bytes_are_rejected() {
+    use gipc::message::Message;
+    use std::io::Write;
+    let (mut connection, mut raw) = raw_pair();
+    let mut payload = Message::Data(5u8).to_bytes().unwrap().split_off(8);
+    payload.extend_from_slice(&[0, 0, 0]);
+    raw.write_all(&frame(&payload)).unwrap();
+    match connection.receive::<u8>() {
+        Err(gipc::Error::Deserialise(error)) => {
+            assert!(error.to_string().contains("trailing bytes"), "{}", error)
+        }
+        result => panic!("expected a deserialisation error, got {:?}", result),
+    }
+    // the frame was consumed as a whole, so the next one is read normally
+    raw.write_all(&Message::Data(6u8).to_bytes().unwrap())
+        .unwrap();
+    assert_eq!(connection.receive::<u8>().unwrap(), 6);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn codecs_can_be_switched_after_negotiating() {
+    use gipc::codec::{CborCodec, JsonCodec};
+    let (mut client, mut server) = pair();
+    client.set_codec(Box::new(JsonCodec));
+    server.set_codec(Box::new(JsonCodec));
+
+    client.send(&"cbor").unwrap();
+    let agreed = server.receive::<String>().unwrap();
+    assert_eq!(agreed, "cbor");
+    server.send(&true).unwrap();
+    assert!(client.receive::<bool>().unwrap());
+
+    // both sides switch right after the negotiation, so everything after it is CBOR
+    client.set_codec(Box::new(CborCodec));
+    server.reset_codec();
+    client.send(&vec![1u16, 2, 3]).unwrap();
+    assert_eq!(
+        server.receive_with::<_, Vec<u16>>(&CborCodec).unwrap(),
+        [1, 2, 3]
+    );
+    client.send(&"still cbor").unwrap();
+    assert!(server.receive_with::<_, String>(&JsonCodec).is_err());
+}
+
+#[test]
+fn iterators_are_streamed_as_one_message() {
+    let (mut sender, mut receiver) = pair();
+    let sending = std::thread::spawn(move || {
+        sender.send_iter(0..1_000_000u32).unwrap();
+        sender
+    });
+    let received = receiver.receive::<Vec<u32>>().unwrap();
+    assert_eq!(received.len(), 1_000_000);
+    assert!(received.iter().copied().eq(0..1_000_000));
+    let mut sender = sending.join().unwrap();
+    sender.send(&"after").unwrap();
+    assert_eq!(receiver.receive::<String>().unwrap(), "after");
+}
+
+#[test]
+fn streamed_messages_respect_the_maximum_message_size() {
+    let (mut sender, mut receiver) = pair();
+    receiver.set_max_message_size(Some(64 * 1024));
+    let sending = std::thread::spawn(move || {
+        // the receiver gives up on the message part of the way through, so sending it fails
+        let _ = sender.send_iter(0..1_000_000u32);
+    });
+    assert!(matches!(
+        receiver.receive::<Vec<u32>>(),
+        Err(gipc::Error::ProtocolDesync(_))
+    ));
+    drop(receiver);
+    sending.join().unwrap();
+}
+
+#[test]
+fn receive_batch_returns_early() {
+    let (mut sender, mut receiver) = pair();
+    for i in 0..3u8 {
+        sender.send(&i).unwrap();
+    }
+    assert_eq!(receiver.receive_batch::<u8>(2).unwrap(), [0, 1]);
+    assert_eq!(receiver.receive_batch::<u8>(10).unwrap(), [2]);
+    assert!(receiver.receive_batch::<u8>(10).unwrap().is_empty());
+    sender.send(&3u8).unwrap();
+    assert_eq!(receiver.receive::<u8>().unwrap(), 3);
+}
+
+#[test]
+fn close_frames_can_be_disabled() {
+    let (mut connection, mut raw) = raw_pair();
+    connection.set_send_close_frame(false);
+    connection.send_bytes(b"last").unwrap();
+    let sent = read_available(&mut raw);
+    connection.close();
+    drop(connection);
+    let mut rest = Vec::new();
+    raw.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty(), "{:?} was written on close", rest);
+    assert!(!sent.is_empty());
+
+    let (mut connection, mut raw) = raw_pair();
+    connection.close();
+    drop(connection);
+    let mut rest = Vec::new();
+    raw.read_to_end(&mut rest).unwrap();
+    assert!(!rest.is_empty(), "the closing message is sent by default");
+}
+
+#[test]
+fn connections_get_distinct_ids() {
+    use gipc::connection::sync::ConnectionImpl;
+    // this only compiles as long as `Connection::new` can be called in const contexts
+    const fn wrap(internal: Box<dyn ConnectionImpl>) -> Connection {
+        Connection::new(internal)
+    }
+    let (a, b) = UnixStream::pair().unwrap();
+    let (first, second) = (wrap(Box::new(a)), wrap(Box::new(b)));
+    assert_ne!(first.id(), second.id());
+    assert!(first.id() < second.id());
+    assert_eq!(first.id(), first.id());
+    assert_eq!(first.describe().id, first.id());
+}
+
+#[test]
+fn try_receive_as_leaves_mismatched_frames_buffered() {
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+    let (mut a, mut b) = pair();
+    a.send(&(1u8, 2u8)).unwrap();
+    assert!(matches!(
+        b.try_receive_as::<Point>(),
+        Err(gipc::Error::Deserialise(_))
+    ));
+    assert_eq!(b.try_receive_as::<(u8, u8)>().unwrap(), (1, 2));
+
+    a.send(&Point { x: 3, y: 4 }).unwrap();
+    assert!(b.try_receive_as::<(u8, u8)>().is_err());
+    assert!(b.try_receive_as::<String>().is_err());
+    assert_eq!(b.try_receive_as::<Point>().unwrap(), Point { x: 3, y: 4 });
+}
+
+#[test]
+fn registries_list_accepted_connections() {
+    use gipc::connection::sync::Listener;
+    use gipc::connection::ConnectionRegistry;
+    let name = format!("gipc-test-registry-{}", std::process::id());
+    let mut listener = Listener::listen_as_socket(&name, false).unwrap();
+    let registry = ConnectionRegistry::new();
+    listener.set_registry(registry.clone());
+
+    let mut clients = Vec::new();
+    let mut accepted = Vec::new();
+    for _ in 0..2 {
+        clients.push(Connection::connect_to_socket(&name, false).unwrap());
+        accepted.push(listener.accept().unwrap());
+    }
+    clients[0].send(&"hello").unwrap();
+    assert_eq!(accepted[0].receive::<String>().unwrap(), "hello");
+
+    let mut listed = registry.iter().collect::<Vec<_>>();
+    listed.sort_by_key(|info| info.id);
+    let ids = listed.iter().map(|info| info.id).collect::<Vec<_>>();
+    assert_eq!(ids, [accepted[0].id(), accepted[1].id()]);
+    assert!(listed[0].bytes_received > 0);
+    assert_eq!(listed[1].bytes_received, 0);
+
+    accepted.remove(0).close();
+    assert_eq!(registry.len(), 1);
+    drop(accepted);
+    assert!(registry.is_empty());
+}
+
+#[test]
+fn accept_hook_runs_before_every_accept() {
+    use gipc::connection::sync::Listener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    let name = format!("gipc-test-accept-hook-{}", std::process::id());
+    let mut listener = Listener::listen_as_socket(&name, false).unwrap();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&calls);
+    listener.set_accept_hook(Box::new(move || {
+        counter.fetch_add(1, Ordering::SeqCst);
+    }));
+
+    let _first = Connection::connect_to_socket(&name, false).unwrap();
+    let _second = Connection::connect_to_socket(&name, false).unwrap();
+    listener.accept().unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    listener.accept().unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn interrupted_reads_resume_the_same_frame() {
+    use gipc::message::Message;
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+    let (mut connection, mut raw) = raw_pair();
+    let expected = "resumed".repeat(1000);
+    let bytes = Message::Data(expected.clone()).to_bytes().unwrap();
+    let (first, rest) = bytes.split_at(bytes.len() / 2);
+    raw.write_all(first).unwrap();
+    connection
+        .set_read_deadline(Some(Instant::now() + Duration::from_millis(50)))
+        .unwrap();
+    assert!(matches!(
+        connection.receive::<String>(),
+        Err(gipc::Error::Timeout)
+    ));
+    raw.write_all(rest).unwrap();
+    raw.write_all(&Message::Data(1u8).to_bytes().unwrap())
+        .unwrap();
+    connection.set_read_deadline(None).unwrap();
+    assert_eq!(connection.receive::<String>().unwrap(), expected);
+    assert_eq!(connection.receive::<u8>().unwrap(), 1);
+}
+
+#[test]
+fn timed_out_receives_resume_with_fresh_deadlines() {
+    use gipc::message::Message;
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+    let (mut connection, mut raw) = raw_pair();
+    let expected = "long poll".repeat(100);
+    let bytes = Message::Data(expected.clone()).to_bytes().unwrap();
+    // the length prefix alone, then part of the body, then the rest of it
+    let pieces = [
+        &bytes[..4],
+        &bytes[4..bytes.len() / 2],
+        &bytes[bytes.len() / 2..],
+    ];
+    for piece in &pieces[..2] {
+        raw.write_all(piece).unwrap();
+        connection
+            .set_read_deadline(Some(Instant::now() + Duration::from_millis(50)))
+            .unwrap();
+        assert!(matches!(
+            connection.receive::<String>(),
+            Err(gipc::Error::Timeout)
+        ));
+    }
+    raw.write_all(pieces[2]).unwrap();
+    connection
+        .set_read_deadline(Some(Instant::now() + Duration::from_secs(5)))
+        .unwrap();
+    assert_eq!(connection.receive::<String>().unwrap(), expected);
+    assert!(!connection.is_closed());
+}
+
+#[test]
+fn subscribers_receive_messages_published_after_they_connect() {
+    use gipc::connection::{Publisher, Subscriber};
+    let name = format!("gipc-test-topic-{}", std::process::id());
+    let mut publisher = Publisher::<String>::listen_as_socket(&name, false).unwrap();
+    let mut early = Subscriber::<String>::connect_to_socket(&name, false).unwrap();
+    assert_eq!(publisher.publish(&"first".to_string()).unwrap(), 1);
+    let mut late = Subscriber::<String>::connect_to_socket(&name, false).unwrap();
+    for message in ["one", "two", "three"] {
+        assert_eq!(publisher.publish(&message.to_string()).unwrap(), 2);
+    }
+    assert_eq!(early.receive().unwrap(), "first");
+    for subscriber in [&mut early, &mut late] {
+        let received = (0..3)
+            .map(|_| subscriber.receive().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(received, ["one", "two", "three"]);
+    }
+
+    drop(late);
+    // the first message after the subscriber went away may still be written into its socket's buffer
+    let mut count = publisher.publish(&"four".to_string()).unwrap();
+    if count == 2 {
+        count = publisher.publish(&"five".to_string()).unwrap();
+    }
+    assert_eq!(count, 1);
+    drop(publisher);
+    let rest = early.map(Result::unwrap).collect::<Vec<_>>();
+    assert!(rest
+        .iter()
+        .all(|message| message == "four" || message == "five"));
+}
+
+#[test]
+fn clients_can_wait_for_listeners_to_start() {
+    use gipc::connection::sync::Listener;
+    use std::time::Duration;
+    let name = format!("gipc-test-connect-wait-{}", std::process::id());
+    let client_name = name.clone();
+    let client = std::thread::spawn(move || {
+        let mut connection =
+            Connection::connect_to_socket_wait(&client_name, false, Duration::from_secs(5))
+                .unwrap();
+        connection.receive::<String>().unwrap()
+    });
+    std::thread::sleep(Duration::from_millis(100));
+    let mut listener = Listener::listen_as_socket(&name, false).unwrap();
+    listener.accept().unwrap().send(&"ready").unwrap();
+    assert_eq!(client.join().unwrap(), "ready");
+
+    let missing = format!("gipc-test-connect-wait-missing-{}", std::process::id());
+    assert!(matches!(
+        Connection::connect_to_socket_wait(&missing, false, Duration::from_millis(50)),
+        Err(gipc::Error::Connect { .. })
+    ));
+}
+
+#[test]
+fn owned_types_are_received_through_deserialize_owned() {
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+    struct Named {
+        name: String,
+    }
+    // the same bound as asynchronous connections use, so generic code can be shared between them
+    fn receive_owned<T: serde::de::DeserializeOwned>(
+        connection: &mut Connection,
+    ) -> gipc::Result<T> {
+        connection.receive()
+    }
+    let (mut a, mut b) = pair();
+    a.send(&Named {
+        name: String::from("owned"),
+    })
+    .unwrap();
+    assert_eq!(
+        receive_owned::<Named>(&mut b).unwrap(),
+        Named {
+            name: String::from("owned")
+        }
+    );
+}
+
+#[test]
+fn send_acked_waits_for_the_peer_to_receive() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    let (mut sender, mut receiver) = pair();
+    let acked = Arc::new(AtomicBool::new(false));
+    let flag = Arc::clone(&acked);
+    let sending = std::thread::spawn(move || {
+        sender.send_acked("acked").unwrap();
+        flag.store(true, Ordering::SeqCst);
+        sender
+    });
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(!acked.load(Ordering::SeqCst));
+    assert_eq!(receiver.receive::<String>().unwrap(), "acked");
+    let _sender = sending.join().unwrap();
+    assert!(acked.load(Ordering::SeqCst));
+}
+
+#[test]
+fn listeners_wrap_already_bound_std_sockets() {
+    use gipc::connection::sync::Listener;
+    use gipc::connection::ResolvedName;
+    use std::os::unix::net::UnixListener;
+    let path = std::env::temp_dir().join(format!("gipc-test-from-std-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut listener = Listener::from_std(UnixListener::bind(&path).unwrap());
+    assert_eq!(
+        listener.resolved_name(),
+        Some(&ResolvedName::Path(path.clone()))
+    );
+    let mut client = Connection::from(UnixStream::connect(&path).unwrap());
+    let mut accepted = listener.accept().unwrap();
+    client.send(&"activated").unwrap();
+    assert_eq!(accepted.receive::<String>().unwrap(), "activated");
+    drop(listener);
+    // the socket's file belongs to whoever bound it
+    assert!(path.exists());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn priority_messages_overtake_waiting_messages() {
+    use gipc::connection::Lane;
+    let (mut sender, mut receiver) = pair();
+    let large = "x".repeat(50_000);
+    sender.send(&large).unwrap();
+    sender.send_priority("urgent").unwrap();
+    assert_eq!(
+        receiver.receive_any::<String>().unwrap(),
+        (Lane::Priority, String::from("urgent"))
+    );
+    assert_eq!(
+        receiver.receive_any::<String>().unwrap(),
+        (Lane::Normal, large)
+    );
+}
+
+#[test]
+fn malformed_messages_can_be_skipped() {
+    use gipc::message::Message;
+    use std::io::Write;
+    let (mut connection, mut raw) = raw_pair();
+    let malformed = frame(&[0xff, 0xff]);
+    raw.write_all(&Message::Data(1u8).to_bytes().unwrap())
+        .unwrap();
+    raw.write_all(&malformed).unwrap();
+    raw.write_all(&malformed).unwrap();
+    raw.write_all(&Message::Data(2u8).to_bytes().unwrap())
+        .unwrap();
+    assert_eq!(connection.receive_or_skip::<u8>().unwrap(), Some(1));
+    assert_eq!(connection.receive_or_skip::<u8>().unwrap(), None);
+    // failing to deserialise a message consumes it all the same
+    assert!(matches!(
+        connection.receive::<u8>(),
+        Err(gipc::Error::Deserialise(_))
+    ));
+    assert_eq!(connection.receive::<u8>().unwrap(), 2);
+}
+
+#[test]
+fn backoff_connects_once_the_listener_starts() {
+    use gipc::connection::sync::Listener;
+    use gipc::connection::BackoffPolicy;
+    use std::time::Duration;
+    let policy = BackoffPolicy {
+        initial_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(40),
+        multiplier: 2.0,
+        jitter: 0.0,
+        max_attempts: None,
+        timeout: Some(Duration::from_secs(5)),
+    };
+    assert_eq!(policy.delay(1), Duration::from_millis(10));
+    assert_eq!(policy.delay(2), Duration::from_millis(20));
+    assert_eq!(policy.delay(10), Duration::from_millis(40));
+
+    let name = format!("gipc-test-backoff-{}", std::process::id());
+    let client_name = name.clone();
+    let client = std::thread::spawn(move || {
+        let mut connection =
+            Connection::connect_to_socket_backoff(&client_name, false, &policy).unwrap();
+        connection.receive::<String>().unwrap()
+    });
+    // long enough for several attempts to fail first
+    std::thread::sleep(Duration::from_millis(150));
+    let mut listener = Listener::listen_as_socket(&name, false).unwrap();
+    listener.accept().unwrap().send(&"up").unwrap();
+    assert_eq!(client.join().unwrap(), "up");
+
+    let missing = format!("gipc-test-backoff-missing-{}", std::process::id());
+    let attempts = BackoffPolicy {
+        max_attempts: Some(3),
+        timeout: None,
+        ..policy
+    };
+    assert!(matches!(
+        Connection::connect_to_socket_backoff(&missing, false, &attempts),
+        Err(gipc::Error::Connect { .. })
+    ));
+    let timeout = BackoffPolicy {
+        timeout: Some(Duration::from_millis(50)),
+        ..policy
+    };
+    assert!(matches!(
+        Connection::connect_to_socket_backoff(&missing, false, &timeout),
+        Err(gipc::Error::Timeout)
+    ));
+}
+
+#[test]
+fn serialised_lengths_match_what_is_written() {
+    use gipc::message::Message;
+    let (mut connection, mut raw) = raw_pair();
+    let value = vec![String::from("measured"); 20];
+    assert_eq!(
+        Message::Data(value.clone()).serialised_len().unwrap(),
+        Message::Data(value.clone()).to_bytes().unwrap().len()
+    );
+    let length = connection.serialised_len(&value).unwrap();
+    connection.send(&value).unwrap();
+    assert_eq!(read_available(&mut raw).len(), length);
+    #[cfg(feature = "json")]
+    {
+        connection.set_codec(Box::new(gipc::codec::JsonCodec));
+        let length = connection.serialised_len(&value).unwrap();
+        connection.send(&value).unwrap();
+        assert_eq!(read_available(&mut raw).len(), length);
+    }
+}
+
+#[cfg(feature = "auth")]
+#[test]
+fn handshakes_accept_only_the_right_secret() {
+    let (mut server, mut client) = pair();
+    let answering = std::thread::spawn(move || {
+        client.authenticate(b"secret").unwrap();
+        client.send(&"authenticated").unwrap();
+        client
+    });
+    server.authenticate_peer(b"secret").unwrap();
+    assert_eq!(server.receive::<String>().unwrap(), "authenticated");
+    answering.join().unwrap();
+
+    let (mut server, mut client) = pair();
+    let answering = std::thread::spawn(move || {
+        let result = client.authenticate(b"wrong");
+        (result, client.is_closed())
+    });
+    assert!(matches!(
+        server.authenticate_peer(b"secret"),
+        Err(gipc::Error::AuthFailed)
+    ));
+    assert!(server.is_closed());
+    let (result, closed) = answering.join().unwrap();
+    assert!(matches!(result, Err(gipc::Error::AuthFailed)));
+    assert!(closed);
+
+    // a peer that goes away mid-handshake fails it as well, which closes the connection
+    let (mut server, client) = pair();
+    drop(client);
+    assert!(server.authenticate_peer(b"secret").is_err());
+    assert!(server.is_closed());
+}
+
+#[cfg(feature = "auth")]
+#[test]
+fn listeners_reject_clients_with_the_wrong_secret() {
+    use gipc::connection::sync::Listener;
+    let name = format!("gipc-test-auth-{}", std::process::id());
+    let mut listener = Listener::listen_as_socket(&name, false).unwrap();
+    listener.set_auth_secret("secret");
+    let connect = |secret: &'static str| {
+        let name = name.clone();
+        std::thread::spawn(move || {
+            let mut client = Connection::connect_to_socket(&name, false).unwrap();
+            client.authenticate(secret.as_bytes()).map(|()| client)
+        })
+    };
+
+    let wrong = connect("wrong");
+    assert!(matches!(listener.accept(), Err(gipc::Error::AuthFailed)));
+    assert!(matches!(
+        wrong.join().unwrap(),
+        Err(gipc::Error::AuthFailed)
+    ));
+
+    let right = connect("secret");
+    let mut accepted = listener.accept().unwrap();
+    let mut client = right.join().unwrap().unwrap();
+    client.send(&"let in").unwrap();
+    assert_eq!(accepted.receive::<String>().unwrap(), "let in");
+}
+
+#[cfg(feature = "async-tokio")]
+#[tokio::test]
+async fn connections_can_be_turned_into_async_ones() {
+    let (a, mut b) = pair();
+    let id = a.id();
+    b.send(&1u8).unwrap();
+    b.send(&2u8).unwrap();
+    let mut a = a.into_async().unwrap();
+    assert_eq!(a.id(), id);
+    // the messages that arrived before are carried over, whether or not the sync side had read them yet
+    assert_eq!(a.receive::<u8>().await.unwrap(), 1);
+    assert_eq!(a.receive::<u8>().await.unwrap(), 2);
+    a.send("upgraded").await.unwrap();
+    assert_eq!(b.receive::<String>().unwrap(), "upgraded");
+    a.close().await;
+    assert!(matches!(b.receive::<u8>(), Err(gipc::Error::Closed(true))));
+}
+
+#[test]
+fn send_timestamps_are_present_and_monotonic() {
+    use gipc::connection::SendInstant;
+    let (mut sender, mut receiver) = pair();
+    sender.send(&0u8).unwrap();
+    sender.set_send_timestamps(true);
+    let before = SendInstant::now();
+    for i in 1..=3u8 {
+        sender.send(&i).unwrap();
+    }
+    assert_eq!(receiver.receive_with_timestamp::<u8>().unwrap(), (0, None));
+    let mut last = before;
+    for i in 1..=3u8 {
+        let (data, sent) = receiver.receive_with_timestamp::<u8>().unwrap();
+        let sent = sent.unwrap();
+        assert_eq!(data, i);
+        assert!(sent >= last);
+        assert!(sent <= SendInstant::now());
+        last = sent;
+    }
+    // stamped messages are received like any other
+    sender.send(&4u8).unwrap();
+    assert_eq!(receiver.receive::<u8>().unwrap(), 4);
+}
+
+/// A connection implementation whose writes all fail, as if the other side had already gone away.
+struct FailingWrites;
+
+impl Read for FailingWrites {
+    fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl std::io::Write for FailingWrites {
+    fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+        Err(std::io::ErrorKind::BrokenPipe.into())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+impl gipc::connection::sync::ConnectionImpl for FailingWrites {
+    fn close(&mut self) {}
+}
+
+#[test]
+fn try_close_reports_undelivered_close_frames() {
+    let mut connection = Connection::new(Box::new(FailingWrites));
+    let Err(gipc::Error::Io(error)) = connection.try_close() else {
+        panic!("the close frame can't have been sent");
+    };
+    assert_eq!(error.kind(), std::io::ErrorKind::BrokenPipe);
+    assert!(connection.is_closed());
+    assert!(matches!(
+        connection.try_close(),
+        Err(gipc::Error::Closed(false))
+    ));
+}
+
+#[test]
+fn peek_variant_tells_data_from_close_frames() {
+    use gipc::message::MessageKind;
+    let (mut a, mut b) = pair();
+    b.send(&1u8).unwrap();
+    b.close();
+    assert_eq!(a.peek_variant().unwrap(), MessageKind::Data);
+    assert_eq!(a.peek_variant().unwrap(), MessageKind::Data);
+    assert_eq!(a.receive::<u8>().unwrap(), 1);
+    assert_eq!(a.peek_variant().unwrap(), MessageKind::ClosingConnection);
+    assert_eq!(a.peek_variant().unwrap(), MessageKind::ClosingConnection);
+    assert!(!a.is_closed());
+    assert!(matches!(a.receive::<u8>(), Err(gipc::Error::Closed(true))));
+}
+
+#[test]
+fn idle_connections_are_closed() {
+    use gipc::connection::CloseReason;
+    use std::time::{Duration, Instant};
+    let (mut a, mut b) = pair();
+    a.set_idle_timeout(Some(Duration::from_millis(100)))
+        .unwrap();
+    // activity keeps the connection open
+    std::thread::sleep(Duration::from_millis(60));
+    b.send(&1u8).unwrap();
+    assert_eq!(a.receive::<u8>().unwrap(), 1);
+    std::thread::sleep(Duration::from_millis(60));
+    assert!(!a.is_closed());
+
+    let started = Instant::now();
+    assert!(matches!(a.receive::<u8>(), Err(gipc::Error::Closed(true))));
+    assert!(started.elapsed() < Duration::from_millis(500));
+    assert_eq!(a.close_reason(), Some(CloseReason::Idle));
+}
+
+#[test]
+fn accept_many_takes_every_pending_connection() {
+    use gipc::connection::sync::Listener;
+    let name = format!("gipc-test-accept-many-{}", std::process::id());
+    let mut listener = Listener::listen_as_socket(&name, false).unwrap();
+    let connect = || Connection::connect_to_socket(&name, false).unwrap();
+    let clients = [connect(), connect(), connect()];
+    assert_eq!(listener.accept_many(10).unwrap().len(), 3);
+
+    let more = [connect(), connect(), connect()];
+    assert_eq!(listener.accept_many(2).unwrap().len(), 2);
+    assert_eq!(listener.accept_many(2).unwrap().len(), 1);
+    drop((clients, more));
+}
+
+#[test]
+fn implausible_length_prefixes_are_desyncs() {
+    use std::io::Write;
+    let (mut connection, mut raw) = raw_pair();
+    raw.write_all(&[0xfe; 16]).unwrap();
+    assert!(matches!(
+        connection.receive::<u8>(),
+        Err(gipc::Error::ProtocolDesync(_))
+    ));
+
+    let (mut connection, mut raw) = raw_pair();
+    connection.set_max_message_size(Some(1024));
+    raw.write_all(&4096u64.to_be_bytes()).unwrap();
+    assert!(matches!(
+        connection.receive::<u8>(),
+        Err(gipc::Error::ProtocolDesync(4096))
+    ));
+
+    // a prefix claiming 64 GiB could be allocated, so without a limit it's only rejected by a maximum message size...
+    let (mut connection, mut raw) = raw_pair();
+    connection.set_max_message_size(Some(1024 * 1024));
+    raw.write_all(&(1u64 << 36).to_be_bytes()).unwrap();
+    assert!(matches!(
+        connection.receive::<u8>(),
+        Err(gipc::Error::ProtocolDesync(length)) if length == 1 << 36
+    ));
+    // ...but nothing is set aside for it before its bytes arrive, so the receive fails once the other side leaves instead of running out of memory
+    let (mut connection, mut raw) = raw_pair();
+    raw.write_all(&(1u64 << 36).to_be_bytes()).unwrap();
+    raw.write_all(b"garbage").unwrap();
+    drop(raw);
+    let Err(gipc::Error::Io(error)) = connection.receive::<u8>() else {
+        panic!("the frame can't have been received");
+    };
+    assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn sockets_can_be_bound_to_explicit_paths() {
+    use gipc::connection::sync::Listener;
+    use gipc::connection::ResolvedName;
+    let path = std::env::temp_dir().join(format!("gipc-test-path-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut listener = Listener::listen_on_path(&path).unwrap();
+    assert_eq!(
+        listener.resolved_name(),
+        Some(&ResolvedName::Path(path.clone()))
+    );
+    let mut client = Connection::connect_to_path(&path).unwrap();
+    client.send(&"by path").unwrap();
+    assert_eq!(
+        listener.accept().unwrap().receive::<String>().unwrap(),
+        "by path"
+    );
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(
+        Connection::connect_to_path(&path),
+        Err(gipc::Error::Connect { .. })
+    ));
+}
+
+#[test]
+fn message_hooks_report_type_names_and_sizes() {
+    use gipc::connection::Direction;
+    use gipc::message::Message;
+    use std::sync::{Arc, Mutex};
+    let reported = Arc::new(Mutex::new(Vec::new()));
+    let hook = |log: Arc<Mutex<Vec<_>>>| -> gipc::connection::MessageHook {
+        Box::new(move |direction, name: &str, length| {
+            log.lock()
+                .unwrap()
+                .push((direction, name.to_owned(), length))
+        })
+    };
+    let (mut a, mut b) = pair();
+    a.set_message_hook(hook(Arc::clone(&reported)));
+    b.set_message_hook(hook(Arc::clone(&reported)));
+
+    let value = vec![1u16, 2, 3];
+    a.send_signal(1).unwrap();
+    a.send(&value).unwrap();
+    assert!(b.receive::<Vec<u16>>().is_err());
+    assert_eq!(b.receive::<Vec<u16>>().unwrap(), value);
+
+    // the reported size is that of the payload, without its length prefix
+    let length = Message::Data(value).to_bytes().unwrap().len() - 8;
+    let name = std::any::type_name::<Vec<u16>>().to_owned();
+    assert_eq!(
+        *reported.lock().unwrap(),
+        [
+            (Direction::Sent, name.clone(), length),
+            (Direction::Received, name, length)
+        ]
+    );
+}
+
+#[test]
+fn serve_answers_requests_until_the_peer_closes() {
+    let (mut server, mut client) = pair();
+    let serving = std::thread::spawn(move || server.serve(|request: u32| request * 2));
+    for request in 1..=3u32 {
+        assert_eq!(
+            client.send_and_receive::<_, u32>(&request).unwrap(),
+            request * 2
+        );
+    }
+    client.close();
+    serving.join().unwrap().unwrap();
+}
+
+#[test]
+fn graceful_closes_accept_pending_connections() {
+    use gipc::connection::sync::Listener;
+    let name = format!("gipc-test-close-graceful-{}", std::process::id());
+    let mut listener = Listener::listen_as_socket(&name, false).unwrap();
+    let connect = || Connection::connect_to_socket(&name, false).unwrap();
+    let mut clients = [connect(), connect()];
+    let mut accepted = listener.close_graceful().unwrap();
+    assert_eq!(accepted.len(), 2);
+    assert!(listener.is_closed());
+    assert!(matches!(listener.accept(), Err(gipc::Error::Closed(false))));
+
+    for (client, connection) in clients.iter_mut().zip(&mut accepted) {
+        client.send(&"still served").unwrap();
+        assert_eq!(connection.receive::<String>().unwrap(), "still served");
+    }
+}
+
+#[test]
+fn socket_buffer_sizes_can_be_tuned() {
+    let (mut connection, _peer) = pair();
+    let before = connection.recv_buffer_size().unwrap();
+    connection.set_recv_buffer_size(before * 4).unwrap();
+    // the kernel may round or double the size, but never ignores a bigger one entirely
+    assert!(connection.recv_buffer_size().unwrap() > before);
+    let before = connection.send_buffer_size().unwrap();
+    connection.set_send_buffer_size(before * 4).unwrap();
+    assert!(connection.send_buffer_size().unwrap() > before);
+}
+
+#[test]
+fn connect_hook_sees_every_accepted_connection() {
+    use gipc::connection::sync::Listener;
+    use std::sync::{Arc, Mutex};
+    let name = format!("gipc-test-connect-hook-{}", std::process::id());
+    let mut listener = Listener::listen_as_socket(&name, false).unwrap();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let recorder = Arc::clone(&seen);
+    listener.set_connect_hook(Box::new(move |connection| {
+        recorder.lock().unwrap().push(connection.id());
+    }));
+
+    let _clients = [
+        Connection::connect_to_socket(&name, false).unwrap(),
+        Connection::connect_to_socket(&name, false).unwrap(),
+        Connection::connect_to_socket(&name, false).unwrap(),
+    ];
+    let first = listener.accept().unwrap();
+    let second = listener.accept().unwrap();
+    assert_eq!(*seen.lock().unwrap(), [first.id(), second.id()]);
+
+    listener.clear_connect_hook();
+    listener.accept().unwrap();
+    assert_eq!(seen.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn custom_encodings_round_trip_as_bytes() {
+    let (mut sender, mut receiver) = pair();
+    // a fixed layout: a big-endian u16 tag followed by a big-endian u32 value
+    sender
+        .send_bytes_with(|buffer| {
+            buffer.extend_from_slice(&7u16.to_be_bytes());
+            buffer.extend_from_slice(&1234u32.to_be_bytes());
+            Ok(())
+        })
+        .unwrap();
+    let decode = |bytes: &[u8]| {
+        let tag = u16::from_be_bytes(bytes[..2].try_into().unwrap());
+        let value = u32::from_be_bytes(bytes[2..6].try_into().unwrap());
+        Ok((tag, value))
+    };
+    assert_eq!(receiver.receive_bytes_with(decode).unwrap(), (7, 1234));
+
+    // nothing is sent if encoding fails, and the bytes helpers interoperate with each other
+    assert!(sender
+        .send_bytes_with(|_| Err(std::io::Error::from(std::io::ErrorKind::InvalidData).into()))
+        .is_err());
+    sender.send_bytes(&[0, 1, 0, 0, 0, 2]).unwrap();
+    assert_eq!(receiver.receive_bytes_with(decode).unwrap(), (1, 2));
+}
+
+#[test]
+fn close_reasons_reach_the_peer() {
+    use gipc::connection::CloseCode;
+    let (mut a, mut b) = pair();
+    a.send(&1u8).unwrap();
+    a.close_with_reason(CloseCode(4000), Some("shutting down"))
+        .unwrap();
+    assert!(a.is_closed());
+    assert_eq!(b.receive::<u8>().unwrap(), 1);
+    match b.receive::<u8>() {
+        Err(gipc::Error::ClosedWithReason { code, message }) => {
+            assert_eq!(code, CloseCode(4000));
+            assert_eq!(message.as_deref(), Some("shutting down"));
+        }
+        other => panic!("expected a close reason, got {:?}", other),
+    }
+    assert!(b.is_closed());
+
+    // plain closes still look the same as before
+    let (mut a, mut b) = pair();
+    a.close();
+    assert!(matches!(b.receive::<u8>(), Err(gipc::Error::Closed(true))));
+}
+
+/// A stream whose first reads and writes fail with [`WouldBlock`](std::io::ErrorKind::WouldBlock).
+struct Blocking {
+    incoming: std::io::Cursor<Vec<u8>>,
+    blocked_reads: usize,
+    blocked_writes: usize,
+}
+
+impl Blocking {
+    fn new(incoming: Vec<u8>, blocked: usize) -> Self {
+        Self {
+            incoming: std::io::Cursor::new(incoming),
+            blocked_reads: blocked,
+            blocked_writes: blocked,
+        }
+    }
+}
+
+impl Read for Blocking {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.blocked_reads > 0 {
+            self.blocked_reads -= 1;
+            return Err(std::io::ErrorKind::WouldBlock.into());
+        }
+        self.incoming.read(buf)
+    }
+}
+
+impl std::io::Write for Blocking {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.blocked_writes > 0 {
+            self.blocked_writes -= 1;
+            return Err(std::io::ErrorKind::WouldBlock.into());
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl gipc::connection::sync::ConnectionImpl for Blocking {
+    fn close(&mut self) {}
+}
+
+#[test]
+fn retrying_connections_hide_transient_errors() {
+    use gipc::connection::{BackoffPolicy, RetryPolicy};
+    use gipc::message::Message;
+    use std::time::Duration;
+    let policy = RetryPolicy {
+        backoff: BackoffPolicy {
+            initial_delay: Duration::from_millis(1),
+            max_attempts: Some(5),
+            ..BackoffPolicy::default()
+        },
+        ..RetryPolicy::default()
+    };
+    let mut incoming = Message::Data(7u32).to_bytes().unwrap();
+    incoming.extend(Message::Data("not a number").to_bytes().unwrap());
+    let stream = Blocking::new(incoming, 2);
+    let mut connection = Connection::new(Box::new(stream)).with_retry(policy.clone());
+    assert_eq!(connection.receive::<u32>().unwrap(), 7);
+    connection.send(&8u32).unwrap();
+    // errors other than the transient I/O ones aren't retried
+    assert!(matches!(
+        connection.receive::<u32>(),
+        Err(gipc::Error::Deserialise(_))
+    ));
+
+    let strict = RetryPolicy {
+        transient_kinds: Vec::new(),
+        ..policy
+    };
+    let stream = Blocking::new(Message::Data(7u32).to_bytes().unwrap(), 1);
+    let mut connection = Connection::new(Box::new(stream)).with_retry(strict);
+    let Err(gipc::Error::Io(error)) = connection.receive::<u32>() else {
+        panic!("WouldBlock isn't retried by this policy");
+    };
+    assert_eq!(error.kind(), std::io::ErrorKind::WouldBlock);
+}
+
+#[test]
+fn dropped_connections_deliver_everything_sent_before_closing() {
+    let (mut a, mut b) = pair();
+    for i in 0..100u32 {
+        a.send(&i).unwrap();
+    }
+    drop(a);
+    for i in 0..100u32 {
+        assert_eq!(b.receive::<u32>().unwrap(), i);
+    }
+    assert!(matches!(b.receive::<u32>(), Err(gipc::Error::Closed(true))));
+}
+
+#[test]
+fn descriptions_reflect_how_connections_are_set_up() {
+    use gipc::connection::sync::Listener;
+    let (connection, _peer) = pair();
+    let description = connection.describe();
+    assert_eq!(description.id, connection.id());
+    assert_eq!(description.codec, "CBOR");
+    assert_eq!(description.transport, "unix socket");
+    assert!(description.endpoint.is_none());
+
+    let name = format!("gipc-test-describe-{}", std::process::id());
+    let mut listener = Listener::listen_as_socket(&name, false).unwrap();
+    let client = Connection::connect_to_socket(&name, false).unwrap();
+    let accepted = listener.accept().unwrap();
+    let resolved = listener.resolved_name().cloned();
+    assert!(resolved.is_some());
+    assert_eq!(client.describe().endpoint, resolved);
+    assert_eq!(accepted.describe().endpoint, resolved);
+    assert_eq!(client.describe().transport, "local socket");
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn descriptions_name_the_current_codec() {
+    let (mut connection, _peer) = pair();
+    connection.set_codec(Box::new(gipc::codec::JsonCodec));
+    assert_eq!(connection.describe().codec, "JSON");
+}
+
+#[cfg(feature = "erased-serde")]
+#[test]
+fn erased_values_are_received_as_their_concrete_types() {
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+    struct Plugin {
+        name: String,
+        version: u32,
+    }
+    let (mut sender, mut receiver) = pair();
+    let values: Vec<Box<dyn erased_serde::Serialize>> = vec![
+        Box::new(Plugin {
+            name: String::from("example"),
+            version: 2,
+        }),
+        Box::new(5u8),
+    ];
+    for value in &values {
+        sender.send_erased(value.as_ref()).unwrap();
+    }
+    assert_eq!(
+        receiver.receive::<Plugin>().unwrap(),
+        Plugin {
+            name: String::from("example"),
+            version: 2
+        }
+    );
+    assert_eq!(receiver.receive::<u8>().unwrap(), 5);
+}
+
+#[test]
+fn receive_outcomes_report_closes_without_errors() {
+    use gipc::connection::{CloseCode, ReceiveOutcome};
+    let (mut a, mut b) = pair();
+    a.send(&1u8).unwrap();
+    a.close();
+    assert_eq!(b.receive_outcome::<u8>().unwrap(), ReceiveOutcome::Data(1));
+    assert_eq!(b.receive_outcome::<u8>().unwrap(), ReceiveOutcome::Closed);
+    // once closed, this fails like receive does
+    assert!(matches!(
+        b.receive_outcome::<u8>(),
+        Err(gipc::Error::Closed(false))
+    ));
+
+    let (mut a, mut b) = pair();
+    a.close_with_reason(CloseCode::NORMAL, Some("done"))
+        .unwrap();
+    assert_eq!(b.receive_outcome::<u8>().unwrap(), ReceiveOutcome::Closed);
+}
+
+#[test]
+fn rate_limits_throttle_sending() {
+    use gipc::connection::RateLimit;
+    use std::time::{Duration, Instant};
+    let (mut sender, _receiver) = pair();
+    sender.set_rate_limit(Some(RateLimit {
+        messages_per_second: Some(50),
+        bytes_per_second: None,
+    }));
+    // a full second's worth of messages goes out straight away, but the ten after it have to wait about 200ms
+    let started = Instant::now();
+    for i in 0..50u32 {
+        sender.send(&i).unwrap();
+    }
+    assert!(started.elapsed() < Duration::from_millis(100));
+    for i in 0..10u32 {
+        sender.send(&i).unwrap();
+    }
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(150), "{:?}", elapsed);
+
+    // waiting past the write deadline fails instead
+    sender
+        .set_write_deadline(Some(Instant::now() + Duration::from_millis(5)))
+        .unwrap();
+    assert!(matches!(sender.send(&0u32), Err(gipc::Error::Timeout)));
+    sender.set_write_deadline(None).unwrap();
+    sender.set_rate_limit(None);
+    assert_eq!(sender.rate_limit(), None);
+}
+
+#[test]
+fn clones_send_and_receive_from_separate_threads() {
+    let (connection, mut peer) = pair();
+    let mut writer = connection.try_clone().unwrap();
+    let mut reader = connection;
+    assert_ne!(writer.id(), reader.id());
+    // only the reader tells the other side when it closes
+    writer.set_send_close_frame(false);
+
+    let echo = std::thread::spawn(move || {
+        while let Ok(message) = peer.receive::<u32>() {
+            peer.send(&(message * 10)).unwrap();
+        }
+    });
+    let writing = std::thread::spawn(move || {
+        for i in 1..=3u32 {
+            writer.send(&i).unwrap();
+        }
+        writer
+    });
+    let reading = std::thread::spawn(move || {
+        let received = (0..3)
+            .map(|_| reader.receive::<u32>().unwrap())
+            .collect::<Vec<_>>();
+        (reader, received)
+    });
+    let mut writer = writing.join().unwrap();
+    let (mut reader, received) = reading.join().unwrap();
+    assert_eq!(received, [10, 20, 30]);
+    writer.close();
+    reader.close();
+    echo.join().unwrap();
+}
+
+#[test]
+fn unanswered_requests_time_out_and_close_the_connection() {
+    use gipc::connection::CloseReason;
+    use std::time::{Duration, Instant};
+    let (mut client, mut server) = pair();
+    let answering = std::thread::spawn(move || {
+        let request = server.receive::<u32>().unwrap();
+        server.send(&(request + 1)).unwrap();
+        // the second request is never answered
+        server.receive::<u32>().unwrap();
+        server
+    });
+    let timeout = Duration::from_millis(100);
+    assert_eq!(
+        client
+            .send_and_receive_timeout::<_, u32>(&1u32, timeout)
+            .unwrap(),
+        2
+    );
+    let started = Instant::now();
+    assert!(matches!(
+        client.send_and_receive_timeout::<_, u32>(&2u32, timeout),
+        Err(gipc::Error::Timeout)
+    ));
+    assert!(started.elapsed() >= timeout);
+    assert!(client.is_closed());
+    assert_eq!(
+        client.close_reason(),
+        Some(CloseReason::Error(std::io::ErrorKind::TimedOut))
+    );
+    drop(answering.join().unwrap());
+}
+
+/// A Unix domain socket whose read timeout can be set but never cleared again.
+struct StuckReadTimeouts(UnixStream);
+
+impl std::io::Read for StuckReadTimeouts {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl std::io::Write for StuckReadTimeouts {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        std::io::Write::write(&mut self.0, buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::Write::flush(&mut self.0)
+    }
+}
+
+impl gipc::connection::sync::ConnectionImpl for StuckReadTimeouts {
+    fn close(&mut self) {}
+    fn set_read_timeout(&mut self, timeout: Option<std::time::Duration>) -> gipc::Result<()> {
+        match timeout {
+            Some(_) => {
+                gipc::connection::sync::ConnectionImpl::set_read_timeout(&mut self.0, timeout)
+            }
+            None => Err(gipc::Error::Io(std::io::ErrorKind::Unsupported.into())),
+        }
+    }
+    fn set_write_timeout(&mut self, timeout: Option<std::time::Duration>) -> gipc::Result<()> {
+        gipc::connection::sync::ConnectionImpl::set_write_timeout(&mut self.0, timeout)
+    }
+}
+
+#[test]
+fn request_timeouts_are_undone_even_if_clearing_the_read_timeout_fails() {
+    use std::time::Duration;
+    let (a, b) = UnixStream::pair().unwrap();
+    let mut client = Connection::new(Box::new(StuckReadTimeouts(a)));
+    let mut server = Connection::new(Box::new(b));
+    let answering = std::thread::spawn(move || {
+        let request = server.receive::<u32>().unwrap();
+        server.send(&(request + 1)).unwrap();
+        // the second request is never answered
+        server.receive::<u32>().unwrap();
+        server
+    });
+    let timeout = Duration::from_millis(50);
+    let Err(gipc::Error::Io(error)) = client.send_and_receive_timeout::<_, u32>(&1u32, timeout)
+    else {
+        panic!("the read timeout can't be cleared");
+    };
+    assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+    // the write deadline was still cleared, so sending works once it has passed
+    std::thread::sleep(timeout * 2);
+    client.send(&2u32).unwrap();
+    assert!(!client.is_closed());
+
+    let (a, _b) = UnixStream::pair().unwrap();
+    let mut client = Connection::new(Box::new(StuckReadTimeouts(a)));
+    // timing out closes the connection rather than failing to clear the read timeout
+    assert!(matches!(
+        client.send_and_receive_timeout::<_, u32>(&1u32, timeout),
+        Err(gipc::Error::Timeout)
+    ));
+    assert!(client.is_closed());
+    drop(answering.join().unwrap());
+}
+
+/// A Unix domain socket that counts how often its peer's credentials are queried.
+struct CountingCredentials {
+    stream: UnixStream,
+    queries: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+}
+
+impl Read for CountingCredentials {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl std::io::Write for CountingCredentials {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stream.write(buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl gipc::connection::sync::ConnectionImpl for CountingCredentials {
+    fn close(&mut self) {}
+    fn peer_credentials(&mut self) -> gipc::Result<gipc::connection::PeerCredentials> {
+        self.queries
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        gipc::connection::sync::ConnectionImpl::peer_credentials(&mut self.stream)
+    }
+}
+
+#[test]
+fn peer_credentials_are_queried_once() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    let (a, _b) = UnixStream::pair().unwrap();
+    let queries = Arc::new(AtomicUsize::new(0));
+    let mut connection = Connection::new(Box::new(CountingCredentials {
+        stream: a,
+        queries: Arc::clone(&queries),
+    }));
+    let first = connection.peer_credentials().unwrap();
+    let second = connection.peer_credentials().unwrap();
+    assert_eq!(first, second);
+    assert!(first.pid.is_none_or(|pid| pid == std::process::id()));
+    assert_eq!(queries.load(Ordering::SeqCst), 1);
+
+    assert_eq!(connection.refresh_peer_credentials().unwrap(), first);
+    connection.peer_credentials().unwrap();
+    assert_eq!(queries.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn draining_closes_surface_messages_that_race_the_close() {
+    use std::time::Duration;
+    let (mut a, mut b) = pair();
+    // b sends these before it sees that a is closing
+    b.send(&1u32).unwrap();
+    b.send_signal(9).unwrap();
+    b.send(&2u32).unwrap();
+    let peer = std::thread::spawn(move || {
+        assert!(matches!(b.receive::<u32>(), Err(gipc::Error::Closed(true))));
+    });
+    let trailing = a.close_draining::<u32>(Duration::from_secs(5)).unwrap();
+    assert_eq!(trailing, [1, 2]);
+    assert!(a.is_closed());
+    peer.join().unwrap();
+
+    // a peer that never closes is cut off by the timeout
+    let (mut a, _b) = pair();
+    let trailing = a.close_draining::<u32>(Duration::from_millis(50)).unwrap();
+    assert!(trailing.is_empty());
+    assert!(a.is_closed());
+}
+
+#[test]
+fn draining_closes_close_even_when_they_fail() {
+    use std::time::Duration;
+    let mut connection = Connection::new(Box::new(FailingWrites));
+    let Err(gipc::Error::Io(error)) = connection.close_draining::<u32>(Duration::from_secs(5))
+    else {
+        panic!("the close frame can't have been sent");
+    };
+    assert_eq!(error.kind(), std::io::ErrorKind::BrokenPipe);
+    assert!(connection.is_closed());
+
+    let (mut a, mut b) = pair();
+    b.send(&"not a number").unwrap();
+    assert!(matches!(
+        a.close_draining::<u32>(Duration::from_secs(5)),
+        Err(gipc::Error::Deserialise(_))
+    ));
+    assert!(a.is_closed());
+}
+
+#[test]
+fn warming_up_leaves_connections_usable() {
+    let (mut client, mut server) = pair();
+    let serving = std::thread::spawn(move || server.serve(|request: u32| request + 1));
+    client.warm_up().unwrap();
+    assert_eq!(client.send_and_receive::<_, u32>(&1u32).unwrap(), 2);
+    client.close();
+    serving.join().unwrap().unwrap();
+
+    // both sides can warm up at once, and messages sent in the meantime aren't lost
+    let (mut a, mut b) = pair();
+    a.send(&"before").unwrap();
+    let other = std::thread::spawn(move || {
+        b.warm_up().unwrap();
+        b.receive::<String>().unwrap()
+    });
+    a.warm_up().unwrap();
+    assert_eq!(other.join().unwrap(), "before");
+}
+
+#[test]
+fn progress_is_reported_whilst_large_messages_arrive() {
+    let (mut receiver, mut sender) = pair();
+    let large = vec![7u8; 4 << 20];
+    let expected = large.clone();
+    let sending = std::thread::spawn(move || sender.send_bytes(&large).unwrap());
+    let mut reports = Vec::new();
+    let received: serde_bytes::ByteBuf = receiver
+        .receive_with_progress(|read, total| reports.push((read, total)))
+        .unwrap();
+    sending.join().unwrap();
+    assert_eq!(received.into_vec(), expected);
+
+    assert!(reports.len() > 1, "{:?}", reports);
+    let total = reports[0].1;
+    assert!(total > expected.len() as u64);
+    assert!(reports.iter().all(|&(_, t)| t == total));
+    assert!(reports.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+    assert_eq!(reports.last(), Some(&(total, total)));
+}
+
+#[test]
+fn connections_can_be_reset_after_timing_out() {
+    use std::time::{Duration, Instant};
+    let (mut a, mut b) = pair();
+    b.set_read_deadline(Some(Instant::now() + Duration::from_millis(20)))
+        .unwrap();
+    assert!(matches!(b.receive::<u32>(), Err(gipc::Error::Timeout)));
+    assert!(b.is_failed());
+    b.reset().unwrap();
+    assert!(!b.is_failed());
+    a.send(&7u32).unwrap();
+    assert_eq!(b.receive::<u32>().unwrap(), 7);
+
+    // messages that can't be deserialised leave the connection as it was
+    a.send(&"not a number").unwrap();
+    assert!(b.receive::<u32>().is_err());
+    assert!(!b.is_failed());
+
+    b.close();
+    assert!(matches!(b.reset(), Err(gipc::Error::Closed(false))));
+}
+
+#[test]
+fn connections_that_time_out_mid_frame_are_reset_once_the_frame_arrives() {
+    use gipc::message::Message;
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+    let (mut connection, mut raw) = raw_pair();
+    let bytes = Message::Data(5u32).to_bytes().unwrap();
+    raw.write_all(&bytes[..2]).unwrap();
+    connection
+        .set_read_deadline(Some(Instant::now() + Duration::from_millis(20)))
+        .unwrap();
+    assert!(matches!(
+        connection.receive::<u32>(),
+        Err(gipc::Error::Timeout)
+    ));
+    assert!(matches!(connection.reset(), Err(gipc::Error::MidFrame)));
+    assert!(connection.is_failed());
+
+    raw.write_all(&bytes[2..]).unwrap();
+    connection
+        .set_read_deadline(Some(Instant::now() + Duration::from_secs(5)))
+        .unwrap();
+    assert_eq!(connection.receive::<u32>().unwrap(), 5);
+    connection.reset().unwrap();
+    assert!(!connection.is_failed());
+}
+
+/// A stream that accepts a few bytes and then times out every write.
+struct StallingWrites {
+    accepted: usize,
+}
+
+impl Read for StallingWrites {
+    fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl std::io::Write for StallingWrites {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        match self.accepted {
+            0 => Err(std::io::ErrorKind::TimedOut.into()),
+            accepted => {
+                let written = bytes.len().min(accepted);
+                self.accepted -= written;
+                Ok(written)
+            }
+        }
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl gipc::connection::sync::ConnectionImpl for StallingWrites {
+    fn close(&mut self) {}
+}
+
+#[test]
+fn partial_writes_poison_connections() {
+    let mut connection = Connection::new(Box::new(StallingWrites { accepted: 0 }));
+    assert!(connection.send(&1u32).is_err());
+    assert!(connection.is_failed());
+    connection.reset().unwrap();
+
+    let mut connection = Connection::new(Box::new(StallingWrites { accepted: 2 }));
+    assert!(connection.send(&1u32).is_err());
+    assert!(connection.is_failed());
+    assert!(matches!(connection.reset(), Err(gipc::Error::MidFrame)));
+    assert!(matches!(connection.send(&2u32), Err(gipc::Error::MidFrame)));
+}
+
+#[test]
+fn messages_larger_than_the_peer_accepts_fail_before_being_sent() {
+    let (mut sender, mut receiver) = pair();
+    receiver.set_max_message_size(Some(64));
+    let exchange = std::thread::spawn(move || {
+        receiver.exchange_max_message_sizes().unwrap();
+        receiver
+    });
+    assert_eq!(sender.exchange_max_message_sizes().unwrap(), Some(64));
+    let mut receiver = exchange.join().unwrap();
+    assert_eq!(receiver.peer_max_message_size(), None);
+    assert_eq!(sender.peer_max_message_size(), Some(64));
+
+    let Err(gipc::Error::MessageTooLarge(length)) = sender.send(&[0u8; 100].as_slice()) else {
+        panic!("the message is larger than the receiver accepts");
+    };
+    assert!(length > 64);
+    assert!(!sender.is_failed());
+    sender.send(&"small").unwrap();
+    assert_eq!(receiver.receive::<String>().unwrap(), "small");
+}
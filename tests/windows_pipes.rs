@@ -0,0 +1,94 @@
+//! Behaviour of Windows named pipes created using `listen_on_pipe`, including restricting who may connect with a security descriptor.
+#![cfg(windows)]
+
+use gipc::connection::PipeOptions;
+
+/// Gets a pipe name that no other test uses.
+fn pipe_name(test: &str) -> String {
+    format!("gipc-test-{}-{}", test, std::process::id())
+}
+
+/// Only lets the user that owns the pipe, which is the user running the tests, connect to it.
+const OWNER_ONLY: &str = "D:P(A;;GA;;;OW)";
+/// Doesn't let anybody connect to the pipe.
+const NOBODY: &str = "D:P";
+
+fn options(name: &str, security_descriptor: &str) -> PipeOptions {
+    PipeOptions {
+        name: name.into(),
+        security_descriptor: Some(security_descriptor.to_string()),
+        ..PipeOptions::default()
+    }
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn restricted_pipes_only_let_permitted_users_connect() {
+    use gipc::connection::sync::{Connection, Listener};
+    let name = pipe_name("sync-owner-only");
+    let mut listener = Listener::listen_on_pipe(&options(&name, OWNER_ONLY)).unwrap();
+    let mut client = Connection::connect_to_pipe(&name).unwrap();
+    let mut server = listener.accept().unwrap();
+    client.send(&"ping").unwrap();
+    assert_eq!(server.receive::<String>().unwrap(), "ping");
+    server.send(&7u32).unwrap();
+    assert_eq!(client.receive::<u32>().unwrap(), 7);
+
+    // clients can keep connecting, since a new instance of the pipe is waiting for the next one
+    let mut second = Connection::connect_to_pipe(&name).unwrap();
+    let mut accepted = listener.accept().unwrap();
+    second.send(&1u8).unwrap();
+    assert_eq!(accepted.receive::<u8>().unwrap(), 1);
+    // ...but another pipe can't take the name
+    assert!(matches!(
+        Listener::listen_on_pipe(&options(&name, OWNER_ONLY)),
+        Err(gipc::Error::Bind { .. })
+    ));
+
+    let name = pipe_name("sync-nobody");
+    let _listener = Listener::listen_on_pipe(&options(&name, NOBODY)).unwrap();
+    let Err(gipc::Error::Connect { source, .. }) = Connection::connect_to_pipe(&name) else {
+        panic!("nobody may connect to the pipe");
+    };
+    assert_eq!(source.kind(), std::io::ErrorKind::PermissionDenied);
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn invalid_security_descriptors_fail_to_bind() {
+    use gipc::connection::sync::Listener;
+    let name = pipe_name("sync-invalid");
+    assert!(matches!(
+        Listener::listen_on_pipe(&options(&name, "not a descriptor")),
+        Err(gipc::Error::Bind { .. })
+    ));
+}
+
+#[cfg(feature = "async-tokio")]
+#[tokio::test]
+async fn restricted_async_pipes_only_let_permitted_users_connect() {
+    use gipc::connection::async_tokio::{Connection, Listener};
+    let name = pipe_name("async-owner-only");
+    let mut listener = Listener::listen_on_pipe(&PipeOptions {
+        message_mode: true,
+        ..options(&name, OWNER_ONLY)
+    })
+    .unwrap();
+    let mut client = Connection::connect_to_pipe(&name).await.unwrap();
+    let mut server = listener.accept().await.unwrap();
+    client.send("ping").await.unwrap();
+    assert_eq!(server.receive::<String>().await.unwrap(), "ping");
+    server.send(vec![0u8; 100_000]).await.unwrap();
+    assert_eq!(client.receive::<Vec<u8>>().await.unwrap().len(), 100_000);
+    client.close().await;
+    server.close().await;
+    listener.close().await.unwrap();
+
+    let name = pipe_name("async-nobody");
+    let mut listener = Listener::listen_on_pipe(&options(&name, NOBODY)).unwrap();
+    let Err(gipc::Error::Connect { source, .. }) = Connection::connect_to_pipe(&name).await else {
+        panic!("nobody may connect to the pipe");
+    };
+    assert_eq!(source.kind(), std::io::ErrorKind::PermissionDenied);
+    listener.close().await.unwrap();
+}